@@ -0,0 +1,128 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ActivityKind {
+    ScanCompleted,
+    GroupFinalized,
+    ExportDelivered,
+    /// With the error as the detail
+    ExportFailed,
+    Comment,
+}
+
+impl ActivityKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ActivityKind::ScanCompleted => "scan_completed",
+            ActivityKind::GroupFinalized => "group_finalized",
+            ActivityKind::ExportDelivered => "export_delivered",
+            ActivityKind::ExportFailed => "export_failed",
+            ActivityKind::Comment => "comment",
+        }
+    }
+
+    fn from_str(kind: &str) -> Self {
+        match kind {
+            "group_finalized" => ActivityKind::GroupFinalized,
+            "export_delivered" => ActivityKind::ExportDelivered,
+            "export_failed" => ActivityKind::ExportFailed,
+            "comment" => ActivityKind::Comment,
+            _ => ActivityKind::ScanCompleted,
+        }
+    }
+}
+
+/// Something that happened, as shown in the home screen's feed.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Activity {
+    pub id: i32,
+    pub kind: ActivityKind,
+    pub group_id: Option<i32>,
+    /// The group's title now, null if it has since been deleted
+    pub group_title: Option<String>,
+    pub scan_id: Option<i32>,
+    /// The user, API key or process that did it, if known
+    pub actor: Option<String>,
+    /// e.g. the export's destination or the comment's text
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Activity {
+    pub fn record(
+        kind: ActivityKind,
+        group_id: Option<i32>,
+        scan_id: Option<i32>,
+        actor: Option<&str>,
+        detail: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+
+        conn.execute(
+            "INSERT INTO activity (kind, scan_group_id, scan_id, actor, detail, created_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![kind.as_str(), group_id, scan_id, actor, detail, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Newest first, optionally only some kinds.
+    pub fn load_recent(
+        kinds: Option<&[ActivityKind]>,
+        limit: i64,
+        offset: i64,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<Activity> {
+        let conn = pool.get().unwrap();
+
+        // The kinds' names are our own, so they can go straight into the query
+        let condition = match kinds {
+            Some(kinds) if !kinds.is_empty() => format!(
+                "WHERE a.kind IN ({})",
+                kinds
+                    .iter()
+                    .map(|kind| format!("'{}'", kind.as_str()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            _ => String::new(),
+        };
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT a.id, a.kind, a.scan_group_id, g.title, a.scan_id, a.actor, a.detail, a.created_at
+                 FROM activity a
+                 LEFT JOIN scan_groups g ON g.id = a.scan_group_id
+                 {}
+                 ORDER BY a.created_at DESC, a.id DESC
+                 LIMIT ? OFFSET ?",
+                condition
+            ))
+            .unwrap();
+
+        let activity: Vec<Activity> = stmt
+            .query_map(params![limit, offset], |row| {
+                let kind: String = row.get(1)?;
+
+                Ok(Activity {
+                    id: row.get(0)?,
+                    kind: ActivityKind::from_str(&kind),
+                    group_id: row.get(2)?,
+                    group_title: row.get(3)?,
+                    scan_id: row.get(4)?,
+                    actor: row.get(5)?,
+                    detail: row.get(6)?,
+                    created_at: row.get(7)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        activity
+    }
+}
@@ -0,0 +1,274 @@
+use std::{env, ffi::CString, io};
+
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+use crate::{simple_broker::SimpleBroker, AssetsDir};
+
+/// Thresholds for problems worth hearing about before the next scanning
+/// session. Each rule is off unless its variable is set.
+#[derive(Debug, Clone)]
+pub struct AlertConfig {
+    /// Free space on the assets directory's disk, in bytes
+    pub min_free_bytes: Option<u64>,
+    /// Failed scans in a row on one scanner
+    pub scan_failure_streak: Option<i64>,
+    /// Failed exports within the last hour
+    pub export_failures_per_hour: Option<i64>,
+    /// How often the rules are checked
+    pub interval_minutes: u64,
+}
+
+impl AlertConfig {
+    /// From `ALERT_MIN_FREE_GB`, `ALERT_SCAN_FAILURE_STREAK`,
+    /// `ALERT_EXPORT_FAILURES_PER_HOUR` and `ALERT_INTERVAL_MINUTES`
+    /// (default 5).
+    pub fn from_env() -> Self {
+        let positive = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|v| *v > 0.0)
+        };
+        AlertConfig {
+            min_free_bytes: positive("ALERT_MIN_FREE_GB")
+                .map(|gb| (gb * 1024.0 * 1024.0 * 1024.0) as u64),
+            scan_failure_streak: positive("ALERT_SCAN_FAILURE_STREAK").map(|n| n as i64),
+            export_failures_per_hour: positive("ALERT_EXPORT_FAILURES_PER_HOUR").map(|n| n as i64),
+            interval_minutes: env::var("ALERT_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|minutes| *minutes > 0)
+                .unwrap_or(5),
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.min_free_bytes.is_some()
+            || self.scan_failure_streak.is_some()
+            || self.export_failures_per_hour.is_some()
+    }
+}
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AlertRule {
+    LowDisk,
+    ScanFailures,
+    ExportFailures,
+}
+
+impl AlertRule {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AlertRule::LowDisk => "low_disk",
+            AlertRule::ScanFailures => "scan_failures",
+            AlertRule::ExportFailures => "export_failures",
+        }
+    }
+
+    fn from_str(rule: &str) -> Self {
+        match rule {
+            "scan_failures" => AlertRule::ScanFailures,
+            "export_failures" => AlertRule::ExportFailures,
+            _ => AlertRule::LowDisk,
+        }
+    }
+}
+
+/// A rule that was broken. It stays active until a check finds the
+/// problem gone. Published when raised and again when resolved.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Alert {
+    pub id: i32,
+    pub rule: AlertRule,
+    /// What it's about within the rule, e.g. the scanner's name
+    pub subject: String,
+    pub message: String,
+    pub raised_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "id, rule, subject, message, raised_at, resolved_at";
+
+fn row_to_alert(row: &duckdb::Row) -> duckdb::Result<Alert> {
+    Ok(Alert {
+        id: row.get(0)?,
+        rule: AlertRule::from_str(&row.get::<usize, String>(1)?),
+        subject: row.get(2)?,
+        message: row.get(3)?,
+        raised_at: row.get(4)?,
+        resolved_at: row.get(5)?,
+    })
+}
+
+impl Alert {
+    /// Newest first, optionally only those still active.
+    pub fn load_all(active_only: bool, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<Alert> {
+        let conn = pool.get().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM alerts WHERE NOT ? OR resolved_at IS NULL ORDER BY raised_at DESC, id DESC",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let alerts: Vec<Alert> = stmt
+            .query_map(params![active_only], row_to_alert)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        alerts
+    }
+
+    fn raise(
+        rule: AlertRule,
+        subject: &str,
+        message: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Alert> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!(
+                "INSERT INTO alerts (rule, subject, message, raised_at) VALUES (?, ?, ?, ?)
+                 RETURNING {}",
+                COLUMNS
+            ),
+            params![rule.as_str(), subject, message, Utc::now()],
+            row_to_alert,
+        )
+    }
+
+    fn resolve(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Alert> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!(
+                "UPDATE alerts SET resolved_at = ? WHERE id = ? RETURNING {}",
+                COLUMNS
+            ),
+            params![Utc::now(), id],
+            row_to_alert,
+        )
+    }
+}
+
+/// Free space on the disk holding `path`, as an unprivileged user sees it.
+// The fields are 32 bits on some 32-bit boards
+#[allow(clippy::unnecessary_cast)]
+fn free_bytes(path: &str) -> io::Result<u64> {
+    let path = CString::new(path)?;
+    // SAFETY: statvfs only writes to the struct it's given
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// The rules broken right now, as (rule, subject, message).
+fn broken_rules(
+    config: &AlertConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Vec<(AlertRule, String, String)> {
+    let mut broken = Vec::new();
+
+    if let Some(min_free_bytes) = config.min_free_bytes {
+        match free_bytes(&assets_dir.0) {
+            Ok(free) if free < min_free_bytes => broken.push((
+                AlertRule::LowDisk,
+                assets_dir.0.clone(),
+                format!(
+                    "Only {:.1} GB free for scans in {}",
+                    free as f64 / (1024.0 * 1024.0 * 1024.0),
+                    assets_dir.0
+                ),
+            )),
+            Ok(_) => {}
+            Err(e) => println!("Could not check free space in {}: {}", assets_dir.0, e),
+        }
+    }
+
+    let conn = pool.get().unwrap();
+    if let Some(streak) = config.scan_failure_streak {
+        // Scanners whose latest `streak` finished scans all failed
+        let mut stmt = conn
+            .prepare(
+                "SELECT scanner FROM (
+                    SELECT scanner, status,
+                           row_number() OVER (PARTITION BY scanner ORDER BY scanned_at DESC, id DESC) AS n
+                    FROM scans WHERE status IN ('COMPLETE', 'FAILED')
+                 )
+                 WHERE n <= ?
+                 GROUP BY scanner
+                 HAVING count(*) = ? AND bool_and(status = 'FAILED')",
+            )
+            .unwrap();
+        let scanners: Vec<String> = stmt
+            .query_map(params![streak, streak], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        for scanner in scanners {
+            broken.push((
+                AlertRule::ScanFailures,
+                scanner.clone(),
+                format!("The last {} scans on {} failed", streak, scanner),
+            ));
+        }
+    }
+
+    if let Some(limit) = config.export_failures_per_hour {
+        let failures: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM activity WHERE kind = 'export_failed' AND created_at > ?::TIMESTAMP",
+                params![Utc::now() - Duration::hours(1)],
+                |row| row.get(0),
+            )
+            .unwrap();
+        if failures >= limit {
+            broken.push((
+                AlertRule::ExportFailures,
+                String::new(),
+                format!("{} exports failed in the last hour", failures),
+            ));
+        }
+    }
+
+    broken
+}
+
+/// Checks the rules, raising an alert for each newly broken one and
+/// resolving those no longer broken. Each change is published.
+pub fn check(
+    config: &AlertConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<()> {
+    let broken = broken_rules(config, pool, assets_dir);
+    let active = Alert::load_all(true, pool);
+
+    for (rule, subject, message) in &broken {
+        if !active
+            .iter()
+            .any(|alert| alert.rule == *rule && alert.subject == *subject)
+        {
+            let alert = Alert::raise(*rule, subject, message, pool)?;
+            println!("Alert: {}", alert.message);
+            SimpleBroker::publish(alert);
+        }
+    }
+    for alert in active {
+        if !broken
+            .iter()
+            .any(|(rule, subject, _)| alert.rule == *rule && alert.subject == *subject)
+        {
+            let alert = Alert::resolve(alert.id, pool)?;
+            println!("Resolved: {}", alert.message);
+            SimpleBroker::publish(alert);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use std::{fs, path::Path};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager, Result};
+
+use crate::{asset_path::AssetPath, AssetsDir};
+
+/// Words in a scan's stored OCR text; NULL if it hasn't been read.
+const WORD_COUNT: &str = r"
+    CASE WHEN t.text IS NULL THEN NULL
+        WHEN trim(t.text) = '' THEN 0
+        ELSE len(string_split_regex(trim(t.text), '\s+'))
+    END
+";
+
+/// Scan metadata with each scan's page number within its group, and how
+/// many words OCR read on it.
+const SCANS_QUERY: &str = r"
+    SELECT
+        id,
+        status,
+        scanner,
+        scan_parameters,
+        scanned_at,
+        scan_group_id,
+        CASE WHEN scan_group_id IS NULL THEN NULL
+            ELSE row_number() OVER (PARTITION BY scan_group_id ORDER BY page_order, scanned_at, id)
+        END AS page_number,
+        rotation,
+        crop_coordinates IS NOT NULL AS cropped,
+        edited_path IS NOT NULL AS edited,
+        path <> original_path AS rescanned,
+        {word_count} AS word_count
+    FROM scans
+    LEFT JOIN scan_texts t ON t.scan_id = scans.id
+    ORDER BY id
+";
+
+/// Group metadata with page counts, words read by OCR across its pages, and
+/// how long capture took, from the first to the last scan in the group.
+const GROUPS_QUERY: &str = r"
+    SELECT
+        g.id,
+        g.title,
+        g.status,
+        g.comment,
+        g.tags,
+        g.created_at,
+        g.updated_at,
+        count(s.id) AS scan_count,
+        count(s.id) FILTER (WHERE s.status = 'COMPLETE') AS complete_count,
+        count(s.id) FILTER (WHERE s.status = 'FAILED') AS failed_count,
+        min(s.scanned_at) AS first_scanned_at,
+        max(s.scanned_at) AS last_scanned_at,
+        epoch(max(s.scanned_at) - min(s.scanned_at)) AS capture_seconds,
+        sum({word_count})::BIGINT AS word_count
+    FROM scan_groups g
+    LEFT JOIN scans s ON s.scan_group_id = g.id
+    LEFT JOIN scan_texts t ON t.scan_id = s.id
+    GROUP BY ALL
+    ORDER BY g.id
+";
+
+/// Parquet files written by `exportAnalytics`, served from the assets directory.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct AnalyticsExport {
+    pub scans: AssetPath,
+    pub groups: AssetPath,
+    /// When the data was read; older than the export itself if it came from a snapshot
+    pub as_of: DateTime<Utc>,
+}
+
+impl AnalyticsExport {
+    /// Writes the scans and groups tables to Parquet under `exports/` in the assets directory.
+    pub fn create(
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        as_of: DateTime<Utc>,
+        assets_dir: &AssetsDir,
+    ) -> Result<AnalyticsExport> {
+        let conn = pool.get().unwrap();
+
+        let dir =
+            Path::new("exports").join(format!("analytics-{}", Utc::now().format("%Y%m%dT%H%M%SZ")));
+        fs::create_dir_all(Path::new(&assets_dir.0).join(&dir)).unwrap();
+
+        let scans = dir.join("scans.parquet").to_string_lossy().to_string();
+        let groups = dir.join("groups.parquet").to_string_lossy().to_string();
+
+        for (query, path) in [(SCANS_QUERY, &scans), (GROUPS_QUERY, &groups)] {
+            // COPY doesn't take a bound parameter for the path
+            let disk_path = Path::new(&assets_dir.0).join(path);
+            conn.execute(
+                &format!(
+                    "COPY ({}) TO '{}' (FORMAT PARQUET)",
+                    query.replace("{word_count}", WORD_COUNT),
+                    disk_path.to_string_lossy().replace('\'', "''")
+                ),
+                params![],
+            )?;
+        }
+
+        Ok(AnalyticsExport {
+            scans: scans.into(),
+            groups: groups.into(),
+            as_of,
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+
+use crate::auth::{generate_token, hash_token, Scope};
+
+const TOKEN_PREFIX: &str = "ssk_";
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Returned once from `createApiKey`; the plaintext token is never stored.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct CreatedApiKey {
+    pub key: ApiKey,
+    pub token: String,
+}
+
+fn row_to_api_key(row: &duckdb::Row) -> duckdb::Result<ApiKey> {
+    let scopes_json: String = row.get(2)?;
+
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scopes: serde_json::from_str(&scopes_json).unwrap_or_default(),
+        created_at: row.get(3)?,
+        last_used_at: row.get(4)?,
+        revoked_at: row.get(5)?,
+    })
+}
+
+impl ApiKey {
+    pub fn create(
+        name: String,
+        scopes: Vec<Scope>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<CreatedApiKey> {
+        let conn = pool.get().unwrap();
+        let token = generate_token(TOKEN_PREFIX);
+        let scopes_json = serde_json::to_string(&scopes).unwrap();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO api_keys (name, key_hash, scopes, created_at)
+             VALUES (?, ?, ?, ?) RETURNING id",
+            params![name, hash_token(&token), scopes_json, Utc::now()],
+            |row| row.get(0),
+        )?;
+
+        Ok(CreatedApiKey {
+            key: Self::load(id, pool)?,
+            token,
+        })
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            "SELECT id, name, scopes, created_at, last_used_at, revoked_at FROM api_keys WHERE id = ?",
+            params![id],
+            row_to_api_key,
+        )
+    }
+
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<ApiKey> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT id, name, scopes, created_at, last_used_at, revoked_at FROM api_keys ORDER BY id")
+            .unwrap();
+
+        let keys: Vec<ApiKey> = stmt
+            .query_map([], row_to_api_key)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        keys
+    }
+
+    /// Looks up an unrevoked key by its plaintext token and records the use.
+    pub fn authenticate(token: &str, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Option<Self> {
+        let conn = pool.get().unwrap();
+
+        let key = conn
+            .query_row(
+                "SELECT id, name, scopes, created_at, last_used_at, revoked_at FROM api_keys
+                 WHERE key_hash = ? AND revoked_at IS NULL",
+                params![hash_token(token)],
+                row_to_api_key,
+            )
+            .optional()
+            .unwrap()?;
+
+        conn.execute(
+            "UPDATE api_keys SET last_used_at = ? WHERE id = ?",
+            params![Utc::now(), key.id],
+        )
+        .unwrap();
+
+        Some(key)
+    }
+
+    pub fn revoke(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+
+        let updated = conn.execute(
+            "UPDATE api_keys SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL",
+            params![Utc::now(), id],
+        )?;
+
+        Ok(updated > 0)
+    }
+}
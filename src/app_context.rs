@@ -0,0 +1,36 @@
+use async_graphql::{Context, Result};
+use duckdb::DuckdbConnectionManager;
+
+use crate::{
+    archive::ArchiveConfig, auth::AuthConfig, batches::BatchRunner, scanners::ScannerManager,
+    schema::Storage, snapshot::Snapshot, AssetsDir, PublicUrl,
+};
+
+/// Everything resolvers need from the server, registered on the schema as
+/// one value. Events go through `SimpleBroker`, which needs no state here.
+#[derive(Clone)]
+pub struct AppContext {
+    pub pool: r2d2::Pool<DuckdbConnectionManager>,
+    pub scanner_manager: ScannerManager,
+    pub batch_runner: BatchRunner,
+    pub assets_dir: AssetsDir,
+    /// For links in exports that resolvers trigger
+    pub public_url: PublicUrl,
+    pub auth_config: AuthConfig,
+    pub snapshot: Snapshot,
+    pub archive_config: ArchiveConfig,
+    pub books: Storage,
+}
+
+pub trait ContextExt {
+    /// The server's `AppContext`, or a GraphQL error if the schema was built
+    /// without one, e.g. in a test harness.
+    fn app(&self) -> Result<&AppContext>;
+}
+
+impl ContextExt for Context<'_> {
+    fn app(&self) -> Result<&AppContext> {
+        self.data::<AppContext>()
+            .map_err(|_| "Server context is not configured".into())
+    }
+}
@@ -0,0 +1,307 @@
+use std::{env, fmt, fs, io::Cursor, path::Path};
+
+use async_graphql::SimpleObject;
+use chrono::{Duration, Utc};
+use duckdb::{params, DuckdbConnectionManager};
+use image::{
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+    DynamicImage, ImageFormat,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{asset_path::AssetPath, AssetsDir};
+
+/// Lossless formats old originals can be recompressed to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArchiveFormat {
+    /// Maximum compression with adaptive filtering; always available
+    Png,
+    /// Lossless WebP, usually a third smaller than PNG; needs the `webp` feature
+    WebP,
+}
+
+impl ArchiveFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Png => "png",
+            ArchiveFormat::WebP => "webp",
+        }
+    }
+
+    fn from_str(format: &str) -> Result<Self, String> {
+        match format {
+            "png" => Ok(ArchiveFormat::Png),
+            "webp" if cfg!(feature = "webp") => Ok(ArchiveFormat::WebP),
+            "webp" => Err("ARCHIVE_FORMAT=webp needs a build with the webp feature".to_string()),
+            other => Err(format!(
+                "unknown ARCHIVE_FORMAT {}, expected png or webp",
+                other
+            )),
+        }
+    }
+
+    fn encode(&self, image: &DynamicImage) -> image::ImageResult<Vec<u8>> {
+        let mut encoded = Vec::new();
+        match self {
+            ArchiveFormat::Png => image.write_with_encoder(PngEncoder::new_with_quality(
+                &mut Cursor::new(&mut encoded),
+                CompressionType::Best,
+                FilterType::Adaptive,
+            ))?,
+            ArchiveFormat::WebP => {
+                image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::WebP)?
+            }
+        }
+        Ok(encoded)
+    }
+}
+
+/// Which originals are recompressed, to what, and how often.
+#[derive(Debug, Clone)]
+pub struct ArchiveConfig {
+    pub format: ArchiveFormat,
+    /// Originals scanned longer ago than this are recompressed
+    pub after_days: i64,
+    /// How often the job runs in the background; 0 only runs it on request
+    pub interval_hours: u64,
+}
+
+impl ArchiveConfig {
+    /// From `ARCHIVE_FORMAT`, `ARCHIVE_AFTER_DAYS` and `ARCHIVE_INTERVAL_HOURS`.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(ArchiveConfig {
+            format: ArchiveFormat::from_str(
+                &env::var("ARCHIVE_FORMAT").unwrap_or("png".to_string()),
+            )?,
+            after_days: env::var("ARCHIVE_AFTER_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(365),
+            interval_hours: env::var("ARCHIVE_INTERVAL_HOURS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// What a run of the recompression job did.
+#[derive(Debug, Clone, Default, SimpleObject)]
+pub struct ArchiveReport {
+    pub recompressed: i32,
+    /// Originals already smaller than their recompressed copy, left as they are
+    pub kept: i32,
+    pub failed: i32,
+    /// Originals of scans in held groups, which are kept exactly as scanned
+    pub held: i32,
+    pub reclaimed_bytes: i64,
+    /// Reclaimed by every run so far
+    pub total_reclaimed_bytes: i64,
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Image(image::ImageError),
+    Io(std::io::Error),
+    /// The recompressed file doesn't decode to the same pixels
+    ChecksumMismatch,
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Image(e) => write!(f, "image error: {}", e),
+            ArchiveError::Io(e) => write!(f, "io error: {}", e),
+            ArchiveError::ChecksumMismatch => {
+                write!(f, "recompressed copy doesn't match the original's pixels")
+            }
+            ArchiveError::Db(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<image::ImageError> for ArchiveError {
+    fn from(e: image::ImageError) -> Self {
+        ArchiveError::Image(e)
+    }
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(e: std::io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+impl From<duckdb::Error> for ArchiveError {
+    fn from(e: duckdb::Error) -> Self {
+        ArchiveError::Db(e)
+    }
+}
+
+/// Checksum of the logical image: its size and pixels, at 16 bits per RGBA
+/// channel so neither bit depth nor how grey is stored can hide a change.
+/// Unlike a file hash it survives recompression.
+pub fn pixel_checksum(image: &DynamicImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(image.width().to_le_bytes());
+    hasher.update(image.height().to_le_bytes());
+    for channel in image.to_rgba16().into_raw() {
+        hasher.update(channel.to_le_bytes());
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+enum Outcome {
+    Recompressed { reclaimed: i64 },
+    Kept,
+}
+
+/// The original's path with the archive format's extension.
+fn archived_path(original: &AssetPath, format: ArchiveFormat) -> AssetPath {
+    let original = original.as_relative_path();
+    let path = Path::new(&original).with_extension(format.as_str());
+    AssetPath::from_relative_path(path.to_string_lossy().into_owned())
+}
+
+fn recompress(
+    scan_id: i32,
+    original: &AssetPath,
+    format: ArchiveFormat,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<Outcome, ArchiveError> {
+    let contents = assets_dir.read(original)?;
+    let image = image::load_from_memory(&contents)?;
+    let checksum = pixel_checksum(&image);
+    let encoded = format.encode(&image)?;
+
+    let conn = pool.get().unwrap();
+    let record = |archived_bytes: usize| {
+        conn.execute(
+            "INSERT INTO archived_originals
+                (scan_id, format, pixel_sha256, original_bytes, archived_bytes, archived_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                scan_id,
+                format.as_str(),
+                checksum,
+                contents.len() as i64,
+                archived_bytes as i64,
+                Utc::now()
+            ],
+        )
+    };
+    // Lossy JPEGs in particular grow when stored losslessly
+    if encoded.len() >= contents.len() {
+        record(contents.len())?;
+        return Ok(Outcome::Kept);
+    }
+
+    // Written aside and read back, so a bad write never replaces the original
+    let archived = archived_path(original, format);
+    let partial = AssetPath::from_relative_path(format!("{}.partial", archived.as_relative_path()));
+    assets_dir.write(&partial, &encoded)?;
+    if pixel_checksum(&assets_dir.read_image(&partial)?) != checksum {
+        fs::remove_file(partial.as_disk_path(&assets_dir.0)).ok();
+        return Err(ArchiveError::ChecksumMismatch);
+    }
+    fs::rename(
+        partial.as_disk_path(&assets_dir.0),
+        archived.as_disk_path(&assets_dir.0),
+    )?;
+
+    let (from, to) = (original.as_relative_path(), archived.as_relative_path());
+    conn.execute(
+        "UPDATE scans SET
+            original_path = ?,
+            path = CASE WHEN path = ? THEN ? ELSE path END,
+            edited_path = CASE WHEN edited_path = ? THEN ? ELSE edited_path END
+         WHERE id = ?",
+        params![to, from, to, from, to, scan_id],
+    )?;
+    record(encoded.len())?;
+    if from != to {
+        fs::remove_file(original.as_disk_path(&assets_dir.0))?;
+    }
+    Ok(Outcome::Recompressed {
+        reclaimed: contents.len() as i64 - encoded.len() as i64,
+    })
+}
+
+/// Recompresses originals older than the configured age that haven't been
+/// before. Each is re-read after writing and only replaces the original if
+/// it decodes to the same pixels; the checksum is kept so the archive can
+/// be verified later. Scans in held groups are left alone, and picked up
+/// once the hold is released. Blocks on the filesystem.
+pub fn run(
+    config: &ArchiveConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> ArchiveReport {
+    let cutoff = Utc::now() - Duration::days(config.after_days);
+    let conn = pool.get().unwrap();
+    let originals: Vec<(i32, String)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, original_path FROM scans
+                 WHERE status = 'COMPLETE' AND original_path IS NOT NULL AND scanned_at < ?::TIMESTAMP
+                   AND id NOT IN (SELECT scan_id FROM archived_originals)
+                   AND (scan_group_id IS NULL
+                        OR scan_group_id NOT IN (SELECT id FROM scan_groups WHERE hold))
+                 ORDER BY scanned_at",
+            )
+            .unwrap();
+        stmt.query_map(params![cutoff], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    };
+    let held: i64 = conn
+        .query_row(
+            "SELECT count(*) FROM scans
+             WHERE status = 'COMPLETE' AND original_path IS NOT NULL AND scanned_at < ?::TIMESTAMP
+               AND id NOT IN (SELECT scan_id FROM archived_originals)
+               AND scan_group_id IN (SELECT id FROM scan_groups WHERE hold)",
+            params![cutoff],
+            |row| row.get(0),
+        )
+        .unwrap();
+    drop(conn);
+
+    let mut report = ArchiveReport {
+        held: held as i32,
+        ..Default::default()
+    };
+    for (scan_id, original) in originals {
+        match recompress(scan_id, &original.into(), config.format, pool, assets_dir) {
+            Ok(Outcome::Recompressed { reclaimed }) => {
+                report.recompressed += 1;
+                report.reclaimed_bytes += reclaimed;
+            }
+            Ok(Outcome::Kept) => report.kept += 1,
+            Err(e) => {
+                println!(
+                    "Failed to recompress the original of scan {}: {}",
+                    scan_id, e
+                );
+                report.failed += 1;
+            }
+        }
+    }
+
+    report.total_reclaimed_bytes = pool
+        .get()
+        .unwrap()
+        .query_row(
+            "SELECT COALESCE(SUM(original_bytes - archived_bytes), 0) FROM archived_originals",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    report
+}
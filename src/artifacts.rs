@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+use crate::{exports::ExportFormat, AssetsDir};
+
+/// Under the assets directory; files are kept by the hash of their contents.
+const ARTIFACTS_DIR: &str = "artifacts";
+/// Exports are rendered here, under names of their own, before being stored.
+const STAGING_DIR: &str = "artifacts/staging";
+
+/// A rendered export file, stored once however many exports produced it.
+/// It can be fetched from `/api/artifacts/{id}`.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Artifact {
+    pub id: i32,
+    pub sha256: String,
+    pub format: ExportFormat,
+    pub bytes: i64,
+    /// Relative to the assets directory
+    #[graphql(skip)]
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "id, sha256, format, bytes, path, created_at";
+
+fn row_to_artifact(row: &duckdb::Row) -> duckdb::Result<Artifact> {
+    Ok(Artifact {
+        id: row.get(0)?,
+        sha256: row.get(1)?,
+        format: ExportFormat::from_str(&row.get::<usize, String>(2)?),
+        bytes: row.get(3)?,
+        path: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+/// A fresh path to render an export to, so exports running at the same
+/// time never write to the same file.
+pub fn staging_path(assets_dir: &AssetsDir) -> io::Result<PathBuf> {
+    let dir = Path::new(&assets_dir.0).join(STAGING_DIR);
+    fs::create_dir_all(&dir)?;
+    let name: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+    Ok(dir.join(format!("{}.partial", name)))
+}
+
+fn hash_file(path: &Path) -> io::Result<(String, u64)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        bytes += n as u64;
+    }
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok((hash, bytes))
+}
+
+impl Artifact {
+    /// Moves a file rendered at a `staging_path` into the store under its
+    /// hash and indexes it. A file with the same contents already stored is
+    /// kept and the staged one dropped, so repeated exports take no extra
+    /// space. Bags are directories and can't be stored.
+    pub fn store(
+        staged: &Path,
+        format: ExportFormat,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> io::Result<Artifact> {
+        let (sha256, bytes) = hash_file(staged)?;
+        let path = format!(
+            "{}/{}/{}.{}",
+            ARTIFACTS_DIR,
+            &sha256[..2],
+            sha256,
+            format.extension()
+        );
+        let disk_path = Path::new(&assets_dir.0).join(&path);
+        if disk_path.is_file() {
+            fs::remove_file(staged)?;
+        } else {
+            fs::create_dir_all(disk_path.parent().unwrap())?;
+            // Atomic, and the same contents if two exports get here at once
+            fs::rename(staged, &disk_path)?;
+        }
+
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "INSERT INTO artifacts (sha256, format, bytes, path, created_at) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (sha256) DO NOTHING",
+            params![sha256, format.as_str(), bytes as i64, path, Utc::now()],
+        )
+        .map_err(io::Error::other)?;
+        conn.query_row(
+            &format!("SELECT {} FROM artifacts WHERE sha256 = ?", COLUMNS),
+            params![sha256],
+            row_to_artifact,
+        )
+        .map_err(io::Error::other)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Option<Artifact>> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM artifacts WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_artifact,
+        )
+        .optional()
+    }
+
+    pub fn disk_path(&self, assets_dir: &AssetsDir) -> PathBuf {
+        Path::new(&assets_dir.0).join(&self.path)
+    }
+}
@@ -1,5 +1,9 @@
 use async_graphql::Object;
 
+/// A file under `ASSETS_DIR`, relative to it. All accounts share one
+/// library, so the directory isn't split per user: `/assets` serves any
+/// path to a request with the Read scope, the same access that lists
+/// every group through the API.
 #[derive(Debug, Clone)]
 pub struct AssetPath(String);
 
@@ -26,9 +30,9 @@ impl From<String> for AssetPath {
         AssetPath::from_relative_path(path)
     }
 }
-impl Into<String> for AssetPath {
-    fn into(self) -> String {
-        self.as_relative_path()
+impl From<AssetPath> for String {
+    fn from(val: AssetPath) -> Self {
+        val.as_relative_path()
     }
 }
 
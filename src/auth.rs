@@ -0,0 +1,203 @@
+use async_graphql::{Context, Enum, Guard, Result};
+use duckdb::DuckdbConnectionManager;
+use poem::http::HeaderMap;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{api_keys::ApiKey, app_context::ContextExt, ldap_sync::LdapConfig, users::User};
+
+pub const SESSION_COOKIE: &str = "scanserv_session";
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Scope {
+    /// Run queries and subscriptions
+    Read,
+    /// Start and retry scans
+    Scan,
+    /// Edit scans and groups
+    Write,
+    /// Everything, including API key management
+    Admin,
+}
+
+/// Whether requests must carry credentials. Off by default so existing
+/// single-user deployments keep working unchanged; create an admin key
+/// first, then set `AUTH_REQUIRED=true`.
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub required: bool,
+    /// Where accounts synced from LDAP sign in, if there's a directory
+    pub ldap: Option<LdapConfig>,
+}
+
+/// Who is making the current request, inserted into the request data.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    ApiKey(ApiKey),
+    User(User),
+}
+
+/// The raw session cookie of the current request, so `logout` can end it.
+#[derive(Debug, Clone)]
+pub struct SessionToken(pub String);
+
+/// The address the request came from, used for login rate limiting.
+#[derive(Debug, Clone)]
+pub struct ClientIp(pub String);
+
+impl Principal {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        let scopes = match self {
+            Principal::ApiKey(key) => key.scopes.clone(),
+            Principal::User(user) => user.role.scopes(),
+        };
+        scopes.contains(&Scope::Admin) || scopes.contains(&scope)
+    }
+
+    /// The key's name or the user's username.
+    pub fn name(&self) -> &str {
+        match self {
+            Principal::ApiKey(key) => &key.name,
+            Principal::User(user) => &user.username,
+        }
+    }
+
+    /// Who to credit for work the request starts, e.g. `alice` or
+    /// `api key office-mfp`.
+    pub fn attribution(&self) -> String {
+        match self {
+            Principal::ApiKey(key) => format!("api key {}", key.name),
+            Principal::User(user) => user.username.clone(),
+        }
+    }
+}
+
+/// A random URL-safe token with a recognizable prefix.
+pub fn generate_token(prefix: &str) -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", prefix, hex)
+}
+
+/// Tokens are high-entropy, so a plain SHA-256 is enough for storage.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn session_cookie_from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all("Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Standard base64, as in Basic credentials.
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut bits = 0u32;
+    let mut pending = 0;
+    let mut decoded = Vec::new();
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6 | value as u32) & 0xffff;
+        pending += 6;
+        if pending >= 8 {
+            pending -= 8;
+            decoded.push((bits >> pending) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+pub fn principal_from_headers(
+    headers: &HeaderMap,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Option<Principal> {
+    let authorization = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok());
+    let bearer = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    // E-reader apps can only send Basic credentials; the password is the key
+    let basic = authorization
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| decode_base64(encoded.trim()))
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        });
+
+    if let Some(token) = bearer.or(basic) {
+        return ApiKey::authenticate(token.trim(), pool).map(Principal::ApiKey);
+    }
+
+    let session = session_cookie_from_headers(headers)?;
+    User::from_session(&session, pool).map(Principal::User)
+}
+
+/// Who a websocket client says it is in its `connection_init` payload, as
+/// `{"authorization": "Bearer <key>"}` like the HTTP header, or as
+/// `{"token": "<key or session token>"}` for clients that can't set
+/// headers on the upgrade request.
+pub fn principal_from_init_payload(
+    payload: &serde_json::Value,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Option<Principal> {
+    let authorization = ["authorization", "Authorization"]
+        .iter()
+        .find_map(|key| payload.get(key)?.as_str());
+    if let Some(token) = authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        return ApiKey::authenticate(token.trim(), pool).map(Principal::ApiKey);
+    }
+
+    let token = payload.get("token")?.as_str()?.trim();
+    ApiKey::authenticate(token, pool)
+        .map(Principal::ApiKey)
+        .or_else(|| User::from_session(token, pool).map(Principal::User))
+}
+
+/// The REST counterpart of `RequireScope`: whether a request with these
+/// headers may go ahead.
+pub fn headers_have_scope(
+    headers: &HeaderMap,
+    scope: Scope,
+    auth_config: &AuthConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> bool {
+    !auth_config.required
+        || principal_from_headers(headers, pool).is_some_and(|principal| principal.has_scope(scope))
+}
+
+pub struct RequireScope(pub Scope);
+
+impl Guard for RequireScope {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if !ctx.app()?.auth_config.required {
+            return Ok(());
+        }
+
+        match ctx.data_opt::<Principal>() {
+            Some(principal) if principal.has_scope(self.0) => Ok(()),
+            Some(_) => Err("Forbidden: missing required scope".into()),
+            None => Err("Unauthorized".into()),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fs, path::Path};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    asset_path::AssetPath,
+    exports::ExportError,
+    locale,
+    scans::{Scan, ScanGroup},
+    AssetsDir, PublicUrl,
+};
+
+pub fn sha256(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// A bag-info value, with line breaks turned into the indented continuation
+/// lines the spec allows.
+fn tag_value(value: &str) -> String {
+    value.trim().lines().collect::<Vec<_>>().join("\n  ")
+}
+
+/// Files written so far and their checksums, relative to the bag.
+struct Manifest(Vec<(String, String)>);
+
+impl Manifest {
+    fn write(&mut self, dir: &Path, name: &str, contents: &[u8]) -> std::io::Result<()> {
+        fs::write(dir.join(name), contents)?;
+        self.0.push((sha256(contents), name.to_string()));
+        Ok(())
+    }
+
+    fn to_text(&self) -> String {
+        self.0
+            .iter()
+            .map(|(hash, name)| format!("{}  {}\n", hash, name))
+            .collect()
+    }
+}
+
+/// Copies the scan's file into the payload as `name`, decrypted but otherwise
+/// exactly as stored.
+fn add_file(
+    payload: &mut Manifest,
+    dir: &Path,
+    name: &str,
+    path: &AssetPath,
+    assets_dir: &AssetsDir,
+) -> Result<(), ExportError> {
+    let contents = assets_dir
+        .read(path)
+        .map_err(|e| ExportError::Image(e.to_string()))?;
+    payload.write(dir, name, &contents)?;
+    Ok(())
+}
+
+/// Writes the group as a BagIt 1.0 bag in `dir`, for deposit in digital
+/// preservation systems: the scan files as captured, and any edited copies,
+/// in page order under `data/` with a `metadata.json` describing them,
+/// SHA-256 manifests, and the group's details in `bag-info.txt`.
+pub fn write_bag(
+    group: &ScanGroup,
+    scans: &[&Scan],
+    dir: &Path,
+    public_url: &PublicUrl,
+    assets_dir: &AssetsDir,
+) -> Result<(), ExportError> {
+    fs::create_dir_all(dir.join("data"))?;
+
+    let mut payload = Manifest(Vec::new());
+    let mut pages = Vec::new();
+    for (i, scan) in scans.iter().enumerate() {
+        let id = scan.id.unwrap();
+        let stem = format!("data/{:04}_scan{}", i + 1, id);
+        let extension = |path: &AssetPath| {
+            Path::new(&path.as_relative_path())
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default()
+        };
+
+        let file = format!("{}{}", stem, extension(&scan.path));
+        add_file(&mut payload, dir, &file, &scan.path, assets_dir)?;
+        let edited = match &scan.edited_path {
+            Some(path) => {
+                let name = format!("{}_edited{}", stem, extension(path));
+                add_file(&mut payload, dir, &name, path, assets_dir)?;
+                Some(name)
+            }
+            None => None,
+        };
+
+        pages.push(serde_json::json!({
+            "position": i + 1,
+            "scanId": id,
+            "file": file.trim_start_matches("data/"),
+            "editedFile": edited.as_deref().map(|name| name.trim_start_matches("data/")),
+            "scannedAt": scan.scanned_at.to_rfc3339(),
+            "scanner": scan.scanner,
+            "scanParameters": scan.scan_parameters,
+            "rotation": scan.rotation,
+            "url": public_url.scan_url(id),
+        }));
+    }
+
+    let metadata = serde_json::json!({
+        "groupId": group.id,
+        "title": group.title,
+        "createdAt": group.created_at.to_rfc3339(),
+        "status": group.status,
+        "comment": group.comment,
+        "tags": group.tags,
+        "url": public_url.group_url(group.id),
+        "pages": pages,
+    });
+    payload.write(
+        dir,
+        "data/metadata.json",
+        serde_json::to_string_pretty(&metadata).unwrap().as_bytes(),
+    )?;
+
+    let payload_bytes: u64 = payload
+        .0
+        .iter()
+        .map(|(_, name)| fs::metadata(dir.join(name)).map(|m| m.len()))
+        .sum::<std::io::Result<u64>>()?;
+    let mut bag_info = vec![
+        // BagIt wants ISO 8601 whatever DATE_FORMAT says
+        (
+            "Bagging-Date",
+            locale::today().format("%Y-%m-%d").to_string(),
+        ),
+        (
+            "Payload-Oxum",
+            format!("{}.{}", payload_bytes, payload.0.len()),
+        ),
+        ("External-Identifier", public_url.group_url(group.id)),
+        ("Scanserv-Group-Id", group.id.to_string()),
+        ("Scanserv-Group-Created", group.created_at.to_rfc3339()),
+        ("Scanserv-Group-Status", group.status.clone()),
+    ];
+    if !group.title.is_empty() {
+        bag_info.push(("External-Description", group.title.clone()));
+    }
+    if !group.tags.is_empty() {
+        bag_info.push(("Scanserv-Group-Tags", group.tags.join(", ")));
+    }
+    if !group.comment.trim().is_empty() {
+        bag_info.push(("Scanserv-Group-Comment", group.comment.clone()));
+    }
+
+    let mut tags = Manifest(Vec::new());
+    tags.write(
+        dir,
+        "bagit.txt",
+        b"BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n",
+    )?;
+    tags.write(
+        dir,
+        "bag-info.txt",
+        bag_info
+            .iter()
+            .map(|(label, value)| format!("{}: {}\n", label, tag_value(value)))
+            .collect::<String>()
+            .as_bytes(),
+    )?;
+    tags.write(dir, "manifest-sha256.txt", payload.to_text().as_bytes())?;
+    fs::write(dir.join("tagmanifest-sha256.txt"), tags.to_text())?;
+
+    Ok(())
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+use crate::{
+    scan_queue::ScanPriority,
+    scanners::ScannerManager,
+    scans::{ProcessingStatus, Scan, ScanGroup},
+    simple_broker::SimpleBroker,
+    AssetsDir,
+};
+
+/// Pages shorter than this fraction of the expected length are taken to be
+/// double feeds: two sheets went through together and only one was captured.
+const SHORT_PAGE_RATIO: f32 = 0.9;
+
+/// Length over width of US letter, assumed when `-x`/`-y` don't give the paper size.
+const LETTER_ASPECT: f32 = 11.0 / 8.5;
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BatchStatus {
+    Running,
+    /// Waiting for the operator, see `pausedReason`
+    Paused,
+    /// The feeder ran out of paper
+    Complete,
+}
+
+impl BatchStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BatchStatus::Running => "running",
+            BatchStatus::Paused => "paused",
+            BatchStatus::Complete => "complete",
+        }
+    }
+
+    fn from_str(status: &str) -> Self {
+        match status {
+            "running" => BatchStatus::Running,
+            "complete" => BatchStatus::Complete,
+            _ => BatchStatus::Paused,
+        }
+    }
+}
+
+/// A stack of pages scanned from the document feeder into a group.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScanBatch {
+    pub id: i32,
+    pub scanner: String,
+    pub scan_parameters: HashMap<String, String>,
+    pub priority: ScanPriority,
+    pub group_id: i32,
+    pub status: BatchStatus,
+    pub pages_scanned: i32,
+    /// How many pages the operator said are in the stack, checked on completion
+    pub expected_pages: Option<i32>,
+    pub paused_reason: Option<String>,
+    /// A page kept although it looks like a double feed. Resuming keeps it,
+    /// or with `rescanSuspect` throws it away and scans it again.
+    pub suspect_scan_id: Option<i32>,
+    /// The user or API key that started the batch, if auth identified one
+    pub started_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn row_to_batch(row: &duckdb::Row) -> duckdb::Result<ScanBatch> {
+    Ok(ScanBatch {
+        id: row.get(0)?,
+        scanner: row.get(1)?,
+        scan_parameters: serde_json::from_str(&row.get::<usize, String>(2)?).unwrap(),
+        priority: ScanPriority::from_str(&row.get::<usize, String>(3)?),
+        group_id: row.get(4)?,
+        status: BatchStatus::from_str(&row.get::<usize, String>(5)?),
+        pages_scanned: row.get(6)?,
+        expected_pages: row.get(7)?,
+        paused_reason: row.get(8)?,
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+        started_by: row.get(11)?,
+        suspect_scan_id: row.get(12)?,
+    })
+}
+
+/// Published when a batch stops itself and needs the operator, e.g. to clear a
+/// jam or re-feed a double-fed page.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct BatchPaused {
+    pub batch_id: i32,
+    /// The page to re-feed, or to check if it is the suspect page
+    pub page: i32,
+    pub reason: String,
+    /// The page if it was kept but looks like a double feed
+    pub suspect_scan_id: Option<i32>,
+    /// Who started the batch, so they can be fetched to the scanner
+    pub started_by: Option<String>,
+}
+
+const BATCH_COLUMNS: &str = "id, scanner, scan_parameters, priority, scan_group_id, status, pages_scanned, expected_pages, paused_reason, created_at, updated_at, started_by, suspect_scan_id";
+
+/// Operator-facing explanation for a page that failed.
+fn paused_reason(failure: Option<&str>, page: i32) -> String {
+    match failure {
+        Some("JAMMED") => format!("Paper jam on page {}", page),
+        Some("COVER_OPEN") => format!("Scanner cover open on page {}", page),
+        Some("NO_DOCS") => "No paper in the feeder".to_string(),
+        _ => format!("Page {} failed to scan", page),
+    }
+}
+
+/// Flags a scanned page that is noticeably shorter than the paper, comparing
+/// shapes rather than sizes so it works without knowing the resolution.
+fn short_page(scan: &Scan, assets_dir: &AssetsDir) -> Option<String> {
+    let (width, height) = assets_dir.image_dimensions(&scan.path).ok()?;
+    let expected = match (scan.numeric_parameter("x"), scan.numeric_parameter("y")) {
+        (Some(x), Some(y)) if x > 0.0 && y > 0.0 => y / x,
+        _ => LETTER_ASPECT,
+    };
+    let captured = height as f32 / width as f32;
+
+    if captured < expected * SHORT_PAGE_RATIO {
+        Some(format!(
+            "{:.0}% of the expected length",
+            captured / expected * 100.0
+        ))
+    } else {
+        None
+    }
+}
+
+/// Flags the batch's group when the feeder produced a different number of
+/// pages than the operator declared, e.g. because a page was skipped.
+fn check_page_count(batch: &ScanBatch, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<()> {
+    match batch.expected_pages {
+        Some(expected) if expected != batch.pages_scanned => ScanGroup::set_page_count_warning(
+            batch.group_id,
+            Some(&format!(
+                "Batch {} expected {} pages but scanned {}",
+                batch.id, expected, batch.pages_scanned
+            )),
+            pool,
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Throws away a rejected attempt or page. In a group on hold it is kept, marked
+/// failed so it isn't exported as a page.
+fn discard(scan: &Scan, pool: &r2d2::Pool<DuckdbConnectionManager>, assets_dir: &AssetsDir) {
+    if !scan.delete(pool, assets_dir).unwrap() && scan.status == "COMPLETE" {
+        Scan::update_status_many(&[scan.id.unwrap()], "FAILED", pool).unwrap();
+    }
+}
+
+impl ScanBatch {
+    pub fn create(
+        scanner: String,
+        scan_parameters: HashMap<String, String>,
+        priority: ScanPriority,
+        group_id: i32,
+        expected_pages: Option<i32>,
+        started_by: Option<String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<ScanBatch> {
+        let conn = pool.get().unwrap();
+        let now = Utc::now();
+
+        conn.query_row(
+            &format!(
+                "INSERT INTO scan_batches (scanner, scan_parameters, priority, scan_group_id, status, expected_pages, started_by, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 RETURNING {}",
+                BATCH_COLUMNS
+            ),
+            params![
+                scanner,
+                serde_json::to_string(&scan_parameters).unwrap(),
+                priority.as_str(),
+                group_id,
+                BatchStatus::Running.as_str(),
+                expected_pages,
+                started_by,
+                now,
+                now
+            ],
+            row_to_batch,
+        )
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<ScanBatch> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM scan_batches WHERE id = ?", BATCH_COLUMNS),
+            params![id],
+            row_to_batch,
+        )
+    }
+
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<ScanBatch> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM scan_batches ORDER BY id DESC",
+                BATCH_COLUMNS
+            ))
+            .unwrap();
+
+        stmt.query_map(params![], row_to_batch)
+            .unwrap()
+            .map(|batch| batch.unwrap())
+            .collect()
+    }
+
+    fn set_status(
+        id: i32,
+        status: BatchStatus,
+        paused_reason: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_batches SET status = ?, paused_reason = ?, updated_at = ? WHERE id = ?",
+            params![status.as_str(), paused_reason, Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    fn record_page(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_batches SET pages_scanned = pages_scanned + 1, updated_at = ? WHERE id = ?",
+            params![Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Counts a page that looks double-fed, keeping it for the operator to check.
+    fn record_suspect_page(
+        id: i32,
+        scan_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_batches SET pages_scanned = pages_scanned + 1, suspect_scan_id = ?, updated_at = ? WHERE id = ?",
+            params![scan_id, Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Settles the suspect page: kept as it is, or uncounted so the next
+    /// page scanned takes its place.
+    fn clear_suspect(
+        id: i32,
+        rescan: bool,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_batches SET
+                pages_scanned = pages_scanned - CASE WHEN ? THEN 1 ELSE 0 END,
+                suspect_scan_id = NULL,
+                updated_at = ?
+             WHERE id = ?",
+            params![rescan, Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Batches left running by a previous process have nothing driving them;
+    /// pause them so the operator can resume from the right page.
+    pub fn pause_interrupted(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<usize> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_batches SET status = ?, paused_reason = ?, updated_at = ? WHERE status = ?",
+            params![
+                BatchStatus::Paused.as_str(),
+                "Server restarted",
+                Utc::now(),
+                BatchStatus::Running.as_str()
+            ],
+        )
+    }
+}
+
+/// Drives running batches, making sure each has exactly one task feeding its pages.
+#[derive(Clone)]
+pub struct BatchRunner {
+    running: Arc<Mutex<HashSet<i32>>>,
+    /// The first page of each batch's current pass through the feeder, which
+    /// cancelling stops it by
+    feeding: Arc<Mutex<HashMap<i32, i32>>>,
+    scanner_manager: ScannerManager,
+    pool: r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: AssetsDir,
+}
+
+impl BatchRunner {
+    pub fn new(
+        scanner_manager: ScannerManager,
+        pool: r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: AssetsDir,
+    ) -> Self {
+        Self {
+            running: Arc::new(Mutex::new(HashSet::new())),
+            feeding: Arc::new(Mutex::new(HashMap::new())),
+            scanner_manager,
+            pool,
+            assets_dir,
+        }
+    }
+
+    /// Starts scanning a new batch into `group_id`, or a new group if none
+    /// is given. Returns it with its first page's scan id.
+    pub fn start(
+        &self,
+        scanner: String,
+        scan_parameters: HashMap<String, String>,
+        priority: ScanPriority,
+        group_id: Option<i32>,
+        expected_pages: Option<i32>,
+        started_by: Option<String>,
+    ) -> Result<(ScanBatch, i32)> {
+        let group_id = match group_id {
+            Some(group_id) => group_id,
+            None => ScanGroup::create("scanning".to_string()).save(&self.pool)?,
+        };
+        let batch = ScanBatch::create(
+            scanner,
+            scan_parameters,
+            priority,
+            group_id,
+            expected_pages,
+            started_by,
+            &self.pool,
+        )?;
+        let first = self.first_page(&batch)?;
+
+        self.running.lock().unwrap().insert(batch.id);
+        self.spawn(batch.id, Some(first));
+        Ok((batch, first))
+    }
+
+    /// Stops the feeder. Pages that landed are kept, and the sheet it was
+    /// scanning is fed again when the batch resumes.
+    pub fn pause(&self, id: i32) -> Result<bool> {
+        let _running = self.running.lock().unwrap();
+        if ScanBatch::load(id, &self.pool)?.status != BatchStatus::Running {
+            return Ok(false);
+        }
+        ScanBatch::set_status(
+            id,
+            BatchStatus::Paused,
+            Some("Paused by operator"),
+            &self.pool,
+        )?;
+        if let Some(first) = self.feeding.lock().unwrap().get(&id) {
+            self.scanner_manager.cancel_scan(*first);
+        }
+        Ok(true)
+    }
+
+    /// Carries on from the next page. Pages already scanned are kept, except
+    /// the suspect page with `rescan_suspect`, which is scanned again.
+    pub fn resume(&self, id: i32, rescan_suspect: bool) -> Result<bool> {
+        let mut running = self.running.lock().unwrap();
+        let batch = ScanBatch::load(id, &self.pool)?;
+        if batch.status != BatchStatus::Paused {
+            return Ok(false);
+        }
+        if let Some(scan_id) = batch.suspect_scan_id {
+            if rescan_suspect {
+                if let Ok(scan) = Scan::load(scan_id, &self.pool) {
+                    discard(&scan, &self.pool, &self.assets_dir);
+                }
+            }
+            ScanBatch::clear_suspect(id, rescan_suspect, &self.pool)?;
+        }
+        ScanBatch::set_status(id, BatchStatus::Running, None, &self.pool)?;
+
+        // If the task hasn't noticed the pause yet it just keeps going
+        if running.insert(id) {
+            self.spawn(id, None);
+        }
+        Ok(true)
+    }
+
+    /// A pending scan for the first page of a pass through the feeder.
+    fn first_page(&self, batch: &ScanBatch) -> Result<i32> {
+        let scan = Scan::create_pending(
+            &batch.scanner,
+            &batch.scan_parameters,
+            Some(batch.group_id),
+            batch.started_by.clone(),
+            &self.pool,
+            &self.assets_dir,
+        )?;
+        Ok(scan.id.unwrap())
+    }
+
+    fn spawn(&self, id: i32, first: Option<i32>) {
+        let runner = self.clone();
+        tokio::spawn(async move {
+            runner.run(id, first).await;
+        });
+    }
+
+    /// Checks a page as it lands, counting it unless it is past an earlier
+    /// suspect page. Stops the feeder at the first that looks double-fed.
+    fn check_page(&self, id: i32, scan_id: i32, first: i32, suspect: &Mutex<Option<String>>) {
+        let pool = &self.pool;
+        let Ok(scan) = Scan::load(scan_id, pool) else {
+            return;
+        };
+        if scan.status != "COMPLETE" {
+            return;
+        }
+        let mut suspect = suspect.lock().unwrap();
+        if suspect.is_some() {
+            // Fed before the feeder stopped; it is re-fed after the suspect page
+            discard(&scan, pool, &self.assets_dir);
+            return;
+        }
+
+        // Left as the group's processing profile sets it, if it has one
+        let checking = scan.processing_status == ProcessingStatus::None;
+        if checking {
+            Scan::set_processing_status(scan_id, ProcessingStatus::Processing, pool).unwrap();
+        }
+        let short = short_page(&scan, &self.assets_dir);
+        if checking {
+            Scan::set_processing_status(scan_id, ProcessingStatus::Done, pool).unwrap();
+        }
+        match short {
+            None => ScanBatch::record_page(id, pool).unwrap(),
+            // Kept, as some sheets really are short, such as receipts
+            Some(length) => {
+                ScanBatch::record_suspect_page(id, scan_id, pool).unwrap();
+                *suspect = Some(length);
+                self.scanner_manager.cancel_scan(first);
+            }
+        }
+    }
+
+    async fn run(&self, id: i32, mut first: Option<i32>) {
+        let pool = &self.pool;
+        loop {
+            // Checked under the lock so a resume can't land between the
+            // status check and this task giving up the batch, and a pause
+            // always finds the pass to stop
+            let (batch, first) = {
+                let mut running = self.running.lock().unwrap();
+                let batch = ScanBatch::load(id, pool).unwrap();
+                if batch.status != BatchStatus::Running {
+                    running.remove(&id);
+                    return;
+                }
+                let first = match first.take() {
+                    Some(first) => first,
+                    None => self.first_page(&batch).unwrap(),
+                };
+                self.feeding.lock().unwrap().insert(id, first);
+                (batch, first)
+            };
+
+            let suspect = Mutex::new(None);
+            let landed = |scan_id| self.check_page(id, scan_id, first, &suspect);
+            let scan_ids = self
+                .scanner_manager
+                .complete_batch(
+                    first,
+                    &batch.scanner,
+                    batch.scan_parameters.clone(),
+                    batch.priority,
+                    pool,
+                    &self.assets_dir,
+                    &landed,
+                )
+                .await;
+            self.feeding.lock().unwrap().remove(&id);
+
+            let batch = ScanBatch::load(id, pool).unwrap();
+            let last = Scan::load(*scan_ids.last().unwrap(), pool).unwrap();
+            let failure = if last.status == "COMPLETE" {
+                None
+            } else {
+                // The sheet that failed or was cut off isn't a page; it's re-fed
+                discard(&last, pool, &self.assets_dir);
+                Some(Scan::load_failure(last.id.unwrap(), pool).unwrap())
+            };
+            let (page, reason) = match (suspect.into_inner().unwrap(), failure) {
+                (Some(length), _) => (
+                    batch.pages_scanned,
+                    format!(
+                        "Page {} is only {}, possibly a double feed. Resume to keep it, or re-feed from page {} and resume with rescanSuspect",
+                        batch.pages_scanned, length, batch.pages_scanned
+                    ),
+                ),
+                (None, _) if batch.status == BatchStatus::Paused => {
+                    let reason = format!(
+                        "Paused by operator. Re-feed from page {}",
+                        batch.pages_scanned + 1
+                    );
+                    ScanBatch::set_status(id, BatchStatus::Paused, Some(&reason), pool).unwrap();
+                    continue;
+                }
+                // The feeder ran out of paper
+                (None, None) => {
+                    ScanBatch::set_status(id, BatchStatus::Complete, None, pool).unwrap();
+                    check_page_count(&batch, pool).unwrap();
+                    continue;
+                }
+                (None, Some(failure))
+                    if failure.as_deref() == Some("NO_DOCS") && batch.pages_scanned > 0 =>
+                {
+                    ScanBatch::set_status(id, BatchStatus::Complete, None, pool).unwrap();
+                    check_page_count(&batch, pool).unwrap();
+                    continue;
+                }
+                (None, Some(failure)) => (
+                    batch.pages_scanned + 1,
+                    paused_reason(failure.as_deref(), batch.pages_scanned + 1),
+                ),
+            };
+
+            ScanBatch::set_status(id, BatchStatus::Paused, Some(&reason), pool).unwrap();
+            SimpleBroker::publish(BatchPaused {
+                batch_id: id,
+                page,
+                reason,
+                suspect_scan_id: batch.suspect_scan_id,
+                started_by: batch.started_by.clone(),
+            });
+        }
+    }
+}
@@ -0,0 +1,633 @@
+use std::{collections::VecDeque, fmt, io::Cursor};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager};
+use image::{
+    codecs::jpeg::JpegEncoder, imageops, imageops::FilterType, DynamicImage, GrayImage, Luma,
+};
+
+use crate::{
+    bitmap_font::{draw_text, fill, text_width, GLYPH_HEIGHT},
+    dewarp::ink_threshold,
+    pdf::{write_pdf, PdfPage},
+    scans::Scan,
+    AssetsDir,
+};
+
+/// Targets are drawn at this resolution on US Letter paper.
+const DPI: u32 = 300;
+const WIDTH: u32 = 2550;
+const HEIGHT: u32 = 3300;
+/// Fiducials are solid squares this wide, centred this far in from each
+/// corner. Nothing else is drawn within `CORNER_CLEAR` of a corner.
+const FIDUCIAL: u32 = 120;
+const FIDUCIAL_INSET: u32 = 225;
+const CORNER_CLEAR: u32 = 375;
+/// Bar groups on the resolution target, in line pairs per inch. Each gives
+/// whole-pixel bars at `DPI`.
+const LINES_PER_INCH: [u32; 5] = [15, 25, 50, 75, 150];
+const BARS_X: u32 = 525;
+const BARS_TOP: u32 = 600;
+const BAR_BLOCK_WIDTH: u32 = 450;
+const BAR_BLOCK_HEIGHT: u32 = 240;
+const BAR_ROW: u32 = 390;
+/// Horizontal bars are the vertical ones turned, this far to the right.
+const HORIZONTAL_BARS_OFFSET: u32 = 900;
+/// Skew grid lines are this far apart and this thick.
+const GRID_STEP: u32 = 150;
+const GRID_LINE: u32 = 2;
+const JPEG_QUALITY: u8 = 95;
+
+/// Scans are searched for fiducials at about this size.
+const ANALYSIS_SIZE: u32 = 1000;
+/// A bar group is resolved while its bars and gaps differ by at least this
+/// share of the difference between ink and paper.
+const MIN_CONTRAST: f32 = 0.2;
+/// Differences from the requested resolution, and skew, worth mentioning.
+const SCALE_TOLERANCE: f32 = 0.02;
+const SKEW_TOLERANCE: f32 = 0.3;
+
+const PAPER: Luma<u8> = Luma([255]);
+const INK: Luma<u8> = Luma([0]);
+
+/// What a scan of a printed calibration target showed about the scanner
+/// that made it.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct CalibrationReport {
+    pub id: i32,
+    pub scan_id: i32,
+    pub device: String,
+    /// From the scan's `--resolution` parameter, if it had one
+    pub requested_dpi: Option<f64>,
+    /// Measured from the distance between the target's corner squares
+    pub horizontal_dpi: f64,
+    pub vertical_dpi: f64,
+    /// Degrees the target slopes down to the right
+    pub skew_degrees: f64,
+    /// The finest bar group still told apart across the page, in line
+    /// pairs per inch. Null if none was, or the scan isn't of the
+    /// resolution target.
+    pub horizontal_lines_per_inch: Option<i32>,
+    /// The same, down the page
+    pub vertical_lines_per_inch: Option<i32>,
+    /// Suggestions for the device's presets
+    pub advice: Vec<String>,
+    pub analyzed_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum CalibrationError {
+    ScanNotFound,
+    Image(String),
+    /// The four corner squares couldn't all be found
+    TargetNotFound,
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::ScanNotFound => write!(f, "scan not found"),
+            CalibrationError::Image(e) => write!(f, "could not read scan image: {}", e),
+            CalibrationError::TargetNotFound => write!(
+                f,
+                "no calibration target found; scan the whole printed page, upright"
+            ),
+            CalibrationError::Db(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for CalibrationError {
+    fn from(e: duckdb::Error) -> Self {
+        CalibrationError::Db(e)
+    }
+}
+
+/// A page with the corner squares analysis finds the target by, and its title.
+fn blank_page(title: &str) -> GrayImage {
+    let mut page = GrayImage::from_pixel(WIDTH, HEIGHT, PAPER);
+    for (x, y) in fiducial_centres() {
+        fill(
+            &mut page,
+            x as u32 - FIDUCIAL / 2,
+            y as u32 - FIDUCIAL / 2,
+            FIDUCIAL,
+            FIDUCIAL,
+            INK,
+        );
+    }
+    let scale = 6;
+    let x = (WIDTH - text_width(title, scale)) / 2;
+    draw_text(&mut page, title, x, CORNER_CLEAR, scale, INK);
+    let note = "PRINT AT ACTUAL SIZE, NOT FIT TO PAGE";
+    let x = (WIDTH - text_width(note, 3)) / 2;
+    draw_text(
+        &mut page,
+        note,
+        x,
+        CORNER_CLEAR + (GLYPH_HEIGHT + 4) * scale,
+        3,
+        INK,
+    );
+    page
+}
+
+/// Top left, top right, bottom left and bottom right, on the target.
+fn fiducial_centres() -> [(f32, f32); 4] {
+    let (near, far_x, far_y) = (
+        FIDUCIAL_INSET as f32,
+        (WIDTH - FIDUCIAL_INSET) as f32,
+        (HEIGHT - FIDUCIAL_INSET) as f32,
+    );
+    [(near, near), (far_x, near), (near, far_y), (far_x, far_y)]
+}
+
+/// The top left corner of the bar group in `row`, as vertical bars or
+/// turned into horizontal ones.
+fn bar_block(row: usize, horizontal: bool) -> (u32, u32) {
+    let x = BARS_X
+        + if horizontal {
+            HORIZONTAL_BARS_OFFSET
+        } else {
+            0
+        };
+    (x, BARS_TOP + row as u32 * BAR_ROW)
+}
+
+/// How far a bar group runs across its bars.
+fn bar_block_length(horizontal: bool) -> u32 {
+    if horizontal {
+        BAR_BLOCK_HEIGHT
+    } else {
+        BAR_BLOCK_WIDTH
+    }
+}
+
+/// Groups of bars from coarse to fine, each as vertical bars, for detail
+/// across the page, and horizontal ones, for detail down it.
+fn resolution_target() -> GrayImage {
+    let mut page = blank_page("RESOLUTION TARGET");
+    for (row, lpi) in LINES_PER_INCH.iter().enumerate() {
+        let bar = DPI / lpi / 2;
+        for horizontal in [false, true] {
+            let (x0, y0) = bar_block(row, horizontal);
+            let mut offset = 0;
+            while offset + bar * 2 <= bar_block_length(horizontal) {
+                match horizontal {
+                    false => fill(&mut page, x0 + offset, y0, bar, BAR_BLOCK_HEIGHT, INK),
+                    true => fill(&mut page, x0, y0 + offset, BAR_BLOCK_WIDTH, bar, INK),
+                }
+                offset += bar * 2;
+            }
+        }
+        let label = format!("{} LPI", lpi);
+        let (x, y) = bar_block(row, true);
+        draw_text(
+            &mut page,
+            &label,
+            x + BAR_BLOCK_WIDTH + 60,
+            y + (BAR_BLOCK_HEIGHT - GLYPH_HEIGHT * 4) / 2,
+            4,
+            INK,
+        );
+    }
+    page
+}
+
+/// A square grid over the page, to check pages come out straight and
+/// evenly scaled by eye.
+fn skew_grid() -> GrayImage {
+    let mut page = blank_page("SKEW GRID");
+    let (left, right) = (CORNER_CLEAR, WIDTH - CORNER_CLEAR);
+    let (top, bottom) = (BARS_TOP, HEIGHT - CORNER_CLEAR);
+    let mut x = left;
+    while x <= right {
+        fill(&mut page, x, top, GRID_LINE, bottom - top, INK);
+        x += GRID_STEP;
+    }
+    let mut y = top;
+    while y <= bottom {
+        fill(&mut page, left, y, right - left, GRID_LINE, INK);
+        y += GRID_STEP;
+    }
+    page
+}
+
+/// The resolution target and skew grid as a two page PDF, to print and
+/// scan with `analyzeCalibrationScan`.
+pub fn target_pdf() -> Result<Vec<u8>, String> {
+    let pages = [resolution_target(), skew_grid()]
+        .into_iter()
+        .map(|page| {
+            let image = DynamicImage::ImageRgb8(DynamicImage::ImageLuma8(page).to_rgb8());
+            let mut jpeg = Vec::new();
+            image
+                .write_with_encoder(JpegEncoder::new_with_quality(
+                    &mut Cursor::new(&mut jpeg),
+                    JPEG_QUALITY,
+                ))
+                .map_err(|e| e.to_string())?;
+            Ok(PdfPage {
+                jpeg,
+                width_px: WIDTH,
+                height_px: HEIGHT,
+                width_pt: WIDTH as f32 * 72.0 / DPI as f32,
+                height_pt: HEIGHT as f32 * 72.0 / DPI as f32,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut pdf = Vec::new();
+    write_pdf(&pages, &mut pdf).map_err(|e| e.to_string())?;
+    Ok(pdf)
+}
+
+/// Centre and width of the solid, roughly square patch of ink nearest the
+/// corner of the image, searching the quarter of `small` that holds that
+/// corner. Returned in `small`'s pixels.
+fn find_fiducial(
+    small: &GrayImage,
+    threshold: u8,
+    right: bool,
+    bottom: bool,
+) -> Option<((f32, f32), f32)> {
+    let (width, height) = (small.width() / 2, small.height() / 2);
+    let (x0, y0) = (
+        if right { small.width() - width } else { 0 },
+        if bottom { small.height() - height } else { 0 },
+    );
+    let min_side = small.width().min(small.height()) as f32 * 0.025;
+    let max_side = small.width().min(small.height()) as f32 * 0.1;
+    let mut seen = vec![false; (width * height) as usize];
+    let (corner_x, corner_y) = (
+        if right { small.width() as f32 } else { 0.0 },
+        if bottom { small.height() as f32 } else { 0.0 },
+    );
+
+    let mut best: Option<((f32, f32), f32, f32)> = None;
+    for start in 0..seen.len() {
+        let (sx, sy) = (start as u32 % width, start as u32 / width);
+        if seen[start] || small.get_pixel(x0 + sx, y0 + sy)[0] >= threshold {
+            continue;
+        }
+        seen[start] = true;
+        let mut queue = VecDeque::from([(sx, sy)]);
+        let (mut area, mut sum_x, mut sum_y) = (0u32, 0f32, 0f32);
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (sx, sy, sx, sy);
+        while let Some((x, y)) = queue.pop_front() {
+            area += 1;
+            sum_x += x as f32;
+            sum_y += y as f32;
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x), max_y.max(y));
+            let neighbours = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            for (nx, ny) in neighbours {
+                if nx >= width || ny >= height {
+                    continue;
+                }
+                let index = (ny * width + nx) as usize;
+                if !seen[index] && small.get_pixel(x0 + nx, y0 + ny)[0] < threshold {
+                    seen[index] = true;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+
+        let (side_x, side_y) = ((max_x - min_x + 1) as f32, (max_y - min_y + 1) as f32);
+        let square = (side_x / side_y).clamp(0.8, 1.25) == side_x / side_y;
+        let solid = area as f32 >= side_x * side_y * 0.8;
+        let sized = side_x >= min_side && side_x <= max_side;
+        if !(square && solid && sized) {
+            continue;
+        }
+        let centre = (
+            x0 as f32 + sum_x / area as f32,
+            y0 as f32 + sum_y / area as f32,
+        );
+        let distance = (centre.0 - corner_x).hypot(centre.1 - corner_y);
+        if best.is_none_or(|(_, _, nearest)| distance < nearest) {
+            best = Some((centre, side_x, distance));
+        }
+    }
+    best.map(|(centre, side, _)| (centre, side))
+}
+
+/// The centre of ink around `centre` at full resolution, within a square
+/// `side` wide, for the sub-pixel accuracy the finest bars need.
+fn refine_centre(gray: &GrayImage, centre: (f32, f32), side: f32, threshold: u8) -> (f32, f32) {
+    let half = side * 0.75;
+    let (x0, y0) = (
+        (centre.0 - half).max(0.0) as u32,
+        (centre.1 - half).max(0.0) as u32,
+    );
+    let (x1, y1) = (
+        ((centre.0 + half) as u32).min(gray.width() - 1),
+        ((centre.1 + half) as u32).min(gray.height() - 1),
+    );
+    let (mut weight, mut sum_x, mut sum_y) = (0f32, 0f32, 0f32);
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let level = gray.get_pixel(x, y)[0];
+            if level < threshold {
+                let ink = (threshold - level) as f32;
+                weight += ink;
+                sum_x += x as f32 * ink;
+                sum_y += y as f32 * ink;
+            }
+        }
+    }
+    if weight == 0.0 {
+        return centre;
+    }
+    (sum_x / weight, sum_y / weight)
+}
+
+/// Bilinear sample of a grayscale image.
+fn sample(image: &GrayImage, x: f32, y: f32) -> f32 {
+    let x = x.clamp(0.0, (image.width() - 1) as f32);
+    let y = y.clamp(0.0, (image.height() - 1) as f32);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width() - 1),
+        (y0 + 1).min(image.height() - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let at = |x, y| image.get_pixel(x, y)[0] as f32;
+    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Where points on the target land in the scan, from where its four
+/// corner squares did.
+struct TargetMap {
+    corners: [(f32, f32); 4],
+}
+
+impl TargetMap {
+    fn to_scan(&self, x: f32, y: f32) -> (f32, f32) {
+        let [tl, tr, bl, br] = self.corners;
+        let u = (x - FIDUCIAL_INSET as f32) / (WIDTH - FIDUCIAL_INSET * 2) as f32;
+        let v = (y - FIDUCIAL_INSET as f32) / (HEIGHT - FIDUCIAL_INSET * 2) as f32;
+        let along = |a: f32, b: f32, c: f32, d: f32| {
+            a * (1.0 - u) * (1.0 - v) + b * u * (1.0 - v) + c * (1.0 - u) * v + d * u * v
+        };
+        (along(tl.0, tr.0, bl.0, br.0), along(tl.1, tr.1, bl.1, br.1))
+    }
+
+    fn sample(&self, gray: &GrayImage, x: f32, y: f32) -> f32 {
+        let (sx, sy) = self.to_scan(x, y);
+        sample(gray, sx, sy)
+    }
+}
+
+/// How well the bars of a group stand out from the gaps between them, as a
+/// share of the difference between paper and ink, sampled across the
+/// middle of the group.
+fn bar_contrast(
+    gray: &GrayImage,
+    map: &TargetMap,
+    row: usize,
+    horizontal: bool,
+    paper: f32,
+    ink: f32,
+) -> f32 {
+    let bar = DPI / LINES_PER_INCH[row] / 2;
+    let (x0, y0) = bar_block(row, horizontal);
+    let length = bar_block_length(horizontal);
+    let (mut bars, mut gaps, mut count) = (0.0, 0.0, 0);
+    for across in [0.3, 0.5, 0.7] {
+        let mut offset = 0;
+        while offset + bar * 2 <= length {
+            let (bar_at, gap_at) = (
+                (offset as f32 + bar as f32 / 2.0),
+                (offset as f32 + bar as f32 * 1.5),
+            );
+            let point = |along: f32| match horizontal {
+                false => (
+                    x0 as f32 + along,
+                    y0 as f32 + BAR_BLOCK_HEIGHT as f32 * across,
+                ),
+                true => (
+                    x0 as f32 + BAR_BLOCK_WIDTH as f32 * across,
+                    y0 as f32 + along,
+                ),
+            };
+            let (bx, by) = point(bar_at);
+            let (gx, gy) = point(gap_at);
+            bars += map.sample(gray, bx, by);
+            gaps += map.sample(gray, gx, gy);
+            count += 1;
+            offset += bar * 2;
+        }
+    }
+    if count == 0 || paper <= ink {
+        return 0.0;
+    }
+    (gaps - bars) / count as f32 / (paper - ink)
+}
+
+/// The finest bar group, by lines per inch, resolved in one direction,
+/// stopping at the first that isn't.
+fn resolved_lpi(
+    gray: &GrayImage,
+    map: &TargetMap,
+    horizontal: bool,
+    paper: f32,
+    ink: f32,
+) -> Option<i32> {
+    let mut finest = None;
+    for (row, lpi) in LINES_PER_INCH.iter().enumerate() {
+        if bar_contrast(gray, map, row, horizontal, paper, ink) < MIN_CONTRAST {
+            break;
+        }
+        finest = Some(*lpi as i32);
+    }
+    finest
+}
+
+/// Measures a scan of the printed target: its real resolution from how far
+/// apart the corner squares came out, its skew from the line between them,
+/// and, on the resolution target, the finest bars still told apart.
+fn measure(gray: &GrayImage) -> Result<Measurement, CalibrationError> {
+    let scale = (ANALYSIS_SIZE as f32 / gray.width().max(gray.height()) as f32).min(1.0);
+    let small = imageops::resize(
+        gray,
+        ((gray.width() as f32 * scale) as u32).max(1),
+        ((gray.height() as f32 * scale) as u32).max(1),
+        FilterType::Triangle,
+    );
+    let threshold = ink_threshold(&small);
+    let mut corners = [(0.0, 0.0); 4];
+    for (corner, (right, bottom)) in
+        corners
+            .iter_mut()
+            .zip([(false, false), (true, false), (false, true), (true, true)])
+    {
+        let ((x, y), side) = find_fiducial(&small, threshold, right, bottom)
+            .ok_or(CalibrationError::TargetNotFound)?;
+        *corner = refine_centre(gray, (x / scale, y / scale), side / scale, threshold);
+    }
+    let [tl, tr, bl, br] = corners;
+    let distance = |a: (f32, f32), b: (f32, f32)| (b.0 - a.0).hypot(b.1 - a.1);
+    let span_x = (WIDTH - FIDUCIAL_INSET * 2) as f32 / DPI as f32;
+    let span_y = (HEIGHT - FIDUCIAL_INSET * 2) as f32 / DPI as f32;
+    let horizontal_dpi = (distance(tl, tr) + distance(bl, br)) / 2.0 / span_x;
+    let vertical_dpi = (distance(tl, bl) + distance(tr, br)) / 2.0 / span_y;
+    let skew = ((tr.1 - tl.1).atan2(tr.0 - tl.0) + (br.1 - bl.1).atan2(br.0 - bl.0)) / 2.0;
+
+    let map = TargetMap { corners };
+    let ink = corners
+        .iter()
+        .map(|(x, y)| sample(gray, *x, *y))
+        .sum::<f32>()
+        / 4.0;
+    // Between the first bar group and the corner square above it
+    let paper = map.sample(gray, BARS_X as f32, (BARS_TOP - 60) as f32);
+
+    Ok(Measurement {
+        horizontal_dpi,
+        vertical_dpi,
+        skew_degrees: skew.to_degrees(),
+        horizontal_lpi: resolved_lpi(gray, &map, false, paper, ink),
+        vertical_lpi: resolved_lpi(gray, &map, true, paper, ink),
+    })
+}
+
+struct Measurement {
+    horizontal_dpi: f32,
+    vertical_dpi: f32,
+    skew_degrees: f32,
+    horizontal_lpi: Option<i32>,
+    vertical_lpi: Option<i32>,
+}
+
+fn advice(measurement: &Measurement, requested_dpi: Option<f32>) -> Vec<String> {
+    let mut advice = Vec::new();
+    if let Some(requested) = requested_dpi {
+        let off = |measured: f32| (measured - requested).abs() / requested > SCALE_TOLERANCE;
+        if off(measurement.horizontal_dpi) || off(measurement.vertical_dpi) {
+            advice.push(format!(
+                "Pages come out at {:.0} by {:.0} dpi against {:.0} requested. If the target was printed at actual size, the scanner's scale needs correcting.",
+                measurement.horizontal_dpi, measurement.vertical_dpi, requested
+            ));
+        }
+        let finest = measurement
+            .horizontal_lpi
+            .into_iter()
+            .chain(measurement.vertical_lpi)
+            .min();
+        // Twice the sampling the finest detail strictly needs, for margin
+        let enough = finest.map(|lpi| lpi as f32 * 4.0);
+        if let Some(enough) = enough.filter(|enough| *enough < requested) {
+            advice.push(format!(
+                "Detail stops at {:.0} lines per inch; scanning above about {:.0} dpi adds size rather than detail.",
+                enough / 4.0,
+                enough
+            ));
+        }
+    }
+    if measurement.skew_degrees.abs() >= SKEW_TOLERANCE {
+        advice.push(format!(
+            "Pages come out skewed by {:.1}°. Check the feeder's guides, or add a deskew step to the device's processing profile.",
+            measurement.skew_degrees
+        ));
+    }
+    advice
+}
+
+const COLUMNS: &str = "id, scan_id, device, requested_dpi, horizontal_dpi, vertical_dpi, skew_degrees, horizontal_lpi, vertical_lpi, advice, analyzed_at";
+
+fn row_to_report(row: &duckdb::Row) -> duckdb::Result<CalibrationReport> {
+    let advice_json: String = row.get(9)?;
+    Ok(CalibrationReport {
+        id: row.get(0)?,
+        scan_id: row.get(1)?,
+        device: row.get(2)?,
+        requested_dpi: row.get(3)?,
+        horizontal_dpi: row.get(4)?,
+        vertical_dpi: row.get(5)?,
+        skew_degrees: row.get(6)?,
+        horizontal_lines_per_inch: row.get(7)?,
+        vertical_lines_per_inch: row.get(8)?,
+        advice: serde_json::from_str(&advice_json).unwrap_or_default(),
+        analyzed_at: row.get(10)?,
+    })
+}
+
+impl CalibrationReport {
+    /// Earlier reports, newest first, optionally only for one device.
+    pub fn load_all(
+        device: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<CalibrationReport> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM calibrations WHERE device = coalesce(?, device)
+                 ORDER BY analyzed_at DESC, id DESC",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let reports: Vec<CalibrationReport> = stmt
+            .query_map([device], row_to_report)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        reports
+    }
+
+    /// Measures the scan of a printed target and records the result against
+    /// the scanner that made it.
+    pub fn analyze(
+        scan_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<CalibrationReport, CalibrationError> {
+        let scan = match Scan::load(scan_id, pool) {
+            Ok(scan) if scan.status == "COMPLETE" => scan,
+            _ => return Err(CalibrationError::ScanNotFound),
+        };
+        // The capture as the scanner made it, before any edits or deskewing
+        let source = scan.original_path.as_ref().unwrap_or(&scan.path);
+        let gray = assets_dir
+            .read_image(source)
+            .map_err(|e| CalibrationError::Image(e.to_string()))?
+            .to_luma8();
+        let measurement = measure(&gray)?;
+        let requested_dpi = scan.resolution();
+        let advice = advice(&measurement, requested_dpi);
+
+        let conn = pool.get().unwrap();
+        let report = conn.query_row(
+            &format!(
+                "INSERT INTO calibrations (scan_id, device, requested_dpi, horizontal_dpi, vertical_dpi, skew_degrees, horizontal_lpi, vertical_lpi, advice, analyzed_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+                COLUMNS
+            ),
+            params![
+                scan_id,
+                scan.scanner,
+                requested_dpi.map(f64::from),
+                measurement.horizontal_dpi as f64,
+                measurement.vertical_dpi as f64,
+                measurement.skew_degrees as f64,
+                measurement.horizontal_lpi,
+                measurement.vertical_lpi,
+                serde_json::to_string(&advice).unwrap(),
+                Utc::now()
+            ],
+            row_to_report,
+        )?;
+        Ok(report)
+    }
+}
@@ -0,0 +1,491 @@
+use std::{fmt, io};
+
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entities::{extract, Entity, EntityKind},
+    ingest::export_to_destination,
+    ocr::ScanText,
+    scans::ScanGroup,
+    AssetsDir, PublicUrl,
+};
+
+/// Files a group by what its pages say, e.g. "text contains 'Account
+/// Statement' and the vendor is Example Bank → tag `banking`, export to the
+/// paperless consume folder, title it `Statement {date}`". Rules run after
+/// OCR, oldest first, and every rule that matches is applied.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ClassificationRule {
+    pub id: i32,
+    pub name: String,
+    /// Phrases that must all appear in the text, case-insensitively
+    pub text_contains: Vec<String>,
+    /// Part of the name of an organization that must be mentioned, e.g. the vendor
+    pub organization: Option<String>,
+    /// Added to the group's tags
+    pub tags: Vec<String>,
+    /// Export the group as a PDF into this directory
+    pub destination: Option<String>,
+    /// Retitles the group. `{title}`, `{organization}`, `{date}`, `{year}`
+    /// and `{amount}` are replaced by the group's current title and the
+    /// first organization, date and year, and largest amount, in its text.
+    pub title_template: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(InputObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassificationRuleInput {
+    pub name: String,
+    #[graphql(default)]
+    #[serde(default)]
+    pub text_contains: Vec<String>,
+    pub organization: Option<String>,
+    #[graphql(default)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub destination: Option<String>,
+    pub title_template: Option<String>,
+}
+
+/// What a rule would do to a group or text, without doing it.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ClassificationTest {
+    pub matched: bool,
+    /// Conditions the text doesn't meet, e.g. `text contains "Statement"`
+    pub unmet: Vec<String>,
+    /// The title the template gives, if the rule has one
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+    pub destination: Option<String>,
+}
+
+/// A rule that was applied to a group, and what it changed.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ClassificationRun {
+    pub id: i32,
+    /// Kept after the rule is deleted
+    pub rule_id: i32,
+    pub rule_name: String,
+    pub group_id: i32,
+    pub title_before: String,
+    pub title_after: String,
+    pub tags_added: Vec<String>,
+    pub destination: Option<String>,
+    /// Why exporting to the destination failed
+    pub export_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum ClassifyError {
+    GroupNotFound,
+    /// A page's text could not be read, e.g. tesseract isn't installed
+    Ocr(io::Error),
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for ClassifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassifyError::GroupNotFound => write!(f, "group not found"),
+            ClassifyError::Ocr(e) => write!(f, "could not read text from scan: {}", e),
+            ClassifyError::Db(e) => write!(f, "could not save classification: {}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for ClassifyError {
+    fn from(e: duckdb::Error) -> Self {
+        ClassifyError::Db(e)
+    }
+}
+
+/// The text of a group and the entities in it, which rules are matched
+/// against.
+pub struct GroupText {
+    text: String,
+    entities: Vec<Entity>,
+}
+
+impl GroupText {
+    pub fn new(text: String) -> Self {
+        let entities = extract(&text);
+        GroupText { text, entities }
+    }
+
+    /// The text of the group's completed pages, in page order, running
+    /// OCR on pages that haven't been read yet.
+    pub fn read(
+        group: &ScanGroup,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> io::Result<Self> {
+        let pages = group
+            .scans
+            .iter()
+            .filter(|scan| scan.status == "COMPLETE")
+            .map(|scan| ScanText::for_scan(scan, pool, assets_dir).map(|text| text.text))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::new(pages.join("\n\n")))
+    }
+
+    fn first(&self, kind: EntityKind) -> Option<&Entity> {
+        self.entities.iter().find(|entity| entity.kind == kind)
+    }
+
+    fn largest_amount(&self) -> Option<&Entity> {
+        self.entities
+            .iter()
+            .filter(|entity| entity.kind == EntityKind::Amount)
+            .max_by_key(|entity| entity.cents)
+    }
+}
+
+const COLUMNS: &str =
+    "id, name, text_contains, organization, tags, destination, title_template, created_at";
+
+fn row_to_rule(row: &duckdb::Row) -> duckdb::Result<ClassificationRule> {
+    let text_contains_json: String = row.get(2)?;
+    let tags_json: String = row.get(4)?;
+
+    Ok(ClassificationRule {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        text_contains: serde_json::from_str(&text_contains_json).unwrap_or_default(),
+        organization: row.get(3)?,
+        tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+        destination: row.get(5)?,
+        title_template: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+impl ClassificationRule {
+    /// A rule as given, for testing before it is saved.
+    pub fn unsaved(input: ClassificationRuleInput) -> Self {
+        ClassificationRule {
+            id: 0,
+            name: input.name,
+            text_contains: input.text_contains,
+            organization: input.organization,
+            tags: input.tags,
+            destination: input.destination,
+            title_template: input.title_template,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// The rule as it would be entered, e.g. to recreate it elsewhere.
+    pub fn to_input(&self) -> ClassificationRuleInput {
+        ClassificationRuleInput {
+            name: self.name.clone(),
+            text_contains: self.text_contains.clone(),
+            organization: self.organization.clone(),
+            tags: self.tags.clone(),
+            destination: self.destination.clone(),
+            title_template: self.title_template.clone(),
+        }
+    }
+
+    pub fn create(
+        input: ClassificationRuleInput,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO classification_rules (name, text_contains, organization, tags, destination, title_template, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            params![
+                input.name.trim(),
+                serde_json::to_string(&input.text_contains).unwrap(),
+                input.organization,
+                serde_json::to_string(&input.tags).unwrap(),
+                input.destination,
+                input.title_template,
+                Utc::now()
+            ],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM classification_rules WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_rule,
+        )
+    }
+
+    /// Every rule, oldest first, which is the order they are applied in.
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<ClassificationRule> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM classification_rules ORDER BY id",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let rules: Vec<ClassificationRule> = stmt
+            .query_map([], row_to_rule)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        rules
+    }
+
+    pub fn delete(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        let deleted = conn.execute("DELETE FROM classification_rules WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
+
+    /// The conditions `text` doesn't meet; none if the rule matches.
+    fn unmet(&self, text: &GroupText) -> Vec<String> {
+        let haystack = text.text.to_lowercase();
+        let mut unmet: Vec<String> = self
+            .text_contains
+            .iter()
+            .filter(|phrase| !haystack.contains(&phrase.to_lowercase()))
+            .map(|phrase| format!("text contains \"{}\"", phrase))
+            .collect();
+        if let Some(organization) = &self.organization {
+            let wanted = organization.to_lowercase();
+            let mentioned = text.entities.iter().any(|entity| {
+                entity.kind == EntityKind::Organization
+                    && entity.tag.to_lowercase().contains(&wanted)
+            });
+            if !mentioned {
+                unmet.push(format!("organization \"{}\"", organization));
+            }
+        }
+        unmet
+    }
+
+    fn render_title(&self, current: &str, text: &GroupText) -> Option<String> {
+        let template = self.title_template.as_ref()?;
+        let tag = |entity: Option<&Entity>| entity.map(|e| e.tag.clone()).unwrap_or_default();
+        let date = text.first(EntityKind::Date);
+        let title = template
+            .replace("{title}", current)
+            .replace("{organization}", &tag(text.first(EntityKind::Organization)))
+            .replace(
+                "{date}",
+                &date.map(|date| date.text.clone()).unwrap_or_default(),
+            )
+            .replace("{year}", &tag(date))
+            .replace("{amount}", &tag(text.largest_amount()));
+        Some(title.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+
+    /// What the rule would do to a group titled `title` with this text.
+    pub fn test(&self, title: &str, text: &GroupText) -> ClassificationTest {
+        let unmet = self.unmet(text);
+        ClassificationTest {
+            matched: unmet.is_empty(),
+            unmet,
+            title: self.render_title(title, text),
+            tags: self.tags.clone(),
+            destination: self.destination.clone(),
+        }
+    }
+}
+
+impl ClassificationRun {
+    fn from_row(row: &duckdb::Row) -> duckdb::Result<Self> {
+        let tags_json: String = row.get(6)?;
+
+        Ok(ClassificationRun {
+            id: row.get(0)?,
+            rule_id: row.get(1)?,
+            rule_name: row.get(2)?,
+            group_id: row.get(3)?,
+            title_before: row.get(4)?,
+            title_after: row.get(5)?,
+            tags_added: serde_json::from_str(&tags_json).unwrap_or_default(),
+            destination: row.get(7)?,
+            export_error: row.get(8)?,
+            created_at: row.get(9)?,
+        })
+    }
+
+    fn record(
+        rule: &ClassificationRule,
+        group_id: i32,
+        title_before: &str,
+        title_after: &str,
+        tags_added: &[String],
+        export_error: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            "INSERT INTO classification_runs (rule_id, rule_name, scan_group_id, title_before, title_after, tags_added, destination, export_error, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING id, rule_id, rule_name, scan_group_id, title_before, title_after, tags_added, destination, export_error, created_at",
+            params![
+                rule.id,
+                rule.name,
+                group_id,
+                title_before,
+                title_after,
+                serde_json::to_string(tags_added).unwrap(),
+                rule.destination,
+                export_error,
+                Utc::now()
+            ],
+            Self::from_row,
+        )
+    }
+
+    /// Rule applications, newest first, optionally only those to one group
+    /// or by one rule.
+    pub fn load_recent(
+        group_id: Option<i32>,
+        rule_id: Option<i32>,
+        limit: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<ClassificationRun> {
+        let conn = pool.get().unwrap();
+
+        let mut conditions: Vec<&str> = vec![];
+        let mut params: Vec<Box<dyn duckdb::ToSql>> = vec![];
+        if let Some(group_id) = group_id {
+            conditions.push("scan_group_id = ?");
+            params.push(Box::new(group_id));
+        }
+        if let Some(rule_id) = rule_id {
+            conditions.push("rule_id = ?");
+            params.push(Box::new(rule_id));
+        }
+        let where_clause = match conditions.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", conditions.join(" AND ")),
+        };
+        params.push(Box::new(limit));
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT id, rule_id, rule_name, scan_group_id, title_before, title_after, tags_added, destination, export_error, created_at
+                 FROM classification_runs {}
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT ?",
+                where_clause
+            ))
+            .unwrap();
+
+        let runs: Vec<ClassificationRun> = stmt
+            .query_map(duckdb::params_from_iter(params.iter()), Self::from_row)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        runs
+    }
+}
+
+/// Applies every rule that matches the group's text, in order: tags are
+/// added and the title set, then the group is exported to each matching
+/// rule's destination. Each application is recorded. Returns the runs.
+pub fn classify_group(
+    group_id: i32,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> std::result::Result<Vec<ClassificationRun>, ClassifyError> {
+    let rules = ClassificationRule::load_all(pool);
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut group = ScanGroup::load(group_id, pool).map_err(|_| ClassifyError::GroupNotFound)?;
+    let text = GroupText::read(&group, pool, assets_dir).map_err(ClassifyError::Ocr)?;
+
+    let matched: Vec<(&ClassificationRule, String, Vec<String>)> = rules
+        .iter()
+        .filter_map(|rule| {
+            let test = rule.test(&group.title, &text);
+            if !test.matched {
+                return None;
+            }
+            let title_before = group.title.clone();
+            if let Some(title) = test.title.filter(|title| !title.is_empty()) {
+                group.title = title;
+            }
+            let tags_added: Vec<String> = rule
+                .tags
+                .iter()
+                .filter(|tag| !group.tags.contains(tag))
+                .cloned()
+                .collect();
+            group.tags.extend(tags_added.iter().cloned());
+            Some((rule, title_before, tags_added))
+        })
+        .collect();
+    if matched.is_empty() {
+        return Ok(Vec::new());
+    }
+    group.save(pool)?;
+
+    let mut runs = Vec::new();
+    for (rule, title_before, tags_added) in matched {
+        let export_error = rule.destination.as_ref().and_then(|destination| {
+            let triggered_by = format!("classification rule {}", rule.id);
+            export_to_destination(
+                group.id,
+                destination,
+                triggered_by,
+                pool,
+                assets_dir,
+                public_url,
+            )
+            .err()
+        });
+        runs.push(ClassificationRun::record(
+            rule,
+            group.id,
+            &title_before,
+            &group.title,
+            &tags_added,
+            export_error.as_deref(),
+            pool,
+        )?);
+    }
+    Ok(runs)
+}
+
+/// Classifies a group whose pages were just imported. Filing shouldn't
+/// fail the import, so a page that can't be read is reported and the group
+/// is left as it was.
+pub fn classify_after_import(
+    group_id: i32,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<ScanGroup> {
+    match classify_group(group_id, pool, assets_dir, public_url) {
+        Ok(runs) => {
+            for run in runs {
+                println!(
+                    "Classified group {} by rule \"{}\"",
+                    group_id, run.rule_name
+                );
+            }
+        }
+        Err(ClassifyError::Db(e)) => return Err(e),
+        Err(e) => println!("Could not classify group {}: {}", group_id, e),
+    }
+    ScanGroup::load(group_id, pool)
+}
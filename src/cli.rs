@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use clap::Subcommand;
+use duckdb::DuckdbConnectionManager;
+
+use crate::{
+    contact_sheet,
+    exports::{export_group, has_pending_scans, ExportError, ExportFormat, ExportOptions},
+    loadtest::{self, LoadtestOptions},
+    locale, sandbox,
+    scan_presets::{self, ScanPreset},
+    scan_queue::ScanPriority,
+    scan_templates,
+    scanners::ScannerManager,
+    scans::{Scan, ScanGroup},
+    self_test, AssetsDir, PublicUrl,
+};
+
+// Exit codes for scripts. Usage errors match clap's own, the rest follow sysexits.h
+const EXIT_OK: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_DATA_ERR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_UNAVAILABLE: i32 = 69;
+const EXIT_CANT_CREATE: i32 = 73;
+const EXIT_TEMP_FAIL: i32 = 75;
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Scan a page without going through the HTTP API, then print the scan id and path
+    Scan {
+        /// SANE device name, as listed by `scanimage --list-devices`
+        #[arg(long, required_unless_present = "preset")]
+        device: Option<String>,
+        /// Scan preset to start from, by id or name. `--device` and `--param`
+        /// override its device and parameters.
+        #[arg(long, value_name = "ID|NAME")]
+        preset: Option<String>,
+        /// Option passed to scanimage, e.g. `--param --resolution=300`
+        #[arg(long = "param", value_name = "KEY=VALUE", allow_hyphen_values = true)]
+        params: Vec<String>,
+        /// Title of the group to add the scan to, created if it doesn't exist
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Export a group's completed scans to a file, or every finalized group
+    /// picked by --unexported or --since into a directory, for a nightly cron
+    /// job.
+    ///
+    /// Exit codes: 0 exported, 2 bad arguments, 65 nothing exportable or an
+    /// unreadable scan, 66 no such group, 69 OCR unavailable for an EPUB,
+    /// 73 could not write the output, 75 scans still pending (retry later).
+    /// With several groups, each is tried and the first failure's code is
+    /// returned.
+    Export {
+        #[arg(long, required_unless_present_any = ["unexported", "since"])]
+        group: Option<i32>,
+        /// Finalized groups that have never been exported
+        #[arg(long, conflicts_with = "group")]
+        unexported: bool,
+        /// Finalized groups changed on or after this day, e.g. 2024-05-01
+        #[arg(long, value_name = "YYYY-MM-DD", conflicts_with = "group")]
+        since: Option<NaiveDate>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Pdf)]
+        format: ExportFormat,
+        /// The file to write, or with --unexported or --since the directory
+        /// to write `group_<id>.<format>` files to
+        #[arg(long)]
+        out: PathBuf,
+        /// Thumbnails on each page of a contact sheet
+        #[arg(long, default_value_t = contact_sheet::DEFAULT_THUMBNAILS_PER_PAGE)]
+        per_page: usize,
+        /// Stamp a QR code linking to the group on the first page of a PDF
+        #[arg(long)]
+        stamp_qr: bool,
+        /// Render again even if the group hasn't changed since an earlier export
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the database, assets directory, scanimage, tesseract and export
+    /// destinations, printing one line per check.
+    ///
+    /// Exit codes: 0 nothing failed, 1 a check failed.
+    SelfTest,
+    /// Run scans through the mock scanner and export them, on an in-memory
+    /// database, then print throughput and latency percentiles.
+    ///
+    /// Exit codes: 0 nothing failed, 1 a scan or export failed, 2 bad arguments.
+    Loadtest(LoadtestOptions),
+    /// Decode an image read from stdin; run by the server for untrusted files
+    #[command(hide = true)]
+    DecodeWorker {
+        /// Images larger than this are rejected without decoding
+        #[arg(long)]
+        max_pixels: u64,
+    },
+}
+
+/// Runs a subcommand to completion and returns the process exit code.
+pub async fn run(
+    command: Command,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> i32 {
+    match command {
+        Command::Scan {
+            device,
+            preset,
+            params,
+            group,
+        } => scan(device, preset, params, group, pool, assets_dir).await,
+        Command::Export {
+            group,
+            unexported,
+            since,
+            format,
+            out,
+            per_page,
+            stamp_qr,
+            force,
+        } => {
+            let options = ExportOptions {
+                format,
+                thumbnails_per_page: per_page,
+                stamp_qr,
+                public_url: public_url.clone(),
+                triggered_by: invoked_by(),
+                force,
+            };
+            match group {
+                Some(group) => export(group, &options, &out, pool, assets_dir),
+                None => export_many(unexported, since, &options, &out, pool, assets_dir),
+            }
+        }
+        Command::SelfTest => self_test(pool, assets_dir),
+        Command::Loadtest(options) => loadtest(options).await,
+        Command::DecodeWorker { max_pixels } => sandbox::run_worker(max_pixels),
+    }
+}
+
+fn self_test(pool: &r2d2::Pool<DuckdbConnectionManager>, assets_dir: &AssetsDir) -> i32 {
+    let report = self_test::run(pool, assets_dir);
+    for check in &report.checks {
+        println!(
+            "{} {}: {}",
+            check.outcome.as_str(),
+            check.name,
+            check.detail
+        );
+    }
+    if report.passed {
+        EXIT_OK
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+/// Runs without the server's database; see `Command::Loadtest`.
+pub async fn loadtest(options: LoadtestOptions) -> i32 {
+    if options.concurrency == 0 || options.pages_per_group == 0 {
+        eprintln!("--concurrency and --pages-per-group must be at least 1");
+        return EXIT_USAGE;
+    }
+    if loadtest::run(options).await {
+        EXIT_OK
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+/// Credited with scans and exports run from the command line.
+fn invoked_by() -> String {
+    match env::var("USER") {
+        Ok(user) => format!("cli ({})", user),
+        Err(_) => "cli".to_string(),
+    }
+}
+
+/// A preset by id, or by name among those for `device` if one is given.
+fn find_preset(
+    key: &str,
+    device: Option<&str>,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Result<ScanPreset, String> {
+    if let Ok(id) = key.parse() {
+        return ScanPreset::load(id, pool).map_err(|_| format!("No scan preset {}", id));
+    }
+    let mut presets: Vec<ScanPreset> = ScanPreset::load_all(device, pool)
+        .into_iter()
+        .filter(|preset| preset.name == key)
+        .collect();
+    match presets.len() {
+        0 => Err(format!("No scan preset named {:?}", key)),
+        1 => Ok(presets.remove(0)),
+        _ => Err(format!(
+            "Several scan presets are named {:?}, give its id or --device: {}",
+            key,
+            presets
+                .iter()
+                .map(|preset| format!("{} ({})", preset.id, preset.scanner))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+async fn scan(
+    device: Option<String>,
+    preset: Option<String>,
+    params: Vec<String>,
+    group: Option<String>,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> i32 {
+    let mut parameters = HashMap::new();
+    for param in params {
+        match param.split_once('=') {
+            Some((key, value)) => parameters.insert(key.to_string(), value.to_string()),
+            None => {
+                eprintln!("Invalid --param {:?}, expected KEY=VALUE", param);
+                return EXIT_USAGE;
+            }
+        };
+    }
+
+    let preset = match preset.map(|key| find_preset(&key, device.as_deref(), pool)) {
+        Some(Ok(preset)) => Some(preset),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+        None => None,
+    };
+    let Some((device, parameters)) = scan_presets::scan_settings(preset, device, parameters) else {
+        eprintln!("Give --device or --preset");
+        return EXIT_USAGE;
+    };
+    // Presets can hold `{dpi}` and the like, filled in as the API does
+    let parameters = match scan_templates::resolve(&parameters, None, &[]) {
+        Ok(parameters) => parameters,
+        Err(e) => {
+            eprintln!("Invalid scan parameters: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let group_id = group.map(|title| ScanGroup::find_or_create_by_title(&title, pool).unwrap().id);
+
+    let scan = Scan::create_pending(
+        &device,
+        &parameters,
+        group_id,
+        Some(invoked_by()),
+        pool,
+        assets_dir,
+    )
+    .unwrap();
+    let scan_id = ScannerManager::new()
+        .complete_scan(
+            scan.id.unwrap(),
+            &device,
+            parameters,
+            ScanPriority::Normal,
+            pool,
+            assets_dir,
+        )
+        .await;
+
+    let scan = Scan::load(scan_id, pool).unwrap();
+    println!("{} {}", scan_id, scan.path.as_disk_path(&assets_dir.0));
+
+    if scan.status == "COMPLETE" {
+        EXIT_OK
+    } else {
+        eprintln!("Scan {} finished with status {}", scan_id, scan.status);
+        EXIT_FAILURE
+    }
+}
+
+fn export(
+    group_id: i32,
+    options: &ExportOptions,
+    out: &Path,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> i32 {
+    let group = match ScanGroup::load(group_id, pool) {
+        Ok(group) => group,
+        Err(_) => {
+            eprintln!("Group {} not found", group_id);
+            return EXIT_NO_INPUT;
+        }
+    };
+    if has_pending_scans(&group) {
+        eprintln!("Group {} still has pending scans", group_id);
+        return EXIT_TEMP_FAIL;
+    }
+
+    if let Some(warning) = &group.page_count_warning {
+        eprintln!("Warning: {}", warning);
+    }
+
+    match export_group(group_id, options, out, pool, assets_dir) {
+        Ok(export) => {
+            if let Some(changes) = export.changes {
+                eprintln!("Warning: {}", changes);
+            }
+            println!("{} {} pages", out.display(), export.pages.unwrap_or(0));
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Export of group {} failed: {}", group_id, e);
+            match e {
+                ExportError::GroupNotFound => EXIT_NO_INPUT,
+                ExportError::NoPages | ExportError::Image(_) => EXIT_DATA_ERR,
+                ExportError::Ocr(_) => EXIT_UNAVAILABLE,
+                ExportError::Io(_) => EXIT_CANT_CREATE,
+            }
+        }
+    }
+}
+
+/// Exports each finalized group picked by `unexported` and `since` to its
+/// own file in `out_dir`. Groups with scans still pending are left for the
+/// next run.
+fn export_many(
+    unexported: bool,
+    since: Option<NaiveDate>,
+    options: &ExportOptions,
+    out_dir: &Path,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> i32 {
+    if let Err(e) = std::fs::create_dir_all(out_dir) {
+        eprintln!("Could not create {}: {}", out_dir.display(), e);
+        return EXIT_CANT_CREATE;
+    }
+    let since = since.map(|day| locale::day_bounds(day).0);
+    let group_ids = ScanGroup::finalized_ids(unexported, since, pool).unwrap();
+    if group_ids.is_empty() {
+        eprintln!("No groups to export");
+    }
+
+    let mut exit_code = EXIT_OK;
+    for group_id in group_ids {
+        let file_name = match options.format.extension() {
+            "" => format!("group_{}", group_id),
+            extension => format!("group_{}.{}", group_id, extension),
+        };
+        let code = export(
+            group_id,
+            options,
+            &out_dir.join(file_name),
+            pool,
+            assets_dir,
+        );
+        if exit_code == EXIT_OK {
+            exit_code = code;
+        }
+    }
+    exit_code
+}
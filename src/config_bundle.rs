@@ -0,0 +1,167 @@
+use std::fmt;
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    classification::{ClassificationRule, ClassificationRuleInput},
+    destination_mappings::DestinationMapping,
+    ingest_rules::{IngestRule, IngestRuleInput},
+    scan_templates::TemplateError,
+};
+
+/// Bumped when a bundle's shape changes in a way older servers can't read.
+const BUNDLE_VERSION: u32 = 1;
+
+/// A server's filing setup as JSON, to provision a replacement or a second
+/// location from. Rules carry their export destinations, and the metadata
+/// mapped for each destination comes along. Credentials such as API keys,
+/// users and the IMAP password aren't included and have to be entered
+/// again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigBundle {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    #[serde(default)]
+    pub ingest_rules: Vec<IngestRuleInput>,
+    #[serde(default)]
+    pub classification_rules: Vec<ClassificationRuleInput>,
+    #[serde(default)]
+    pub destination_mappings: Vec<DestinationMapping>,
+}
+
+/// What importing a bundle changed.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ConfigImport {
+    pub ingest_rules_created: i32,
+    pub classification_rules_created: i32,
+    /// Destination metadata mappings saved, replacing any for the same
+    /// destination
+    pub destination_mappings_set: i32,
+    /// Rules left out because an identical one already exists
+    pub skipped: i32,
+    /// Rules and mappings deleted first, when importing with `replace`
+    pub deleted: i32,
+}
+
+#[derive(Debug)]
+pub enum ConfigBundleError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    Mapping(String, TemplateError),
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for ConfigBundleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigBundleError::Json(e) => write!(f, "not a valid config bundle: {}", e),
+            ConfigBundleError::UnsupportedVersion(version) => write!(
+                f,
+                "config bundle version {} is newer than this server supports ({})",
+                version, BUNDLE_VERSION
+            ),
+            ConfigBundleError::Mapping(destination, e) => {
+                write!(f, "metadata mapping for {}: {}", destination, e)
+            }
+            ConfigBundleError::Db(e) => write!(f, "could not save rules: {}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for ConfigBundleError {
+    fn from(e: duckdb::Error) -> Self {
+        ConfigBundleError::Db(e)
+    }
+}
+
+impl ConfigBundle {
+    pub fn export(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Self {
+        ConfigBundle {
+            version: BUNDLE_VERSION,
+            exported_at: Utc::now(),
+            ingest_rules: IngestRule::load_all(pool)
+                .iter()
+                .map(IngestRule::to_input)
+                .collect(),
+            classification_rules: ClassificationRule::load_all(pool)
+                .iter()
+                .map(ClassificationRule::to_input)
+                .collect(),
+            destination_mappings: DestinationMapping::load_all(pool),
+        }
+    }
+
+    /// Creates the bundle's rules, skipping any identical to one already on
+    /// the server. With `replace`, the server's rules are deleted first.
+    /// Classification rules keep the bundle's order, which is the order
+    /// they are applied in. Destination mappings replace the server's
+    /// mapping for the same destination.
+    pub fn import(
+        json: &str,
+        replace: bool,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<ConfigImport, ConfigBundleError> {
+        let bundle: ConfigBundle = serde_json::from_str(json).map_err(ConfigBundleError::Json)?;
+        if bundle.version > BUNDLE_VERSION {
+            return Err(ConfigBundleError::UnsupportedVersion(bundle.version));
+        }
+        for mapping in &bundle.destination_mappings {
+            DestinationMapping::validate(&mapping.fields)
+                .map_err(|e| ConfigBundleError::Mapping(mapping.destination.clone(), e))?;
+        }
+
+        let mut deleted = 0;
+        if replace {
+            let conn = pool.get().unwrap();
+            deleted += conn.execute("DELETE FROM ingest_rules", params![])?;
+            deleted += conn.execute("DELETE FROM classification_rules", params![])?;
+            deleted += conn.execute("DELETE FROM destination_mappings", params![])?;
+        }
+
+        let mut import = ConfigImport {
+            ingest_rules_created: 0,
+            classification_rules_created: 0,
+            destination_mappings_set: 0,
+            skipped: 0,
+            deleted: deleted as i32,
+        };
+
+        let mut existing: Vec<IngestRuleInput> = IngestRule::load_all(pool)
+            .iter()
+            .map(IngestRule::to_input)
+            .collect();
+        for input in bundle.ingest_rules {
+            if existing.contains(&input) {
+                import.skipped += 1;
+                continue;
+            }
+            existing.push(IngestRule::create(input, pool)?.to_input());
+            import.ingest_rules_created += 1;
+        }
+
+        let mut existing: Vec<ClassificationRuleInput> = ClassificationRule::load_all(pool)
+            .iter()
+            .map(ClassificationRule::to_input)
+            .collect();
+        for input in bundle.classification_rules {
+            if existing.contains(&input) {
+                import.skipped += 1;
+                continue;
+            }
+            existing.push(ClassificationRule::create(input, pool)?.to_input());
+            import.classification_rules_created += 1;
+        }
+
+        for mapping in bundle.destination_mappings {
+            if DestinationMapping::set(&mapping.destination, mapping.fields, pool)?.is_some() {
+                import.destination_mappings_set += 1;
+            }
+        }
+
+        Ok(import)
+    }
+}
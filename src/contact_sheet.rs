@@ -0,0 +1,180 @@
+use image::{imageops, GrayImage, ImageResult, Luma};
+
+use crate::{
+    bitmap_font::{draw_text, fill, text_width, GLYPH_HEIGHT},
+    qr,
+    scans::{Scan, ScanGroup},
+    AssetsDir, PublicUrl,
+};
+
+/// Sheets are US letter, rendered at this resolution.
+pub const DPI: f32 = 150.0;
+/// Thumbnails on each sheet unless the export asks for another number.
+pub const DEFAULT_THUMBNAILS_PER_PAGE: usize = 12;
+const PAGE_WIDTH: u32 = 1275;
+const PAGE_HEIGHT: u32 = 1650;
+const MARGIN: u32 = 75;
+/// Space at the top of each sheet for the group's title and QR code.
+const HEADER_HEIGHT: u32 = 170;
+/// Gap between cells and around the contents of each cell.
+const PADDING: u32 = 10;
+
+const PAPER: Luma<u8> = Luma([255]);
+const INK: Luma<u8> = Luma([0]);
+const RULE: Luma<u8> = Luma([160]);
+
+/// Largest font scale that fits `text` in `width`, capped at `max`.
+fn fit_scale(text: &str, width: u32, max: u32) -> u32 {
+    (width / text_width(text, 1).max(1)).clamp(1, max)
+}
+
+/// Draws a one pixel frame around a rectangle.
+fn frame(image: &mut GrayImage, x: u32, y: u32, width: u32, height: u32) {
+    fill(image, x, y, width, 1, RULE);
+    fill(image, x, y + height - 1, width, 1, RULE);
+    fill(image, x, y, 1, height, RULE);
+    fill(image, x + width - 1, y, 1, height, RULE);
+}
+
+/// A QR code for `url` as close to `size` pixels square as whole modules allow.
+fn qr_code(url: &str, size: u32) -> GrayImage {
+    qr::render(url, (size / qr::modules(url)).max(1))
+}
+
+fn header(sheet: &mut GrayImage, group: &ScanGroup, page: usize, pages: usize, url: &str) {
+    let code = qr_code(url, HEADER_HEIGHT - PADDING * 2);
+    let code_x = PAGE_WIDTH - MARGIN - code.width();
+    imageops::overlay(sheet, &code, code_x as i64, MARGIN as i64);
+
+    let text_area = code_x - MARGIN - PADDING;
+    let title = if group.title.is_empty() {
+        "UNTITLED"
+    } else {
+        &group.title
+    };
+    let title_scale = fit_scale(title, text_area, 5);
+    draw_text(sheet, title, MARGIN, MARGIN, title_scale, INK);
+
+    let details = [
+        format!("GROUP #{}", group.id),
+        format!("SHEET {} OF {}", page, pages),
+        url.to_string(),
+    ];
+    let mut y = MARGIN + (GLYPH_HEIGHT + 4) * title_scale;
+    for line in &details {
+        let scale = fit_scale(line, text_area, 3);
+        draw_text(sheet, line, MARGIN, y, scale, INK);
+        y += (GLYPH_HEIGHT + 3) * scale;
+    }
+
+    fill(
+        sheet,
+        MARGIN,
+        MARGIN + HEADER_HEIGHT - 2,
+        PAGE_WIDTH - MARGIN * 2,
+        2,
+        INK,
+    );
+}
+
+/// One cell: the thumbnail above, with the scan's QR code and its id and
+/// position in the group underneath.
+fn cell(
+    sheet: &mut GrayImage,
+    scan: &Scan,
+    position: usize,
+    (x, y, width, height): (u32, u32, u32, u32),
+    public_url: &PublicUrl,
+    assets_dir: &AssetsDir,
+) -> ImageResult<()> {
+    frame(sheet, x, y, width, height);
+
+    let url = public_url.scan_url(scan.id.unwrap());
+    let code = qr_code(&url, (width.min(height) / 3).min(150));
+    let label_y = y + height - PADDING - code.height();
+    imageops::overlay(sheet, &code, (x + PADDING) as i64, label_y as i64);
+
+    let text_x = x + PADDING * 2 + code.width();
+    let text_area = (x + width).saturating_sub(text_x + PADDING);
+    let mut text_y = label_y + PADDING;
+    for line in [
+        format!("#{}", scan.id.unwrap()),
+        format!("PAGE {}", position),
+    ] {
+        let scale = fit_scale(&line, text_area, 3);
+        draw_text(sheet, &line, text_x, text_y, scale, INK);
+        text_y += (GLYPH_HEIGHT + 3) * scale;
+    }
+
+    let thumb_width = width - PADDING * 2;
+    let thumb_height = label_y.saturating_sub(y + PADDING * 2).max(1);
+    let thumbnail = scan
+        .open_image(assets_dir)?
+        .thumbnail(thumb_width, thumb_height)
+        .to_luma8();
+    let thumb_x = x + PADDING + (thumb_width - thumbnail.width()) / 2;
+    let thumb_y = y + PADDING + (thumb_height - thumbnail.height()) / 2;
+    imageops::overlay(sheet, &thumbnail, thumb_x as i64, thumb_y as i64);
+    frame(
+        sheet,
+        thumb_x,
+        thumb_y,
+        thumbnail.width(),
+        thumbnail.height(),
+    );
+
+    Ok(())
+}
+
+/// Renders an index of the group for filing with the paper originals: each
+/// sheet shows up to `per_page` thumbnails in page order, each labelled with
+/// its scan id, its position and a QR code linking to the scan, under a
+/// header with a QR code linking to the group.
+pub fn render(
+    group: &ScanGroup,
+    scans: &[&Scan],
+    per_page: usize,
+    public_url: &PublicUrl,
+    assets_dir: &AssetsDir,
+) -> ImageResult<Vec<GrayImage>> {
+    let per_page = per_page.max(1);
+    let pages = scans.len().div_ceil(per_page);
+    let group_url = public_url.group_url(group.id);
+
+    // As square a grid as the space below the header allows
+    let grid_width = PAGE_WIDTH - MARGIN * 2;
+    let grid_height = PAGE_HEIGHT - MARGIN * 2 - HEADER_HEIGHT - PADDING;
+    let columns = ((per_page as f32 * grid_width as f32 / grid_height as f32)
+        .sqrt()
+        .ceil() as usize)
+        .clamp(1, per_page);
+    let rows = per_page.div_ceil(columns);
+    let cell_width = (grid_width + PADDING) / columns as u32;
+    let cell_height = (grid_height + PADDING) / rows as u32;
+
+    let mut sheets = Vec::new();
+    for (page, chunk) in scans.chunks(per_page).enumerate() {
+        let mut sheet = GrayImage::from_pixel(PAGE_WIDTH, PAGE_HEIGHT, PAPER);
+        header(&mut sheet, group, page + 1, pages, &group_url);
+
+        for (i, scan) in chunk.iter().enumerate() {
+            let (column, row) = ((i % columns) as u32, (i / columns) as u32);
+            let bounds = (
+                MARGIN + column * cell_width,
+                MARGIN + HEADER_HEIGHT + PADDING + row * cell_height,
+                cell_width - PADDING,
+                cell_height - PADDING,
+            );
+            cell(
+                &mut sheet,
+                scan,
+                page * per_page + i + 1,
+                bounds,
+                public_url,
+                assets_dir,
+            )?;
+        }
+        sheets.push(sheet);
+    }
+    Ok(sheets)
+}
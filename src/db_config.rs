@@ -0,0 +1,49 @@
+use duckdb::{params, Config, Connection, Result};
+
+/// Batches that write at least this many rows checkpoint when they finish,
+/// rather than leaving the WAL to grow until DuckDB's threshold is reached.
+pub const BATCH_CHECKPOINT_ROWS: usize = 100;
+
+/// DuckDB settings for constrained devices. Unset fields keep DuckDB's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct DbConfig {
+    /// e.g. "512MB"; DuckDB defaults to 80% of system memory
+    pub memory_limit: Option<String>,
+    /// Where larger-than-memory operations spill to
+    pub temp_directory: Option<String>,
+    /// WAL size that triggers an automatic checkpoint, e.g. "16MB"
+    pub checkpoint_threshold: Option<String>,
+}
+
+impl DbConfig {
+    pub fn to_duckdb_config(&self) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(memory_limit) = &self.memory_limit {
+            config = config.max_memory(memory_limit)?;
+        }
+        if let Some(temp_directory) = &self.temp_directory {
+            config = config.with("temp_directory", temp_directory)?;
+        }
+        if let Some(checkpoint_threshold) = &self.checkpoint_threshold {
+            config = config.with("checkpoint_threshold", checkpoint_threshold)?;
+        }
+        Ok(config)
+    }
+}
+
+/// Flushes the WAL into the database file. Skipped, not failed, when other
+/// transactions are running; DuckDB will checkpoint later on its own.
+pub fn checkpoint(conn: &Connection) {
+    if let Err(e) = conn.execute("CHECKPOINT", params![]) {
+        println!("Skipped checkpoint: {}", e);
+    }
+}
+
+/// The file the database is stored in, as DuckDB opened it.
+pub fn database_path(conn: &Connection) -> Result<String> {
+    conn.query_row(
+        "SELECT path FROM duckdb_databases() WHERE database_name = current_database()",
+        params![],
+        |row| row.get(0),
+    )
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+use crate::scans::Scan;
+
+/// A scan that failed on every attempt it was given. It stays here, with
+/// what went wrong, until someone requeues it.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct DeadLetter {
+    /// The scan that failed; requeueing runs it again under the same id
+    pub job_id: i32,
+    pub scanner: String,
+    pub scan_parameters: HashMap<String, String>,
+    pub attempts: i32,
+    /// scanimage's exit code on the last attempt
+    pub exit_code: i32,
+    /// As recorded on the scan, e.g. EXIT_9
+    pub failure: String,
+    /// What scanimage printed to stderr on the last attempt
+    pub error: String,
+    /// The user or API key that asked for the scan, if auth identified one
+    pub started_by: Option<String>,
+    pub dead_at: DateTime<Utc>,
+    /// Set once it's been sent back to the queue
+    pub requeued_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "scan_id, scanner, scan_parameters, attempts, exit_code, failure, error, started_by, dead_at, requeued_at";
+
+fn row_to_dead_letter(row: &duckdb::Row) -> duckdb::Result<DeadLetter> {
+    Ok(DeadLetter {
+        job_id: row.get(0)?,
+        scanner: row.get(1)?,
+        scan_parameters: serde_json::from_str(&row.get::<usize, String>(2)?).unwrap_or_default(),
+        attempts: row.get(3)?,
+        exit_code: row.get(4)?,
+        failure: row.get(5)?,
+        error: row.get(6)?,
+        started_by: row.get(7)?,
+        dead_at: row.get(8)?,
+        requeued_at: row.get(9)?,
+    })
+}
+
+impl DeadLetter {
+    /// Records a scan that ran out of attempts, replacing its earlier
+    /// dead letter if it had been requeued and failed again.
+    pub fn create(
+        scan: &Scan,
+        attempts: i32,
+        exit_code: i32,
+        failure: &str,
+        error: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO dead_letters (scan_id, scanner, scan_parameters, attempts, exit_code, failure, error, started_by, dead_at, requeued_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, NULL)",
+            params![
+                scan.id,
+                scan.scanner,
+                serde_json::to_string(&scan.scan_parameters).unwrap(),
+                attempts,
+                exit_code,
+                failure,
+                error,
+                scan.started_by,
+                Utc::now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn load(job_id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<DeadLetter> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM dead_letters WHERE scan_id = ?", COLUMNS),
+            params![job_id],
+            row_to_dead_letter,
+        )
+    }
+
+    /// Newest first. Requeued ones are left out unless asked for.
+    pub fn load_all(
+        include_requeued: bool,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<DeadLetter> {
+        let conn = pool.get().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM dead_letters WHERE ? OR requeued_at IS NULL ORDER BY dead_at DESC",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let dead_letters: Vec<DeadLetter> = stmt
+            .query_map(params![include_requeued], row_to_dead_letter)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        dead_letters
+    }
+
+    pub fn mark_requeued(job_id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE dead_letters SET requeued_at = ? WHERE scan_id = ?",
+            params![Utc::now(), job_id],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager, OptionalExt, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    locale,
+    scan_templates::{substitute, TemplateError},
+    scans::ScanGroup,
+    PublicUrl,
+};
+
+/// Placeholders a metadata template may use.
+const VARIABLES: &[&str] = &[
+    "id", "title", "comment", "status", "tags", "created", "updated", "pages", "url",
+];
+
+/// One key of the metadata written for a destination, e.g. `correspondent`
+/// from `{title}`.
+#[derive(SimpleObject, InputObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[graphql(input_name = "MetadataFieldInput")]
+pub struct MetadataField {
+    pub key: String,
+    /// `{id}`, `{title}`, `{comment}`, `{status}`, `{tags}`, `{created}`,
+    /// `{updated}`, `{pages}` and `{url}` are filled in from the group. A
+    /// template of exactly `{tags}` gives a list rather than a string.
+    pub template: String,
+}
+
+/// Metadata written beside each export to a destination directory, as
+/// `group_<id>.json` next to the PDF, shaped for whatever picks the files
+/// up there: a paperless post-consume script, a Nextcloud tagging flow, an
+/// S3 sync or a mail-out.
+#[derive(SimpleObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DestinationMapping {
+    /// The export directory, as given in ingest and classification rules
+    pub destination: String,
+    pub fields: Vec<MetadataField>,
+    #[graphql(skip)]
+    #[serde(skip)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Trailing slashes don't make a different directory.
+fn normalize(destination: &str) -> &str {
+    match destination.trim_end_matches('/') {
+        "" => destination,
+        trimmed => trimmed,
+    }
+}
+
+fn row_to_mapping(row: &duckdb::Row) -> duckdb::Result<DestinationMapping> {
+    let fields_json: String = row.get(1)?;
+    Ok(DestinationMapping {
+        destination: row.get(0)?,
+        fields: serde_json::from_str(&fields_json).unwrap_or_default(),
+        updated_at: row.get(2)?,
+    })
+}
+
+impl DestinationMapping {
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<DestinationMapping> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT destination, fields, updated_at FROM destination_mappings
+                 ORDER BY destination",
+            )
+            .unwrap();
+
+        let mappings: Vec<DestinationMapping> = stmt
+            .query_map([], row_to_mapping)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        mappings
+    }
+
+    pub fn find(
+        destination: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<DestinationMapping>> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            "SELECT destination, fields, updated_at FROM destination_mappings
+             WHERE destination = ?",
+            params![normalize(destination)],
+            row_to_mapping,
+        )
+        .optional()
+    }
+
+    /// Checks each template only uses known placeholders.
+    pub fn validate(fields: &[MetadataField]) -> Result<(), TemplateError> {
+        let values: HashMap<&str, String> = VARIABLES
+            .iter()
+            .map(|name| (*name, String::new()))
+            .collect();
+        for field in fields {
+            substitute(&field.template, &values)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the destination's mapping, or removes it when `fields` is
+    /// empty. Returns the mapping saved, if any.
+    pub fn set(
+        destination: &str,
+        fields: Vec<MetadataField>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<DestinationMapping>> {
+        let conn = pool.get().unwrap();
+        let destination = normalize(destination);
+        if fields.is_empty() {
+            conn.execute(
+                "DELETE FROM destination_mappings WHERE destination = ?",
+                params![destination],
+            )?;
+            return Ok(None);
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO destination_mappings (destination, fields, updated_at)
+             VALUES (?, ?, ?)",
+            params![
+                destination,
+                serde_json::to_string(&fields).unwrap(),
+                Utc::now()
+            ],
+        )?;
+        Self::find(destination, pool)
+    }
+
+    /// The metadata for the group, as a JSON object.
+    pub fn render(&self, group: &ScanGroup, public_url: &PublicUrl) -> serde_json::Value {
+        let pages = group
+            .scans
+            .iter()
+            .filter(|scan| scan.status == "COMPLETE")
+            .count();
+        let values: HashMap<&str, String> = HashMap::from([
+            ("id", group.id.to_string()),
+            ("title", group.title.clone()),
+            ("comment", group.comment.clone()),
+            ("status", group.status.clone()),
+            ("tags", group.tags.join(", ")),
+            ("created", locale::format_date(group.created_at)),
+            ("updated", locale::format_date(group.updated_at)),
+            ("pages", pages.to_string()),
+            ("url", public_url.group_url(group.id)),
+        ]);
+
+        let mut metadata = serde_json::Map::new();
+        for field in &self.fields {
+            let value = if field.template.trim() == "{tags}" {
+                serde_json::json!(group.tags)
+            } else {
+                // Checked when the mapping was saved
+                substitute(&field.template, &values)
+                    .unwrap_or_else(|_| field.template.clone())
+                    .into()
+            };
+            metadata.insert(field.key.clone(), value);
+        }
+        serde_json::Value::Object(metadata)
+    }
+
+    /// Writes the group's metadata beside its export at `out`.
+    pub fn write_sidecar(
+        &self,
+        group: &ScanGroup,
+        out: &Path,
+        public_url: &PublicUrl,
+    ) -> io::Result<()> {
+        let metadata = self.render(group, public_url);
+        fs::write(
+            out.with_extension("json"),
+            serde_json::to_string_pretty(&metadata).unwrap(),
+        )
+    }
+}
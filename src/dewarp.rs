@@ -0,0 +1,260 @@
+use image::{imageops, DynamicImage, GrayImage, Rgb, RgbImage};
+
+/// Vertical strips the page is cut into to follow its text lines.
+const STRIPS: u32 = 32;
+/// Pages are analysed at about this height; the correction is applied at full size.
+const ANALYSIS_HEIGHT: u32 = 1000;
+/// Strips with less ink than this fraction of their area are margins or
+/// pictures and don't say anything about line curvature.
+const MIN_INK: f32 = 0.01;
+/// Below this much bend, in analysis pixels, the page is left as it is.
+const MIN_DISPLACEMENT: f32 = 1.5;
+
+/// Gray level separating ink from paper, by Otsu's method.
+pub fn ink_threshold(gray: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total = gray.len() as f64;
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(level, count)| level as f64 * *count as f64)
+        .sum();
+
+    let (mut best, mut best_variance) = (128, 0.0);
+    let (mut background, mut background_sum) = (0.0, 0.0);
+    for (level, count) in histogram.iter().enumerate() {
+        background += *count as f64;
+        background_sum += level as f64 * *count as f64;
+        let foreground = total - background;
+        if background == 0.0 || foreground == 0.0 {
+            continue;
+        }
+        let difference = background_sum / background - (sum - background_sum) / foreground;
+        let variance = background * foreground * difference * difference;
+        if variance > best_variance {
+            best_variance = variance;
+            best = level as u8;
+        }
+    }
+    best
+}
+
+/// Ink per row in each strip.
+fn strip_profiles(gray: &GrayImage, threshold: u8) -> Vec<Vec<f32>> {
+    let strip_width = gray.width() / STRIPS;
+    (0..STRIPS)
+        .map(|strip| {
+            (0..gray.height())
+                .map(|y| {
+                    (strip * strip_width..(strip + 1) * strip_width)
+                        .filter(|x| gray.get_pixel(*x, y)[0] <= threshold)
+                        .count() as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// How far `b` is shifted down relative to `a`, within `range` of `around`.
+fn profile_shift(a: &[f32], b: &[f32], around: i32, range: i32) -> i32 {
+    let mut best = (around, f32::MIN);
+    for shift in around - range..=around + range {
+        let score: f32 = a
+            .iter()
+            .enumerate()
+            .filter_map(|(y, ink)| {
+                let y = y as i32 + shift;
+                (y >= 0 && (y as usize) < b.len()).then(|| ink * b[y as usize])
+            })
+            .sum();
+        if score > best.1 {
+            best = (shift, score);
+        }
+    }
+    best.0
+}
+
+/// Terms in the curve fitted to the line displacement: a cubic, enough for
+/// the steep rise into the spine.
+const CURVE_TERMS: usize = 4;
+
+type Curve = [f32; CURVE_TERMS];
+
+fn evaluate(curve: &Curve, x: f32) -> f32 {
+    curve.iter().rev().fold(0.0, |y, c| y * x + c)
+}
+
+/// Weighted least squares polynomial fit to `(x, y, weight)` points.
+fn fit_curve(points: &[(f32, f32, f32)]) -> Option<Curve> {
+    // Normal equations as an augmented matrix, solved by Gaussian elimination
+    let mut m = [[0f64; CURVE_TERMS + 1]; CURVE_TERMS];
+    for (x, y, w) in points {
+        let powers: Vec<f64> = (0..CURVE_TERMS)
+            .map(|i| (*x as f64).powi(i as i32))
+            .collect();
+        for i in 0..CURVE_TERMS {
+            for j in 0..CURVE_TERMS {
+                m[i][j] += *w as f64 * powers[i] * powers[j];
+            }
+            m[i][CURVE_TERMS] += *w as f64 * powers[i] * *y as f64;
+        }
+    }
+
+    for col in 0..CURVE_TERMS {
+        let pivot =
+            (col..CURVE_TERMS).max_by(|a, b| m[*a][col].abs().total_cmp(&m[*b][col].abs()))?;
+        if m[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        m.swap(col, pivot);
+        let pivot_row = m[col];
+        for (row, values) in m.iter_mut().enumerate() {
+            if row != col {
+                let factor = values[col] / pivot_row[col];
+                for (value, p) in values.iter_mut().zip(pivot_row).skip(col) {
+                    *value -= factor * p;
+                }
+            }
+        }
+    }
+
+    let mut curve = [0f32; CURVE_TERMS];
+    for (i, c) in curve.iter_mut().enumerate() {
+        *c = (m[i][CURVE_TERMS] / m[i][i]) as f32;
+    }
+    Some(curve)
+}
+
+/// Vertical displacement of the text lines across the page, as a curve in
+/// full size pixels over x from 0 to 1. None if the page looks flat or has
+/// too little text to tell.
+fn line_displacement(image: &DynamicImage) -> Option<Curve> {
+    let scale = (image.height() as f32 / ANALYSIS_HEIGHT as f32).max(1.0);
+    let gray = imageops::resize(
+        &image.to_luma8(),
+        (image.width() as f32 / scale) as u32,
+        (image.height() as f32 / scale) as u32,
+        imageops::FilterType::Triangle,
+    );
+    if gray.width() < STRIPS * 4 {
+        return None;
+    }
+
+    let profiles = strip_profiles(&gray, ink_threshold(&gray));
+    let strip_area = (gray.width() / STRIPS * gray.height()) as f32;
+    let ink: Vec<f32> = profiles.iter().map(|p| p.iter().sum::<f32>()).collect();
+
+    // Follow the lines outwards from the inkiest strip, each strip compared
+    // with its neighbour so the shift can't jump to a different line
+    let reference = (0..STRIPS as usize).max_by(|a, b| ink[*a].total_cmp(&ink[*b]))?;
+    if ink[reference] < strip_area * MIN_INK {
+        return None;
+    }
+    let range = (gray.height() as i32 / 200).max(2);
+    let mut shifts = vec![0i32; STRIPS as usize];
+    let mut track = |strips: &mut dyn Iterator<Item = usize>| {
+        let mut previous = reference;
+        for strip in strips {
+            shifts[strip] = shifts[previous];
+            if ink[strip] >= strip_area * MIN_INK {
+                shifts[strip] += profile_shift(&profiles[previous], &profiles[strip], 0, range);
+                previous = strip;
+            }
+        }
+    };
+    track(&mut (reference + 1..STRIPS as usize));
+    track(&mut (0..reference).rev());
+
+    let points: Vec<(f32, f32, f32)> = (0..STRIPS as usize)
+        .filter(|strip| ink[*strip] >= strip_area * MIN_INK)
+        .map(|strip| {
+            (
+                (strip as f32 + 0.5) / STRIPS as f32,
+                shifts[strip] as f32,
+                ink[strip],
+            )
+        })
+        .collect();
+    // Too few strips with text to pin the curve down
+    if points.len() < CURVE_TERMS * 2 {
+        return None;
+    }
+    let curve = fit_curve(&points)?;
+
+    let samples = (0..=STRIPS).map(|i| evaluate(&curve, i as f32 / STRIPS as f32));
+    let (low, high) = samples.fold((f32::MAX, f32::MIN), |(low, high), y| {
+        (low.min(y), high.max(y))
+    });
+    if high - low < MIN_DISPLACEMENT {
+        return None;
+    }
+
+    Some(curve.map(|c| c * scale))
+}
+
+/// Bilinear sample, clamped to the image.
+pub fn sample(image: &RgbImage, x: f32, y: f32) -> Rgb<u8> {
+    let x = x.clamp(0.0, (image.width() - 1) as f32);
+    let y = y.clamp(0.0, (image.height() - 1) as f32);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width() - 1),
+        (y0 + 1).min(image.height() - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let mut out = [0u8; 3];
+    for (c, channel) in out.iter_mut().enumerate() {
+        let top =
+            image.get_pixel(x0, y0)[c] as f32 * (1.0 - fx) + image.get_pixel(x1, y0)[c] as f32 * fx;
+        let bottom =
+            image.get_pixel(x0, y1)[c] as f32 * (1.0 - fx) + image.get_pixel(x1, y1)[c] as f32 * fx;
+        *channel = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgb(out)
+}
+
+/// Flattens a book page that curves away from the glass towards the spine.
+///
+/// Models the page as a cylinder: the bend of the text lines gives its
+/// height, lines are straightened by shifting each column back, and the
+/// squashed part near the spine is stretched out to its length along the
+/// curve. Pages without enough text to follow are returned unchanged.
+pub fn dewarp(image: DynamicImage) -> DynamicImage {
+    let Some(curve) = line_displacement(&image) else {
+        return image;
+    };
+    let source = image.to_rgb8();
+    let (width, height) = source.dimensions();
+    let displacement = |x: f32| evaluate(&curve, x / width as f32);
+
+    // Distance along the page surface to each source column
+    let mut arc = Vec::with_capacity(width as usize);
+    let mut length = 0.0;
+    for x in 0..width {
+        let slope = displacement(x as f32 + 0.5) - displacement(x as f32 - 0.5);
+        length += (1.0 + slope * slope).sqrt();
+        arc.push(length);
+    }
+
+    // Lines are moved up to where they sit on the flattest part of the page
+    let flattest = (0..width)
+        .map(|x| displacement(x as f32))
+        .fold(f32::MAX, f32::min);
+
+    let mut output = RgbImage::new(length.round() as u32, height);
+    let mut x = 0;
+    for u in 0..output.width() {
+        while x + 1 < arc.len() && arc[x] < u as f32 + 1.0 {
+            x += 1;
+        }
+        let shift = displacement(x as f32) - flattest;
+        for v in 0..height {
+            output.put_pixel(u, v, sample(&source, x as f32, v as f32 + shift));
+        }
+    }
+    DynamicImage::ImageRgb8(output)
+}
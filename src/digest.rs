@@ -0,0 +1,158 @@
+use std::{env, time::Duration as StdDuration};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, TimeZone, Utc, Weekday};
+use duckdb::{params, DuckdbConnectionManager};
+
+/// When the weekly digest goes out, in the server's local time.
+#[derive(Debug, Clone)]
+pub struct DigestConfig {
+    pub weekday: Weekday,
+    pub hour: u32,
+}
+
+impl DigestConfig {
+    /// From `DIGEST_DAY` (e.g. `sat`) and `DIGEST_HOUR` (0-23, default 9).
+    /// None if `DIGEST_DAY` isn't set, which turns the digest off.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(day) = env::var("DIGEST_DAY").ok().filter(|v| !v.is_empty()) else {
+            return Ok(None);
+        };
+        let weekday = day
+            .parse::<Weekday>()
+            .map_err(|_| format!("DIGEST_DAY must be a day of the week, not {}", day))?;
+        let hour = env::var("DIGEST_HOUR")
+            .ok()
+            .map(|v| v.parse::<u32>().ok().filter(|hour| *hour < 24))
+            .unwrap_or(Some(9))
+            .ok_or("DIGEST_HOUR must be an hour from 0 to 23")?;
+        Ok(Some(DigestConfig { weekday, hour }))
+    }
+
+    /// How long until the next digest is due.
+    pub fn until_next(&self, now: DateTime<Local>) -> StdDuration {
+        let time = NaiveTime::from_hms_opt(self.hour, 0, 0).unwrap();
+        let days_ahead = (7 + self.weekday.num_days_from_monday() as i64
+            - now.weekday().num_days_from_monday() as i64)
+            % 7;
+        let mut next = (now.date_naive() + Duration::days(days_ahead)).and_time(time);
+        if next <= now.naive_local() {
+            next += Duration::days(7);
+        }
+        // On a DST change the hour may not exist; an hour later will do
+        let next = Local
+            .from_local_datetime(&next)
+            .earliest()
+            .unwrap_or_else(|| now + Duration::hours(1));
+        (next - now).to_std().unwrap_or_default()
+    }
+}
+
+/// The week's scanning, and what's still waiting on someone. Published to
+/// `digest` subscribers when it's made, and kept.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct WeeklyDigest {
+    pub id: i32,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub scans_completed: i32,
+    pub groups_finalized: i32,
+    pub exports_delivered: i32,
+    pub comments: i32,
+    /// Pages no one has reviewed yet
+    pub unreviewed_pages: i32,
+    /// Pages a reviewer sent back to be scanned again
+    pub needs_rescan_pages: i32,
+    /// Groups not yet finalized
+    pub open_groups: i32,
+    /// Scans that failed every attempt and haven't been requeued
+    pub dead_letters: i32,
+}
+
+const COLUMNS: &str = "id, period_start, period_end, scans_completed, groups_finalized, exports_delivered, comments, unreviewed_pages, needs_rescan_pages, open_groups, dead_letters";
+
+fn row_to_digest(row: &duckdb::Row) -> duckdb::Result<WeeklyDigest> {
+    Ok(WeeklyDigest {
+        id: row.get(0)?,
+        period_start: row.get(1)?,
+        period_end: row.get(2)?,
+        scans_completed: row.get(3)?,
+        groups_finalized: row.get(4)?,
+        exports_delivered: row.get(5)?,
+        comments: row.get(6)?,
+        unreviewed_pages: row.get(7)?,
+        needs_rescan_pages: row.get(8)?,
+        open_groups: row.get(9)?,
+        dead_letters: row.get(10)?,
+    })
+}
+
+impl WeeklyDigest {
+    /// Summarizes the seven days up to now from the activity feed and
+    /// saves it.
+    pub fn create(pool: &r2d2::Pool<DuckdbConnectionManager>) -> duckdb::Result<WeeklyDigest> {
+        let conn = pool.get().unwrap();
+        let period_end = Utc::now();
+        let period_start = period_end - Duration::days(7);
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO digests (period_start, period_end, scans_completed, groups_finalized, exports_delivered, comments, unreviewed_pages, needs_rescan_pages, open_groups, dead_letters)
+             SELECT ?::TIMESTAMP, ?::TIMESTAMP,
+                 (SELECT count(*) FROM activity WHERE kind = 'scan_completed' AND created_at >= ?::TIMESTAMP),
+                 (SELECT count(*) FROM activity WHERE kind = 'group_finalized' AND created_at >= ?::TIMESTAMP),
+                 (SELECT count(*) FROM activity WHERE kind = 'export_delivered' AND created_at >= ?::TIMESTAMP),
+                 (SELECT count(*) FROM activity WHERE kind = 'comment' AND created_at >= ?::TIMESTAMP),
+                 (SELECT count(*) FROM scans WHERE status = 'COMPLETE' AND COALESCE(review_state, 'UNREVIEWED') = 'UNREVIEWED'),
+                 (SELECT count(*) FROM scans WHERE review_state = 'NEEDS_RESCAN'),
+                 (SELECT count(*) FROM scan_groups WHERE status != 'finalized'),
+                 (SELECT count(*) FROM dead_letters WHERE requeued_at IS NULL)
+             RETURNING id",
+            params![
+                period_start,
+                period_end,
+                period_start,
+                period_start,
+                period_start,
+                period_start
+            ],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(
+        id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> duckdb::Result<WeeklyDigest> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM digests WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_digest,
+        )
+    }
+
+    /// Newest first.
+    pub fn load_recent(
+        limit: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<WeeklyDigest> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM digests ORDER BY period_end DESC, id DESC LIMIT ?",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let digests: Vec<WeeklyDigest> = stmt
+            .query_map([limit], row_to_digest)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        digests
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use duckdb::DuckdbConnectionManager;
+
+use crate::{
+    ingest::{self, IncomingDocument, IngestError},
+    ingest_rules::IngestSource,
+    locale, AssetsDir, PublicUrl,
+};
+
+/// Processed files are moved here, inside the drop folder
+const IMPORTED_DIR: &str = ".imported";
+
+/// A directory that scan-to-FTP devices upload into, usually the root of an
+/// FTP(S) server such as vsftpd. Files directly in it, or in a subdirectory
+/// per FTP user, are each imported as a group.
+#[derive(Clone)]
+pub struct DropFolderConfig {
+    pub dir: PathBuf,
+    /// Files modified more recently than this may still be uploading
+    pub settle: Duration,
+}
+
+/// A file waiting in the drop folder, with the FTP user it came from when it
+/// is in a user's subdirectory.
+struct DroppedFile {
+    path: PathBuf,
+    user: Option<String>,
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_none_or(|name| name.starts_with('.'))
+}
+
+/// Settled files in the drop folder and its user subdirectories. Hidden
+/// files are skipped, since FTP servers upload into those before renaming.
+fn dropped_files(config: &DropFolderConfig) -> std::io::Result<Vec<DroppedFile>> {
+    let settled = |path: &Path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    >= config.settle
+            })
+            .unwrap_or(false)
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(&config.dir)? {
+        let path = entry?.path();
+        if is_hidden(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            let user = path.file_name().unwrap().to_string_lossy().to_string();
+            for entry in fs::read_dir(&path)? {
+                let path = entry?.path();
+                if path.is_file() && !is_hidden(&path) && settled(&path) {
+                    files.push(DroppedFile {
+                        path,
+                        user: Some(user.clone()),
+                    });
+                }
+            }
+        } else if settled(&path) {
+            files.push(DroppedFile { path, user: None });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Moves a processed file under the imported directory, keeping the user's
+/// subdirectory and prefixing the time so repeated names don't collide.
+fn archive(config: &DropFolderConfig, file: &DroppedFile) -> std::io::Result<()> {
+    let mut dir = config.dir.join(IMPORTED_DIR);
+    if let Some(user) = &file.user {
+        dir = dir.join(user);
+    }
+    fs::create_dir_all(&dir)?;
+    let name = format!(
+        "{}_{}",
+        locale::filename_stamp(Utc::now()),
+        file.path.file_name().unwrap().to_string_lossy()
+    );
+    fs::rename(&file.path, dir.join(name))
+}
+
+/// Creates a group from the file, titled with its name, holding a scan for
+/// each of its pages, unless an ingest rule for its FTP user files the pages
+/// elsewhere. Returns how many scans were created.
+fn import_file(
+    file: &DroppedFile,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<usize, IngestError> {
+    let name = file.path.file_name().unwrap().to_string_lossy().to_string();
+    let pages = ingest::file_pages(fs::read(&file.path)?);
+    if pages.is_empty() {
+        println!("Skipping dropped file {:?}: no scanned pages", name);
+        return Ok(0);
+    }
+
+    let uploaded_at = fs::metadata(&file.path)?
+        .modified()
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now());
+    let mut scan_parameters = HashMap::from([("file".to_string(), name.clone())]);
+    if let Some(user) = &file.user {
+        scan_parameters.insert("user".to_string(), user.clone());
+    }
+    let document = IncomingDocument {
+        title: file
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or(name.clone()),
+        comment: match &file.user {
+            Some(user) => format!("Imported from {} uploaded by {}", name, user),
+            None => format!("Imported from {} in the drop folder", name),
+        },
+        source: IngestSource::Ftp,
+        routes: file.user.iter().cloned().collect(),
+        scan_parameters,
+        scanned_at: uploaded_at,
+        pages,
+        upload_id: None,
+    };
+    let group = ingest::import_document(&document, pool, assets_dir, public_url)?;
+
+    println!(
+        "Imported {} page(s) from dropped file {:?} into group {}",
+        document.pages.len(),
+        name,
+        group.id
+    );
+    Ok(document.pages.len())
+}
+
+/// Imports every settled file in the drop folder, then moves each under its
+/// imported directory. A file that fails to import is left for the next poll.
+/// Returns how many scans were created.
+pub fn poll(
+    config: &DropFolderConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<usize, IngestError> {
+    let mut imported = 0;
+    for file in dropped_files(config)? {
+        imported += import_file(&file, pool, assets_dir, public_url)?;
+        archive(config, &file)?;
+    }
+    Ok(imported)
+}
@@ -0,0 +1,53 @@
+use async_graphql::Enum;
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+/// The ink colour of a form's printed lines and boxes, to be dropped.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DropoutColor {
+    Red,
+    Green,
+    Blue,
+}
+
+impl DropoutColor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropoutColor::Red => "red",
+            DropoutColor::Green => "green",
+            DropoutColor::Blue => "blue",
+        }
+    }
+
+    pub fn from_str(color: &str) -> Option<Self> {
+        match color {
+            "red" => Some(DropoutColor::Red),
+            "green" => Some(DropoutColor::Green),
+            "blue" => Some(DropoutColor::Blue),
+            _ => None,
+        }
+    }
+
+    /// Reads the nullable column as stored.
+    pub fn from_column(color: Option<String>) -> Option<Self> {
+        color.as_deref().and_then(Self::from_str)
+    }
+}
+
+/// Drops a form's printed colour so only what was filled in is left, the
+/// way a scanner's coloured lamp does: the page becomes grey from the
+/// channel of that colour alone, in which its ink is as light as the paper
+/// while black or blue pen stays dark.
+pub fn drop_color(image: DynamicImage, color: DropoutColor) -> DynamicImage {
+    let channel = match color {
+        DropoutColor::Red => 0,
+        DropoutColor::Green => 1,
+        DropoutColor::Blue => 2,
+    };
+    let rgb = image.to_rgb8();
+    let gray = GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+        image::Luma([rgb.get_pixel(x, y)[channel]])
+    });
+    DynamicImage::ImageLuma8(gray)
+}
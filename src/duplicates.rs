@@ -0,0 +1,381 @@
+use std::{collections::HashSet, fmt};
+
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use image::{imageops::FilterType, ImageResult};
+
+use crate::{
+    exports::page_fingerprint,
+    group_links::GroupLink,
+    ocr::ScanText,
+    scans::{Scan, ScanGroup},
+    simple_broker::SimpleBroker,
+    AssetsDir,
+};
+
+/// Bits two page hashes may differ by and still be the same page, rescanned
+/// on another day or another scanner.
+const PAGE_HASH_DISTANCE: u32 = 10;
+/// Share of words two groups' texts must have in common to be the same
+/// document, when their pages look different, e.g. one was scanned in colour.
+const TEXT_SIMILARITY: f64 = 0.9;
+
+/// A finalized group that looks like an earlier one, e.g. the same bill
+/// scanned by two people. Published when found, and listed on the group
+/// until resolved.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupDuplicate {
+    pub group_id: i32,
+    /// The earlier group it duplicates
+    pub duplicate_of: i32,
+    pub duplicate_of_title: String,
+    /// From 0 to 1: the share of pages that match, or of words in common
+    pub similarity: f64,
+    /// `pages` or `text`
+    pub matched_by: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// What to do with a group found to duplicate another.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DuplicateResolution {
+    /// Fold it into the earlier group: its tags, comment and any pages the
+    /// earlier group lacks are added there, then it is deleted
+    Merge,
+    /// Delete it and its pages
+    Discard,
+    /// Keep both; they only look alike
+    Keep,
+}
+
+#[derive(Debug)]
+pub enum DuplicateError {
+    GroupNotFound,
+    /// The group is under a hold, so its pages can't be deleted
+    Held,
+    Image(String),
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for DuplicateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DuplicateError::GroupNotFound => write!(f, "group not found"),
+            DuplicateError::Held => write!(f, "group is on hold"),
+            DuplicateError::Image(e) => write!(f, "could not read scan image: {}", e),
+            DuplicateError::Db(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for DuplicateError {
+    fn from(e: duckdb::Error) -> Self {
+        DuplicateError::Db(e)
+    }
+}
+
+/// Difference hash of the page: whether each pixel of a 9×8 grayscale
+/// thumbnail is brighter than the one to its right. Survives rescanning,
+/// recompression and small shifts, unlike a hash of the file.
+fn difference_hash(scan: &Scan, assets_dir: &AssetsDir) -> ImageResult<u64> {
+    let thumbnail = scan
+        .open_image(assets_dir)?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y)[0] > thumbnail.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// The page's hash, computing it first if it hasn't been, or the page was
+/// rotated or cropped since.
+fn page_hash(
+    scan: &Scan,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<u64, DuplicateError> {
+    let scan_id = scan.id.unwrap();
+    let fingerprint = page_fingerprint(scan);
+    let conn = pool.get().unwrap();
+
+    let cached: Option<i64> = conn
+        .query_row(
+            "SELECT hash FROM scan_hashes WHERE scan_id = ? AND fingerprint = ?",
+            params![scan_id, fingerprint],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(hash) = cached {
+        return Ok(hash as u64);
+    }
+
+    let hash =
+        difference_hash(scan, assets_dir).map_err(|e| DuplicateError::Image(e.to_string()))?;
+    conn.execute(
+        "INSERT OR REPLACE INTO scan_hashes (scan_id, fingerprint, hash) VALUES (?, ?, ?)",
+        params![scan_id, fingerprint, hash as i64],
+    )?;
+    Ok(hash)
+}
+
+fn pages(group: &ScanGroup) -> Vec<&Scan> {
+    group
+        .scans
+        .iter()
+        .filter(|scan| scan.status == "COMPLETE")
+        .collect()
+}
+
+fn page_hashes(
+    group: &ScanGroup,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<Vec<u64>, DuplicateError> {
+    pages(group)
+        .into_iter()
+        .map(|scan| page_hash(scan, pool, assets_dir))
+        .collect()
+}
+
+/// Words in the text already read from every page of the group, or None if
+/// some page hasn't been read. Duplicate checks don't run OCR themselves.
+fn words(group: &ScanGroup, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Option<HashSet<String>> {
+    let mut words = HashSet::new();
+    for scan in pages(group) {
+        let text = ScanText::load(scan.id?, pool)
+            .ok()
+            .flatten()
+            .filter(|text| text.fingerprint == page_fingerprint(scan))?;
+        words.extend(text.text.split_whitespace().map(str::to_lowercase));
+    }
+    (!words.is_empty()).then_some(words)
+}
+
+/// Share of `hashes` with a matching page at the same position in `other`.
+fn page_similarity(hashes: &[u64], other: &[u64]) -> f64 {
+    if hashes.is_empty() || hashes.len() != other.len() {
+        return 0.0;
+    }
+    let matching = hashes
+        .iter()
+        .zip(other)
+        .filter(|(a, b)| (*a ^ *b).count_ones() <= PAGE_HASH_DISTANCE)
+        .count();
+    matching as f64 / hashes.len() as f64
+}
+
+fn text_similarity(words: &HashSet<String>, other: &HashSet<String>) -> f64 {
+    let union = words.union(other).count();
+    match union {
+        0 => 0.0,
+        _ => words.intersection(other).count() as f64 / union as f64,
+    }
+}
+
+impl GroupDuplicate {
+    /// Unresolved duplicates found for the group.
+    pub fn load_all_by_group(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<GroupDuplicate> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT d.scan_group_id, d.duplicate_of_id, g.title, d.similarity, d.matched_by, d.detected_at
+                 FROM group_duplicates d JOIN scan_groups g ON g.id = d.duplicate_of_id
+                 WHERE d.scan_group_id = ?
+                 ORDER BY d.similarity DESC, d.duplicate_of_id",
+            )
+            .unwrap();
+
+        let duplicates: Vec<GroupDuplicate> = stmt
+            .query_map([group_id], |row| {
+                Ok(GroupDuplicate {
+                    group_id: row.get(0)?,
+                    duplicate_of: row.get(1)?,
+                    duplicate_of_title: row.get(2)?,
+                    similarity: row.get(3)?,
+                    matched_by: row.get(4)?,
+                    detected_at: row.get(5)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        duplicates
+    }
+
+    /// Compares a newly finalized group with the other finalized groups of
+    /// as many pages: it duplicates one if every page looks the same, or if
+    /// both have been read and nearly all their words are shared. What is
+    /// found replaces the group's earlier findings and is published.
+    pub fn detect(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<Vec<GroupDuplicate>, DuplicateError> {
+        let group = ScanGroup::load(group_id, pool).map_err(|_| DuplicateError::GroupNotFound)?;
+        let page_count = pages(&group).len();
+        if page_count == 0 {
+            return Ok(Vec::new());
+        }
+        let hashes = page_hashes(&group, pool, assets_dir)?;
+        let own_words = words(&group, pool);
+
+        let candidates: Vec<i32> = {
+            let conn = pool.get().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT g.id FROM scan_groups g JOIN scans s ON s.scan_group_id = g.id
+                 WHERE g.status = 'finalized' AND g.id != ? AND s.status = 'COMPLETE'
+                 GROUP BY g.id HAVING count(*) = ?
+                 ORDER BY g.id",
+            )?;
+            let ids = stmt
+                .query_map(params![group_id, page_count as i64], |row| row.get(0))?
+                .collect::<duckdb::Result<Vec<i32>>>()?;
+            ids
+        };
+
+        let mut found: Vec<(i32, f64, &str)> = Vec::new();
+        for candidate_id in candidates {
+            let Ok(candidate) = ScanGroup::load(candidate_id, pool) else {
+                continue;
+            };
+            let pages_alike = page_similarity(&hashes, &page_hashes(&candidate, pool, assets_dir)?);
+            if pages_alike == 1.0 {
+                found.push((candidate_id, pages_alike, "pages"));
+                continue;
+            }
+            let text_alike = match (&own_words, words(&candidate, pool)) {
+                (Some(words), Some(other)) => text_similarity(words, &other),
+                _ => 0.0,
+            };
+            if text_alike >= TEXT_SIMILARITY {
+                found.push((candidate_id, text_alike, "text"));
+            }
+        }
+
+        let mut conn = pool.get().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM group_duplicates WHERE scan_group_id = ?",
+            params![group_id],
+        )?;
+        let now = Utc::now();
+        for (duplicate_of, similarity, matched_by) in &found {
+            tx.execute(
+                "INSERT INTO group_duplicates (scan_group_id, duplicate_of_id, similarity, matched_by, detected_at)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![group_id, duplicate_of, similarity, matched_by, now],
+            )?;
+        }
+        tx.commit()?;
+
+        let duplicates = Self::load_all_by_group(group_id, pool);
+        for duplicate in &duplicates {
+            SimpleBroker::publish(duplicate.clone());
+        }
+        Ok(duplicates)
+    }
+
+    /// Checks groups that were just finalized, in the background: hashing
+    /// their pages and those of earlier groups can take a while the first
+    /// time.
+    pub fn detect_later(
+        group_ids: Vec<i32>,
+        pool: r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: AssetsDir,
+    ) {
+        tokio::task::spawn_blocking(move || {
+            for group_id in group_ids {
+                match Self::detect(group_id, &pool, &assets_dir) {
+                    Ok(duplicates) => {
+                        for duplicate in duplicates {
+                            println!(
+                                "Group {} looks like a duplicate of group {} ({} {:.0}%)",
+                                group_id,
+                                duplicate.duplicate_of,
+                                duplicate.matched_by,
+                                duplicate.similarity * 100.0
+                            );
+                        }
+                    }
+                    Err(e) => println!("Could not check group {} for duplicates: {}", group_id, e),
+                }
+            }
+        });
+    }
+
+    /// Settles a duplicate warning on `group_id`. Returns the id of the
+    /// group left: the earlier one after a merge or discard, or `group_id`
+    /// if both are kept.
+    pub fn resolve(
+        group_id: i32,
+        duplicate_of: i32,
+        resolution: DuplicateResolution,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<i32, DuplicateError> {
+        let group = ScanGroup::load(group_id, pool).map_err(|_| DuplicateError::GroupNotFound)?;
+        let mut earlier =
+            ScanGroup::load(duplicate_of, pool).map_err(|_| DuplicateError::GroupNotFound)?;
+        if resolution != DuplicateResolution::Keep && group.hold {
+            return Err(DuplicateError::Held);
+        }
+
+        match resolution {
+            DuplicateResolution::Keep => {
+                let conn = pool.get().unwrap();
+                conn.execute(
+                    "DELETE FROM group_duplicates WHERE scan_group_id = ? AND duplicate_of_id = ?",
+                    params![group_id, duplicate_of],
+                )?;
+                return Ok(group_id);
+            }
+            DuplicateResolution::Merge => {
+                for tag in &group.tags {
+                    if !earlier.tags.contains(tag) {
+                        earlier.tags.push(tag.clone());
+                    }
+                }
+                if !group.comment.is_empty() && !earlier.comment.contains(&group.comment) {
+                    earlier.comment = match earlier.comment.is_empty() {
+                        true => group.comment.clone(),
+                        false => format!("{}\n\n{}", earlier.comment, group.comment),
+                    };
+                }
+                earlier.save(pool)?;
+
+                let earlier_hashes = page_hashes(&earlier, pool, assets_dir)?;
+                // Pages the earlier group lacks move there; the rest go with the group
+                for scan in pages(&group) {
+                    let own = page_hash(scan, pool, assets_dir)?;
+                    let known = earlier_hashes
+                        .iter()
+                        .any(|hash| (own ^ hash).count_ones() <= PAGE_HASH_DISTANCE);
+                    if !known {
+                        scan.clone().set_group(duplicate_of, pool)?;
+                    }
+                }
+                GroupLink::move_to(group_id, duplicate_of, pool)?;
+            }
+            DuplicateResolution::Discard => {}
+        }
+
+        for scan in ScanGroup::load(group_id, pool)?.scans {
+            scan.delete(pool, assets_dir)?;
+        }
+        ScanGroup::delete_empty(group_id, pool)?;
+        Ok(duplicate_of)
+    }
+}
@@ -0,0 +1,155 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// What kind of thing an entity in a page's text is.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, async_graphql::Enum)]
+pub enum EntityKind {
+    Date,
+    Organization,
+    Amount,
+}
+
+impl EntityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntityKind::Date => "date",
+            EntityKind::Organization => "organization",
+            EntityKind::Amount => "amount",
+        }
+    }
+
+    pub fn from_str(kind: &str) -> Self {
+        match kind {
+            "organization" => EntityKind::Organization,
+            "amount" => EntityKind::Amount,
+            _ => EntityKind::Date,
+        }
+    }
+}
+
+/// A date, organization or amount of money found in text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub kind: EntityKind,
+    /// As it appears in the text
+    pub text: String,
+    /// What the group would be tagged with for it: the year of a date, the
+    /// name of an organization, or an amount as currency and value
+    pub tag: String,
+    /// Amounts only, in hundredths of the currency, to compare them
+    pub cents: Option<i64>,
+}
+
+const MONTHS: &str = "January|February|March|April|May|June|July|August|September|October|November|December|Jan|Feb|Mar|Apr|Jun|Jul|Aug|Sep|Sept|Oct|Nov|Dec|Januar|Februar|März|Juni|Juli|Oktober|Dezember";
+
+static ISO_DATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b((?:19|20)\d{2})-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])\b").unwrap()
+});
+/// 31/12/2024, 12/31/2024 or 31.12.2024. Only the year is certain.
+static NUMERIC_DATE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b\d{1,2}[./]\d{1,2}[./]((?:19|20)\d{2})\b").unwrap());
+/// 31 December 2024, 31. Dezember 2024, December 31, 2024 or Dec 2024
+static WRITTEN_DATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"\b(?:\d{{1,2}}\.?\s+)?(?:{})\.?(?:\s+\d{{1,2}},)?\s+((?:19|20)\d{{2}})\b",
+        MONTHS
+    ))
+    .unwrap()
+});
+
+/// Up to four capitalized words ending in a word that marks a company or
+/// institution, e.g. "Acme Widgets Ltd" or "Stadtwerke München GmbH".
+static ORGANIZATION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"\b((?:\p{Lu}[\p{L}\d&'-]*\.?\s+){1,4}(?:Inc|Ltd|LLC|GmbH|AG|KG|Corp|Corporation|Company|Co|plc|PLC|Bank|University|Universität|Insurance|Versicherung))\b\.?",
+    )
+    .unwrap()
+});
+/// Capitalized words that start sentences rather than names.
+const LEADING_WORDS: &[&str] = &[
+    "The", "A", "An", "From", "To", "Dear", "By", "At", "Der", "Die", "Das", "Von", "Le", "La",
+    "Les",
+];
+
+/// $1,234.56, € 12,50, EUR 1.234,56, 12,50 € or 1234.56 USD
+static AMOUNT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?:([$€£]|\b(?:USD|EUR|GBP|CHF))\s?(\d{1,3}(?:[,. ]\d{3})*(?:[.,]\d{2})?|\d+(?:[.,]\d{2})?)\b)|(?:\b(\d{1,3}(?:[,. ]\d{3})*(?:[.,]\d{2})?|\d+(?:[.,]\d{2})?)\s?([$€£]|(?:USD|EUR|GBP|CHF)\b))",
+    )
+    .unwrap()
+});
+
+fn currency(symbol: &str) -> &str {
+    match symbol {
+        "$" => "USD",
+        "€" => "EUR",
+        "£" => "GBP",
+        code => code,
+    }
+}
+
+/// An amount's value in hundredths. The last `.` or `,` is the decimal
+/// point if two digits follow it; every other separator groups thousands.
+fn parse_cents(number: &str) -> Option<i64> {
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    let value: i64 = digits.parse().ok()?;
+    let decimal = number
+        .rfind(['.', ','])
+        .is_some_and(|i| number.len() - i == 3);
+    Some(if decimal { value } else { value * 100 })
+}
+
+fn dates(text: &str) -> impl Iterator<Item = Entity> + '_ {
+    [&*ISO_DATE, &*NUMERIC_DATE, &*WRITTEN_DATE]
+        .into_iter()
+        .flat_map(move |pattern| {
+            pattern.captures_iter(text).map(|captures| Entity {
+                kind: EntityKind::Date,
+                text: captures[0].to_string(),
+                tag: captures[1].to_string(),
+                cents: None,
+            })
+        })
+}
+
+fn organizations(text: &str) -> impl Iterator<Item = Entity> + '_ {
+    ORGANIZATION.captures_iter(text).filter_map(|captures| {
+        let mut words: Vec<&str> = captures[1].split_whitespace().collect();
+        while words.len() > 1 && LEADING_WORDS.contains(&words[0]) {
+            words.remove(0);
+        }
+        // A marker alone, e.g. "The Bank", names nothing in particular
+        (words.len() > 1).then(|| Entity {
+            kind: EntityKind::Organization,
+            text: captures[0].to_string(),
+            tag: words.join(" "),
+            cents: None,
+        })
+    })
+}
+
+fn amounts(text: &str) -> impl Iterator<Item = Entity> + '_ {
+    AMOUNT.captures_iter(text).filter_map(|captures| {
+        let (symbol, number) = match (captures.get(1), captures.get(2)) {
+            (Some(symbol), Some(number)) => (symbol.as_str(), number.as_str()),
+            _ => (captures.get(4)?.as_str(), captures.get(3)?.as_str()),
+        };
+        let cents = parse_cents(number)?;
+        Some(Entity {
+            kind: EntityKind::Amount,
+            text: captures[0].trim().to_string(),
+            tag: format!("{} {}.{:02}", currency(symbol), cents / 100, cents % 100),
+            cents: Some(cents),
+        })
+    })
+}
+
+/// Dates, organizations and amounts of money in OCR text. Simple patterns
+/// rather than a language model: they miss some, but what they find is
+/// usually right, which is what suggesting tags needs.
+pub fn extract(text: &str) -> Vec<Entity> {
+    dates(text)
+        .chain(organizations(text))
+        .chain(amounts(text))
+        .collect()
+}
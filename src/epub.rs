@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+
+use crate::{scans::ScanGroup, xml::escape, zip::write_zip};
+
+/// One page of an EPUB: the page image, followed by the text read from it.
+pub struct EpubPage<'a> {
+    pub jpeg: &'a [u8],
+    pub width_px: u32,
+    pub height_px: u32,
+    pub text: String,
+}
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+const STYLE: &str = "img { display: block; max-width: 100%; max-height: 100vh; margin: 0 auto; }
+section.text { margin-top: 1em; }
+";
+
+fn page_name(number: usize) -> String {
+    format!("page_{:04}.xhtml", number)
+}
+
+fn image_name(number: usize) -> String {
+    format!("images/{:04}.jpg", number)
+}
+
+/// The text as paragraphs, split where tesseract left a blank line.
+fn paragraphs(text: &str) -> Vec<String> {
+    text.split("\n\n")
+        .map(|block| {
+            block
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect()
+}
+
+fn page_xhtml(number: usize, page: &EpubPage, title: &str, language: &str) -> String {
+    let mut xhtml = String::new();
+    xhtml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xhtml.push_str(&format!(
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{0}\" lang=\"{0}\">\n",
+        escape(language)
+    ));
+    xhtml.push_str(&format!(
+        "<head>\n  <title>{} – p. {}</title>\n  <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n",
+        escape(title),
+        number
+    ));
+    xhtml.push_str(&format!(
+        "<body>\n  <div epub:type=\"pagebreak\" id=\"p{0}\" role=\"doc-pagebreak\" aria-label=\"{0}\"></div>\n",
+        number
+    ));
+    xhtml.push_str(&format!(
+        "  <img src=\"{}\" width=\"{}\" height=\"{}\" alt=\"Page {}\"/>\n",
+        image_name(number),
+        page.width_px,
+        page.height_px,
+        number
+    ));
+    let paragraphs = paragraphs(&page.text);
+    if !paragraphs.is_empty() {
+        xhtml.push_str("  <section class=\"text\">\n");
+        for paragraph in paragraphs {
+            xhtml.push_str(&format!("    <p>{}</p>\n", escape(&paragraph)));
+        }
+        xhtml.push_str("  </section>\n");
+    }
+    xhtml.push_str("</body>\n</html>\n");
+    xhtml
+}
+
+fn nav_xhtml(pages: usize, title: &str, language: &str) -> String {
+    let mut xhtml = String::new();
+    xhtml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xhtml.push_str(&format!(
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{0}\" lang=\"{0}\">\n",
+        escape(language)
+    ));
+    xhtml.push_str(&format!(
+        "<head>\n  <title>{}</title>\n</head>\n<body>\n",
+        escape(title)
+    ));
+    xhtml.push_str("  <nav epub:type=\"toc\" id=\"toc\">\n    <ol>\n");
+    xhtml.push_str(&format!(
+        "      <li><a href=\"{}\">{}</a></li>\n",
+        page_name(1),
+        escape(title)
+    ));
+    xhtml.push_str("    </ol>\n  </nav>\n");
+    xhtml.push_str("  <nav epub:type=\"page-list\" hidden=\"\">\n    <ol>\n");
+    for number in 1..=pages {
+        xhtml.push_str(&format!(
+            "      <li><a href=\"{}#p{}\">{}</a></li>\n",
+            page_name(number),
+            number,
+            number
+        ));
+    }
+    xhtml.push_str("    </ol>\n  </nav>\n</body>\n</html>\n");
+    xhtml
+}
+
+fn content_opf(group: &ScanGroup, pages: usize, language: &str) -> String {
+    let mut opf = String::new();
+    opf.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    opf.push_str("<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n");
+    opf.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+    opf.push_str(&format!(
+        "    <dc:identifier id=\"id\">urn:scanserv:group:{}</dc:identifier>\n",
+        group.id
+    ));
+    opf.push_str(&format!(
+        "    <dc:title>{}</dc:title>\n",
+        escape(&group.title)
+    ));
+    opf.push_str(&format!(
+        "    <dc:language>{}</dc:language>\n",
+        escape(language)
+    ));
+    if !group.comment.is_empty() {
+        opf.push_str(&format!(
+            "    <dc:description>{}</dc:description>\n",
+            escape(&group.comment)
+        ));
+    }
+    for tag in &group.tags {
+        opf.push_str(&format!("    <dc:subject>{}</dc:subject>\n", escape(tag)));
+    }
+    opf.push_str(&format!(
+        "    <meta property=\"dcterms:modified\">{}</meta>\n",
+        group.updated_at.format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    opf.push_str("  </metadata>\n  <manifest>\n");
+    opf.push_str("    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n");
+    opf.push_str("    <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n");
+    for number in 1..=pages {
+        opf.push_str(&format!(
+            "    <item id=\"page{0}\" href=\"{1}\" media-type=\"application/xhtml+xml\"/>\n",
+            number,
+            page_name(number)
+        ));
+        opf.push_str(&format!(
+            "    <item id=\"image{0}\" href=\"{1}\" media-type=\"image/jpeg\"{2}/>\n",
+            number,
+            image_name(number),
+            if number == 1 {
+                " properties=\"cover-image\""
+            } else {
+                ""
+            }
+        ));
+    }
+    opf.push_str("  </manifest>\n  <spine>\n");
+    for number in 1..=pages {
+        opf.push_str(&format!("    <itemref idref=\"page{}\"/>\n", number));
+    }
+    opf.push_str("  </spine>\n</package>\n");
+    opf
+}
+
+/// Writes an EPUB 3 book of the group with one chapter per page: the page
+/// image, then its text as reflowable paragraphs, so e-readers can show
+/// either. `language` is the BCP 47 tag of the text.
+pub fn write_epub(
+    group: &ScanGroup,
+    pages: &[EpubPage],
+    language: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let opf = content_opf(group, pages.len(), language);
+    let nav = nav_xhtml(pages.len(), &group.title, language);
+    let chapters: Vec<(String, String, String)> = pages
+        .iter()
+        .enumerate()
+        .map(|(i, page)| {
+            let number = i + 1;
+            (
+                format!("OEBPS/{}", page_name(number)),
+                page_xhtml(number, page, &group.title, language),
+                format!("OEBPS/{}", image_name(number)),
+            )
+        })
+        .collect();
+
+    let mut entries: Vec<(&str, &[u8])> = vec![
+        // Readers find the type from this, so it must be the first entry
+        // and stored uncompressed
+        ("mimetype", "application/epub+zip".as_bytes()),
+        ("META-INF/container.xml", CONTAINER.as_bytes()),
+        ("OEBPS/content.opf", opf.as_bytes()),
+        ("OEBPS/nav.xhtml", nav.as_bytes()),
+        ("OEBPS/style.css", STYLE.as_bytes()),
+    ];
+    for ((xhtml_path, xhtml, image_path), page) in chapters.iter().zip(pages) {
+        entries.push((xhtml_path, xhtml.as_bytes()));
+        entries.push((image_path, page.jpeg));
+    }
+    write_zip(&entries, out)
+}
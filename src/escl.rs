@@ -0,0 +1,920 @@
+use std::{
+    collections::HashMap,
+    env, fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+use crate::{
+    scanner_options::{ScannerOption, ScannerOptionType},
+    xml,
+};
+
+/// mDNS services eSCL scanners advertise, over HTTP and HTTPS.
+const SERVICES: [&str; 2] = ["_uscan._tcp.local", "_uscans._tcp.local"];
+const MDNS_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+/// How long discovery listens for scanners to answer.
+const DISCOVERY_WAIT: Duration = Duration::from_secs(2);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// A page can take a while to come through, especially at high resolutions.
+const READ_TIMEOUT: Duration = Duration::from_secs(120);
+/// NextDocument answers 503 until the page is ready; asked again this often,
+/// this many times.
+const DOCUMENT_POLL: Duration = Duration::from_secs(1);
+const DOCUMENT_POLLS: u32 = 120;
+
+const SCAN_NAMESPACE: &str = "http://schemas.hp.com/imaging/escl/2011/05/03";
+const PWG_NAMESPACE: &str = "http://www.pwg.org/schemas/2010/12/sm";
+
+/// Device names of eSCL scanners start with this, before their URL.
+pub const DEVICE_PREFIX: &str = "escl:";
+
+/// A network scanner spoken to over eSCL, also known as AirScan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EsclDevice {
+    /// Where its eSCL resources are, e.g. `http://192.168.1.20/eSCL`
+    pub url: String,
+    /// Its make and model, from its mDNS record
+    pub model: String,
+}
+
+impl EsclDevice {
+    /// As listed among the scanners, e.g. `escl:http://192.168.1.20/eSCL`.
+    pub fn name(&self) -> String {
+        format!("{}{}", DEVICE_PREFIX, self.url)
+    }
+}
+
+/// The URL of an eSCL device name.
+pub fn device_url(name: &str) -> Option<&str> {
+    name.strip_prefix(DEVICE_PREFIX)
+}
+
+/// Scanners from `ESCL_SCANNERS`, a comma-separated list of eSCL URLs such
+/// as `http://192.168.1.20/eSCL`, for networks mDNS doesn't reach across.
+pub fn from_env() -> Vec<EsclDevice> {
+    env::var("ESCL_SCANNERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|url| url.trim().trim_end_matches('/'))
+        .filter(|url| !url.is_empty())
+        .map(|url| EsclDevice {
+            url: url.to_string(),
+            model: url.to_string(),
+        })
+        .collect()
+}
+
+/// A DNS question for each service's PTR records.
+fn query_packet() -> Vec<u8> {
+    let mut packet = vec![0, 0, 0, 0, 0, SERVICES.len() as u8, 0, 0, 0, 0, 0, 0];
+    for service in SERVICES {
+        for label in service.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        // PTR, class IN
+        packet.extend_from_slice(&[0, 0, 0, 12, 0, 1]);
+    }
+    packet
+}
+
+/// Reads a possibly compressed name at `offset`, returning it and the
+/// offset just past it.
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bounds the pointers followed, so a looping packet can't hang us
+    for _ in 0..64 {
+        let len = *packet.get(offset)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(offset + 1)));
+        }
+        if len & 0xC0 == 0xC0 {
+            let pointer = ((len & 0x3F) << 8) | *packet.get(offset + 1)? as usize;
+            end.get_or_insert(offset + 2);
+            offset = pointer;
+            continue;
+        }
+        let label = packet.get(offset + 1..offset + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += 1 + len;
+    }
+    None
+}
+
+enum RecordData {
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(HashMap<String, String>),
+    A(Ipv4Addr),
+}
+
+struct Record {
+    name: String,
+    data: RecordData,
+}
+
+/// The PTR, SRV, TXT and A records in an mDNS response.
+fn parse_response(packet: &[u8]) -> Option<Vec<Record>> {
+    let count = |at: usize| Some(u16::from_be_bytes([*packet.get(at)?, *packet.get(at + 1)?]));
+    let questions = count(4)?;
+    let records = count(6)? as usize + count(8)? as usize + count(10)? as usize;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(packet, offset)?.1 + 4;
+    }
+    let mut parsed = Vec::new();
+    for _ in 0..records {
+        let (name, after) = read_name(packet, offset)?;
+        let kind = count(after)?;
+        let length = count(after + 8)? as usize;
+        let start = after + 10;
+        let data = packet.get(start..start + length)?;
+        offset = start + length;
+
+        let data = match kind {
+            12 => RecordData::Ptr(read_name(packet, start)?.0),
+            33 if length >= 6 => RecordData::Srv {
+                port: u16::from_be_bytes([data[4], data[5]]),
+                target: read_name(packet, start + 6)?.0,
+            },
+            16 => {
+                let mut entries = HashMap::new();
+                let mut at = 0;
+                while at < data.len() {
+                    let len = data[at] as usize;
+                    let entry = String::from_utf8_lossy(data.get(at + 1..at + 1 + len)?);
+                    if let Some((key, value)) = entry.split_once('=') {
+                        entries.insert(key.to_lowercase(), value.to_string());
+                    }
+                    at += 1 + len;
+                }
+                RecordData::Txt(entries)
+            }
+            1 if length == 4 => RecordData::A(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            _ => continue,
+        };
+        parsed.push(Record { name, data });
+    }
+    Some(parsed)
+}
+
+/// Asks the local network for eSCL scanners over mDNS and collects the
+/// answers for `DISCOVERY_WAIT`. Blocks meanwhile. The query goes from an
+/// ordinary port, so scanners answer it directly rather than to the
+/// multicast group.
+pub fn discover() -> io::Result<Vec<EsclDevice>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.send_to(&query_packet(), MDNS_ADDRESS)?;
+
+    let mut records = Vec::new();
+    let started = Instant::now();
+    let mut buf = [0u8; 9000];
+    while let Some(left) = DISCOVERY_WAIT.checked_sub(started.elapsed()) {
+        socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                if let Some(parsed) = parse_response(&buf[..len]) {
+                    records.extend(parsed.into_iter().map(|record| (record, from.ip())));
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut devices: Vec<(IpAddr, EsclDevice)> = Vec::new();
+    for (record, from) in &records {
+        let RecordData::Ptr(instance) = &record.data else {
+            continue;
+        };
+        let Some(service) = SERVICES.iter().find(|service| record.name == **service) else {
+            continue;
+        };
+        let Some((port, target)) = records.iter().find_map(|(other, _)| match &other.data {
+            RecordData::Srv { port, target } if &other.name == instance => Some((*port, target)),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let txt = records.iter().find_map(|(other, _)| match &other.data {
+            RecordData::Txt(entries) if &other.name == instance => Some(entries),
+            _ => None,
+        });
+        // The address the target resolves to, or failing that who answered
+        let address = records
+            .iter()
+            .find_map(|(other, _)| match other.data {
+                RecordData::A(address) if &other.name == target => Some(IpAddr::V4(address)),
+                _ => None,
+            })
+            .unwrap_or(*from);
+        let scheme = if service.starts_with("_uscans") {
+            "https"
+        } else {
+            "http"
+        };
+        let resource = txt
+            .and_then(|txt| txt.get("rs"))
+            .map(|rs| rs.trim_matches('/').to_string())
+            .unwrap_or_else(|| "eSCL".to_string());
+        let model = txt
+            .and_then(|txt| txt.get("ty"))
+            .cloned()
+            .unwrap_or_else(|| instance.split('.').next().unwrap_or(instance).to_string());
+        let url = format!(
+            "{}://{}/{}",
+            scheme,
+            SocketAddr::new(address, port),
+            resource
+        );
+        let url = url.trim_end_matches('/').to_string();
+
+        devices.push((address, EsclDevice { url, model }));
+    }
+    // `http://` sorts before `https://`, so scanners offering both and each
+    // answer repeated keep the first plain HTTP one
+    devices.sort_by(|a, b| a.1.url.cmp(&b.1.url));
+    let mut seen = Vec::new();
+    devices.retain(|(address, device)| {
+        let key = (*address, device.model.clone());
+        let first = !seen.contains(&key);
+        seen.push(key);
+        first
+    });
+    Ok(devices.into_iter().map(|(_, device)| device).collect())
+}
+
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+fn dechunk(mut body: &[u8]) -> io::Result<Vec<u8>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed chunked body");
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(malformed)?;
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| malformed())?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = body
+            .get(line_end + 2..line_end + 2 + size)
+            .ok_or_else(malformed)?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(line_end + 4 + size..).ok_or_else(malformed)?;
+    }
+}
+
+fn parse_http(raw: &[u8]) -> io::Result<Response> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+    let head_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = String::from_utf8_lossy(&raw[..head_end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response {
+        status,
+        headers,
+        body: raw[head_end + 4..].to_vec(),
+    };
+    if response
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        response.body = dechunk(&response.body)?;
+    } else if let Some(length) = response
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+    {
+        response.body.truncate(length);
+    }
+    Ok(response)
+}
+
+/// Sends the request and reads until the device closes the connection.
+fn exchange(stream: &mut (impl Read + Write), request: &[u8]) -> io::Result<Vec<u8>> {
+    stream.write_all(request)?;
+    let mut raw = Vec::new();
+    match stream.read_to_end(&mut raw) {
+        Ok(_) => Ok(raw),
+        // Some devices drop TLS connections without closing them properly
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !raw.is_empty() => Ok(raw),
+        Err(e) => Err(e),
+    }
+}
+
+/// A one-off HTTP/1.1 request. HTTPS devices' certificates are accepted
+/// unchecked, since scanners only ever have self-signed ones.
+pub fn request(method: &str, url: &str, body: Option<&str>) -> io::Result<Response> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("bad URL {}", url));
+    let (tls, rest) = match url.split_once("://") {
+        Some(("http", rest)) => (false, rest),
+        Some(("https", rest)) => (true, rest),
+        _ => return Err(invalid()),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(at) => (&rest[..at], &rest[at..]),
+        None => (rest, "/"),
+    };
+    let default_port = if tls { 443 } else { 80 };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => {
+            (host, port.parse::<u16>().map_err(|_| invalid())?)
+        }
+        _ => (authority, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let address = (host, port).to_socket_addrs()?.next().ok_or_else(invalid)?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(READ_TIMEOUT))?;
+
+    let mut message = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method, path, authority
+    );
+    if let Some(body) = body {
+        message.push_str(&format!(
+            "Content-Type: text/xml\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+    } else {
+        message.push_str("\r\n");
+    }
+
+    let raw = if tls {
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .map_err(io::Error::other)?;
+        let mut stream = connector.connect(host, stream).map_err(io::Error::other)?;
+        exchange(&mut stream, message.as_bytes())?
+    } else {
+        exchange(&mut stream, message.as_bytes())?
+    };
+    parse_http(&raw)
+}
+
+/// The text of each element named `name`, whatever its namespace prefix.
+fn elements(xml: &str, name: &str) -> Vec<String> {
+    Regex::new(&format!(
+        r"<(?:\w+:)?{0}(?:\s[^>]*)?>([^<]*)</(?:\w+:)?{0}>",
+        name
+    ))
+    .unwrap()
+    .captures_iter(xml)
+    .map(|captures| captures[1].trim().to_string())
+    .collect()
+}
+
+fn has_element(xml: &str, name: &str) -> bool {
+    Regex::new(&format!(r"<(?:\w+:)?{}[\s/>]", name))
+        .unwrap()
+        .is_match(xml)
+}
+
+/// The part of the capabilities document for one input, e.g. `Platen`.
+fn section<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = Regex::new(&format!(r"<(?:\w+:)?{}[\s>]", name)).unwrap();
+    let close = Regex::new(&format!(r"</(?:\w+:)?{}>", name)).unwrap();
+    let start = open.find(xml)?.start();
+    let end = close.find_at(xml, start)?.end();
+    Some(&xml[start..end])
+}
+
+/// What a device can do, from its `ScannerCapabilities`.
+pub struct Capabilities {
+    xml: String,
+}
+
+impl Capabilities {
+    pub fn load(url: &str) -> io::Result<Capabilities> {
+        let response = request("GET", &format!("{}/ScannerCapabilities", url), None)?;
+        if response.status != 200 {
+            return Err(io::Error::other(format!(
+                "capabilities request failed with HTTP {}",
+                response.status
+            )));
+        }
+        Ok(Capabilities {
+            xml: response.text(),
+        })
+    }
+
+    pub fn has_feeder(&self) -> bool {
+        has_element(&self.xml, "Adf")
+    }
+
+    /// The same options SANE backends list, so scans take the same
+    /// parameters whichever backend runs them.
+    pub fn options(&self) -> Vec<ScannerOption> {
+        let option = |flag: &str, group: &str, option_type, description: &str| ScannerOption {
+            flag: flag.to_string(),
+            name: flag.trim_start_matches('-').to_string(),
+            group: Some(group.to_string()),
+            option_type,
+            values: Vec::new(),
+            min: None,
+            max: None,
+            step: None,
+            unit: None,
+            default: None,
+            active: true,
+            read_only: false,
+            description: description.to_string(),
+        };
+        let input = section(&self.xml, "Platen")
+            .or_else(|| section(&self.xml, "Adf"))
+            .unwrap_or(&self.xml);
+        let mut options = Vec::new();
+
+        let mut mode = option(
+            "--mode",
+            "Scan Mode",
+            ScannerOptionType::Choice,
+            "Selects the scan mode.",
+        );
+        for color_mode in elements(input, "ColorMode") {
+            let value = match color_mode.as_str() {
+                "RGB24" | "RGB48" => "Color",
+                "Grayscale8" | "Grayscale16" => "Gray",
+                "BlackAndWhite1" => "Lineart",
+                _ => continue,
+            };
+            if !mode.values.iter().any(|known| known == value) {
+                mode.values.push(value.to_string());
+            }
+        }
+        mode.default = mode
+            .values
+            .iter()
+            .find(|value| *value == "Color")
+            .or(mode.values.first())
+            .cloned();
+        options.push(mode);
+
+        let mut resolution = option(
+            "--resolution",
+            "Scan Mode",
+            ScannerOptionType::Choice,
+            "Sets the resolution of the scanned image.",
+        );
+        resolution.values = elements(input, "XResolution");
+        resolution.values.dedup();
+        resolution.unit = Some("dpi".to_string());
+        resolution.default = resolution
+            .values
+            .iter()
+            .find(|dpi| *dpi == "300")
+            .or(resolution.values.first())
+            .cloned();
+        options.push(resolution);
+
+        let mut source = option(
+            "--source",
+            "Scan Mode",
+            ScannerOptionType::Choice,
+            "Selects the scan source.",
+        );
+        if has_element(&self.xml, "Platen") {
+            source.values.push("Flatbed".to_string());
+        }
+        if has_element(&self.xml, "Adf") {
+            source.values.push("ADF".to_string());
+        }
+        if has_element(&self.xml, "AdfDuplexInputCaps") {
+            source.values.push("ADF Duplex".to_string());
+        }
+        source.default = source.values.first().cloned();
+        options.push(source);
+
+        // Sizes are in 300ths of an inch
+        let size = |name: &str| {
+            elements(input, name)
+                .first()
+                .and_then(|size| size.parse::<f64>().ok())
+                .map(|size| (size / 300.0 * 25.4 * 10.0).round() / 10.0)
+        };
+        for (flag, limit, description) in [
+            ("-l", "MaxWidth", "Top-left x position of scan area."),
+            ("-t", "MaxHeight", "Top-left y position of scan area."),
+            ("-x", "MaxWidth", "Width of scan-area."),
+            ("-y", "MaxHeight", "Height of scan-area."),
+        ] {
+            let Some(max) = size(limit) else {
+                continue;
+            };
+            let mut geometry = option(flag, "Geometry", ScannerOptionType::Range, description);
+            geometry.min = Some(0.0);
+            geometry.max = Some(max);
+            geometry.unit = Some("mm".to_string());
+            geometry.default = Some(match flag {
+                "-x" | "-y" => max.to_string(),
+                _ => "0".to_string(),
+            });
+            options.push(geometry);
+        }
+        options
+    }
+}
+
+/// The state of the device's feeder, e.g. `ScannerAdfLoaded`.
+pub struct Status {
+    pub adf_state: Option<String>,
+}
+
+impl Status {
+    pub fn load(url: &str) -> io::Result<Status> {
+        let response = request("GET", &format!("{}/ScannerStatus", url), None)?;
+        let text = response.text();
+        Ok(Status {
+            adf_state: elements(&text, "AdfState").into_iter().next(),
+        })
+    }
+
+    /// Whether the feeder has paper in it, if the device says.
+    pub fn paper_loaded(&self) -> Option<bool> {
+        match self.adf_state.as_deref() {
+            Some("ScannerAdfLoaded") => Some(true),
+            Some("ScannerAdfEmpty") => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The paper problem the feeder reports, as the scan failures scanimage
+    /// gives for the same thing.
+    fn paper_failure(&self) -> Option<&'static str> {
+        match self.adf_state.as_deref()? {
+            "ScannerAdfJam" | "ScannerAdfMispick" | "ScannerAdfMultipickDetected" => Some("JAMMED"),
+            "ScannerAdfEmpty" => Some("NO_DOCS"),
+            "ScannerAdfHatchOpen" | "ScannerAdfDoorOpen" => Some("COVER_OPEN"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScanFailure {
+    /// The paper, not the device: `JAMMED`, `NO_DOCS` or `COVER_OPEN`
+    Paper(&'static str),
+    Http(u16, String),
+    Io(io::Error),
+    /// The page couldn't be decoded or saved
+    Image(image::ImageError),
+    /// Stopped with `Cancel`, and the job deleted
+    Cancelled,
+}
+
+impl ScanFailure {
+    /// Recorded as the scan's failure.
+    pub fn code(&self) -> String {
+        match self {
+            ScanFailure::Paper(code) => code.to_string(),
+            ScanFailure::Http(status, _) => format!("HTTP_{}", status),
+            ScanFailure::Io(_) => "UNREACHABLE".to_string(),
+            ScanFailure::Image(_) => "IMAGE_FAILED".to_string(),
+            ScanFailure::Cancelled => "CANCELLED".to_string(),
+        }
+    }
+
+    /// The HTTP status, or -1 if the device couldn't be reached.
+    pub fn status(&self) -> i32 {
+        match self {
+            ScanFailure::Http(status, _) => *status as i32,
+            _ => -1,
+        }
+    }
+}
+
+impl fmt::Display for ScanFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanFailure::Paper(code) => write!(f, "paper problem: {}", code),
+            ScanFailure::Http(status, body) => write!(f, "HTTP {}: {}", status, body),
+            ScanFailure::Io(e) => write!(f, "could not reach scanner: {}", e),
+            ScanFailure::Image(e) => write!(f, "could not save page: {}", e),
+            ScanFailure::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl From<io::Error> for ScanFailure {
+    fn from(e: io::Error) -> Self {
+        ScanFailure::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ScanFailure {
+    fn from(e: image::ImageError) -> Self {
+        ScanFailure::Image(e)
+    }
+}
+
+/// Leading number of a scan argument, e.g. 300 for `--resolution 300dpi`.
+fn number(arguments: &HashMap<String, String>, name: &str) -> Option<f32> {
+    arguments
+        .iter()
+        .find(|(key, _)| key.trim_start_matches('-') == name)
+        .and_then(|(_, value)| {
+            let digits: String = value
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            digits.parse().ok()
+        })
+}
+
+fn text<'a>(arguments: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    arguments
+        .iter()
+        .find(|(key, _)| key.trim_start_matches('-') == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// A job's `ScanSettings` from scanimage-style arguments: `--resolution`,
+/// `--mode` Color, Gray or Lineart, `--source` Flatbed, ADF or ADF Duplex,
+/// and the `-l`, `-t`, `-x` and `-y` geometry in millimetres. Without
+/// geometry the device scans its whole area.
+pub fn scan_settings(arguments: &HashMap<String, String>) -> String {
+    let mut settings = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <scan:ScanSettings xmlns:scan=\"{}\" xmlns:pwg=\"{}\">\n\
+         <pwg:Version>2.0</pwg:Version>\n",
+        SCAN_NAMESPACE, PWG_NAMESPACE
+    );
+
+    let geometry = ["l", "t", "x", "y"].map(|name| number(arguments, name));
+    if geometry.iter().any(Option::is_some) {
+        // In 300ths of an inch; letter size unless given
+        let units = |mm: Option<f32>, default: u32| {
+            mm.map_or(default, |mm| (mm / 25.4 * 300.0).round() as u32)
+        };
+        settings.push_str(&format!(
+            "<pwg:ScanRegions><pwg:ScanRegion>\
+             <pwg:ContentRegionUnits>escl:ThreeHundredthsOfInches</pwg:ContentRegionUnits>\
+             <pwg:XOffset>{}</pwg:XOffset><pwg:YOffset>{}</pwg:YOffset>\
+             <pwg:Width>{}</pwg:Width><pwg:Height>{}</pwg:Height>\
+             </pwg:ScanRegion></pwg:ScanRegions>\n",
+            units(geometry[0], 0),
+            units(geometry[1], 0),
+            units(geometry[2], 2550),
+            units(geometry[3], 3300)
+        ));
+    }
+
+    let source = text(arguments, "source").unwrap_or("").to_lowercase();
+    let feeder = ["adf", "feeder", "duplex"]
+        .iter()
+        .any(|word| source.contains(word));
+    settings.push_str(&format!(
+        "<pwg:InputSource>{}</pwg:InputSource>\n",
+        if feeder { "Feeder" } else { "Platen" }
+    ));
+    if source.contains("duplex") {
+        settings.push_str("<scan:Duplex>true</scan:Duplex>\n");
+    }
+
+    let mode = text(arguments, "mode").unwrap_or("").to_lowercase();
+    let color_mode = if mode.contains("gray") || mode.contains("grey") {
+        "Grayscale8"
+    } else if mode.contains("lineart") || mode.contains("binary") {
+        "BlackAndWhite1"
+    } else {
+        "RGB24"
+    };
+    settings.push_str(&format!(
+        "<scan:ColorMode>{}</scan:ColorMode>\n",
+        xml::escape(color_mode)
+    ));
+
+    let dpi = number(arguments, "resolution").unwrap_or(300.0).round() as u32;
+    settings.push_str(&format!(
+        "<scan:XResolution>{0}</scan:XResolution>\n<scan:YResolution>{0}</scan:YResolution>\n",
+        dpi
+    ));
+    settings.push_str(
+        "<pwg:DocumentFormat>image/jpeg</pwg:DocumentFormat>\n\
+         <scan:DocumentFormatExt>image/jpeg</scan:DocumentFormatExt>\n\
+         </scan:ScanSettings>\n",
+    );
+    settings
+}
+
+/// Stops a scan from outside the thread running it. The device is told by
+/// deleting the job, which ends the scan there too and frees it for the next.
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<Mutex<CancelState>>);
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: bool,
+    /// The job being run, until it's been deleted
+    job: Option<String>,
+}
+
+impl Cancel {
+    /// Cancels the scan, deleting its job on a thread of its own so async
+    /// callers aren't held up by the device.
+    pub fn cancel(&self) {
+        let job = {
+            let mut state = self.0.lock().unwrap();
+            state.cancelled = true;
+            state.job.take()
+        };
+        if let Some(job) = job {
+            thread::spawn(move || delete_job(&job));
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+
+    /// Records the job just created, or returns false if the scan was
+    /// cancelled while it was being created.
+    fn start(&self, job: &str) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if !state.cancelled {
+            state.job = Some(job.to_string());
+        }
+        !state.cancelled
+    }
+
+    /// Whether the finished job is still there to delete, rather than
+    /// deleted already by `cancel`.
+    fn finish(&self) -> bool {
+        self.0.lock().unwrap().job.take().is_some()
+    }
+}
+
+fn delete_job(job: &str) {
+    if let Err(e) = request("DELETE", job, None) {
+        println!("Failed to delete eSCL job {}: {}", job, e);
+    }
+}
+
+/// Why a request the device refused failed: a paper problem if the feeder
+/// reports one, or else the HTTP status.
+fn refused(url: &str, response: &Response) -> ScanFailure {
+    match Status::load(url)
+        .ok()
+        .and_then(|status| status.paper_failure())
+    {
+        Some(code) => ScanFailure::Paper(code),
+        None => ScanFailure::Http(response.status, response.text().trim().to_string()),
+    }
+}
+
+/// Waits for the job's next document. `None` once a feeder job has run
+/// out of paper after its first page.
+fn next_document(
+    url: &str,
+    job: &str,
+    first: bool,
+    cancel: &Cancel,
+) -> Result<Option<Vec<u8>>, ScanFailure> {
+    let mut document = request("GET", &format!("{}/NextDocument", job), None)?;
+    for _ in 0..DOCUMENT_POLLS {
+        if document.status != 503 || cancel.cancelled() {
+            break;
+        }
+        thread::sleep(DOCUMENT_POLL);
+        document = request("GET", &format!("{}/NextDocument", job), None)?;
+    }
+    // Whatever the device answered, the job is gone or going
+    if cancel.cancelled() {
+        return Err(ScanFailure::Cancelled);
+    }
+    match document.status {
+        200 => Ok(Some(document.body)),
+        // The job has no more pages, which is only a problem if the feeder
+        // says something went wrong
+        404 if !first => match Status::load(url).ok().and_then(|s| s.paper_failure()) {
+            Some(code) if code != "NO_DOCS" => Err(ScanFailure::Paper(code)),
+            _ => Ok(None),
+        },
+        _ => Err(refused(url, &document)),
+    }
+}
+
+/// Scans pages: creates a job, hands each document to `page` as it comes,
+/// until the feeder is empty or there are `limit` of them, then deletes the
+/// job, so the device is free for the next. Blocks until done or until
+/// `cancel` is used. Documents are JPEGs, in the order the device sent
+/// them, which for duplex jobs is each sheet's front then back. Returns how
+/// many there were.
+pub fn scan_pages(
+    url: &str,
+    arguments: &HashMap<String, String>,
+    limit: Option<usize>,
+    cancel: &Cancel,
+    mut page: impl FnMut(Vec<u8>),
+) -> Result<usize, ScanFailure> {
+    let created = request(
+        "POST",
+        &format!("{}/ScanJobs", url),
+        Some(&scan_settings(arguments)),
+    )?;
+    if created.status != 201 {
+        return Err(refused(url, &created));
+    }
+    let location = created
+        .header("Location")
+        .ok_or_else(|| ScanFailure::Http(created.status, "no job location".to_string()))?;
+    // Usually a path on the same device, sometimes a whole URL
+    let job = if location.contains("://") {
+        location.trim_end_matches('/').to_string()
+    } else {
+        let origin_end = url.find("://").map_or(0, |at| at + 3);
+        let origin = match url[origin_end..].find('/') {
+            Some(at) => &url[..origin_end + at],
+            None => url,
+        };
+        format!("{}/{}", origin, location.trim_matches('/'))
+    };
+    if !cancel.start(&job) {
+        delete_job(&job);
+        return Err(ScanFailure::Cancelled);
+    }
+
+    let mut pages = 0;
+    let result = loop {
+        if limit.is_some_and(|limit| pages >= limit) {
+            break Ok(pages);
+        }
+        match next_document(url, &job, pages == 0, cancel) {
+            Ok(Some(document)) => {
+                page(document);
+                pages += 1;
+            }
+            Ok(None) => break Ok(pages),
+            Err(e) => break Err(e),
+        }
+    };
+    if cancel.finish() {
+        delete_job(&job);
+    }
+    result
+}
+
+/// Scans one page. Returns it as the device sent it, a JPEG.
+pub fn scan(
+    url: &str,
+    arguments: &HashMap<String, String>,
+    cancel: &Cancel,
+) -> Result<Vec<u8>, ScanFailure> {
+    let mut first = None;
+    scan_pages(url, arguments, Some(1), cancel, |document| {
+        first = Some(document)
+    })?;
+    first.ok_or(ScanFailure::Paper("NO_DOCS"))
+}
@@ -0,0 +1,232 @@
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+
+use crate::{
+    exports::{ExportFormat, ExportOptions},
+    locale,
+};
+
+const COLUMNS: &str = "id, scan_group_id, format, destination, artifact_path, triggered_by, pages, changes, page_fingerprints, created_at, artifact_id";
+
+/// What an export was made from, kept to spot changes on the next export.
+pub struct ExportContent {
+    /// Covers the options, the group's settings and every page
+    pub hash: String,
+    /// Pages written, or scans bagged
+    pub pages: usize,
+    /// Each page's scan id and a hash of its file and edits, in order
+    pub page_fingerprints: Vec<(i32, String)>,
+    /// The stored copy of the file, for everything but bags
+    pub artifact_id: Option<i32>,
+}
+
+/// A finished export of a group. The artifact can be fetched again from
+/// `/api/exports/{id}` while it is still on disk.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupExport {
+    pub id: i32,
+    pub group_id: i32,
+    pub format: ExportFormat,
+    /// The output path as it was given
+    pub destination: String,
+    /// Absolute path of the file or bag directory that was written
+    pub artifact_path: String,
+    /// Who ran the export, e.g. `cli (alice)`
+    pub triggered_by: String,
+    /// Pages rendered, or scans bagged
+    pub pages: Option<i32>,
+    /// Pages added, removed or edited since the previous export to the same path
+    pub changes: Option<String>,
+    #[graphql(skip)]
+    pub page_fingerprints: Option<Vec<(i32, String)>>,
+    pub created_at: DateTime<Utc>,
+    /// The stored copy of the file, served from `/api/artifacts/{id}` even
+    /// once the file at the destination is gone
+    pub artifact_id: Option<i32>,
+}
+
+impl GroupExport {
+    pub fn record(
+        group_id: i32,
+        options: &ExportOptions,
+        destination: &str,
+        artifact_path: &str,
+        content: &ExportContent,
+        changes: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO exports (scan_group_id, format, destination, artifact_path, triggered_by, content_hash, pages, changes, page_fingerprints, created_at, artifact_id)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            params![
+                group_id,
+                options.format.as_str(),
+                destination,
+                artifact_path,
+                options.triggered_by,
+                content.hash,
+                content.pages as i32,
+                changes,
+                serde_json::to_string(&content.page_fingerprints).unwrap(),
+                Utc::now(),
+                content.artifact_id
+            ],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    /// The group's latest export rendered from the same content, if any.
+    pub fn find_by_content(
+        group_id: i32,
+        content_hash: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<Self>> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM exports WHERE scan_group_id = ? AND content_hash = ?
+                 ORDER BY created_at DESC, id DESC LIMIT 1",
+                COLUMNS
+            ),
+            params![group_id, content_hash],
+            Self::from_row,
+        )
+        .optional()
+    }
+
+    /// The group's latest export written to `artifact_path`, if any.
+    pub fn find_last_at(
+        group_id: i32,
+        artifact_path: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<Self>> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM exports WHERE scan_group_id = ? AND artifact_path = ?
+                 ORDER BY created_at DESC, id DESC LIMIT 1",
+                COLUMNS
+            ),
+            params![group_id, artifact_path],
+            Self::from_row,
+        )
+        .optional()
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM exports WHERE id = ?", COLUMNS),
+            params![id],
+            Self::from_row,
+        )
+    }
+
+    /// The group's exports, newest first.
+    pub fn load_all_by_group(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<GroupExport> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM exports WHERE scan_group_id = ?
+                 ORDER BY created_at DESC, id DESC",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let exports: Vec<GroupExport> = stmt
+            .query_map([group_id], Self::from_row)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        exports
+    }
+
+    /// What changed in `current` since this export, or None if nothing did
+    /// or this export predates page tracking.
+    pub fn describe_changes(&self, current: &[(i32, String)]) -> Option<String> {
+        let previous = self.page_fingerprints.as_ref()?;
+        let fingerprint = |pages: &[(i32, String)], id: i32| {
+            pages
+                .iter()
+                .find(|(page, _)| *page == id)
+                .map(|(_, fingerprint)| fingerprint.clone())
+        };
+
+        let added = current
+            .iter()
+            .filter(|(id, _)| fingerprint(previous, *id).is_none())
+            .count();
+        let removed = previous
+            .iter()
+            .filter(|(id, _)| fingerprint(current, *id).is_none())
+            .count();
+        let edited = current
+            .iter()
+            .filter(|(id, page)| fingerprint(previous, *id).is_some_and(|before| before != *page))
+            .count();
+        let kept = |pages: &[(i32, String)], other: &[(i32, String)]| -> Vec<i32> {
+            pages
+                .iter()
+                .map(|(id, _)| *id)
+                .filter(|id| fingerprint(other, *id).is_some())
+                .collect()
+        };
+        let reordered = kept(previous, current) != kept(current, previous);
+
+        let pages = |count: usize, what: &str| match count {
+            0 => None,
+            1 => Some(format!("1 page {}", what)),
+            _ => Some(format!("{} pages {}", count, what)),
+        };
+        let summary: Vec<String> = [
+            pages(added, "added"),
+            pages(removed, "removed"),
+            pages(edited, "edited"),
+            reordered.then(|| "pages reordered".to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        (!summary.is_empty()).then(|| {
+            format!(
+                "Changed since the export of {}: {}",
+                locale::format_datetime(self.created_at),
+                summary.join(", ")
+            )
+        })
+    }
+
+    fn from_row(row: &duckdb::Row) -> Result<Self> {
+        let format: String = row.get(2)?;
+        let page_fingerprints: Option<String> = row.get(8)?;
+
+        Ok(GroupExport {
+            id: row.get(0)?,
+            group_id: row.get(1)?,
+            format: ExportFormat::from_str(&format),
+            destination: row.get(3)?,
+            artifact_path: row.get(4)?,
+            triggered_by: row.get(5)?,
+            pages: row.get(6)?,
+            changes: row.get(7)?,
+            page_fingerprints: page_fingerprints.and_then(|json| serde_json::from_str(&json).ok()),
+            created_at: row.get(9)?,
+            artifact_id: row.get(10)?,
+        })
+    }
+}
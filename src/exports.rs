@@ -0,0 +1,518 @@
+use std::{
+    fmt, fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use duckdb::DuckdbConnectionManager;
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat, RgbImage};
+
+use crate::{
+    activity::{Activity, ActivityKind},
+    artifacts::{self, Artifact},
+    bagit::{sha256, write_bag},
+    contact_sheet,
+    dewarp::dewarp,
+    dropout::drop_color,
+    epub::{write_epub, EpubPage},
+    export_history::{ExportContent, GroupExport},
+    gutter::remove_gutter_shadow,
+    label, long_image, ocr,
+    pdf::{write_pdf, PdfPage},
+    qr,
+    scans::{Scan, ScanGroup},
+    zip::write_zip,
+    AssetsDir, PublicUrl,
+};
+
+/// Used to size pages when the scan parameters don't say what resolution was used.
+pub const DEFAULT_DPI: f32 = 300.0;
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, clap::ValueEnum, async_graphql::Enum)]
+pub enum ExportFormat {
+    Pdf,
+    /// Thumbnail index sheets with QR codes, to file with the paper originals
+    ContactSheet,
+    /// A single label with the group's QR code, for a folder or box of originals
+    Label,
+    /// A BagIt directory of the original scan files, for preservation systems
+    Bagit,
+    /// A comic book archive of the page images, for e-reader apps
+    Cbz,
+    /// An e-book of the page images, each followed by its OCR text, so
+    /// e-readers can reflow text-heavy groups
+    Epub,
+    /// All pages stacked into one tall PNG, for expense tools that want a
+    /// receipt as a single image
+    LongPng,
+    /// The same as a JPEG, which is smaller but limited to 65535 pixels tall
+    LongJpeg,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::ContactSheet => "contact_sheet",
+            ExportFormat::Label => "label",
+            ExportFormat::Bagit => "bagit",
+            ExportFormat::Cbz => "cbz",
+            ExportFormat::Epub => "epub",
+            ExportFormat::LongPng => "long_png",
+            ExportFormat::LongJpeg => "long_jpeg",
+        }
+    }
+
+    /// File extension of the artifact. Bags are directories and have none.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Pdf | ExportFormat::ContactSheet | ExportFormat::Label => "pdf",
+            ExportFormat::Bagit => "",
+            ExportFormat::Cbz => "cbz",
+            ExportFormat::Epub => "epub",
+            ExportFormat::LongPng => "png",
+            ExportFormat::LongJpeg => "jpg",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Pdf | ExportFormat::ContactSheet | ExportFormat::Label => {
+                "application/pdf"
+            }
+            ExportFormat::Bagit => "application/octet-stream",
+            ExportFormat::Cbz => "application/vnd.comicbook+zip",
+            ExportFormat::Epub => "application/epub+zip",
+            ExportFormat::LongPng => "image/png",
+            ExportFormat::LongJpeg => "image/jpeg",
+        }
+    }
+
+    pub fn from_str(format: &str) -> Self {
+        match format {
+            "contact_sheet" => ExportFormat::ContactSheet,
+            "label" => ExportFormat::Label,
+            "bagit" => ExportFormat::Bagit,
+            "cbz" => ExportFormat::Cbz,
+            "epub" => ExportFormat::Epub,
+            "long_png" => ExportFormat::LongPng,
+            "long_jpeg" => ExportFormat::LongJpeg,
+            _ => ExportFormat::Pdf,
+        }
+    }
+}
+
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Thumbnails on each contact sheet page
+    pub thumbnails_per_page: usize,
+    /// Stamp a QR code linking to the group on the first page of a PDF
+    pub stamp_qr: bool,
+    pub public_url: PublicUrl,
+    /// Recorded in the group's export history
+    pub triggered_by: String,
+    /// Render even if an earlier export of the unchanged group is on disk
+    pub force: bool,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    GroupNotFound,
+    /// The group has no completed scans to export
+    NoPages,
+    Image(String),
+    /// Text could not be read from a page, e.g. tesseract isn't installed
+    Ocr(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::GroupNotFound => write!(f, "group not found"),
+            ExportError::NoPages => write!(f, "group has no completed scans"),
+            ExportError::Image(e) => write!(f, "could not read scan image: {}", e),
+            ExportError::Ocr(e) => write!(f, "could not read text from scan: {}", e),
+            ExportError::Io(e) => write!(f, "could not write export: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Io(e)
+    }
+}
+
+/// Whether any scan in the group is still being captured.
+pub fn has_pending_scans(group: &ScanGroup) -> bool {
+    group
+        .scans
+        .iter()
+        .any(|scan| scan.status == "PENDING" || scan.status == "QUEUED")
+}
+
+/// Renders the group's completed scans, in page order, into the artifact
+/// store and copies the file to `out`, or copies an earlier export if
+/// nothing it was rendered from has changed. Returns the export's history
+/// entry, noting what changed if `out` was exported to before.
+pub fn export_group(
+    group_id: i32,
+    options: &ExportOptions,
+    out: &Path,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<GroupExport, ExportError> {
+    let result = render_group(group_id, options, out, pool, assets_dir);
+    // Counted by the export failure alert
+    if let Err(e) = &result {
+        if let Err(record_error) = Activity::record(
+            ActivityKind::ExportFailed,
+            Some(group_id),
+            None,
+            Some(&options.triggered_by),
+            Some(&e.to_string()),
+            pool,
+        ) {
+            println!(
+                "Failed to record failed export of group {} in activity: {}",
+                group_id, record_error
+            );
+        }
+    }
+    result
+}
+
+fn render_group(
+    group_id: i32,
+    options: &ExportOptions,
+    out: &Path,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<GroupExport, ExportError> {
+    let group = ScanGroup::load(group_id, pool).map_err(|_| ExportError::GroupNotFound)?;
+
+    let scans: Vec<&Scan> = group
+        .scans
+        .iter()
+        .filter(|scan| scan.status == "COMPLETE")
+        .collect();
+    // A label only needs the group
+    if scans.is_empty() && options.format != ExportFormat::Label {
+        return Err(ExportError::NoPages);
+    }
+
+    // Write next to the destination and rename, so a half-written file is
+    // never left at `out` for whatever picks it up.
+    let partial = out.with_extension("partial");
+    let page_fingerprints: Vec<(i32, String)> = scans
+        .iter()
+        .map(|scan| (scan.id.unwrap(), page_fingerprint(scan)))
+        .collect();
+    let hash = content_hash(&group, &page_fingerprints, options);
+
+    if options.format == ExportFormat::Bagit {
+        if partial.exists() {
+            fs::remove_dir_all(&partial)?;
+        }
+        write_bag(&group, &scans, &partial, &options.public_url, assets_dir)?;
+        let content = ExportContent {
+            hash,
+            pages: scans.len(),
+            page_fingerprints,
+            artifact_id: None,
+        };
+        return deliver(&group, options, Some(&partial), out, &content, pool);
+    }
+
+    // Bags are dated, so only rendered files are reused. Exports from before
+    // the artifact store are reused from where they were written.
+    let cached = GroupExport::find_by_content(group.id, &hash, pool)
+        .unwrap()
+        .filter(|_| !options.force)
+        .and_then(|previous| {
+            let stored = previous
+                .artifact_id
+                .and_then(|id| Artifact::load(id, pool).unwrap())
+                .map(|artifact| artifact.disk_path(assets_dir))
+                .filter(|path| path.is_file());
+            let (source, artifact_id) = match stored {
+                Some(path) => (path, previous.artifact_id),
+                None => (PathBuf::from(&previous.artifact_path), None),
+            };
+            source
+                .is_file()
+                .then_some((previous.pages?, source, artifact_id))
+        });
+    if let Some((pages, source, artifact_id)) = cached {
+        let copied = std::path::absolute(out)? != source;
+        if copied {
+            fs::copy(&source, &partial)?;
+        }
+        let content = ExportContent {
+            hash,
+            pages: pages as usize,
+            page_fingerprints,
+            artifact_id,
+        };
+        let partial = copied.then_some(partial.as_path());
+        return deliver(&group, options, partial, out, &content, pool);
+    }
+
+    let pages = match options.format {
+        ExportFormat::Pdf | ExportFormat::Cbz | ExportFormat::Epub => {
+            let stamp = (options.stamp_qr && options.format == ExportFormat::Pdf)
+                .then(|| options.public_url.group_url(group.id));
+            scans
+                .iter()
+                .enumerate()
+                .map(|(i, scan)| {
+                    let stamp = stamp.as_deref().filter(|_| i == 0);
+                    render_page(scan, &group, stamp, assets_dir)
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        ExportFormat::ContactSheet => contact_sheet::render(
+            &group,
+            &scans,
+            options.thumbnails_per_page,
+            &options.public_url,
+            assets_dir,
+        )
+        .map_err(|e| ExportError::Image(e.to_string()))?
+        .into_iter()
+        .map(|sheet| pdf_page(DynamicImage::ImageLuma8(sheet), contact_sheet::DPI))
+        .collect::<Result<Vec<_>, _>>()?,
+        ExportFormat::Label => vec![pdf_page(
+            DynamicImage::ImageLuma8(label::render(&group, &options.public_url)),
+            label::DPI,
+        )?],
+        // Stacked from the full images below, not from JPEG pages
+        ExportFormat::LongPng | ExportFormat::LongJpeg => Vec::new(),
+        ExportFormat::Bagit => unreachable!(),
+    };
+    let page_count = match options.format {
+        ExportFormat::LongPng | ExportFormat::LongJpeg => scans.len(),
+        _ => pages.len(),
+    };
+
+    let staged = artifacts::staging_path(assets_dir)?;
+    let mut file = fs::File::create(&staged)?;
+    match options.format {
+        ExportFormat::Epub => {
+            let epub_pages = pages
+                .iter()
+                .zip(&scans)
+                .map(|(page, scan)| {
+                    let text = ocr::ScanText::for_scan(scan, pool, assets_dir)
+                        .map_err(|e| ExportError::Ocr(e.to_string()))?;
+                    Ok(EpubPage {
+                        jpeg: &page.jpeg,
+                        width_px: page.width_px,
+                        height_px: page.height_px,
+                        text: text.text,
+                    })
+                })
+                .collect::<Result<Vec<_>, ExportError>>()?;
+            write_epub(
+                &group,
+                &epub_pages,
+                &ocr::language_tag(&ocr::languages()),
+                &mut file,
+            )?;
+        }
+        ExportFormat::Cbz => {
+            let names: Vec<String> = (1..=pages.len())
+                .map(|number| format!("{:04}.jpg", number))
+                .collect();
+            let entries: Vec<(&str, &[u8])> = names
+                .iter()
+                .zip(&pages)
+                .map(|(name, page)| (name.as_str(), page.jpeg.as_slice()))
+                .collect();
+            write_zip(&entries, &mut file)?;
+        }
+        ExportFormat::LongPng | ExportFormat::LongJpeg => {
+            let images = scans
+                .iter()
+                .map(|scan| prepare_page(scan, &group, None, assets_dir).map(|(image, _)| image))
+                .collect::<Result<Vec<_>, _>>()?;
+            let long = DynamicImage::ImageRgb8(long_image::concatenate(&images));
+            let mut encoded = Vec::new();
+            if options.format == ExportFormat::LongJpeg {
+                if long.height() > long_image::MAX_JPEG_HEIGHT {
+                    return Err(ExportError::Image(format!(
+                        "{} pixels is too tall for a JPEG, export as a long PNG instead",
+                        long.height()
+                    )));
+                }
+                long.write_with_encoder(JpegEncoder::new_with_quality(
+                    &mut Cursor::new(&mut encoded),
+                    JPEG_QUALITY,
+                ))
+            } else {
+                long.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+            }
+            .map_err(|e| ExportError::Image(e.to_string()))?;
+            file.write_all(&encoded)?;
+        }
+        _ => write_pdf(&pages, &mut file)?,
+    }
+    drop(file);
+    let artifact = Artifact::store(&staged, options.format, pool, assets_dir)?;
+    fs::copy(artifact.disk_path(assets_dir), &partial)?;
+    let content = ExportContent {
+        hash,
+        pages: page_count,
+        page_fingerprints,
+        artifact_id: Some(artifact.id),
+    };
+    deliver(&group, options, Some(&partial), out, &content, pool)
+}
+
+/// Hash of what a page is rendered from: its file and the edits on it.
+pub fn page_fingerprint(scan: &Scan) -> String {
+    let mut page = serde_json::json!([
+        scan.path.as_relative_path(),
+        scan.edited_path
+            .as_ref()
+            .map(|path| path.as_relative_path()),
+        scan.rotation,
+        scan.crop_coordinates,
+        scan.scanned_at.to_rfc3339(),
+        scan.scan_parameters,
+    ]);
+    // Only when set, so turning it on doesn't change every existing page
+    if scan.remove_punch_holes {
+        page.as_array_mut().unwrap().push("removePunchHoles".into());
+    }
+    sha256(page.to_string().as_bytes())
+}
+
+/// Fingerprint of everything the export is rendered from: the options, the
+/// group's settings, and each page, in order.
+fn content_hash(
+    group: &ScanGroup,
+    page_fingerprints: &[(i32, String)],
+    options: &ExportOptions,
+) -> String {
+    let mut content = serde_json::json!({
+        "format": options.format.as_str(),
+        "thumbnailsPerPage": options.thumbnails_per_page,
+        "stampQr": options.stamp_qr,
+        "url": options.public_url.group_url(group.id),
+        "title": group.title,
+        "status": group.status,
+        "comment": group.comment,
+        "tags": group.tags,
+        "dewarp": group.dewarp,
+        "removeGutterShadow": group.remove_gutter_shadow,
+        "colorDropout": group.color_dropout.map(|color| color.as_str()),
+        "pages": page_fingerprints,
+    });
+    // The text layer changes with the languages it is read in
+    if options.format == ExportFormat::Epub {
+        content["ocrLanguages"] = ocr::languages().into();
+    }
+    sha256(content.to_string().as_bytes())
+}
+
+/// Moves the finished export from `partial` to `out`, unless it was already
+/// there, and adds it to the group's history with what changed since the
+/// last export to the same place.
+fn deliver(
+    group: &ScanGroup,
+    options: &ExportOptions,
+    partial: Option<&Path>,
+    out: &Path,
+    content: &ExportContent,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Result<GroupExport, ExportError> {
+    let artifact = std::path::absolute(out)?.to_string_lossy().into_owned();
+    let changes = GroupExport::find_last_at(group.id, &artifact, pool)
+        .unwrap()
+        .and_then(|previous| previous.describe_changes(&content.page_fingerprints));
+
+    if let Some(partial) = partial {
+        fs::rename(partial, out)?;
+    }
+    let export = GroupExport::record(
+        group.id,
+        options,
+        &out.to_string_lossy(),
+        &artifact,
+        content,
+        changes.as_deref(),
+        pool,
+    )
+    .unwrap();
+    if let Err(e) = Activity::record(
+        ActivityKind::ExportDelivered,
+        Some(group.id),
+        None,
+        Some(&options.triggered_by),
+        Some(&export.destination),
+        pool,
+    ) {
+        println!("Failed to record export {} in activity: {}", export.id, e);
+    }
+    Ok(export)
+}
+
+fn render_page(
+    scan: &Scan,
+    group: &ScanGroup,
+    stamp: Option<&str>,
+    assets_dir: &AssetsDir,
+) -> Result<PdfPage, ExportError> {
+    let (image, dpi) = prepare_page(scan, group, stamp, assets_dir)?;
+    pdf_page(DynamicImage::ImageRgb8(image), dpi)
+}
+
+/// The page as exported, with the group's cleanup applied, and its
+/// resolution.
+fn prepare_page(
+    scan: &Scan,
+    group: &ScanGroup,
+    stamp: Option<&str>,
+    assets_dir: &AssetsDir,
+) -> Result<(RgbImage, f32), ExportError> {
+    let mut image = scan
+        .open_image(assets_dir)
+        .map_err(|e| ExportError::Image(e.to_string()))?;
+    if let Some(color) = group.color_dropout {
+        image = drop_color(image, color);
+    }
+    // Shadow first, so the dark gutter isn't mistaken for text when dewarping
+    if group.remove_gutter_shadow {
+        image = remove_gutter_shadow(image);
+    }
+    if group.dewarp {
+        image = dewarp(image);
+    }
+    let dpi = scan.resolution().unwrap_or(DEFAULT_DPI);
+    // Stamped last, so the code isn't bent by dewarping
+    if let Some(url) = stamp {
+        image = qr::stamp(image, url, dpi);
+    }
+    Ok((image.to_rgb8(), dpi))
+}
+
+fn pdf_page(image: DynamicImage, dpi: f32) -> Result<PdfPage, ExportError> {
+    let mut jpeg = Vec::new();
+    image
+        .write_with_encoder(JpegEncoder::new_with_quality(
+            &mut Cursor::new(&mut jpeg),
+            JPEG_QUALITY,
+        ))
+        .map_err(|e| ExportError::Image(e.to_string()))?;
+
+    Ok(PdfPage {
+        jpeg,
+        width_px: image.width(),
+        height_px: image.height(),
+        width_pt: image.width() as f32 * 72.0 / dpi,
+        height_pt: image.height() as f32 * 72.0 / dpi,
+    })
+}
@@ -0,0 +1,177 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::fd::FromRawFd,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+
+/// Where the server's output is kept when there's no journald to catch it.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub path: PathBuf,
+    /// The log is rotated once it would grow past this
+    pub max_bytes: u64,
+    /// ...or once it's been written to for this long; None only rotates by size
+    pub max_age: Option<Duration>,
+    /// Rotated logs kept, as `<path>.1` (newest) to `<path>.<keep>`
+    pub keep: usize,
+}
+
+impl LogConfig {
+    /// From `LOG_FILE`, `LOG_MAX_MB` (default 10), `LOG_ROTATE_HOURS`
+    /// (default 24, 0 for size only) and `LOG_KEEP` (default 5). None if
+    /// `LOG_FILE` isn't set.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("LOG_FILE").ok().filter(|v| !v.is_empty())?;
+        Some(LogConfig {
+            path: path.into(),
+            max_bytes: env::var("LOG_MAX_MB")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(10)
+                * 1024
+                * 1024,
+            max_age: Some(
+                env::var("LOG_ROTATE_HOURS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(24),
+            )
+            .filter(|hours| *hours > 0)
+            .map(|hours| Duration::from_secs(hours * 3600)),
+            keep: env::var("LOG_KEEP")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+        })
+    }
+}
+
+/// Sends everything the process writes to stdout and stderr, its children
+/// and panics included, to the log file as well as the console. Each line
+/// in the file is stamped with the time.
+pub fn start(config: LogConfig) -> io::Result<()> {
+    let mut log = RotatingLog::open(config)?;
+
+    let mut fds = [0; 2];
+    // SAFETY: plain fd calls; the pipe's ends and the console's copy are
+    // each owned by exactly one File below
+    let (console, pipe) = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let console = libc::dup(libc::STDOUT_FILENO);
+        if console < 0
+            || libc::dup2(fds[1], libc::STDOUT_FILENO) < 0
+            || libc::dup2(fds[1], libc::STDERR_FILENO) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        libc::close(fds[1]);
+        (File::from_raw_fd(console), File::from_raw_fd(fds[0]))
+    };
+
+    thread::spawn(move || {
+        let mut console = console;
+        for line in BufReader::new(pipe).split(b'\n') {
+            let Ok(line) = line else {
+                break;
+            };
+            console.write_all(&line).ok();
+            console.write_all(b"\n").ok();
+            // Nowhere left to report to; the console still has the line
+            log.write_line(&line).ok();
+        }
+    });
+    Ok(())
+}
+
+struct RotatingLog {
+    config: LogConfig,
+    file: File,
+    bytes: u64,
+    opened: Instant,
+}
+
+impl RotatingLog {
+    fn open(config: LogConfig) -> io::Result<Self> {
+        if let Some(dir) = config
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let bytes = file.metadata()?.len();
+        Ok(RotatingLog {
+            config,
+            file,
+            bytes,
+            opened: Instant::now(),
+        })
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let stamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ ").to_string();
+        let len = (stamp.len() + line.len() + 1) as u64;
+        let too_big = self.bytes > 0 && self.bytes + len > self.config.max_bytes;
+        let too_old = self
+            .config
+            .max_age
+            .is_some_and(|max_age| self.bytes > 0 && self.opened.elapsed() >= max_age);
+        if too_big || too_old {
+            self.rotate()?;
+        }
+
+        self.file.write_all(stamp.as_bytes())?;
+        self.file.write_all(line)?;
+        self.file.write_all(b"\n")?;
+        self.bytes += len;
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and starts
+    /// a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| -> PathBuf {
+            let mut name = self.config.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            name.into()
+        };
+        if self.config.keep == 0 {
+            fs::remove_file(&self.config.path)?;
+        } else {
+            remove_if_present(&rotated(self.config.keep))?;
+            for n in (1..self.config.keep).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.config.path, rotated(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.path)?;
+        self.bytes = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,108 @@
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+
+/// Who a group has been handed to for review. A group is assigned to at
+/// most one user at a time.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupAssignment {
+    pub group_id: i32,
+    pub user_id: i32,
+    /// The user or API key that made the assignment, if auth identified one
+    pub assigned_by: Option<String>,
+    pub assigned_at: DateTime<Utc>,
+}
+
+/// Published whenever a group is assigned, reassigned or unassigned.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupAssignmentChanged {
+    pub group_id: i32,
+    /// Null once the group is unassigned
+    pub user_id: Option<i32>,
+    pub previous_user_id: Option<i32>,
+    pub assigned_by: Option<String>,
+}
+
+const COLUMNS: &str = "scan_group_id, user_id, assigned_by, assigned_at";
+
+fn row_to_assignment(row: &duckdb::Row) -> duckdb::Result<GroupAssignment> {
+    Ok(GroupAssignment {
+        group_id: row.get(0)?,
+        user_id: row.get(1)?,
+        assigned_by: row.get(2)?,
+        assigned_at: row.get(3)?,
+    })
+}
+
+impl GroupAssignment {
+    pub fn load(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<GroupAssignment>> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM group_assignments WHERE scan_group_id = ?",
+                COLUMNS
+            ),
+            params![group_id],
+            row_to_assignment,
+        )
+        .optional()
+    }
+
+    /// The user's assignments, oldest first.
+    pub fn load_all_by_user(
+        user_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<GroupAssignment> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM group_assignments WHERE user_id = ? ORDER BY assigned_at, scan_group_id",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let assignments: Vec<GroupAssignment> = stmt
+            .query_map([user_id], row_to_assignment)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assignments
+    }
+
+    /// Assigns the group to `user_id`, replacing any earlier assignment, or
+    /// unassigns it. Returns what changed.
+    pub fn assign(
+        group_id: i32,
+        user_id: Option<i32>,
+        assigned_by: Option<String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<GroupAssignmentChanged> {
+        let previous_user_id = Self::load(group_id, pool)?.map(|a| a.user_id);
+
+        let conn = pool.get().unwrap();
+        match user_id {
+            Some(user_id) => conn.execute(
+                "INSERT OR REPLACE INTO group_assignments (scan_group_id, user_id, assigned_by, assigned_at)
+                 VALUES (?, ?, ?, ?)",
+                params![group_id, user_id, assigned_by, Utc::now()],
+            )?,
+            None => conn.execute(
+                "DELETE FROM group_assignments WHERE scan_group_id = ?",
+                params![group_id],
+            )?,
+        };
+
+        Ok(GroupAssignmentChanged {
+            group_id,
+            user_id,
+            previous_user_id,
+            assigned_by,
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+/// A note left on a group by someone reviewing it, optionally about one of
+/// its pages. Replies belong to the thread of the comment they answer.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupComment {
+    pub id: i32,
+    pub group_id: i32,
+    /// The comment that started the thread, or null if this one did
+    pub thread_id: Option<i32>,
+    /// The page the comment is about, if any
+    pub scan_id: Option<i32>,
+    /// The user or API key that wrote it, if auth identified one
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CommentChange {
+    Created,
+    Edited,
+    Deleted,
+}
+
+/// Published whenever a comment on a group is written, edited or deleted.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupCommentChanged {
+    pub change: CommentChange,
+    /// As it was before being deleted
+    pub comment: GroupComment,
+}
+
+const COLUMNS: &str = "id, scan_group_id, thread_id, scan_id, author, body, created_at, edited_at";
+
+fn row_to_comment(row: &duckdb::Row) -> duckdb::Result<GroupComment> {
+    Ok(GroupComment {
+        id: row.get(0)?,
+        group_id: row.get(1)?,
+        thread_id: row.get(2)?,
+        scan_id: row.get(3)?,
+        author: row.get(4)?,
+        body: row.get(5)?,
+        created_at: row.get(6)?,
+        edited_at: row.get(7)?,
+    })
+}
+
+impl GroupComment {
+    pub fn create(
+        group_id: i32,
+        thread_id: Option<i32>,
+        scan_id: Option<i32>,
+        author: Option<String>,
+        body: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO group_comments (scan_group_id, thread_id, scan_id, author, body, created_at)
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+            params![group_id, thread_id, scan_id, author, body, Utc::now()],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM group_comments WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_comment,
+        )
+    }
+
+    /// The group's comments, oldest first. Threads are put together from
+    /// `thread_id`.
+    pub fn load_all_by_group(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<GroupComment> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM group_comments WHERE scan_group_id = ? ORDER BY created_at, id",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let comments: Vec<GroupComment> = stmt
+            .query_map([group_id], row_to_comment)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        comments
+    }
+
+    pub fn edit(id: i32, body: &str, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE group_comments SET body = ?, edited_at = ? WHERE id = ?",
+            params![body, Utc::now(), id],
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    /// Deletes the comment, and its replies if it started a thread.
+    pub fn delete(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM group_comments WHERE id = ? OR thread_id = ?",
+            params![id, id],
+        )?;
+        Ok(deleted > 0)
+    }
+}
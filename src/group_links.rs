@@ -0,0 +1,206 @@
+use std::collections::{HashSet, VecDeque};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+/// How far `traverse` follows links at most.
+pub const MAX_DEPTH: usize = 10;
+
+/// Roles are compared trimmed and in lower case, so `Invoice ` and
+/// `invoice` are the same role.
+pub fn normalize_role(role: &str) -> String {
+    role.trim().to_lowercase()
+}
+
+/// A relationship between two groups, each playing a role in it, e.g. an
+/// `invoice` and its `payment receipt`, or an `original` and its `redacted
+/// copy`. Seen from `group_id`: links made from either end are listed on
+/// both groups, with the roles the right way round.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct GroupLink {
+    pub id: i32,
+    pub group_id: i32,
+    /// What `group_id` is in the relationship
+    pub role: String,
+    pub linked_group_id: i32,
+    pub linked_group_title: String,
+    /// What the linked group is in the relationship
+    pub linked_role: String,
+    /// The user or API key that made the link, if auth identified one
+    pub linked_by: Option<String>,
+    pub linked_at: DateTime<Utc>,
+}
+
+impl GroupLink {
+    /// The group's links, oldest first.
+    pub fn load_all_by_group(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<GroupLink> {
+        let conn = pool.get().unwrap();
+
+        // Each link read from whichever end is `group_id`
+        let mut stmt = conn
+            .prepare(
+                "SELECT l.id, l.from_group_id, l.from_role, l.to_group_id, g.title, l.to_role, l.linked_by, l.linked_at
+                 FROM group_links l JOIN scan_groups g ON g.id = l.to_group_id
+                 WHERE l.from_group_id = ?
+                 UNION ALL
+                 SELECT l.id, l.to_group_id, l.to_role, l.from_group_id, g.title, l.from_role, l.linked_by, l.linked_at
+                 FROM group_links l JOIN scan_groups g ON g.id = l.from_group_id
+                 WHERE l.to_group_id = ?
+                 ORDER BY linked_at, id",
+            )
+            .unwrap();
+
+        let links: Vec<GroupLink> = stmt
+            .query_map([group_id, group_id], |row| {
+                Ok(GroupLink {
+                    id: row.get(0)?,
+                    group_id: row.get(1)?,
+                    role: row.get(2)?,
+                    linked_group_id: row.get(3)?,
+                    linked_group_title: row.get(4)?,
+                    linked_role: row.get(5)?,
+                    linked_by: row.get(6)?,
+                    linked_at: row.get(7)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        links
+    }
+
+    /// Links the two groups, unless they're already linked in those roles.
+    /// Returns the link as seen from `group_id`.
+    pub fn link(
+        group_id: i32,
+        role: &str,
+        linked_group_id: i32,
+        linked_role: &str,
+        linked_by: Option<String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<GroupLink> {
+        let (role, linked_role) = (normalize_role(role), normalize_role(linked_role));
+        let existing = Self::load_all_by_group(group_id, pool)
+            .into_iter()
+            .find(|link| {
+                link.linked_group_id == linked_group_id
+                    && link.role == role
+                    && link.linked_role == linked_role
+            });
+        if let Some(link) = existing {
+            return Ok(link);
+        }
+
+        let conn = pool.get().unwrap();
+        let id: i32 = conn.query_row(
+            "INSERT INTO group_links (from_group_id, from_role, to_group_id, to_role, linked_by, linked_at)
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+            params![
+                group_id,
+                role,
+                linked_group_id,
+                linked_role,
+                linked_by,
+                Utc::now()
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(Self::load_all_by_group(group_id, pool)
+            .into_iter()
+            .find(|link| link.id == id)
+            .unwrap())
+    }
+
+    /// Removes the links between the two groups, from either end, or only
+    /// those where the linked group plays `linked_role`. Returns how many
+    /// were removed.
+    pub fn unlink(
+        group_id: i32,
+        linked_group_id: i32,
+        linked_role: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<usize> {
+        let conn = pool.get().unwrap();
+        match linked_role.map(normalize_role) {
+            Some(linked_role) => conn.execute(
+                "DELETE FROM group_links
+                 WHERE (from_group_id = ? AND to_group_id = ? AND to_role = ?)
+                    OR (from_group_id = ? AND to_group_id = ? AND from_role = ?)",
+                params![
+                    group_id,
+                    linked_group_id,
+                    linked_role,
+                    linked_group_id,
+                    group_id,
+                    linked_role
+                ],
+            ),
+            None => conn.execute(
+                "DELETE FROM group_links
+                 WHERE (from_group_id = ? AND to_group_id = ?)
+                    OR (from_group_id = ? AND to_group_id = ?)",
+                params![group_id, linked_group_id, linked_group_id, group_id],
+            ),
+        }
+    }
+
+    /// Follows links out from the group, breadth first, up to `depth` links
+    /// away, e.g. from an invoice to its receipt and on to the receipt's
+    /// bank statement. With `linked_role`, only links to groups in that
+    /// role are followed. Each group is reached once, by the first link
+    /// found to it.
+    pub fn traverse(
+        group_id: i32,
+        linked_role: Option<&str>,
+        depth: usize,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<GroupLink> {
+        let mut seen = HashSet::from([group_id]);
+        let mut queue = VecDeque::from([(group_id, 0)]);
+        let mut found = Vec::new();
+        while let Some((id, distance)) = queue.pop_front() {
+            if distance >= depth.min(MAX_DEPTH) {
+                continue;
+            }
+            for link in Self::load_all_by_group(id, pool) {
+                if linked_role.is_some_and(|role| link.linked_role != normalize_role(role)) {
+                    continue;
+                }
+                if seen.insert(link.linked_group_id) {
+                    queue.push_back((link.linked_group_id, distance + 1));
+                    found.push(link);
+                }
+            }
+        }
+        found
+    }
+
+    /// Moves the links of a group being merged away onto the group it's
+    /// merged into, dropping any that would link that group to itself.
+    pub fn move_to(
+        group_id: i32,
+        into_group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE group_links SET from_group_id = ? WHERE from_group_id = ?",
+            params![into_group_id, group_id],
+        )?;
+        conn.execute(
+            "UPDATE group_links SET to_group_id = ? WHERE to_group_id = ?",
+            params![into_group_id, group_id],
+        )?;
+        conn.execute(
+            "DELETE FROM group_links WHERE from_group_id = to_group_id",
+            params![],
+        )?;
+        Ok(())
+    }
+}
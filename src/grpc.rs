@@ -0,0 +1,295 @@
+// tonic returns its large `Status` everywhere; boxing it here would only
+// mean unboxing it again at the trait
+#![allow(clippy::result_large_err)]
+
+use std::{net::SocketAddr, pin::Pin};
+
+use async_graphql::{InputType, Name, Value};
+use futures_util::{Stream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    app_context::AppContext,
+    auth::{self, Principal, Scope},
+    contact_sheet,
+    exports::{export_group, ExportError, ExportFormat, ExportOptions},
+    scan_templates::TemplateVariable,
+    scanners::{ScanProgress, ScannerActivity, ScannerState},
+    scans::Scan,
+    schema::{self, NewScan},
+    simple_broker::SimpleBroker,
+};
+
+mod proto {
+    tonic::include_proto!("scanserv");
+}
+
+use proto::scanserv_server::{Scanserv, ScanservServer};
+
+/// Bytes of the exported file per message, well under gRPC's 4 MiB default
+const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+
+type EventStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// The gRPC side of the API, for backends where GraphQL over HTTP is
+/// awkward. It shares the server's state with the GraphQL resolvers.
+pub struct GrpcService {
+    app: AppContext,
+}
+
+/// Serves the gRPC API on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr, app: AppContext) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(ScanservServer::new(GrpcService { app }))
+        .serve(addr)
+        .await
+}
+
+impl GrpcService {
+    /// The caller, from the same `authorization` an HTTP request would send,
+    /// or an error if auth is required and it may not use `scope`.
+    fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        scope: Scope,
+    ) -> Result<Option<Principal>, Status> {
+        let headers = request.metadata().clone().into_headers();
+        let principal = auth::principal_from_headers(&headers, &self.app.pool);
+        if !self.app.auth_config.required {
+            return Ok(principal);
+        }
+        match principal {
+            Some(principal) if principal.has_scope(scope) => Ok(Some(principal)),
+            Some(_) => Err(Status::permission_denied("missing required scope")),
+            None => Err(Status::unauthenticated("Unauthorized")),
+        }
+    }
+}
+
+/// A GraphQL enum value from its name, e.g. `HIGH`.
+fn enum_value<T: InputType>(field: &str, name: &str) -> Result<T, Status> {
+    T::parse(Some(Value::Enum(Name::new(name))))
+        .map_err(|_| Status::invalid_argument(format!("Unknown {} {}", field, name)))
+}
+
+/// Whether the device is done with the scan, one way or another.
+fn finished(status: &str) -> bool {
+    !matches!(status, "QUEUED" | "PENDING")
+}
+
+impl From<ScannerActivity> for proto::ScannerActivityEvent {
+    fn from(activity: ScannerActivity) -> Self {
+        proto::ScannerActivityEvent {
+            device: activity.device,
+            state: activity.state.to_value().to_string(),
+            scan_id: activity.scan_id,
+            failure: activity.failure,
+            waiting: activity.waiting,
+            started_by: activity.started_by,
+            at: activity.at.to_rfc3339(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Scanserv for GrpcService {
+    async fn scan(
+        &self,
+        request: Request<proto::ScanRequest>,
+    ) -> Result<Response<proto::ScanReply>, Status> {
+        let principal = self.authorize(&request, Scope::Scan)?;
+        let request = request.into_inner();
+        let scan = NewScan {
+            name: request.name,
+            parameters: request.parameters,
+            preset_id: request.preset_id,
+            group_id: request.group_id,
+            priority: match request.priority {
+                Some(priority) => enum_value("priority", &priority)?,
+                None => Default::default(),
+            },
+            paper_size: match request.paper_size {
+                Some(paper_size) => Some(enum_value("paper size", &paper_size)?),
+                None => None,
+            },
+            variables: request
+                .variables
+                .into_iter()
+                .map(|(name, value)| TemplateVariable { name, value })
+                .collect(),
+            batch: request.batch,
+            duplex: request.duplex,
+        };
+        let started_by = principal.as_ref().map(Principal::attribution);
+        let scan_id = schema::start_scan(&self.app, scan, started_by)
+            .await
+            .map_err(|e| Status::invalid_argument(e.message))?;
+        Ok(Response::new(proto::ScanReply { scan_id }))
+    }
+
+    async fn cancel_scan(
+        &self,
+        request: Request<proto::CancelScanRequest>,
+    ) -> Result<Response<proto::CancelScanReply>, Status> {
+        self.authorize(&request, Scope::Scan)?;
+        let cancelled = self
+            .app
+            .scanner_manager
+            .cancel_scan(request.into_inner().scan_id);
+        Ok(Response::new(proto::CancelScanReply { cancelled }))
+    }
+
+    type WatchScanStream = EventStream<proto::ScanEvent>;
+
+    async fn watch_scan(
+        &self,
+        request: Request<proto::WatchScanRequest>,
+    ) -> Result<Response<Self::WatchScanStream>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let scan_id = request.into_inner().scan_id;
+
+        // Subscribe before looking at the scan so nothing in between is missed
+        let mut progress = Box::pin(SimpleBroker::<ScanProgress>::subscribe());
+        let mut activity = Box::pin(SimpleBroker::<ScannerActivity>::subscribe());
+        let pool = self.app.pool.clone();
+        let scan = Scan::load(scan_id, &pool).map_err(|_| Status::not_found("No such scan"))?;
+        let (device, status) = (scan.scanner, scan.status);
+        // A batch's first page is finished while the rest are still feeding
+        let feeding = self.app.scanner_manager.current_activity().iter().any(|a| {
+            a.device == device
+                && matches!(a.state, ScannerState::Scanning | ScannerState::PoweringOn)
+                && a.scan_id >= scan_id
+        });
+
+        let events = async_stream::stream! {
+            let mut percent = 0.0;
+            if !finished(&status) || feeding {
+                loop {
+                    tokio::select! {
+                        Some(event) = progress.next() => {
+                            if event.scan_id == scan_id {
+                                percent = event.percent;
+                                yield Ok(proto::ScanEvent {
+                                    scan_id,
+                                    percent,
+                                    status: None,
+                                    failure: None,
+                                });
+                            }
+                        }
+                        Some(event) = activity.next() => {
+                            // The device goes idle or errors once the scan, or
+                            // the whole batch, is done; or after an earlier
+                            // scan, while this one is still queued
+                            let settled = event.device == device
+                                && matches!(event.state, ScannerState::Idle | ScannerState::Error);
+                            if settled
+                                && Scan::load(scan_id, &pool).is_ok_and(|scan| finished(&scan.status))
+                            {
+                                break;
+                            }
+                        }
+                        else => break,
+                    }
+                }
+            }
+            match Scan::load(scan_id, &pool) {
+                Ok(scan) => {
+                    if scan.status == "COMPLETE" {
+                        percent = 100.0;
+                    }
+                    yield Ok(proto::ScanEvent {
+                        scan_id,
+                        percent,
+                        failure: Scan::load_failure(scan_id, &pool).ok().flatten(),
+                        status: Some(scan.status),
+                    });
+                }
+                Err(_) => yield Err(Status::not_found("The scan was deleted")),
+            }
+        };
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    type ScannerActivityStream = EventStream<proto::ScannerActivityEvent>;
+
+    async fn scanner_activity(
+        &self,
+        request: Request<proto::ScannerActivityRequest>,
+    ) -> Result<Response<Self::ScannerActivityStream>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let device = request.into_inner().device;
+
+        // Subscribe before taking the snapshot so nothing in between is missed
+        let updates = SimpleBroker::<ScannerActivity>::subscribe();
+        let current = self.app.scanner_manager.current_activity();
+        let events = futures_util::stream::iter(current)
+            .chain(updates)
+            .filter(move |event| {
+                let res = device.as_ref().is_none_or(|device| event.device == *device);
+                async move { res }
+            })
+            .map(|event| Ok(event.into()));
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    type ExportGroupStream = EventStream<proto::ExportChunk>;
+
+    async fn export_group(
+        &self,
+        request: Request<proto::ExportGroupRequest>,
+    ) -> Result<Response<Self::ExportGroupStream>, Status> {
+        let principal = self.authorize(&request, Scope::Read)?;
+        let request = request.into_inner();
+        let group_id = request.group_id;
+        let format: ExportFormat = enum_value("format", &request.format)?;
+
+        let options = ExportOptions {
+            format,
+            thumbnails_per_page: contact_sheet::DEFAULT_THUMBNAILS_PER_PAGE,
+            stamp_qr: false,
+            public_url: self.app.public_url.clone(),
+            triggered_by: match &principal {
+                Some(principal) => format!("grpc ({})", principal.name()),
+                None => "grpc".to_string(),
+            },
+            force: request.force,
+        };
+        let dir = std::env::temp_dir().join("scanserv-downloads");
+        let file_name = format!("group_{}.{}", group_id, format.extension());
+        let out = dir.join(&file_name);
+        let (pool, assets_dir) = (self.app.pool.clone(), self.app.assets_dir.clone());
+        let (export, contents) = tokio::task::spawn_blocking(move || {
+            std::fs::create_dir_all(&dir).map_err(|e| Status::internal(e.to_string()))?;
+            let export = export_group(group_id, &options, &out, &pool, &assets_dir).map_err(
+                |e| match e {
+                    ExportError::GroupNotFound => Status::not_found(e.to_string()),
+                    ExportError::NoPages => Status::failed_precondition(e.to_string()),
+                    e => Status::internal(e.to_string()),
+                },
+            )?;
+            let contents = std::fs::read(&export.artifact_path)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            Ok::<_, Status>((export, contents))
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+
+        let mut chunks: Vec<proto::ExportChunk> = contents
+            .chunks(EXPORT_CHUNK_BYTES)
+            .map(|data| proto::ExportChunk {
+                data: data.to_vec(),
+                ..Default::default()
+            })
+            .collect();
+        if chunks.is_empty() {
+            chunks.push(proto::ExportChunk::default());
+        }
+        chunks[0].export_id = Some(export.id);
+        chunks[0].file_name = Some(file_name);
+        chunks[0].content_type = Some(format.content_type().to_string());
+        Ok(Response::new(Box::pin(futures_util::stream::iter(
+            chunks.into_iter().map(Ok),
+        ))))
+    }
+}
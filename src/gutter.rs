@@ -0,0 +1,77 @@
+use image::{DynamicImage, GrayImage};
+
+/// Brightness percentile taken as a column's paper colour, high enough to
+/// skip over the text on it.
+const PAPER_PERCENTILE: f32 = 0.9;
+/// Columns are averaged with this many neighbours each side, as a fraction
+/// of the page width, so a column of dense text doesn't read as shadow.
+const SMOOTHING: f32 = 0.01;
+/// Columns within this fraction of the page's paper colour are left alone.
+const TOLERANCE: f32 = 0.03;
+/// Deep shadows are lightened at most this much, so black binding or the
+/// scanner lid beyond the page edge isn't blown out to grey noise.
+const MAX_GAIN: f32 = 3.0;
+
+/// Paper colour of each column, smoothed across neighbouring columns.
+fn paper_levels(gray: &GrayImage) -> Vec<f32> {
+    let rank = ((gray.height() - 1) as f32 * PAPER_PERCENTILE) as usize;
+    let levels: Vec<f32> = (0..gray.width())
+        .map(|x| {
+            let mut column: Vec<u8> = (0..gray.height())
+                .map(|y| gray.get_pixel(x, y)[0])
+                .collect();
+            *column.select_nth_unstable(rank).1 as f32
+        })
+        .collect();
+
+    let radius = ((gray.width() as f32 * SMOOTHING) as usize).max(1);
+    (0..levels.len())
+        .map(|x| {
+            let window = &levels[x.saturating_sub(radius)..(x + radius + 1).min(levels.len())];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect()
+}
+
+/// Lightens the shadow that a bound book's gutter casts on a flatbed scan.
+///
+/// The shadow runs parallel to the spine, so each column is brightened until
+/// its paper matches the paper across the rest of the page. Columns that are
+/// already as light are untouched.
+pub fn remove_gutter_shadow(image: DynamicImage) -> DynamicImage {
+    if image.width() == 0 || image.height() == 0 {
+        return image;
+    }
+    let levels = paper_levels(&image.to_luma8());
+
+    let mut sorted = levels.clone();
+    sorted.sort_by(f32::total_cmp);
+    // The median column is clear of the gutter unless the shadow covers half the page
+    let paper = sorted[sorted.len() / 2];
+    if paper <= 0.0 {
+        return image;
+    }
+
+    let gains: Vec<f32> = levels
+        .iter()
+        .map(|level| {
+            if *level >= paper * (1.0 - TOLERANCE) {
+                1.0
+            } else {
+                (paper / level.max(1.0)).min(MAX_GAIN)
+            }
+        })
+        .collect();
+    if gains.iter().all(|gain| *gain == 1.0) {
+        return image;
+    }
+
+    let mut rgb = image.to_rgb8();
+    for (x, _, pixel) in rgb.enumerate_pixels_mut() {
+        let gain = gains[x as usize];
+        for channel in pixel.0.iter_mut() {
+            *channel = (*channel as f32 * gain).min(255.0) as u8;
+        }
+    }
+    DynamicImage::ImageRgb8(rgb)
+}
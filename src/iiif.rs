@@ -0,0 +1,89 @@
+use serde_json::{json, Value};
+
+use crate::{locale, scans::ScanGroup, AssetsDir, PublicUrl};
+
+/// A language map with no particular language, which is what titles and
+/// comments typed into the UI are.
+fn text(value: &str) -> Value {
+    json!({ "none": [value] })
+}
+
+/// IIIF Presentation API 3.0 manifest of the group's completed pages, in
+/// page order. Each page is painted from the scan's IIIF image service, so
+/// viewers can tile it. Pages whose image can't be read are left out.
+pub fn manifest(group: &ScanGroup, public_url: &PublicUrl, assets_dir: &AssetsDir) -> Value {
+    let manifest_id = public_url.manifest_url(group.id);
+
+    let canvases: Vec<Value> = group
+        .scans
+        .iter()
+        .filter(|scan| scan.status == "COMPLETE")
+        .filter_map(|scan| {
+            let (width, height) = scan.dimensions(assets_dir).ok()?;
+            Some((scan.id?, width, height))
+        })
+        .enumerate()
+        .map(|(i, (scan_id, width, height))| {
+            let canvas_id = format!("{}/canvas/{}", manifest_id, scan_id);
+            let service_id = public_url.image_service_url(scan_id);
+            json!({
+                "id": canvas_id,
+                "type": "Canvas",
+                "label": text(&format!("p. {}", i + 1)),
+                "width": width,
+                "height": height,
+                "items": [{
+                    "id": format!("{}/page", canvas_id),
+                    "type": "AnnotationPage",
+                    "items": [{
+                        "id": format!("{}/image", canvas_id),
+                        "type": "Annotation",
+                        "motivation": "painting",
+                        "target": canvas_id,
+                        "body": {
+                            "id": format!("{}/full/max/0/default.jpg", service_id),
+                            "type": "Image",
+                            "format": "image/jpeg",
+                            "width": width,
+                            "height": height,
+                            "service": [{
+                                "id": service_id,
+                                "type": "ImageService3",
+                                "profile": "level0",
+                            }],
+                        },
+                    }],
+                }],
+            })
+        })
+        .collect();
+
+    let mut manifest = json!({
+        "@context": "http://iiif.io/api/presentation/3/context.json",
+        "id": manifest_id,
+        "type": "Manifest",
+        "label": text(&group.title),
+        "behavior": ["paged"],
+        "homepage": [{
+            "id": public_url.group_url(group.id),
+            "type": "Text",
+            "label": text(&group.title),
+            "format": "text/html",
+        }],
+        "metadata": [{
+            "label": { "en": ["Created"] },
+            "value": text(&locale::format_date(group.created_at)),
+        }],
+        "items": canvases,
+    });
+    if !group.comment.is_empty() {
+        manifest["summary"] = text(&group.comment);
+    }
+    if !group.tags.is_empty() {
+        manifest["metadata"].as_array_mut().unwrap().push(json!({
+            "label": { "en": ["Tags"] },
+            "value": text(&group.tags.join(", ")),
+        }));
+    }
+    manifest
+}
@@ -0,0 +1,336 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use chrono::{DateTime, Utc};
+use duckdb::DuckdbConnectionManager;
+use image::ImageFormat;
+
+use crate::{
+    classification, contact_sheet,
+    destination_mappings::DestinationMapping,
+    exports::{export_group, ExportFormat, ExportOptions},
+    ingest_rules::{IngestRule, IngestSource},
+    locale, processing_profiles,
+    scans::{Scan, ScanGroup},
+    uploads::Upload,
+    validation, AssetsDir, PublicUrl,
+};
+
+/// A page pushed to us by a device, stored under `extension` as sent.
+pub struct IncomingPage {
+    pub contents: Vec<u8>,
+    pub extension: &'static str,
+}
+
+/// Where a group of incoming pages came from.
+pub struct IncomingDocument {
+    pub title: String,
+    pub comment: String,
+    /// Recorded as each scan's scanner
+    pub source: IngestSource,
+    /// What ingest rules for the source match against, e.g. recipient addresses
+    pub routes: Vec<String>,
+    pub scan_parameters: HashMap<String, String>,
+    pub scanned_at: DateTime<Utc>,
+    pub pages: Vec<IncomingPage>,
+    /// The upload it arrived in, whose row records pages as they're filed
+    pub upload_id: Option<i32>,
+}
+
+#[derive(Debug)]
+pub enum IngestError {
+    Io(std::io::Error),
+    Db(duckdb::Error),
+    /// Every page failed to decode
+    NoReadablePages,
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::Io(e) => write!(f, "could not write scan: {}", e),
+            IngestError::Db(e) => write!(f, "could not save scan: {}", e),
+            IngestError::NoReadablePages => write!(f, "none of the pages could be read"),
+        }
+    }
+}
+
+impl From<std::io::Error> for IngestError {
+    fn from(e: std::io::Error) -> Self {
+        IngestError::Io(e)
+    }
+}
+
+impl From<duckdb::Error> for IngestError {
+    fn from(e: duckdb::Error) -> Self {
+        IngestError::Db(e)
+    }
+}
+
+/// JPEG images embedded in a PDF, which is how most devices wrap the pages
+/// they scan. PDFs with any other kind of page content yield nothing.
+fn pdf_jpegs(pdf: &[u8]) -> Vec<Vec<u8>> {
+    let find = |from: usize, needle: &[u8]| {
+        pdf.get(from..)?
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map(|i| from + i)
+    };
+
+    let mut jpegs = Vec::new();
+    let mut at = 0;
+    while let Some(filter) = find(at, b"/DCTDecode") {
+        let Some(stream) = find(filter, b"stream") else {
+            break;
+        };
+        let mut start = stream + b"stream".len();
+        if pdf.get(start) == Some(&b'\r') {
+            start += 1;
+        }
+        if pdf.get(start) == Some(&b'\n') {
+            start += 1;
+        }
+        let Some(end) = find(start, b"endstream") else {
+            break;
+        };
+        let jpeg = pdf[start..end].trim_ascii_end();
+        if jpeg.starts_with(&[0xff, 0xd8]) {
+            jpegs.push(jpeg.to_vec());
+        }
+        at = end;
+    }
+    jpegs
+}
+
+/// The pages in a file a device sent. PNG and JPEG files are a page each;
+/// PDFs give their embedded JPEGs. Anything else, and files over the size
+/// limit or that look like programs, give no pages.
+pub fn file_pages(contents: Vec<u8>) -> Vec<IncomingPage> {
+    if let Err(rejection) = validation::check_file(&contents) {
+        println!("Rejected incoming file: {}", rejection);
+        return Vec::new();
+    }
+    match image::guess_format(&contents) {
+        Ok(ImageFormat::Png) => vec![IncomingPage {
+            contents,
+            extension: "png",
+        }],
+        Ok(ImageFormat::Jpeg) => vec![IncomingPage {
+            contents,
+            extension: "jpg",
+        }],
+        _ if contents.starts_with(b"%PDF-") => pdf_jpegs(&contents)
+            .into_iter()
+            .map(|jpeg| IncomingPage {
+                contents: jpeg,
+                extension: "jpg",
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The group a document's pages go into: the rule's default group if it has
+/// one, otherwise a new scanning group. The rule's tags and settings are
+/// added to it.
+fn target_group(
+    document: &IncomingDocument,
+    rule: Option<&IngestRule>,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Result<ScanGroup, IngestError> {
+    let mut group = match rule.and_then(|rule| rule.group_title.as_deref()) {
+        Some(title) => ScanGroup::find_or_create_by_title(title, pool)?,
+        None => {
+            let mut group = ScanGroup::create("scanning".to_string());
+            group.title = document.title.clone();
+            group.comment = document.comment.clone();
+            group
+        }
+    };
+    if let Some(rule) = rule {
+        for tag in &rule.tags {
+            if !group.tags.contains(tag) {
+                group.tags.push(tag.clone());
+            }
+        }
+        group.dewarp |= rule.dewarp;
+        group.remove_gutter_shadow |= rule.remove_gutter_shadow;
+        if rule.color_dropout.is_some() {
+            group.color_dropout = rule.color_dropout;
+        }
+    }
+    group.save(pool)?;
+    Ok(group)
+}
+
+/// Exports the group as a PDF into `export_dir`. The pages are already
+/// imported, so a failed export is reported, and returned for the caller
+/// to record, rather than failing the import.
+pub fn export_to_destination(
+    group_id: i32,
+    export_dir: &str,
+    triggered_by: String,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<(), String> {
+    let options = ExportOptions {
+        format: ExportFormat::Pdf,
+        thumbnails_per_page: contact_sheet::DEFAULT_THUMBNAILS_PER_PAGE,
+        stamp_qr: false,
+        public_url: public_url.clone(),
+        triggered_by,
+        force: false,
+    };
+    let out = Path::new(export_dir).join(format!("group_{}.pdf", group_id));
+    let exported = std::fs::create_dir_all(export_dir)
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            export_group(group_id, &options, &out, pool, assets_dir).map_err(|e| e.to_string())
+        });
+    match exported {
+        Ok(export) => {
+            match export.changes {
+                Some(changes) => println!(
+                    "Exported group {} to {}: {}",
+                    group_id,
+                    out.display(),
+                    changes
+                ),
+                None => println!("Exported group {} to {}", group_id, out.display()),
+            }
+            write_metadata(group_id, export_dir, &out, pool, public_url);
+            Ok(())
+        }
+        Err(e) => {
+            println!(
+                "Failed to export group {} to {}: {}",
+                group_id,
+                out.display(),
+                e
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Writes the destination's mapped metadata beside the export, if it has a
+/// mapping. A failure here is logged but doesn't fail the export.
+fn write_metadata(
+    group_id: i32,
+    export_dir: &str,
+    out: &Path,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    public_url: &PublicUrl,
+) {
+    let mapping = match DestinationMapping::find(export_dir, pool) {
+        Ok(Some(mapping)) => mapping,
+        Ok(None) => return,
+        Err(e) => {
+            println!("Failed to load metadata mapping for {}: {}", export_dir, e);
+            return;
+        }
+    };
+    let written = ScanGroup::load(group_id, pool)
+        .map_err(|e| e.to_string())
+        .and_then(|group| {
+            mapping
+                .write_sidecar(&group, out, public_url)
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = written {
+        println!(
+            "Failed to write metadata for group {} to {}: {}",
+            group_id, export_dir, e
+        );
+    }
+}
+
+/// Files the document's pages, in order, as completed scans in a group
+/// chosen by the first ingest rule that matches it, runs the classification
+/// rules over the group, then exports the group if the ingest rule names a
+/// destination.
+pub fn import_document(
+    document: &IncomingDocument,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<ScanGroup, IngestError> {
+    // Decoded in a worker first, so a malformed file can't take the server down
+    let pages: Vec<(usize, &IncomingPage)> = document
+        .pages
+        .iter()
+        .enumerate()
+        .filter(|(i, page)| match validation::check_page(&page.contents) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("Skipping page {} of {}: {}", i + 1, document.title, e);
+                false
+            }
+        })
+        .collect();
+    if pages.is_empty() {
+        return Err(IngestError::NoReadablePages);
+    }
+
+    let rule = IngestRule::find(document.source, &document.routes, pool)?;
+    let group = target_group(document, rule.as_ref(), pool)?;
+
+    std::fs::create_dir_all(Path::new(&assets_dir.0).join("scans"))?;
+    let received = locale::filename_stamp(Utc::now());
+    // A default group may already hold pages; number on from them
+    let first_page = group.scans.len() + 1;
+    for (i, page) in pages {
+        let path = Path::new("scans")
+            .join(format!(
+                "{}_{}_{}_{}.{}",
+                document.source.as_str(),
+                received,
+                group.id,
+                first_page + i,
+                page.extension
+            ))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut scan = Scan::new(
+            "COMPLETE".to_string(),
+            path,
+            document.source.as_str().to_string(),
+            document.scan_parameters.clone(),
+            document.scanned_at,
+        );
+        scan.remove_punch_holes = rule.as_ref().is_some_and(|rule| rule.remove_punch_holes);
+        assets_dir.write(&scan.path, &page.contents)?;
+        scan.save(pool)?;
+        scan.set_group(group.id, pool)?;
+        if let Err(e) = processing_profiles::process_scan(scan.id.unwrap(), pool, assets_dir) {
+            println!(
+                "Failed to process imported page {}: {}",
+                scan.id.unwrap(),
+                e
+            );
+        }
+        if let Some(upload_id) = document.upload_id {
+            Upload::set_pages_imported(upload_id, i + 1, group.id, pool)?;
+        }
+    }
+
+    let group = classification::classify_after_import(group.id, pool, assets_dir, public_url)?;
+
+    if let Some(rule) = &rule {
+        if let Some(export_dir) = &rule.export_dir {
+            let triggered_by = format!("ingest rule {}", rule.id);
+            export_to_destination(
+                group.id,
+                export_dir,
+                triggered_by,
+                pool,
+                assets_dir,
+                public_url,
+            )
+            .ok();
+        }
+    }
+
+    Ok(group)
+}
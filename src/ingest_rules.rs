@@ -0,0 +1,216 @@
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+use serde::{Deserialize, Serialize};
+
+use crate::dropout::DropoutColor;
+
+/// Where pushed documents arrive from.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestSource {
+    /// The IMAP mailbox; routed by recipient address
+    Email,
+    /// The FTP drop folder; routed by FTP user. Files dropped in the folder
+    /// itself have no user and only match catch-all rules.
+    Ftp,
+    /// The upload endpoint phones share to; routed by API key name or username
+    Upload,
+}
+
+impl IngestSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IngestSource::Email => "email",
+            IngestSource::Ftp => "ftp",
+            IngestSource::Upload => "upload",
+        }
+    }
+
+    pub fn from_str(source: &str) -> Self {
+        match source {
+            "ftp" => IngestSource::Ftp,
+            "upload" => IngestSource::Upload,
+            _ => IngestSource::Email,
+        }
+    }
+}
+
+/// How documents from one source and route are classified on import, so a
+/// device's "invoices" button lands documents already filed.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct IngestRule {
+    pub id: i32,
+    pub source: IngestSource,
+    /// Recipient address, FTP user or uploader to match, case-insensitively. Empty
+    /// matches anything from the source that no other rule routes.
+    pub route: String,
+    /// Add pages to the latest group with this title instead of a new group
+    pub group_title: Option<String>,
+    /// Added to the group's tags
+    pub tags: Vec<String>,
+    pub dewarp: bool,
+    pub remove_gutter_shadow: bool,
+    /// Set as the group's dropout colour, for forms from this route
+    pub color_dropout: Option<DropoutColor>,
+    /// Clean punch holes and edge shadows off each imported page
+    pub remove_punch_holes: bool,
+    /// Export the group as a PDF into this directory after each import
+    pub export_dir: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(InputObject, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestRuleInput {
+    pub source: IngestSource,
+    #[graphql(default)]
+    #[serde(default)]
+    pub route: String,
+    pub group_title: Option<String>,
+    #[graphql(default)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[graphql(default)]
+    #[serde(default)]
+    pub dewarp: bool,
+    #[graphql(default)]
+    #[serde(default)]
+    pub remove_gutter_shadow: bool,
+    pub color_dropout: Option<DropoutColor>,
+    #[graphql(default)]
+    #[serde(default)]
+    pub remove_punch_holes: bool,
+    pub export_dir: Option<String>,
+}
+
+const COLUMNS: &str =
+    "id, source, route, group_title, tags, dewarp, remove_gutter_shadow, export_dir, created_at, color_dropout, remove_punch_holes";
+
+fn row_to_ingest_rule(row: &duckdb::Row) -> duckdb::Result<IngestRule> {
+    let source: String = row.get(1)?;
+    let tags_json: String = row.get(4)?;
+
+    Ok(IngestRule {
+        id: row.get(0)?,
+        source: IngestSource::from_str(&source),
+        route: row.get(2)?,
+        group_title: row.get(3)?,
+        tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+        dewarp: row.get(5)?,
+        remove_gutter_shadow: row.get(6)?,
+        export_dir: row.get(7)?,
+        created_at: row.get(8)?,
+        color_dropout: DropoutColor::from_column(row.get(9)?),
+        remove_punch_holes: row.get(10)?,
+    })
+}
+
+impl IngestRule {
+    /// The rule as it would be entered, e.g. to recreate it elsewhere.
+    pub fn to_input(&self) -> IngestRuleInput {
+        IngestRuleInput {
+            source: self.source,
+            route: self.route.clone(),
+            group_title: self.group_title.clone(),
+            tags: self.tags.clone(),
+            dewarp: self.dewarp,
+            remove_gutter_shadow: self.remove_gutter_shadow,
+            color_dropout: self.color_dropout,
+            remove_punch_holes: self.remove_punch_holes,
+            export_dir: self.export_dir.clone(),
+        }
+    }
+
+    pub fn create(
+        input: IngestRuleInput,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+        let tags_json = serde_json::to_string(&input.tags).unwrap();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO ingest_rules (source, route, group_title, tags, dewarp, remove_gutter_shadow, export_dir, created_at, color_dropout, remove_punch_holes)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            params![
+                input.source.as_str(),
+                input.route.trim(),
+                input.group_title,
+                tags_json,
+                input.dewarp,
+                input.remove_gutter_shadow,
+                input.export_dir,
+                Utc::now(),
+                input.color_dropout.map(|color| color.as_str()),
+                input.remove_punch_holes
+            ],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM ingest_rules WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_ingest_rule,
+        )
+    }
+
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<IngestRule> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM ingest_rules ORDER BY source, id",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let rules: Vec<IngestRule> = stmt
+            .query_map([], row_to_ingest_rule)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        rules
+    }
+
+    /// The rule for a document from `source` arriving by any of `routes`. A
+    /// rule naming one of the routes wins over a catch-all; among equals the
+    /// oldest wins.
+    pub fn find(
+        source: IngestSource,
+        routes: &[String],
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<Self>> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM ingest_rules WHERE source = ? ORDER BY id",
+            COLUMNS
+        ))?;
+        let rules = stmt
+            .query_map(params![source.as_str()], row_to_ingest_rule)?
+            .collect::<Result<Vec<_>>>()?;
+
+        let routed = rules.iter().find(|rule| {
+            routes
+                .iter()
+                .any(|route| route.eq_ignore_ascii_case(&rule.route))
+                && !rule.route.is_empty()
+        });
+        let catch_all = rules.iter().find(|rule| rule.route.is_empty());
+        Ok(routed.or(catch_all).cloned())
+    }
+
+    pub fn delete(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        let deleted = conn.execute("DELETE FROM ingest_rules WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
+}
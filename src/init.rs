@@ -0,0 +1,86 @@
+use std::{fs, path::Path};
+
+use duckdb::DuckdbConnectionManager;
+
+use crate::{
+    api_keys::ApiKey,
+    auth::{AuthConfig, Scope},
+    db_config, ocr,
+    users::User,
+    AssetsDir,
+};
+
+const BOOTSTRAP_KEY_NAME: &str = "bootstrap-admin";
+
+/// Where trained data for missing OCR languages comes from, unless
+/// `TESSDATA_URL` points elsewhere, e.g. at `tessdata_best` or a mirror.
+pub const DEFAULT_TESSDATA_URL: &str = "https://github.com/tesseract-ocr/tessdata_fast/raw/main";
+
+/// Prepares a fresh deployment so a container can start against empty volumes.
+///
+/// Creates the assets layout on every start, and downloads trained data for
+/// any `OCR_LANGUAGES` tesseract lacks if `tessdata_url` is given. On first
+/// run (no API keys or users yet) it also creates an admin API key and prints
+/// it with a summary of the configuration, since the token can't be
+/// recovered later.
+pub fn run(
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    auth_config: &AuthConfig,
+    tessdata_url: Option<&str>,
+) {
+    fs::create_dir_all(Path::new(&assets_dir.0).join("scans")).unwrap();
+    if let Some(url) = tessdata_url {
+        download_languages(url);
+    }
+
+    let first_run = ApiKey::load_all(pool).is_empty() && User::load_all(pool).is_empty();
+    if !first_run {
+        return;
+    }
+
+    let created = ApiKey::create(BOOTSTRAP_KEY_NAME.to_string(), vec![Scope::Admin], pool).unwrap();
+
+    println!("First run, initialized scanserv:");
+    println!("  Assets directory: {}", assets_dir.0);
+    println!(
+        "  Database: {}",
+        db_config::database_path(&pool.get().unwrap()).unwrap()
+    );
+    println!(
+        "  Authentication: {}",
+        if auth_config.required {
+            "required"
+        } else {
+            "not required (set AUTH_REQUIRED=true to enforce)"
+        }
+    );
+    println!(
+        "  Admin API key ({}): {}",
+        BOOTSTRAP_KEY_NAME, created.token
+    );
+    println!(
+        "  This key is only shown once. Use it to create users and other keys, then revoke it."
+    );
+}
+
+/// Fetches trained data for the OCR languages tesseract doesn't have yet.
+/// Failures are printed and startup carries on; OCR in those languages
+/// fails until the data is there.
+fn download_languages(url: &str) {
+    let languages = ocr::languages();
+    let missing = match ocr::missing_languages(&languages) {
+        Ok(missing) => missing,
+        Err(e) => {
+            println!("Not downloading OCR languages: {}", e);
+            return;
+        }
+    };
+    for language in missing {
+        println!("Downloading tesseract data for {}...", language);
+        match ocr::download_language(&language, url) {
+            Ok(file) => println!("  Saved {}", file.display()),
+            Err(e) => println!("  Failed to download {}: {}", language, e),
+        }
+    }
+}
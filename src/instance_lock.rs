@@ -0,0 +1,210 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, SystemTime},
+};
+
+use chrono::Utc;
+
+/// In the assets directory, which every instance sharing a database shares too.
+const LOCK_FILE: &str = "scanserv.lock";
+/// How often the holder rewrites the lock file to show it's alive.
+const HEARTBEAT: Duration = Duration::from_secs(30);
+/// A lock not rewritten for this long was left by an instance that died,
+/// e.g. on another host sharing the volume.
+const LEASE: Duration = Duration::from_secs(120);
+
+/// Held by the one process driving the scanners and job queue for an
+/// assets directory and database: the server, or a one-off command run
+/// while it's stopped. DuckDB only lets one process write the database.
+pub struct InstanceLock {
+    path: PathBuf,
+    holder: Holder,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Holder {
+    pid: u32,
+    host: String,
+    started_at: String,
+}
+
+impl Holder {
+    fn current() -> Self {
+        Holder {
+            pid: process::id(),
+            host: hostname(),
+            started_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let mut parts = contents.split_whitespace();
+        Some(Holder {
+            pid: parts.next()?.parse().ok()?,
+            host: parts.next()?.to_string(),
+            started_at: parts.next()?.to_string(),
+        })
+    }
+
+    fn line(&self) -> String {
+        format!("{} {} {}\n", self.pid, self.host, self.started_at)
+    }
+
+    /// Whether the holder is known to have exited: a process on this host
+    /// that isn't running, or this very process id left from before a
+    /// container restart.
+    fn exited(&self) -> bool {
+        self.host == hostname()
+            && (self.pid == process::id() || !Path::new(&format!("/proc/{}", self.pid)).exists())
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pid {} on {}, started {}",
+            self.pid, self.host, self.started_at
+        )
+    }
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another instance is running against the same assets directory
+    Held {
+        path: PathBuf,
+        holder: String,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Held { path, holder } => write!(
+                f,
+                "another scanserv instance ({}) is using this assets directory and database. \
+                 Stop it first; if it has already died, the lock at {} expires {} seconds \
+                 after its last heartbeat, or can be deleted.",
+                holder,
+                path.display(),
+                LEASE.as_secs()
+            ),
+            LockError::Io(e) => write!(f, "could not take the instance lock: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+impl InstanceLock {
+    /// Takes the lock in `assets_dir`, or fails with who holds it. A lock
+    /// whose holder exited or stopped renewing it is taken over.
+    pub fn acquire(assets_dir: &str) -> Result<Self, LockError> {
+        fs::create_dir_all(assets_dir)?;
+        let path = Path::new(assets_dir).join(LOCK_FILE);
+        let holder = Holder::current();
+
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => {
+                    fs::write(&path, holder.line())?;
+                    return Ok(InstanceLock { path, holder });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let existing = fs::read_to_string(&path).unwrap_or_default();
+            let renewed = fs::metadata(&path).and_then(|metadata| metadata.modified());
+            let expired = renewed.is_ok_and(|renewed| {
+                SystemTime::now()
+                    .duration_since(renewed)
+                    .is_ok_and(|age| age > LEASE)
+            });
+            match Holder::parse(&existing) {
+                Some(other) if !expired && !other.exited() => {
+                    return Err(LockError::Held {
+                        path,
+                        holder: other.to_string(),
+                    })
+                }
+                // Created but not yet written by an instance starting now
+                None if !expired => {
+                    return Err(LockError::Held {
+                        path,
+                        holder: "just starting".to_string(),
+                    })
+                }
+                _ => {
+                    println!("Taking over a stale instance lock: {}", existing.trim());
+                    match fs::remove_file(&path) {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Renews the lock for as long as the process runs. Exits if another
+    /// instance has taken it over, rather than both driving the scanners.
+    pub fn keep_alive(&self) {
+        let path = self.path.clone();
+        let holder = self.holder.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(HEARTBEAT).await;
+                let current = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|contents| Holder::parse(&contents));
+                if let Some(other) = current.filter(|current| *current != holder) {
+                    eprintln!(
+                        "The instance lock at {} was taken over by {}; exiting",
+                        path.display(),
+                        other
+                    );
+                    process::exit(1);
+                }
+                if let Err(e) = fs::write(&path, holder.line()) {
+                    println!("Failed to renew the instance lock: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Gives the lock up, e.g. when a one-off command finishes, so the next
+    /// instance doesn't have to take it over. Left alone if someone else
+    /// holds it by now.
+    pub fn release(self) {
+        let current = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|contents| Holder::parse(&contents));
+        if current.as_ref() == Some(&self.holder) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                println!("Failed to release the instance lock: {}", e);
+            }
+        }
+    }
+}
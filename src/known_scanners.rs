@@ -0,0 +1,90 @@
+use chrono::Utc;
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+
+use crate::{scanner_options::ScannerOption, scanners::ScannerInfo};
+
+const COLUMNS: &str = "name, description, has_feeder, options, last_seen_at";
+
+fn row_to_scanner(row: &duckdb::Row) -> duckdb::Result<ScannerInfo> {
+    let options_json: String = row.get(3)?;
+
+    Ok(ScannerInfo {
+        name: row.get(0)?,
+        description: row.get(1)?,
+        has_feeder: row.get(2)?,
+        // Only known while it's listed
+        paper_loaded: None,
+        offline: true,
+        last_seen_at: row.get(4)?,
+        options: serde_json::from_str(&options_json).unwrap_or_default(),
+    })
+}
+
+/// Remembers the devices just listed. A device whose options couldn't be
+/// read this time, e.g. because it was scanning, keeps the ones stored.
+pub fn save(scanners: &[ScannerInfo], pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<()> {
+    let conn = pool.get().unwrap();
+    for scanner in scanners.iter().filter(|scanner| !scanner.offline) {
+        conn.execute(
+            "INSERT INTO scanners (name, description, has_feeder, options, last_seen_at)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (name) DO UPDATE SET
+                description = excluded.description,
+                has_feeder = excluded.has_feeder,
+                options = CASE WHEN excluded.options = '[]' THEN scanners.options ELSE excluded.options END,
+                last_seen_at = excluded.last_seen_at",
+            params![
+                scanner.name,
+                scanner.description,
+                scanner.has_feeder,
+                serde_json::to_string(&scanner.options).unwrap(),
+                Utc::now()
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Every device ever listed, as last seen, marked offline.
+pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<ScannerInfo> {
+    let conn = pool.get().unwrap();
+
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM scanners ORDER BY name", COLUMNS))
+        .unwrap();
+
+    let scanners: Vec<ScannerInfo> = stmt
+        .query_map([], row_to_scanner)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+
+    scanners
+}
+
+/// The device's options as last read, if it's been seen and they were.
+pub fn options(
+    name: &str,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Result<Option<Vec<ScannerOption>>> {
+    let conn = pool.get().unwrap();
+    let options_json: Option<String> = conn
+        .query_row(
+            "SELECT options FROM scanners WHERE name = ?",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(options_json
+        .and_then(|json| serde_json::from_str::<Vec<ScannerOption>>(&json).ok())
+        .filter(|options| !options.is_empty()))
+}
+
+/// Stops listing a device that's gone for good. It's remembered again if
+/// it's ever listed.
+pub fn forget(name: &str, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+    let conn = pool.get().unwrap();
+    let deleted = conn.execute("DELETE FROM scanners WHERE name = ?", params![name])?;
+    Ok(deleted > 0)
+}
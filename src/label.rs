@@ -0,0 +1,49 @@
+use image::{imageops, GrayImage, Luma};
+
+use crate::{
+    bitmap_font::{draw_text, text_width, GLYPH_HEIGHT},
+    qr,
+    scans::ScanGroup,
+    PublicUrl,
+};
+
+/// Labels are 4 by 2 inches, the common shipping label size, at this resolution.
+pub const DPI: f32 = 300.0;
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 600;
+const MARGIN: u32 = 45;
+
+const PAPER: Luma<u8> = Luma([255]);
+const INK: Luma<u8> = Luma([0]);
+
+/// A label to stick on a folder or box of paper originals: the group's QR
+/// code on the left, its title, id and link on the right.
+pub fn render(group: &ScanGroup, public_url: &PublicUrl) -> GrayImage {
+    let mut label = GrayImage::from_pixel(WIDTH, HEIGHT, PAPER);
+
+    let url = public_url.group_url(group.id);
+    let code = qr::render(&url, ((HEIGHT - MARGIN * 2) / qr::modules(&url)).max(1));
+    let code_y = (HEIGHT - code.height()) / 2;
+    imageops::overlay(&mut label, &code, MARGIN as i64, code_y as i64);
+
+    let text_x = MARGIN * 2 + code.width();
+    let text_area = WIDTH - text_x - MARGIN;
+    let title = if group.title.is_empty() {
+        "UNTITLED"
+    } else {
+        &group.title
+    };
+    let lines = [
+        (title.to_string(), 8),
+        (format!("GROUP #{}", group.id), 6),
+        (url.clone(), 3),
+    ];
+    let mut y = MARGIN * 2;
+    for (line, max_scale) in &lines {
+        let scale = (text_area / text_width(line, 1).max(1)).clamp(1, *max_scale);
+        draw_text(&mut label, line, text_x, y, scale, INK);
+        y += (GLYPH_HEIGHT + 5) * scale;
+    }
+
+    label
+}
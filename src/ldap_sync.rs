@@ -0,0 +1,263 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use async_graphql::SimpleObject;
+use chrono::Utc;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use ldap3::{
+    adapters::{Adapter, EntriesOnly, PagedResults},
+    LdapConn, LdapConnSettings, LdapError, Scope, SearchEntry,
+};
+
+use crate::users::Role;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stored as the password hash of LDAP accounts. It isn't a valid hash, so
+/// it never matches a password; they sign in against the directory instead.
+const NO_LOCAL_PASSWORD: &str = "!ldap";
+
+/// A directory to take accounts from, for offices that already keep their
+/// people in LDAP or Active Directory. Members of the role groups become
+/// users with that role.
+#[derive(Clone)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://`
+    pub url: String,
+    /// Who to search the directory as
+    pub bind_dn: String,
+    pub bind_password: String,
+    /// Where to look for users
+    pub base_dn: String,
+    pub user_filter: String,
+    /// The attribute that holds the login name, `uid` or AD's `sAMAccountName`
+    pub username_attribute: String,
+    /// The group whose direct members get each role. Someone in several gets
+    /// the highest.
+    pub role_groups: Vec<(Role, String)>,
+}
+
+#[derive(Debug)]
+pub enum LdapSyncError {
+    Ldap(LdapError),
+    Database(duckdb::Error),
+    /// Nobody was in the role groups. A wrong group DN is far likelier than an
+    /// office with no staff, so accounts are left as they are.
+    NoMembers,
+}
+
+impl fmt::Display for LdapSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LdapSyncError::Ldap(e) => write!(f, "LDAP error: {}", e),
+            LdapSyncError::Database(e) => write!(f, "database error: {}", e),
+            LdapSyncError::NoMembers => write!(
+                f,
+                "nobody in the directory is in a role group, so no accounts were changed"
+            ),
+        }
+    }
+}
+
+impl From<LdapError> for LdapSyncError {
+    fn from(e: LdapError) -> Self {
+        LdapSyncError::Ldap(e)
+    }
+}
+
+impl From<duckdb::Error> for LdapSyncError {
+    fn from(e: duckdb::Error) -> Self {
+        LdapSyncError::Database(e)
+    }
+}
+
+/// What a sync changed, by username.
+#[derive(Debug, Clone, Default, SimpleObject)]
+pub struct LdapSyncReport {
+    pub created: Vec<String>,
+    /// Role or DN changed, or re-enabled after coming back
+    pub updated: Vec<String>,
+    /// Gone from the directory or its role groups; their sessions were ended
+    pub disabled: Vec<String>,
+    /// In the directory, but the username belongs to a local account, which
+    /// is left alone
+    pub skipped: Vec<String>,
+}
+
+struct DirectoryUser {
+    username: String,
+    dn: String,
+    role: Role,
+}
+
+fn connect(config: &LdapConfig) -> Result<LdapConn, LdapError> {
+    LdapConn::with_settings(
+        LdapConnSettings::new().set_conn_timeout(TIMEOUT),
+        &config.url,
+    )
+}
+
+/// Whether the directory accepts `password` for `dn`. An empty password
+/// would be an anonymous bind, which most directories allow, so it never
+/// matches.
+pub fn authenticate(config: &LdapConfig, dn: &str, password: &str) -> bool {
+    if password.is_empty() {
+        return false;
+    }
+    let result = connect(config).and_then(|mut ldap| {
+        let bound = ldap.simple_bind(dn, password)?.success();
+        let _ = ldap.unbind();
+        bound
+    });
+    match result {
+        Ok(_) => true,
+        Err(LdapError::LdapResult { .. }) => false,
+        Err(e) => {
+            println!("Could not check {} against LDAP: {}", dn, e);
+            false
+        }
+    }
+}
+
+/// Everyone `config.user_filter` finds who is in a role group, with the
+/// highest role they're in.
+fn directory_users(config: &LdapConfig) -> Result<Vec<DirectoryUser>, LdapError> {
+    let mut ldap = connect(config)?;
+    ldap.simple_bind(&config.bind_dn, &config.bind_password)?
+        .success()?;
+
+    // Member DNs of groupOfNames and AD groups, or usernames of posixGroups
+    let mut members: Vec<(Role, HashSet<String>)> = Vec::new();
+    for (role, group_dn) in &config.role_groups {
+        let (entries, _) = ldap
+            .search(
+                group_dn,
+                Scope::Base,
+                "(objectClass=*)",
+                vec!["member", "uniqueMember", "memberUid"],
+            )?
+            .success()?;
+        let group: HashSet<String> = entries
+            .into_iter()
+            .map(SearchEntry::construct)
+            .flat_map(|entry| entry.attrs.into_values().flatten())
+            .map(|member| member.to_lowercase())
+            .collect();
+        members.push((*role, group));
+    }
+
+    // Paged, as Active Directory returns at most 1000 entries per search
+    let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+        Box::new(EntriesOnly::new()),
+        Box::new(PagedResults::new(500)),
+    ];
+    let mut search = ldap.streaming_search_with(
+        adapters,
+        &config.base_dn,
+        Scope::Subtree,
+        &config.user_filter,
+        vec![config.username_attribute.as_str()],
+    )?;
+    let mut users = Vec::new();
+    while let Some(entry) = search.next()? {
+        let entry = SearchEntry::construct(entry);
+        let Some(username) = entry
+            .attrs
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&config.username_attribute))
+            .and_then(|(_, values)| values.first())
+        else {
+            continue;
+        };
+        let (dn, name) = (entry.dn.to_lowercase(), username.to_lowercase());
+        let role = members
+            .iter()
+            .filter(|(_, group)| group.contains(&dn) || group.contains(&name))
+            .map(|(role, _)| *role)
+            .max();
+        if let Some(role) = role {
+            users.push(DirectoryUser {
+                username: username.clone(),
+                dn: entry.dn,
+                role,
+            });
+        }
+    }
+    search.result().success()?;
+    let _ = ldap.unbind();
+
+    Ok(users)
+}
+
+/// Brings LDAP accounts in line with the directory: members of the role
+/// groups are created or given their current role, and accounts no longer
+/// in any are disabled and signed out. Local accounts are never touched.
+pub fn sync(
+    config: &LdapConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Result<LdapSyncReport, LdapSyncError> {
+    let found = directory_users(config)?;
+    if found.is_empty() {
+        return Err(LdapSyncError::NoMembers);
+    }
+
+    let conn = pool.get().unwrap();
+    let now = Utc::now();
+    let mut report = LdapSyncReport::default();
+
+    for user in &found {
+        let existing: Option<(i32, Option<String>, String, bool)> = conn
+            .query_row(
+                "SELECT id, ldap_dn, role, disabled_at IS NOT NULL FROM users WHERE username = ?",
+                params![user.username],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+        match existing {
+            None => {
+                conn.execute(
+                    "INSERT INTO users (username, password_hash, role, created_at, ldap_dn)
+                     VALUES (?, ?, ?, ?, ?)",
+                    params![
+                        user.username,
+                        NO_LOCAL_PASSWORD,
+                        user.role.as_str(),
+                        now,
+                        user.dn
+                    ],
+                )?;
+                report.created.push(user.username.clone());
+            }
+            Some((_, None, _, _)) => report.skipped.push(user.username.clone()),
+            Some((id, Some(dn), role, disabled)) => {
+                if dn != user.dn || role != user.role.as_str() || disabled {
+                    conn.execute(
+                        "UPDATE users SET ldap_dn = ?, role = ?, disabled_at = NULL WHERE id = ?",
+                        params![user.dn, user.role.as_str(), id],
+                    )?;
+                    report.updated.push(user.username.clone());
+                }
+            }
+        }
+    }
+
+    let listed: HashSet<&str> = found.iter().map(|user| user.username.as_str()).collect();
+    let mut stmt = conn.prepare(
+        "SELECT id, username FROM users WHERE ldap_dn IS NOT NULL AND disabled_at IS NULL",
+    )?;
+    let active: Vec<(i32, String)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, username) in active {
+        if listed.contains(username.as_str()) {
+            continue;
+        }
+        conn.execute(
+            "UPDATE users SET disabled_at = ? WHERE id = ?",
+            params![now, id],
+        )?;
+        conn.execute("DELETE FROM sessions WHERE user_id = ?", params![id])?;
+        report.disabled.push(username);
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use duckdb::DuckdbConnectionManager;
+use tokio::sync::Semaphore;
+
+use crate::{
+    contact_sheet,
+    exports::{export_group, ExportFormat, ExportOptions},
+    migrations::{migrate, BackupConfig},
+    scan_queue::ScanPriority,
+    scanners::ScannerManager,
+    scans::{Scan, ScanGroup},
+    test_page, AssetsDir, PublicUrl,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct LoadtestOptions {
+    #[arg(long, default_value_t = 100)]
+    pub scans: usize,
+    /// Scans in flight at once, each on its own mock device; also limits
+    /// concurrent exports
+    #[arg(long, default_value_t = 4)]
+    pub concurrency: usize,
+    #[arg(long, default_value_t = 10)]
+    pub pages_per_group: usize,
+    /// How long each mock scan takes, to leave out the scanner itself
+    #[arg(long, default_value_t = 0)]
+    pub scan_delay_ms: u64,
+}
+
+/// Timings of one stage, summarized.
+struct Stage {
+    name: &'static str,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+    failed: usize,
+}
+
+impl Stage {
+    /// The latency below which `percent` of them fall, by nearest rank.
+    fn percentile(&self, percent: usize) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent * self.latencies.len()).div_ceil(100).max(1);
+        self.latencies[rank - 1]
+    }
+
+    fn report(&mut self) {
+        self.latencies.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!(
+            "{}: {} in {:.2}s, {:.1}/s, {} failed",
+            self.name,
+            self.latencies.len(),
+            self.elapsed.as_secs_f64(),
+            self.latencies.len() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON),
+            self.failed
+        );
+        println!(
+            "  latency ms: p50 {:.1}, p90 {:.1}, p99 {:.1}, max {:.1}",
+            ms(self.percentile(50)),
+            ms(self.percentile(90)),
+            ms(self.percentile(99)),
+            ms(self.latencies.last().copied().unwrap_or_default())
+        );
+    }
+}
+
+/// Runs scans through the mock scanner into groups, then exports each
+/// group as a PDF, timing both. Uses an in-memory database and a scratch
+/// assets directory, so the server's data is never touched. Returns true
+/// if nothing failed.
+pub async fn run(options: LoadtestOptions) -> bool {
+    let dir: PathBuf = env::temp_dir().join(format!("scanserv-loadtest-{}", std::process::id()));
+    let assets_dir = AssetsDir(dir.join("assets").to_string_lossy().into_owned(), None);
+    let passed = run_in(&options, &dir, &assets_dir).await;
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        println!("Could not remove {}: {}", dir.display(), e);
+    }
+    passed
+}
+
+async fn run_in(options: &LoadtestOptions, dir: &Path, assets_dir: &AssetsDir) -> bool {
+    // Every mock scan copies this page
+    let samples = PathBuf::from(&assets_dir.0).join("mock_scanner_samples");
+    fs::create_dir_all(&samples).unwrap();
+    test_page::render(1275, 1650, &["load test".to_string()])
+        .save(samples.join("page.png"))
+        .unwrap();
+
+    let manager = DuckdbConnectionManager::memory().unwrap();
+    let pool = r2d2::Pool::builder().max_size(15).build(manager).unwrap();
+    let backup_config = BackupConfig {
+        dir: dir.join("backups"),
+        keep: 0,
+        max_copy_bytes: 0,
+    };
+    migrate(&pool, &backup_config).await;
+
+    let group_ids: Vec<i32> = (0..options.scans.div_ceil(options.pages_per_group))
+        .map(|i| {
+            let mut group = ScanGroup::create("scanning".to_string());
+            group.title = format!("Load test {}", i + 1);
+            group.save(&pool).unwrap()
+        })
+        .collect();
+
+    let scanners = ScannerManager::mock(Duration::from_millis(options.scan_delay_ms));
+    let next = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|worker| {
+            let (scanners, next, pool, assets_dir) = (
+                scanners.clone(),
+                next.clone(),
+                pool.clone(),
+                assets_dir.clone(),
+            );
+            let (group_ids, options) = (group_ids.clone(), options.clone());
+            tokio::spawn(async move {
+                let device = format!("mock:loadtest-{}", worker);
+                let mut results = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= options.scans {
+                        break;
+                    }
+                    let scan_started = Instant::now();
+                    let scan = Scan::create_pending(
+                        &device,
+                        &HashMap::new(),
+                        Some(group_ids[i / options.pages_per_group]),
+                        Some("loadtest".to_string()),
+                        &pool,
+                        &assets_dir,
+                    )
+                    .unwrap();
+                    let scan_id = scanners
+                        .complete_scan(
+                            scan.id.unwrap(),
+                            &device,
+                            HashMap::new(),
+                            ScanPriority::Normal,
+                            &pool,
+                            &assets_dir,
+                        )
+                        .await;
+                    let complete =
+                        Scan::load(scan_id, &pool).is_ok_and(|scan| scan.status == "COMPLETE");
+                    results.push((scan_started.elapsed(), complete));
+                }
+                results
+            })
+        })
+        .collect();
+
+    let mut scans = Stage {
+        name: "scans",
+        elapsed: Duration::ZERO,
+        latencies: Vec::new(),
+        failed: 0,
+    };
+    for worker in workers {
+        for (latency, complete) in worker.await.unwrap() {
+            scans.latencies.push(latency);
+            if !complete {
+                scans.failed += 1;
+            }
+        }
+    }
+    scans.elapsed = started.elapsed();
+
+    // Each export renders on a blocking thread; only `concurrency` at once
+    let permits = Arc::new(Semaphore::new(options.concurrency));
+    let out_dir = dir.join("exports");
+    fs::create_dir_all(&out_dir).unwrap();
+    let started = Instant::now();
+    let exports: Vec<_> = group_ids
+        .iter()
+        .map(|&group_id| {
+            let (permits, pool, assets_dir) = (permits.clone(), pool.clone(), assets_dir.clone());
+            let out = out_dir.join(format!("group_{}.pdf", group_id));
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await.unwrap();
+                tokio::task::spawn_blocking(move || {
+                    let export_started = Instant::now();
+                    let options = ExportOptions {
+                        format: ExportFormat::Pdf,
+                        thumbnails_per_page: contact_sheet::DEFAULT_THUMBNAILS_PER_PAGE,
+                        stamp_qr: false,
+                        public_url: PublicUrl("http://localhost:8080".to_string()),
+                        triggered_by: "loadtest".to_string(),
+                        force: true,
+                    };
+                    let result = export_group(group_id, &options, &out, &pool, &assets_dir);
+                    if let Err(e) = &result {
+                        println!("Export of group {} failed: {}", group_id, e);
+                    }
+                    (export_started.elapsed(), result.is_ok())
+                })
+                .await
+                .unwrap()
+            })
+        })
+        .collect();
+
+    let mut exported = Stage {
+        name: "exports",
+        elapsed: Duration::ZERO,
+        latencies: Vec::new(),
+        failed: 0,
+    };
+    for export in exports {
+        let (latency, ok) = export.await.unwrap();
+        exported.latencies.push(latency);
+        if !ok {
+            exported.failed += 1;
+        }
+    }
+    exported.elapsed = started.elapsed();
+
+    println!(
+        "Load test: {} scans in {} groups, concurrency {}, {}ms per mock scan",
+        options.scans,
+        group_ids.len(),
+        options.concurrency,
+        options.scan_delay_ms
+    );
+    scans.report();
+    exported.report();
+    scans.failed == 0 && exported.failed == 0
+}
@@ -0,0 +1,94 @@
+use std::env;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Duration, Local, NaiveDate, TimeZone, Utc,
+};
+use once_cell::sync::Lazy;
+
+/// How times are shown to people: in file names, default titles and
+/// manifests. They're in the server's local time zone, which follows `TZ`
+/// (e.g. `TZ=Europe/Berlin`); times in the database stay UTC.
+#[derive(Debug, Clone)]
+pub struct LocaleConfig {
+    pub date_format: String,
+    pub datetime_format: String,
+}
+
+impl LocaleConfig {
+    /// From `DATE_FORMAT` (default `%Y-%m-%d`) and `DATETIME_FORMAT`
+    /// (default `%Y-%m-%d %H:%M`), both strftime formats.
+    pub fn from_env() -> Self {
+        LocaleConfig {
+            date_format: format_from_env("DATE_FORMAT", "%Y-%m-%d"),
+            datetime_format: format_from_env("DATETIME_FORMAT", "%Y-%m-%d %H:%M"),
+        }
+    }
+}
+
+/// Formatting with a bad strftime format panics, so one is replaced by the
+/// default up front.
+fn format_from_env(name: &str, default: &str) -> String {
+    match env::var(name).ok().filter(|v| !v.is_empty()) {
+        Some(format) if StrftimeItems::new(&format).any(|item| item == Item::Error) => {
+            println!("Ignoring {}: {} is not a valid date format", name, format);
+            default.to_string()
+        }
+        Some(format) => format,
+        None => default.to_string(),
+    }
+}
+
+pub static LOCALE: Lazy<LocaleConfig> = Lazy::new(LocaleConfig::from_env);
+
+pub fn format_date(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .format(&LOCALE.date_format)
+        .to_string()
+}
+
+pub fn format_datetime(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .format(&LOCALE.datetime_format)
+        .to_string()
+}
+
+/// The time as `YYYYMMDDHHMMSS` in local time, for file names. Not
+/// configurable, so names keep sorting in time order.
+pub fn filename_stamp(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .format("%Y%m%d%H%M%S")
+        .to_string()
+}
+
+pub fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// When the local day starts and the next one starts, in UTC.
+pub fn day_bounds(day: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    (start_of(day), start_of(day + Duration::days(1)))
+}
+
+/// When the local year starts and the next one starts, in UTC.
+pub fn year_bounds(year: i32) -> (DateTime<Utc>, DateTime<Utc>) {
+    (
+        start_of(NaiveDate::from_ymd_opt(year, 1, 1).unwrap()),
+        start_of(NaiveDate::from_ymd_opt(year + 1, 1, 1).unwrap()),
+    )
+}
+
+/// Midnight, or the first time after it when a DST change skips midnight.
+fn start_of(day: NaiveDate) -> DateTime<Utc> {
+    let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+    let start = Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| {
+            Local
+                .from_local_datetime(&(midnight + Duration::hours(1)))
+                .earliest()
+        })
+        .unwrap_or_else(|| Utc.from_utc_datetime(&midnight).with_timezone(&Local));
+    start.with_timezone(&Utc)
+}
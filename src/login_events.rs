@@ -0,0 +1,114 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+/// Failed attempts allowed per username or IP within the lockout window.
+pub const MAX_FAILED_LOGINS: i64 = 5;
+pub const LOCKOUT_MINUTES: i64 = 15;
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LoginOutcome {
+    Success,
+    Failure,
+    /// Rejected without checking the password because of too many failures
+    LockedOut,
+}
+
+impl LoginOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LoginOutcome::Success => "success",
+            LoginOutcome::Failure => "failure",
+            LoginOutcome::LockedOut => "locked_out",
+        }
+    }
+
+    fn from_str(outcome: &str) -> Self {
+        match outcome {
+            "success" => LoginOutcome::Success,
+            "locked_out" => LoginOutcome::LockedOut,
+            _ => LoginOutcome::Failure,
+        }
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct LoginEvent {
+    pub id: i32,
+    pub username: String,
+    pub ip: Option<String>,
+    pub outcome: LoginOutcome,
+    pub created_at: DateTime<Utc>,
+}
+
+impl LoginEvent {
+    pub fn record(
+        username: &str,
+        ip: Option<&str>,
+        outcome: LoginOutcome,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+
+        conn.execute(
+            "INSERT INTO login_events (username, ip, outcome, created_at) VALUES (?, ?, ?, ?)",
+            params![username, ip, outcome.as_str(), Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Whether the username or IP has hit the failure limit within the window.
+    pub fn is_locked_out(
+        username: &str,
+        ip: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> bool {
+        let conn = pool.get().unwrap();
+        let since = Utc::now() - Duration::minutes(LOCKOUT_MINUTES);
+
+        let failures: i64 = conn
+            .query_row(
+                "SELECT
+                    COUNT(*) FILTER (WHERE username = ?),
+                    COUNT(*) FILTER (WHERE ip IS NOT NULL AND ip = ?)
+                 FROM login_events
+                 WHERE outcome = 'failure' AND created_at > ?::TIMESTAMP",
+                params![username, ip, since],
+                |row| Ok(row.get::<usize, i64>(0)?.max(row.get(1)?)),
+            )
+            .unwrap();
+
+        failures >= MAX_FAILED_LOGINS
+    }
+
+    pub fn load_recent(limit: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<LoginEvent> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, username, ip, outcome, created_at FROM login_events
+                 ORDER BY created_at DESC, id DESC LIMIT ?",
+            )
+            .unwrap();
+
+        let events: Vec<LoginEvent> = stmt
+            .query_map([limit], |row| {
+                let outcome: String = row.get(3)?;
+
+                Ok(LoginEvent {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    ip: row.get(2)?,
+                    outcome: LoginOutcome::from_str(&outcome),
+                    created_at: row.get(4)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        events
+    }
+}
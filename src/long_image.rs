@@ -0,0 +1,22 @@
+use image::{imageops, Rgb, RgbImage};
+
+/// JPEG can't be taller than this.
+pub const MAX_JPEG_HEIGHT: u32 = 65_535;
+
+const PAPER: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// The pages stacked top to bottom in one image, the way expense tools
+/// like a long receipt. Pages narrower than the widest are centred on
+/// white rather than scaled, so text keeps the same size throughout.
+pub fn concatenate(pages: &[RgbImage]) -> RgbImage {
+    let width = pages.iter().map(RgbImage::width).max().unwrap_or(0);
+    let height = pages.iter().map(RgbImage::height).sum();
+    let mut long = RgbImage::from_pixel(width, height, PAPER);
+    let mut y = 0;
+    for page in pages {
+        let x = (width - page.width()) / 2;
+        imageops::replace(&mut long, page, x as i64, y as i64);
+        y += page.height();
+    }
+    long
+}
@@ -0,0 +1,190 @@
+use std::{collections::HashMap, fmt};
+
+use chrono::{DateTime, Utc};
+use duckdb::DuckdbConnectionManager;
+use mailparse::{MailAddr, MailHeaderMap, ParsedMail};
+
+use crate::{
+    ingest::{self, IncomingDocument, IncomingPage, IngestError},
+    ingest_rules::IngestSource,
+    AssetsDir, PublicUrl,
+};
+
+/// A mailbox that scan-to-email devices send to. Every message in it is
+/// imported as a group and then moved to `archive_mailbox`.
+#[derive(Clone)]
+pub struct MailImportConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub mailbox: String,
+    pub archive_mailbox: String,
+}
+
+#[derive(Debug)]
+pub enum MailImportError {
+    Tls(native_tls::Error),
+    Imap(imap::Error),
+    Parse(mailparse::MailParseError),
+    Ingest(IngestError),
+}
+
+impl fmt::Display for MailImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailImportError::Tls(e) => write!(f, "could not set up TLS: {}", e),
+            MailImportError::Imap(e) => write!(f, "IMAP error: {}", e),
+            MailImportError::Parse(e) => write!(f, "could not parse message: {}", e),
+            MailImportError::Ingest(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<native_tls::Error> for MailImportError {
+    fn from(e: native_tls::Error) -> Self {
+        MailImportError::Tls(e)
+    }
+}
+
+impl From<imap::Error> for MailImportError {
+    fn from(e: imap::Error) -> Self {
+        MailImportError::Imap(e)
+    }
+}
+
+impl From<mailparse::MailParseError> for MailImportError {
+    fn from(e: mailparse::MailParseError) -> Self {
+        MailImportError::Parse(e)
+    }
+}
+
+impl From<IngestError> for MailImportError {
+    fn from(e: IngestError) -> Self {
+        MailImportError::Ingest(e)
+    }
+}
+
+/// Pages attached to a message, in order.
+fn attached_pages(mail: &ParsedMail) -> Result<Vec<IncomingPage>, MailImportError> {
+    let mut pages = Vec::new();
+    for part in mail.parts().filter(|part| part.subparts.is_empty()) {
+        pages.extend(ingest::file_pages(part.get_body_raw()?));
+    }
+    Ok(pages)
+}
+
+/// Addresses the message was sent to, which ingest rules route by. Devices
+/// often send each scan button's documents to a different address.
+fn recipients(mail: &ParsedMail) -> Vec<String> {
+    let mut addresses = Vec::new();
+    for header in mail.headers.get_all_headers("To") {
+        let Ok(list) = mailparse::addrparse_header(header) else {
+            continue;
+        };
+        for addr in list.iter() {
+            match addr {
+                MailAddr::Single(info) => addresses.push(info.addr.clone()),
+                MailAddr::Group(group) => {
+                    addresses.extend(group.addrs.iter().map(|info| info.addr.clone()))
+                }
+            }
+        }
+    }
+    addresses
+}
+
+/// Creates a group from the message, titled with its subject, holding a
+/// scan for each attached page, unless an ingest rule files the pages
+/// elsewhere. Returns how many scans were created.
+fn import_message(
+    message: &[u8],
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<usize, MailImportError> {
+    let mail = mailparse::parse_mail(message)?;
+    let subject = mail.headers.get_first_value("Subject").unwrap_or_default();
+    let from = mail.headers.get_first_value("From").unwrap_or_default();
+    let sent_at = mail
+        .headers
+        .get_first_value("Date")
+        .and_then(|date| mailparse::dateparse(&date).ok())
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        .unwrap_or_else(Utc::now);
+
+    let pages = attached_pages(&mail)?;
+    if pages.is_empty() {
+        println!(
+            "Skipping email {:?} from {}: no scanned pages",
+            subject, from
+        );
+        return Ok(0);
+    }
+
+    let document = IncomingDocument {
+        title: if subject.trim().is_empty() {
+            format!("Email from {}", from)
+        } else {
+            subject.trim().to_string()
+        },
+        comment: format!("Imported from an email from {}", from),
+        source: IngestSource::Email,
+        routes: recipients(&mail),
+        scan_parameters: HashMap::from([
+            ("from".to_string(), from.clone()),
+            ("subject".to_string(), subject.clone()),
+        ]),
+        scanned_at: sent_at,
+        pages,
+        upload_id: None,
+    };
+    let group = ingest::import_document(&document, pool, assets_dir, public_url)?;
+
+    println!(
+        "Imported {} page(s) from email {:?} into group {}",
+        document.pages.len(),
+        subject,
+        group.id
+    );
+    Ok(document.pages.len())
+}
+
+/// Imports every message waiting in the mailbox, then moves each to the
+/// archive mailbox. A message that fails to import is left for the next
+/// poll. Returns how many scans were created.
+pub fn poll(
+    config: &MailImportConfig,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    public_url: &PublicUrl,
+) -> Result<usize, MailImportError> {
+    let tls = native_tls::TlsConnector::new()?;
+    let client = imap::connect((config.host.as_str(), config.port), &config.host, &tls)?;
+    let mut session = client
+        .login(&config.user, &config.password)
+        .map_err(|(e, _)| e)?;
+
+    // Fails harmlessly when the archive already exists
+    session.create(&config.archive_mailbox).ok();
+    session.select(&config.mailbox)?;
+
+    let mut uids: Vec<u32> = session.uid_search("ALL")?.into_iter().collect();
+    uids.sort();
+
+    let mut imported = 0;
+    for uid in uids {
+        let messages = session.uid_fetch(uid.to_string(), "RFC822")?;
+        let Some(message) = messages.iter().next().and_then(|fetch| fetch.body()) else {
+            continue;
+        };
+        imported += import_message(message, pool, assets_dir, public_url)?;
+
+        session.uid_copy(uid.to_string(), &config.archive_mailbox)?;
+        session.uid_store(uid.to_string(), "+FLAGS (\\Deleted)")?;
+    }
+    session.expunge()?;
+    session.logout()?;
+
+    Ok(imported)
+}
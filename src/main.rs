@@ -1,29 +1,195 @@
+mod activity;
+mod alerts;
+mod analytics;
+mod api_keys;
+mod app_context;
+mod archive;
+mod artifacts;
 mod asset_path;
+mod auth;
+mod bagit;
+mod batches;
+mod bitmap_font;
+mod calibration;
+mod classification;
+mod cli;
+mod config_bundle;
+mod contact_sheet;
+mod db_config;
+mod dead_letters;
+mod destination_mappings;
+mod dewarp;
+mod digest;
+mod drop_folder;
+mod dropout;
+mod duplicates;
+mod entities;
+mod epub;
+mod escl;
+mod export_history;
+mod exports;
+mod file_log;
+mod group_assignments;
+mod group_comments;
+mod group_links;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod gutter;
+mod iiif;
+mod ingest;
+mod ingest_rules;
+mod init;
+mod instance_lock;
+mod known_scanners;
+mod label;
+mod ldap_sync;
+mod loadtest;
+mod locale;
+mod login_events;
+mod long_image;
+mod mail_import;
 mod migrations;
+mod ocr;
+mod opds;
+mod page_cleanup;
+mod page_numbers;
+mod pdf;
+mod preview;
+mod processing_profiles;
+mod punch_holes;
+mod qr;
+mod sandbox;
 mod scan_dividers;
+mod scan_presets;
+mod scan_queue;
+mod scan_templates;
+mod scanner_options;
+mod scanner_power;
 mod scanners;
 mod scans;
 mod schema;
+mod schema_changes;
+mod self_test;
 mod simple_broker;
+mod snapshot;
+mod stitch;
+mod storage;
+mod system_status;
+mod tag_suggestions;
+mod test_page;
+mod tiles;
+mod title_suggestions;
+mod uploads;
+mod users;
+mod validation;
+mod xml;
+mod year_in_review;
+mod zip;
 
 use std::env;
 
+use alerts::AlertConfig;
+use app_context::AppContext;
+use archive::ArchiveConfig;
+use artifacts::Artifact;
+use asset_path::AssetPath;
 use async_graphql::http::GraphiQLSource;
-use async_graphql_poem::{GraphQL, GraphQLSubscription};
+use async_graphql::{http::ALL_WEBSOCKET_PROTOCOLS, Data as GraphQLData};
+use async_graphql_poem::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use auth::{AuthConfig, Scope};
+use batches::{BatchRunner, ScanBatch};
+use clap::Parser;
+use db_config::DbConfig;
+use digest::{DigestConfig, WeeklyDigest};
+use drop_folder::DropFolderConfig;
 use duckdb::{DuckdbConnectionManager, Result};
-use migrations::migrate;
+use export_history::GroupExport;
+use exports::{export_group, ExportError, ExportFormat, ExportOptions};
+use file_log::LogConfig;
+use ingest::{IncomingDocument, IngestError};
+use ingest_rules::IngestSource;
+use instance_lock::InstanceLock;
+use ldap_sync::LdapConfig;
+use mail_import::MailImportConfig;
+use migrations::{migrate, BackupConfig};
 use poem::{
-    endpoint::StaticFilesEndpoint,
     get, handler,
+    http::{header, HeaderMap, StatusCode},
     listener::TcpListener,
-    web::{Html, Path},
-    IntoResponse, Route, Server,
+    post,
+    web::{websocket::WebSocket, Data, Html, Json, Multipart, Path, Query, RemoteAddr},
+    EndpointExt, IntoResponse, Response, Route, Server,
 };
+use preview::{PreviewEdits, PreviewError};
 use scanners::ScannerManager;
+use scans::{CropCoordinates, Scan, ScanGroup};
 use schema::{BooksSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot};
+use serde::{Deserialize, Serialize};
+use simple_broker::SimpleBroker;
+use snapshot::Snapshot;
+use storage::StorageKey;
+use tokio::io::AsyncReadExt;
+use uploads::{Upload, UploadStatus};
+use users::Role;
 
+/// Where scan files are kept, and the key they are encrypted with if any.
 #[derive(Clone)]
-pub struct AssetsDir(String);
+pub struct AssetsDir(String, Option<StorageKey>);
+
+/// Base URL the web UI is reached at, for links printed on paper.
+#[derive(Clone)]
+pub struct PublicUrl(String);
+
+impl PublicUrl {
+    pub fn group_url(&self, id: i32) -> String {
+        format!("{}/groups/{}", self.0.trim_end_matches('/'), id)
+    }
+
+    pub fn scan_url(&self, id: i32) -> String {
+        format!("{}/scans/{}", self.0.trim_end_matches('/'), id)
+    }
+
+    pub fn opds_url(&self) -> String {
+        format!("{}/api/opds", self.0.trim_end_matches('/'))
+    }
+
+    /// The group exported on demand, for e-reader apps.
+    pub fn download_url(&self, group_id: i32, format: ExportFormat) -> String {
+        format!(
+            "{}/api/groups/{}/download.{}",
+            self.0.trim_end_matches('/'),
+            group_id,
+            format.extension()
+        )
+    }
+
+    /// IIIF Presentation manifest of the group's pages.
+    pub fn manifest_url(&self, group_id: i32) -> String {
+        format!(
+            "{}/api/iiif/groups/{}/manifest",
+            self.0.trim_end_matches('/'),
+            group_id
+        )
+    }
+
+    /// IIIF Image API service for the scan's tiles.
+    pub fn image_service_url(&self, scan_id: i32) -> String {
+        format!(
+            "{}/api/iiif/scans/{}",
+            self.0.trim_end_matches('/'),
+            scan_id
+        )
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "scanserv")]
+struct Cli {
+    /// Run a one-off command instead of starting the server. Commands need
+    /// the server stopped, as only one process can use the database.
+    #[command(subcommand)]
+    command: Option<cli::Command>,
+}
 
 #[handler]
 async fn graphiql() -> impl IntoResponse {
@@ -35,6 +201,639 @@ async fn graphiql() -> impl IntoResponse {
     )
 }
 
+#[handler]
+async fn graphql_handler(
+    schema: Data<&BooksSchema>,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    headers: &HeaderMap,
+    remote_addr: &RemoteAddr,
+    req: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut req = req.0;
+    if let Some(addr) = remote_addr.as_socket_addr() {
+        req = req.data(auth::ClientIp(addr.ip().to_string()));
+    }
+    if let Some(principal) = auth::principal_from_headers(headers, &pool) {
+        req = req.data(principal);
+    }
+    if let Some(session) = auth::session_cookie_from_headers(headers) {
+        req = req.data(auth::SessionToken(session));
+    }
+    schema.execute(req).await.into()
+}
+
+/// Subscriptions, authenticated like `graphql_handler`: by the upgrade
+/// request's headers, or by credentials in the `connection_init` payload
+/// for browsers that can't set headers on a websocket. With auth required,
+/// a connection without valid credentials is closed at init.
+#[handler]
+async fn graphql_ws(
+    schema: Data<&BooksSchema>,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    auth_config: Data<&AuthConfig>,
+    headers: &HeaderMap,
+    protocol: GraphQLProtocol,
+    websocket: WebSocket,
+) -> impl IntoResponse {
+    let schema = schema.0.clone();
+    let pool = pool.0.clone();
+    let required = auth_config.required;
+    let from_headers = auth::principal_from_headers(headers, &pool);
+
+    websocket
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| {
+            GraphQLWebSocket::new(stream, schema, protocol)
+                .on_connection_init(move |payload| async move {
+                    let principal = tokio::task::spawn_blocking(move || {
+                        auth::principal_from_init_payload(&payload, &pool).or(from_headers)
+                    })
+                    .await?;
+
+                    let mut data = GraphQLData::default();
+                    match principal {
+                        Some(principal) => data.insert(principal),
+                        None if required => return Err("Unauthorized".into()),
+                        None => {}
+                    }
+                    Ok(data)
+                })
+                .serve()
+        })
+}
+
+/// Serves scan files, decrypted if they are stored encrypted, to callers
+/// that may read scans.
+#[handler]
+fn asset(
+    Path(path): Path<String>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if path.split('/').any(|part| part.is_empty() || part == "..") {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let content_type = match path.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("pdf") => "application/pdf",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    };
+    match assets_dir.read(&AssetPath::from_relative_path(path)) {
+        Ok(contents) => Response::builder()
+            .content_type(content_type)
+            .body(contents),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn attachment(format: ExportFormat, file_name: &str, contents: Vec<u8>) -> Response {
+    Response::builder()
+        .content_type(format.content_type())
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", file_name.replace('"', "")),
+        )
+        .body(contents)
+}
+
+/// Downloads a previous export again, from the artifact store or, for
+/// exports from before it, where it was written. Bags are directories and
+/// can't be served this way.
+#[handler]
+fn export_download(
+    Path(id): Path<i32>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Ok(export) = GroupExport::load(id, &pool) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let file_name = std::path::Path::new(&export.artifact_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stored = export
+        .artifact_id
+        .and_then(|id| Artifact::load(id, &pool).ok().flatten())
+        .map(|artifact| artifact.disk_path(&assets_dir));
+    let contents = match stored {
+        Some(path) => std::fs::read(path),
+        None => std::fs::read(&export.artifact_path),
+    };
+    match contents {
+        Ok(contents) => attachment(export.format, &file_name, contents),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Downloads a stored export file by its artifact id.
+#[handler]
+fn artifact_download(
+    Path(id): Path<i32>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Ok(Some(artifact)) = Artifact::load(id, &pool) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let file_name = format!("{}.{}", artifact.sha256, artifact.format.extension());
+    match std::fs::read(artifact.disk_path(&assets_dir)) {
+        Ok(contents) => attachment(artifact.format, &file_name, contents),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// The printable calibration targets, to scan for `analyzeCalibrationScan`.
+#[handler]
+async fn calibration_target(
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match tokio::task::spawn_blocking(calibration::target_pdf)
+        .await
+        .unwrap()
+    {
+        Ok(pdf) => Response::builder()
+            .content_type("application/pdf")
+            .header(
+                "Content-Disposition",
+                "attachment; filename=\"calibration-target.pdf\"",
+            )
+            .body(pdf),
+        Err(e) => {
+            println!("Failed to render calibration target: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Runs `render` on a completed scan off the async runtime, or gives the
+/// response to send instead.
+async fn render_scan<T: Send + 'static>(
+    scan_id: i32,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+    render: fn(&Scan, &AssetsDir) -> image::ImageResult<T>,
+) -> Result<T, Response> {
+    let scan = match Scan::load(scan_id, pool) {
+        Ok(scan) if scan.status == "COMPLETE" => scan,
+        _ => return Err(StatusCode::NOT_FOUND.into_response()),
+    };
+    let assets_dir = assets_dir.clone();
+    tokio::task::spawn_blocking(move || render(&scan, &assets_dir))
+        .await
+        .unwrap()
+        .map_err(|e| {
+            println!("Failed to render scan {}: {}", scan_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+}
+
+/// IIIF Image API description of a scan's tiles, so viewers can pan and
+/// zoom large scans without downloading the whole image.
+#[handler]
+async fn iiif_info(
+    Path(scan_id): Path<i32>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    public_url: Data<&PublicUrl>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    match render_scan(scan_id, &pool, &assets_dir, tiles::pyramid).await {
+        Ok(pyramid) => Response::builder()
+            .content_type("application/ld+json;profile=\"http://iiif.io/api/image/3/context.json\"")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(tiles::info_json(scan_id, &pyramid, &public_url).to_string()),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+struct PreviewQuery {
+    rotation: Option<i32>,
+    /// The crop, as fractions of the rotated page; all four or none
+    x: Option<f32>,
+    y: Option<f32>,
+    width: Option<f32>,
+    height: Option<f32>,
+    size: Option<u32>,
+}
+
+/// A low-resolution render of a rotation and crop the editor is trying
+/// out, made the way the saved result is. Nothing is saved. Without a
+/// rotation or crop the scan's own is used; a crop of the whole page
+/// shows it uncropped.
+#[handler]
+async fn scan_preview(
+    Path(scan_id): Path<i32>,
+    Query(query): Query<PreviewQuery>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let scan = match Scan::load(scan_id, &pool) {
+        Ok(scan) if scan.status == "COMPLETE" => scan,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let crop = match (query.x, query.y, query.width, query.height) {
+        (Some(x), Some(y), Some(width), Some(height)) => Some(CropCoordinates {
+            x,
+            y,
+            width,
+            height,
+        }),
+        (None, None, None, None) => scan.crop(),
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    let edits = PreviewEdits {
+        rotation: query
+            .rotation
+            .map(|rotation| rotation.rem_euclid(360))
+            .unwrap_or(scan.rotation),
+        crop,
+        size: query.size.unwrap_or(preview::DEFAULT_SIZE),
+    };
+
+    let assets_dir = assets_dir.clone();
+    let rendered = tokio::task::spawn_blocking(move || {
+        preview::render(&scan, scan.group.as_ref(), &edits, &assets_dir)
+    })
+    .await
+    .unwrap();
+    match rendered {
+        Ok(jpeg) => Response::builder()
+            .content_type("image/jpeg")
+            .header("Cache-Control", "no-store")
+            .body(jpeg),
+        Err(e @ PreviewError::BadCrop) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(e.to_string()),
+        Err(e) => {
+            println!("Failed to render preview of scan {}: {}", scan_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// One tile of a scan, requested the way IIIF Image API viewers do.
+#[handler]
+async fn iiif_tile(
+    Path((scan_id, region, size, rotation, file)): Path<(i32, String, String, String, String)>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let jpeg = if region == "full" && size == "max" && rotation == "0" && file == "default.jpg" {
+        match render_scan(scan_id, &pool, &assets_dir, tiles::full_image).await {
+            Ok(jpeg) => Some(jpeg),
+            Err(response) => return response,
+        }
+    } else {
+        let pyramid = match render_scan(scan_id, &pool, &assets_dir, tiles::pyramid).await {
+            Ok(pyramid) => pyramid,
+            Err(response) => return response,
+        };
+        tiles::tile(
+            scan_id,
+            &pyramid,
+            &region,
+            &size,
+            &rotation,
+            &file,
+            &assets_dir,
+        )
+    };
+    match jpeg {
+        Some(jpeg) => Response::builder()
+            .content_type("image/jpeg")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(jpeg),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// IIIF Presentation manifest of a group's completed pages, for viewers
+/// such as Mirador and Universal Viewer.
+#[handler]
+fn iiif_manifest(
+    Path(group_id): Path<i32>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    public_url: Data<&PublicUrl>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Ok(group) = ScanGroup::load(group_id, &pool) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    Response::builder()
+        .content_type(
+            "application/ld+json;profile=\"http://iiif.io/api/presentation/3/context.json\"",
+        )
+        .header("Access-Control-Allow-Origin", "*")
+        .body(iiif::manifest(&group, &public_url, &assets_dir).to_string())
+}
+
+/// A 401 that asks for Basic credentials, which is all e-reader apps can
+/// send. The password is an API key.
+fn basic_challenge() -> Response {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("WWW-Authenticate", "Basic realm=\"scanserv\"")
+        .finish()
+}
+
+#[derive(Deserialize)]
+struct FeedPage {
+    #[serde(default)]
+    page: i64,
+}
+
+/// OPDS catalog of finalized groups, so e-reader apps can browse and
+/// download the library.
+#[handler]
+fn opds_feed(
+    Query(FeedPage { page }): Query<FeedPage>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    public_url: Data<&PublicUrl>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    if !auth::headers_have_scope(headers, Scope::Read, &auth_config, &pool) {
+        return basic_challenge();
+    }
+    match opds::feed(page.max(0), &public_url, &pool) {
+        Ok(feed) => Response::builder().content_type(opds::FEED_TYPE).body(feed),
+        Err(e) => {
+            println!("Failed to build OPDS feed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Exports a group on demand in one of the catalog's formats. The export is
+/// kept in a temporary directory, so an unchanged group is only rendered once.
+#[handler]
+async fn group_download(
+    Path((group_id, file)): Path<(i32, String)>,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    public_url: Data<&PublicUrl>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    let principal = auth::principal_from_headers(headers, &pool);
+    if auth_config.required
+        && !principal
+            .as_ref()
+            .is_some_and(|principal| principal.has_scope(Scope::Read))
+    {
+        return basic_challenge();
+    }
+    let Some(format) = opds::DOWNLOAD_FORMATS
+        .into_iter()
+        .find(|format| file == format!("download.{}", format.extension()))
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let options = ExportOptions {
+        format,
+        thumbnails_per_page: contact_sheet::DEFAULT_THUMBNAILS_PER_PAGE,
+        stamp_qr: false,
+        public_url: public_url.clone(),
+        triggered_by: match &principal {
+            Some(principal) => format!("download ({})", principal.name()),
+            None => "download".to_string(),
+        },
+        force: false,
+    };
+    let dir = std::env::temp_dir().join("scanserv-downloads");
+    let out = dir.join(format!("group_{}.{}", group_id, format.extension()));
+    let (pool, assets_dir) = (pool.clone(), assets_dir.clone());
+    let exported = tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&dir)?;
+        let export = export_group(group_id, &options, &out, &pool, &assets_dir)?;
+        let title = ScanGroup::load(group_id, &pool)
+            .map(|group| group.title)
+            .unwrap_or_default();
+        Ok::<_, ExportError>((title, std::fs::read(&export.artifact_path)?))
+    })
+    .await
+    .unwrap();
+
+    match exported {
+        Ok((title, contents)) => {
+            let name: String = title
+                .chars()
+                .filter(|c| !c.is_control() && !matches!(c, '"' | '/' | '\\'))
+                .collect();
+            let name = match name.trim() {
+                "" => format!("group_{}", group_id),
+                name => name.to_string(),
+            };
+            Response::builder()
+                .content_type(format.content_type())
+                .header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{}.{}\"", name, format.extension()),
+                )
+                .body(contents)
+        }
+        Err(ExportError::GroupNotFound | ExportError::NoPages) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            println!("Failed to export group {} for download: {}", group_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Uploaded {
+    group_id: i32,
+    pages: usize,
+    url: String,
+}
+
+/// Takes documents shared from a phone in one multipart POST: image or PDF
+/// files plus an optional `title` field. They become a new group, unless an
+/// ingest rule for the uploader files them elsewhere, and the group's web
+/// URL is returned. Bytes received and pages filed are kept on an upload
+/// row while it runs.
+#[handler]
+async fn upload(
+    mut multipart: Multipart,
+    headers: &HeaderMap,
+    pool: Data<&r2d2::Pool<DuckdbConnectionManager>>,
+    assets_dir: Data<&AssetsDir>,
+    public_url: Data<&PublicUrl>,
+    auth_config: Data<&AuthConfig>,
+) -> Response {
+    let principal = auth::principal_from_headers(headers, &pool);
+    if auth_config.required
+        && !principal
+            .as_ref()
+            .is_some_and(|principal| principal.has_scope(Scope::Scan))
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let uploader = principal
+        .as_ref()
+        .map(|principal| principal.name().to_string());
+
+    let bytes_total = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok());
+    let upload_id = Upload::create(uploader.as_deref(), bytes_total, &pool).unwrap();
+    let fail = |status: StatusCode| {
+        Upload::finish(upload_id, UploadStatus::Failed, &pool).ok();
+        status.into_response()
+    };
+
+    let mut title = None;
+    let mut pages = Vec::new();
+    let (mut received, mut reported) = (0i64, 0i64);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(_) => return fail(StatusCode::BAD_REQUEST),
+        };
+        if field.name() == Some("title") {
+            title = field
+                .text()
+                .await
+                .ok()
+                .filter(|title| !title.trim().is_empty());
+            continue;
+        }
+
+        // Read in chunks so the upload's row can show how far along it is
+        let mut reader = field.into_async_read();
+        let mut contents = Vec::new();
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    // Stop before an oversized file is all in memory
+                    if (contents.len() + n) as u64 > validation::CONFIG.max_file_bytes {
+                        return fail(StatusCode::PAYLOAD_TOO_LARGE);
+                    }
+                    contents.extend_from_slice(&buf[..n]);
+                    received += n as i64;
+                    if received - reported >= uploads::PROGRESS_STEP {
+                        Upload::set_bytes_received(upload_id, received, &pool).ok();
+                        reported = received;
+                    }
+                }
+                Err(_) => return fail(StatusCode::BAD_REQUEST),
+            }
+        }
+        pages.extend(ingest::file_pages(contents));
+    }
+    if pages.is_empty() {
+        return fail(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    // The whole request is in, multipart framing included
+    Upload::start_import(
+        upload_id,
+        bytes_total.unwrap_or(received).max(received),
+        pages.len(),
+        &pool,
+    )
+    .ok();
+
+    let now = chrono::Utc::now();
+    let document = IncomingDocument {
+        title: title
+            .map(|title| title.trim().to_string())
+            .unwrap_or_else(|| format!("Upload {}", locale::format_datetime(now))),
+        comment: match &uploader {
+            Some(uploader) => format!("Uploaded by {}", uploader),
+            None => "Uploaded".to_string(),
+        },
+        source: IngestSource::Upload,
+        routes: uploader.iter().cloned().collect(),
+        scan_parameters: uploader
+            .iter()
+            .map(|uploader| ("uploader".to_string(), uploader.clone()))
+            .collect(),
+        scanned_at: now,
+        pages,
+        upload_id: Some(upload_id),
+    };
+    let pages = document.pages.len();
+    let (pool_clone, assets_dir, public_url) =
+        (pool.clone(), assets_dir.clone(), public_url.clone());
+    // Classification rules may run OCR over the new pages
+    let imported = tokio::task::spawn_blocking(move || {
+        ingest::import_document(&document, &pool_clone, &assets_dir, &public_url)
+            .map(|group| (group.id, public_url.group_url(group.id)))
+    })
+    .await
+    .unwrap();
+    match imported {
+        Ok((group_id, url)) => {
+            Upload::finish(upload_id, UploadStatus::Complete, &pool).ok();
+            (
+                StatusCode::CREATED,
+                Json(Uploaded {
+                    group_id,
+                    pages,
+                    url,
+                }),
+            )
+                .into_response()
+        }
+        Err(IngestError::NoReadablePages) => fail(StatusCode::UNPROCESSABLE_ENTITY),
+        Err(e) => {
+            println!("Failed to import upload: {}", e);
+            fail(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 #[handler]
 fn hello(Path(name): Path<String>) -> String {
     format!("hello: {}", name)
@@ -42,44 +841,424 @@ fn hello(Path(name): Path<String>) -> String {
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
+    let cli = Cli::parse();
+
+    // Decode workers only read stdin, so they skip the database entirely
+    if let Some(cli::Command::DecodeWorker { max_pixels }) = cli.command {
+        std::process::exit(sandbox::run_worker(max_pixels));
+    }
+    // Load tests bring their own in-memory database and assets
+    if let Some(cli::Command::Loadtest(options)) = cli.command {
+        std::process::exit(cli::loadtest(options).await);
+    }
+
+    // Keep a rotated log file for boxes without journald; one-off commands
+    // only print to the console
+    if cli.command.is_none() {
+        if let Some(config) = LogConfig::from_env() {
+            if let Err(e) = file_log::start(config) {
+                eprintln!("Could not start logging to LOG_FILE: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     println!("Starting up...");
 
-    let manager = DuckdbConnectionManager::file("./db.duckdb").unwrap();
+    // One process at a time drives the scanners and writes the database.
+    // One-off commands can't run alongside the server, so they fail here
+    // with who holds the lock rather than on DuckDB's file lock.
+    let instance_lock = {
+        let assets_dir = env::var("ASSETS_DIR").unwrap_or("./assets".to_string());
+        match InstanceLock::acquire(&assets_dir) {
+            Ok(lock) => lock,
+            Err(e) if cli.command.is_some() => {
+                eprintln!(
+                    "Refusing to run: {} While the server is up, use its API instead.",
+                    e
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Refusing to start: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    instance_lock.keep_alive();
+
+    let db_config = DbConfig {
+        memory_limit: env::var("DUCKDB_MEMORY_LIMIT").ok(),
+        temp_directory: env::var("DUCKDB_TEMP_DIRECTORY").ok(),
+        checkpoint_threshold: env::var("DUCKDB_CHECKPOINT_THRESHOLD").ok(),
+    };
+    let manager = DuckdbConnectionManager::file_with_flags(
+        "./db.duckdb",
+        db_config.to_duckdb_config().unwrap(),
+    )
+    .unwrap();
     let pool = r2d2::Pool::builder().max_size(15).build(manager).unwrap();
     let assets_dir = env::var("ASSETS_DIR").unwrap_or("./assets".to_string());
+    let public_url =
+        PublicUrl(env::var("PUBLIC_URL").unwrap_or("http://localhost:8080".to_string()));
+    // Encrypt scan files at rest with the key in this file
+    let storage_key = env::var("STORAGE_KEY_FILE")
+        .ok()
+        .map(|path| StorageKey::from_file(path.as_ref()).unwrap());
+    let assets = AssetsDir(assets_dir, storage_key);
+    // Take accounts and their roles from LDAP or Active Directory groups
+    let ldap = env::var("LDAP_URL").ok().map(|url| LdapConfig {
+        url,
+        bind_dn: env::var("LDAP_BIND_DN").unwrap(),
+        bind_password: env::var("LDAP_BIND_PASSWORD").unwrap(),
+        base_dn: env::var("LDAP_BASE_DN").unwrap(),
+        user_filter: env::var("LDAP_USER_FILTER").unwrap_or("(objectClass=person)".to_string()),
+        username_attribute: env::var("LDAP_USERNAME_ATTRIBUTE").unwrap_or("uid".to_string()),
+        role_groups: [
+            (Role::Admin, "LDAP_ADMIN_GROUP"),
+            (Role::Operator, "LDAP_OPERATOR_GROUP"),
+            (Role::Viewer, "LDAP_VIEWER_GROUP"),
+        ]
+        .into_iter()
+        .filter_map(|(role, var)| env::var(var).ok().map(|dn| (role, dn)))
+        .collect(),
+    });
+    let auth_config = AuthConfig {
+        required: env::var("AUTH_REQUIRED").unwrap_or_default() == "true",
+        ldap,
+    };
+
+    let backup_config = BackupConfig {
+        dir: env::var("BACKUP_DIR")
+            .unwrap_or("./backups".to_string())
+            .into(),
+        keep: env::var("BACKUP_KEEP")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5),
+        max_copy_bytes: env::var("BACKUP_MAX_COPY_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(512)
+            * 1024
+            * 1024,
+    };
+
+    migrate(&pool, &backup_config).await;
 
-    migrate(&pool).await;
+    if let Some(command) = cli.command {
+        let exit_code = cli::run(command, &pool, &assets, &public_url).await;
+        instance_lock.release();
+        std::process::exit(exit_code);
+    }
 
+    // Opt in to fetching trained data for OCR_LANGUAGES that tesseract lacks
+    let tessdata_url = (env::var("OCR_DOWNLOAD_LANGUAGES").unwrap_or_default() == "true")
+        .then(|| env::var("TESSDATA_URL").unwrap_or(init::DEFAULT_TESSDATA_URL.to_string()));
+    init::run(&pool, &assets, &auth_config, tessdata_url.as_deref());
+    for warning in schema_changes::check(locale::today()) {
+        println!("Schema changes: {}", warning);
+    }
+
+    // Frequent enough to notice devices being plugged in and unplugged
+    let scanner_refresh_seconds = env::var("SCANNER_REFRESH_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60)
+        .max(1);
     let scanner_manager = ScannerManager::new();
-    let scanner_manager_clone = scanner_manager.clone();
-    tokio::spawn(async move {
-        loop {
-            scanner_manager_clone.force_list_scanners().await;
-            println!("Refreshed scanners");
-            tokio::time::sleep(tokio::time::Duration::from_secs(600)).await;
-        }
+    scanner_manager.refresh_in_background(
+        tokio::time::Duration::from_secs(scanner_refresh_seconds),
+        pool.clone(),
+    );
+
+    let interrupted = ScanBatch::pause_interrupted(&pool).unwrap();
+    if interrupted > 0 {
+        println!(
+            "Paused {} batch(es) interrupted by the last shutdown",
+            interrupted
+        );
+    }
+    let batch_runner = BatchRunner::new(scanner_manager.clone(), pool.clone(), assets.clone());
+
+    let snapshot = Snapshot::new(
+        env::var("SNAPSHOT_DIR")
+            .unwrap_or("./snapshots".to_string())
+            .into(),
+        db_config,
+    );
+    let snapshot_refresh_minutes = env::var("SNAPSHOT_REFRESH_MINUTES")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(0);
+    if snapshot_refresh_minutes > 0 {
+        let snapshot_clone = snapshot.clone();
+        let pool_clone = pool.clone();
+        tokio::spawn(async move {
+            loop {
+                match snapshot_clone.refresh(&pool_clone) {
+                    Ok(_) => println!("Refreshed database snapshot"),
+                    Err(e) => println!("Failed to refresh database snapshot: {}", e),
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(
+                    snapshot_refresh_minutes * 60,
+                ))
+                .await;
+            }
+        });
+    }
+
+    // Recompress old originals losslessly, so the archive doesn't grow forever
+    let archive_config = ArchiveConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
     });
+    let interval_hours = archive_config.interval_hours;
+    if interval_hours > 0 {
+        let config = archive_config.clone();
+        let pool_clone = pool.clone();
+        let assets_clone = assets.clone();
+        tokio::spawn(async move {
+            loop {
+                let (config, pool, assets) =
+                    (config.clone(), pool_clone.clone(), assets_clone.clone());
+                let report =
+                    tokio::task::spawn_blocking(move || archive::run(&config, &pool, &assets))
+                        .await
+                        .unwrap();
+                println!(
+                    "Recompressed {} original(s), reclaiming {} bytes ({} kept, {} failed, {} held)",
+                    report.recompressed,
+                    report.reclaimed_bytes,
+                    report.kept,
+                    report.failed,
+                    report.held
+                );
+                tokio::time::sleep(tokio::time::Duration::from_secs(interval_hours * 3600)).await;
+            }
+        });
+    }
+
+    // Catch a full disk or a failing scanner before the next scanning session
+    let alert_config = AlertConfig::from_env();
+    if alert_config.any() {
+        let pool_clone = pool.clone();
+        let assets_clone = assets.clone();
+        tokio::spawn(async move {
+            loop {
+                let (config, pool, assets) = (
+                    alert_config.clone(),
+                    pool_clone.clone(),
+                    assets_clone.clone(),
+                );
+                let result =
+                    tokio::task::spawn_blocking(move || alerts::check(&config, &pool, &assets))
+                        .await
+                        .unwrap();
+                if let Err(e) = result {
+                    println!("Failed to check alert rules: {}", e);
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(
+                    alert_config.interval_minutes * 60,
+                ))
+                .await;
+            }
+        });
+    }
+
+    // Summarize the week for whoever does the paperwork at the weekend
+    let digest_config = DigestConfig::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(config) = digest_config {
+        let pool_clone = pool.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(config.until_next(chrono::Local::now())).await;
+                let pool = pool_clone.clone();
+                match tokio::task::spawn_blocking(move || WeeklyDigest::create(&pool))
+                    .await
+                    .unwrap()
+                {
+                    Ok(digest) => {
+                        println!("Sent the weekly digest");
+                        SimpleBroker::publish(digest);
+                    }
+                    Err(e) => println!("Failed to make the weekly digest: {}", e),
+                }
+            }
+        });
+    }
+
+    // Import scans from a mailbox that scan-to-email devices send to
+    let mail_import = env::var("IMAP_HOST").ok().map(|host| MailImportConfig {
+        host,
+        port: env::var("IMAP_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(993),
+        user: env::var("IMAP_USER").unwrap(),
+        password: env::var("IMAP_PASSWORD").unwrap(),
+        mailbox: env::var("IMAP_MAILBOX").unwrap_or("INBOX".to_string()),
+        archive_mailbox: env::var("IMAP_ARCHIVE_MAILBOX").unwrap_or("Archive".to_string()),
+    });
+    let mail_poll_seconds = env::var("IMAP_POLL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+    if let Some(config) = mail_import {
+        let pool_clone = pool.clone();
+        let assets_clone = assets.clone();
+        let public_url_clone = public_url.clone();
+        tokio::spawn(async move {
+            loop {
+                let (config, pool, assets, public_url) = (
+                    config.clone(),
+                    pool_clone.clone(),
+                    assets_clone.clone(),
+                    public_url_clone.clone(),
+                );
+                let result = tokio::task::spawn_blocking(move || {
+                    mail_import::poll(&config, &pool, &assets, &public_url)
+                })
+                .await
+                .unwrap();
+                if let Err(e) = result {
+                    println!("Failed to import scans from email: {}", e);
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(mail_poll_seconds)).await;
+            }
+        });
+    }
+
+    // Import files that scan-to-FTP devices upload into a drop folder
+    let drop_folder = env::var("DROP_FOLDER").ok().map(|dir| DropFolderConfig {
+        dir: dir.into(),
+        settle: std::time::Duration::from_secs(
+            env::var("DROP_FOLDER_SETTLE_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+        ),
+    });
+    let drop_poll_seconds = env::var("DROP_FOLDER_POLL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(15);
+    if let Some(config) = drop_folder {
+        let pool_clone = pool.clone();
+        let assets_clone = assets.clone();
+        let public_url_clone = public_url.clone();
+        tokio::spawn(async move {
+            loop {
+                let (config, pool, assets, public_url) = (
+                    config.clone(),
+                    pool_clone.clone(),
+                    assets_clone.clone(),
+                    public_url_clone.clone(),
+                );
+                let result = tokio::task::spawn_blocking(move || {
+                    drop_folder::poll(&config, &pool, &assets, &public_url)
+                })
+                .await
+                .unwrap();
+                if let Err(e) = result {
+                    println!("Failed to import scans from the drop folder: {}", e);
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(drop_poll_seconds)).await;
+            }
+        });
+    }
+
+    let ldap_sync_minutes = env::var("LDAP_SYNC_MINUTES")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+    if let Some(config) = auth_config.ldap.clone() {
+        let pool_clone = pool.clone();
+        tokio::spawn(async move {
+            loop {
+                let (config, pool) = (config.clone(), pool_clone.clone());
+                match tokio::task::spawn_blocking(move || ldap_sync::sync(&config, &pool))
+                    .await
+                    .unwrap()
+                {
+                    Ok(report) => {
+                        println!(
+                            "Synced users from LDAP: {} created, {} updated, {} disabled",
+                            report.created.len(),
+                            report.updated.len(),
+                            report.disabled.len()
+                        );
+                        for username in report.skipped {
+                            println!(
+                                "Not syncing {} from LDAP, a local account has that username",
+                                username
+                            );
+                        }
+                    }
+                    Err(e) => println!("Failed to sync users from LDAP: {}", e),
+                }
+                tokio::time::sleep(tokio::time::Duration::from_secs(ldap_sync_minutes * 60)).await;
+            }
+        });
+    }
+
+    let app_context = AppContext {
+        pool: pool.clone(),
+        scanner_manager,
+        batch_runner,
+        assets_dir: assets.clone(),
+        public_url: public_url.clone(),
+        auth_config: auth_config.clone(),
+        snapshot,
+        archive_config,
+        books: Storage::default(),
+    };
+
+    // The same API over gRPC, for backends that would rather not use GraphQL
+    #[cfg(feature = "grpc")]
+    if let Ok(addr) = env::var("GRPC_ADDR") {
+        let addr = addr
+            .parse()
+            .expect("GRPC_ADDR should be an address like 0.0.0.0:50051");
+        let app = app_context.clone();
+        tokio::spawn(async move {
+            println!("Serving gRPC on {}", addr);
+            if let Err(e) = grpc::serve(addr, app).await {
+                println!("gRPC server stopped: {}", e);
+            }
+        });
+    }
 
     let schema = BooksSchema::build(QueryRoot, MutationRoot, SubscriptionRoot)
-        .data(Storage::default())
-        .data(scanner_manager)
-        .data(pool)
-        .data(AssetsDir(assets_dir.clone()))
+        .data(app_context)
         .finish();
 
     let app = Route::new()
         .at("/api/hello/:name", get(hello))
+        .at("/api/graphql", get(graphiql).post(graphql_handler))
+        .at("/api/exports/:id", get(export_download))
+        .at("/api/artifacts/:id", get(artifact_download))
+        .at("/api/calibration/target.pdf", get(calibration_target))
+        .at("/api/upload", post(upload))
+        .at("/api/opds", get(opds_feed))
+        .at("/api/groups/:id/:file", get(group_download))
+        .at("/api/scans/:id/preview.jpg", get(scan_preview))
+        .at("/api/iiif/groups/:id/manifest", get(iiif_manifest))
+        .at("/api/iiif/scans/:id/info.json", get(iiif_info))
         .at(
-            "/api/graphql",
-            get(graphiql).post(GraphQL::new(schema.clone())),
+            "/api/iiif/scans/:id/:region/:size/:rotation/:file",
+            get(iiif_tile),
         )
-        .at("/api/graphql/ws", get(GraphQLSubscription::new(schema)))
-        .nest(
-            "/assets",
-            StaticFilesEndpoint::new(assets_dir)
-                .show_files_listing()
-                .index_file("index.html"),
-        );
+        .at("/api/graphql/ws", get(graphql_ws))
+        .at("/assets/*path", get(asset))
+        .data(schema)
+        .data(assets)
+        .data(public_url)
+        .data(auth_config)
+        .data(pool);
 
     // println!("Scanners: {:?}", scanners);
     println!("GraphiQL IDE: http://localhost:8080/api/graphql");
@@ -1,3 +1,9 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
 use duckdb::{params, DuckdbConnectionManager, OptionalExt};
 
 static META_MIGRATION: &str = r"
@@ -83,9 +89,489 @@ static MIGRATIONS: &[&str] = &[
         WHERE g.created_at = d.ts
     );
     ",
+    // API keys for token auth
+    r"
+    CREATE SEQUENCE seq_api_keys_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS api_keys (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_api_keys_id'),
+        name TEXT NOT NULL,
+        key_hash TEXT NOT NULL UNIQUE,
+        scopes TEXT NOT NULL DEFAULT '[]',
+        created_at TIMESTAMP NOT NULL,
+        last_used_at TIMESTAMP,
+        revoked_at TIMESTAMP
+    );
+    ",
+    // Users and login sessions for the bundled frontend
+    r"
+    CREATE SEQUENCE seq_users_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_users_id'),
+        username TEXT NOT NULL UNIQUE,
+        password_hash TEXT NOT NULL,
+        role TEXT NOT NULL DEFAULT 'viewer',
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS sessions (
+        token_hash TEXT PRIMARY KEY,
+        user_id INTEGER NOT NULL,
+        created_at TIMESTAMP NOT NULL,
+        expires_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Login audit, also used for brute-force lockouts
+    r"
+    CREATE SEQUENCE seq_login_events_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS login_events (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_login_events_id'),
+        username TEXT NOT NULL,
+        ip TEXT,
+        outcome TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Why a scan failed, so batches can tell an empty feeder from a jam
+    r"
+    ALTER TABLE scans ADD COLUMN failure TEXT;
+    ",
+    // Multi-page feeder batches
+    r"
+    CREATE SEQUENCE seq_scan_batches_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS scan_batches (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_scan_batches_id'),
+        scanner TEXT NOT NULL,
+        scan_parameters TEXT NOT NULL,
+        priority TEXT NOT NULL,
+        scan_group_id INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        pages_scanned INTEGER NOT NULL DEFAULT 0,
+        paused_reason TEXT,
+        created_at TIMESTAMP NOT NULL,
+        updated_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Page count the operator expects a batch to produce, checked when it completes
+    r"
+    ALTER TABLE scan_batches ADD COLUMN expected_pages INTEGER;
+    ",
+    r"
+    ALTER TABLE scan_groups ADD COLUMN page_count_warning TEXT;
+    ",
+    // Book page corrections applied when a group is exported
+    r"
+    ALTER TABLE scan_groups ADD COLUMN dewarp BOOLEAN DEFAULT false;
+    ",
+    r"
+    ALTER TABLE scan_groups ADD COLUMN remove_gutter_shadow BOOLEAN DEFAULT false;
+    ",
+    // Explicit page order within a group; unordered scans follow in scan order
+    r"
+    ALTER TABLE scans ADD COLUMN page_order INTEGER;
+    ",
+    // Legal or retention hold; held groups and their scans are never deleted
+    r"
+    ALTER TABLE scan_groups ADD COLUMN hold BOOLEAN DEFAULT false;
+    ",
+    // Post-capture processing, tracked apart from acquisition status
+    r"
+    ALTER TABLE scans ADD COLUMN processing_status TEXT DEFAULT 'NONE';
+    ",
+    // History of group exports, so earlier artifacts can be downloaded again
+    r"
+    CREATE SEQUENCE seq_exports_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS exports (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_exports_id'),
+        scan_group_id INTEGER NOT NULL,
+        format TEXT NOT NULL,
+        destination TEXT NOT NULL,
+        artifact_path TEXT NOT NULL,
+        triggered_by TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    // What an export was rendered from, so an unchanged group can reuse it
+    r"
+    ALTER TABLE exports ADD COLUMN content_hash TEXT;
+    ",
+    r"
+    ALTER TABLE exports ADD COLUMN pages INTEGER;
+    ",
+    // Each page as exported, to summarize changes when a destination is overwritten
+    r"
+    ALTER TABLE exports ADD COLUMN page_fingerprints TEXT;
+    ",
+    r"
+    ALTER TABLE exports ADD COLUMN changes TEXT;
+    ",
+    // How documents pushed by email or FTP are filed on import
+    r"
+    CREATE SEQUENCE seq_ingest_rules_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS ingest_rules (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_ingest_rules_id'),
+        source TEXT NOT NULL,
+        route TEXT NOT NULL,
+        group_title TEXT,
+        tags TEXT NOT NULL,
+        dewarp BOOLEAN NOT NULL,
+        remove_gutter_shadow BOOLEAN NOT NULL,
+        export_dir TEXT,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Text read from each scan, for exports that carry a text layer
+    r"
+    CREATE TABLE IF NOT EXISTS scan_texts (
+        scan_id INTEGER PRIMARY KEY,
+        text TEXT NOT NULL,
+        languages TEXT NOT NULL,
+        fingerprint TEXT NOT NULL,
+        recognized_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Text read before hyphenated line breaks were merged, read again on use
+    r"
+    DELETE FROM scan_texts;
+    ",
+    // Tags suggested by the dates, organizations and amounts in a group's text
+    r"
+    CREATE SEQUENCE seq_tag_suggestions_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS tag_suggestions (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_tag_suggestions_id'),
+        scan_group_id INTEGER NOT NULL,
+        tag TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        evidence TEXT NOT NULL,
+        scan_id INTEGER NOT NULL,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Rules that file groups by their OCR text, and what they did
+    r"
+    CREATE SEQUENCE seq_classification_rules_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS classification_rules (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_classification_rules_id'),
+        name TEXT NOT NULL,
+        text_contains TEXT NOT NULL,
+        organization TEXT,
+        tags TEXT NOT NULL,
+        destination TEXT,
+        title_template TEXT,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    r"
+    CREATE SEQUENCE seq_classification_runs_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS classification_runs (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_classification_runs_id'),
+        rule_id INTEGER NOT NULL,
+        rule_name TEXT NOT NULL,
+        scan_group_id INTEGER NOT NULL,
+        title_before TEXT NOT NULL,
+        title_after TEXT NOT NULL,
+        tags_added TEXT NOT NULL,
+        destination TEXT,
+        export_error TEXT,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Page hashes, and finalized groups that duplicate earlier ones
+    r"
+    CREATE TABLE IF NOT EXISTS scan_hashes (
+        scan_id INTEGER PRIMARY KEY,
+        fingerprint TEXT NOT NULL,
+        hash BIGINT NOT NULL
+    );
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS group_duplicates (
+        scan_group_id INTEGER NOT NULL,
+        duplicate_of_id INTEGER NOT NULL,
+        similarity DOUBLE NOT NULL,
+        matched_by TEXT NOT NULL,
+        detected_at TIMESTAMP NOT NULL,
+        PRIMARY KEY (scan_group_id, duplicate_of_id)
+    );
+    ",
+    // Who started each scan and batch, for shared scanners
+    r"
+    ALTER TABLE scans ADD COLUMN started_by TEXT;
+    ",
+    r"
+    ALTER TABLE scan_batches ADD COLUMN started_by TEXT;
+    ",
+    // Form colour dropout, per group and per ingest rule
+    r"
+    ALTER TABLE scan_groups ADD COLUMN color_dropout TEXT;
+    ",
+    r"
+    ALTER TABLE ingest_rules ADD COLUMN color_dropout TEXT;
+    ",
+    // Punch hole and edge shadow cleanup, per scan and per ingest rule
+    r"
+    ALTER TABLE scans ADD COLUMN remove_punch_holes BOOLEAN DEFAULT false;
+    ",
+    r"
+    ALTER TABLE ingest_rules ADD COLUMN remove_punch_holes BOOLEAN DEFAULT false;
+    ",
+    // Originals recompressed for long-term storage, with the checksum of their pixels
+    r"
+    CREATE TABLE IF NOT EXISTS archived_originals (
+        scan_id INTEGER PRIMARY KEY,
+        format TEXT NOT NULL,
+        pixel_sha256 TEXT NOT NULL,
+        original_bytes BIGINT NOT NULL,
+        archived_bytes BIGINT NOT NULL,
+        archived_at TIMESTAMP NOT NULL
+    );
+    ",
+    r"
+    CREATE SEQUENCE seq_processing_profiles_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS processing_profiles (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_processing_profiles_id'),
+        name TEXT NOT NULL,
+        steps TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    r"
+    ALTER TABLE scan_groups ADD COLUMN processing_profile_id INTEGER;
+    ",
+    // Uploads, with their progress so it survives a reload
+    r"
+    CREATE SEQUENCE seq_uploads_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS uploads (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_uploads_id'),
+        uploader TEXT,
+        status TEXT NOT NULL,
+        bytes_received BIGINT NOT NULL DEFAULT 0,
+        bytes_total BIGINT,
+        pages_imported INTEGER NOT NULL DEFAULT 0,
+        pages_total INTEGER,
+        scan_group_id INTEGER,
+        created_at TIMESTAMP NOT NULL,
+        updated_at TIMESTAMP NOT NULL
+    );
+    ",
+    // Scans that failed every attempt, kept until they're requeued
+    r"
+    CREATE TABLE IF NOT EXISTS dead_letters (
+        scan_id INTEGER PRIMARY KEY,
+        scanner TEXT NOT NULL,
+        scan_parameters TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        exit_code INTEGER NOT NULL,
+        failure TEXT NOT NULL,
+        error TEXT NOT NULL,
+        started_by TEXT,
+        dead_at TIMESTAMP NOT NULL,
+        requeued_at TIMESTAMP
+    );
+    ",
+    // Export files kept by the hash of their contents
+    r"
+    CREATE SEQUENCE seq_artifacts_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS artifacts (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_artifacts_id'),
+        sha256 TEXT NOT NULL UNIQUE,
+        format TEXT NOT NULL,
+        bytes BIGINT NOT NULL,
+        path TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL
+    );
+    ",
+    r"
+    ALTER TABLE exports ADD COLUMN artifact_id INTEGER;
+    ",
+    // Review threads on groups, optionally about a page
+    r"
+    CREATE SEQUENCE seq_group_comments_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS group_comments (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_group_comments_id'),
+        scan_group_id INTEGER NOT NULL,
+        thread_id INTEGER,
+        scan_id INTEGER,
+        author TEXT,
+        body TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL,
+        edited_at TIMESTAMP
+    );
+    ", // Per-page review by a second person
+    r"
+    ALTER TABLE scans ADD COLUMN review_state TEXT DEFAULT 'UNREVIEWED';
+    ",
+    r"
+    ALTER TABLE scans ADD COLUMN reviewed_by TEXT;
+    ",
+    r"
+    ALTER TABLE scans ADD COLUMN reviewed_at TIMESTAMP;
+    ", // Groups handed to a user to review
+    r"
+    CREATE TABLE IF NOT EXISTS group_assignments (
+        scan_group_id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL,
+        assigned_by TEXT,
+        assigned_at TIMESTAMP NOT NULL
+    );
+    ", // What the home screen's activity feed shows
+    r"
+    CREATE SEQUENCE seq_activity_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS activity (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_activity_id'),
+        kind TEXT NOT NULL,
+        scan_group_id INTEGER,
+        scan_id INTEGER,
+        actor TEXT,
+        detail TEXT,
+        created_at TIMESTAMP NOT NULL
+    );
+    ", // Weekly digests of the activity feed
+    r"
+    CREATE SEQUENCE seq_digests_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS digests (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_digests_id'),
+        period_start TIMESTAMP NOT NULL,
+        period_end TIMESTAMP NOT NULL,
+        scans_completed INTEGER NOT NULL,
+        groups_finalized INTEGER NOT NULL,
+        exports_delivered INTEGER NOT NULL,
+        comments INTEGER NOT NULL,
+        unreviewed_pages INTEGER NOT NULL,
+        needs_rescan_pages INTEGER NOT NULL,
+        open_groups INTEGER NOT NULL,
+        dead_letters INTEGER NOT NULL
+    );
+    ", // Problems found by the alert rules
+    r"
+    CREATE SEQUENCE seq_alerts_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS alerts (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_alerts_id'),
+        rule TEXT NOT NULL,
+        subject TEXT NOT NULL,
+        message TEXT NOT NULL,
+        raised_at TIMESTAMP NOT NULL,
+        resolved_at TIMESTAMP
+    );
+    ", // Metadata written beside exports to each destination directory
+    r"
+    CREATE TABLE IF NOT EXISTS destination_mappings (
+        destination TEXT PRIMARY KEY,
+        fields TEXT NOT NULL,
+        updated_at TIMESTAMP NOT NULL
+    );
+    ", // Typed relationships between groups
+    r"
+    CREATE SEQUENCE seq_group_links_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS group_links (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_group_links_id'),
+        from_group_id INTEGER NOT NULL,
+        from_role TEXT NOT NULL,
+        to_group_id INTEGER NOT NULL,
+        to_role TEXT NOT NULL,
+        linked_by TEXT,
+        linked_at TIMESTAMP NOT NULL
+    );
+    ", // Scans of printed calibration targets, measured
+    r"
+    CREATE SEQUENCE seq_calibrations_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS calibrations (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_calibrations_id'),
+        scan_id INTEGER NOT NULL,
+        device TEXT NOT NULL,
+        requested_dpi DOUBLE,
+        horizontal_dpi DOUBLE NOT NULL,
+        vertical_dpi DOUBLE NOT NULL,
+        skew_degrees DOUBLE NOT NULL,
+        horizontal_lpi INTEGER,
+        vertical_lpi INTEGER,
+        advice TEXT NOT NULL,
+        analyzed_at TIMESTAMP NOT NULL
+    );
+    ", // Named scan parameters per device
+    r"
+    CREATE SEQUENCE seq_scan_presets_id START 1;
+    ",
+    r"
+    CREATE TABLE IF NOT EXISTS scan_presets (
+        id INTEGER PRIMARY KEY DEFAULT nextval('seq_scan_presets_id'),
+        name TEXT NOT NULL,
+        scanner TEXT NOT NULL,
+        scan_parameters TEXT NOT NULL,
+        created_at TIMESTAMP NOT NULL,
+        updated_at TIMESTAMP NOT NULL
+    );
+    ", // Devices seen, with their options, to list before they're probed
+    r"
+    CREATE TABLE IF NOT EXISTS scanners (
+        name TEXT PRIMARY KEY,
+        description TEXT NOT NULL,
+        has_feeder BOOLEAN NOT NULL,
+        options TEXT NOT NULL,
+        last_seen_at TIMESTAMP NOT NULL
+    );
+    ", // Accounts synced from LDAP, and when they were dropped from it
+    r"
+    ALTER TABLE users ADD COLUMN ldap_dn TEXT;
+    ",
+    r"
+    ALTER TABLE users ADD COLUMN disabled_at TIMESTAMP;
+    ", // A kept batch page that looks double-fed, until the operator accepts or rescans it
+    r"
+    ALTER TABLE scan_batches ADD COLUMN suspect_scan_id INTEGER;
+    ",
 ];
 
-pub async fn migrate(r2d2_pool: &r2d2::Pool<DuckdbConnectionManager>) {
+/// Where and how the database is backed up before migrations run.
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    /// Number of backups to keep; older ones are deleted after each new backup
+    pub keep: usize,
+    /// Databases larger than this are backed up with `EXPORT DATABASE`, which
+    /// writes compressed Parquet instead of a second full copy of the file
+    pub max_copy_bytes: u64,
+}
+
+pub async fn migrate(
+    r2d2_pool: &r2d2::Pool<DuckdbConnectionManager>,
+    backup_config: &BackupConfig,
+) {
     println!("Running migrations...");
     let conn = r2d2_pool.get().unwrap();
 
@@ -111,13 +597,22 @@ pub async fn migrate(r2d2_pool: &r2d2::Pool<DuckdbConnectionManager>) {
         }
     };
 
-    for (idx, migration) in MIGRATIONS[next_migration_idx..].iter().enumerate() {
-        // Backup database in case
-        if let Some(path) = conn.path() {
-            let backup_path = format!("{}.pre-{}-backup", path.display(), idx + next_migration_idx);
-            std::fs::copy(path, backup_path).unwrap();
-        }
+    if next_migration_idx > MIGRATIONS.len() {
+        eprintln!(
+            "The database has {} migrations applied but this build of scanserv only knows {}. \
+             It was last run by a newer version; run that version or restore a backup.",
+            next_migration_idx,
+            MIGRATIONS.len()
+        );
+        std::process::exit(1);
+    }
 
+    // Backup database in case, once for the whole run rather than per migration
+    if next_migration_idx > 0 && next_migration_idx < MIGRATIONS.len() {
+        backup(&conn, next_migration_idx, backup_config);
+    }
+
+    for (idx, migration) in MIGRATIONS[next_migration_idx..].iter().enumerate() {
         println!("Applying migration {}...", idx + next_migration_idx);
         conn.execute(migration, params![]).unwrap();
         conn.execute(
@@ -128,4 +623,149 @@ pub async fn migrate(r2d2_pool: &r2d2::Pool<DuckdbConnectionManager>) {
     }
 
     println!("Migrations complete!");
+
+    let drift = schema_drift(&conn);
+    if !drift.is_empty() {
+        eprintln!("The database schema doesn't match what its migrations create:");
+        for difference in drift {
+            eprintln!("  {}", difference);
+        }
+        eprintln!(
+            "It was probably changed by hand or by another build of scanserv. \
+             Restore a backup or fix the tables listed above before starting."
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Column names of every table in the connection's own database, leaving
+/// out attached ones such as the snapshot.
+fn columns(conn: &duckdb::Connection) -> BTreeMap<String, BTreeSet<String>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT table_name, column_name FROM information_schema.columns
+             WHERE table_catalog = current_database() AND table_schema = 'main'
+               AND table_name != 'meta_migration_schema'",
+        )
+        .unwrap();
+    let mut tables: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for row in stmt
+        .query_map(params![], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .unwrap()
+    {
+        let (table, column) = row.unwrap();
+        tables.entry(table).or_default().insert(column);
+    }
+    tables
+}
+
+/// Differences between the live schema and the one every migration
+/// produces on an empty in-memory database, one line each.
+fn schema_drift(conn: &duckdb::Connection) -> Vec<String> {
+    let expected_conn = duckdb::Connection::open_in_memory().unwrap();
+    for migration in MIGRATIONS {
+        expected_conn.execute(migration, params![]).unwrap();
+    }
+    let expected = columns(&expected_conn);
+    let live = columns(conn);
+
+    let mut drift = Vec::new();
+    for (table, expected_columns) in &expected {
+        let Some(live_columns) = live.get(table) else {
+            drift.push(format!("table {} is missing", table));
+            continue;
+        };
+        let missing: Vec<&str> = expected_columns
+            .difference(live_columns)
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            drift.push(format!(
+                "table {} is missing columns {}",
+                table,
+                missing.join(", ")
+            ));
+        }
+        let unexpected: Vec<&str> = live_columns
+            .difference(expected_columns)
+            .map(String::as_str)
+            .collect();
+        if !unexpected.is_empty() {
+            drift.push(format!(
+                "table {} has unexpected columns {}",
+                table,
+                unexpected.join(", ")
+            ));
+        }
+    }
+    for table in live.keys().filter(|table| !expected.contains_key(*table)) {
+        drift.push(format!("table {} isn't created by any migration", table));
+    }
+    drift
+}
+
+fn backup(conn: &duckdb::Connection, migration_idx: usize, config: &BackupConfig) {
+    let Some(path) = conn.path().map(Path::to_path_buf) else {
+        return;
+    };
+    let db_name = path.file_name().unwrap().to_string_lossy().to_string();
+
+    fs::create_dir_all(&config.dir).unwrap();
+
+    // Flush the WAL so the copy or export has everything
+    conn.execute("CHECKPOINT", params![]).unwrap();
+
+    let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    if size > config.max_copy_bytes {
+        let export_dir = config
+            .dir
+            .join(format!("{}.pre-{}-export", db_name, migration_idx));
+        println!("Exporting database to {}...", export_dir.display());
+        if export_dir.exists() {
+            fs::remove_dir_all(&export_dir).unwrap();
+        }
+        // EXPORT doesn't take a bound parameter for the path
+        conn.execute(
+            &format!(
+                "EXPORT DATABASE '{}' (FORMAT PARQUET)",
+                export_dir.to_string_lossy().replace('\'', "''")
+            ),
+            params![],
+        )
+        .unwrap();
+    } else {
+        let backup_path = config
+            .dir
+            .join(format!("{}.pre-{}-backup", db_name, migration_idx));
+        println!("Backing up database to {}...", backup_path.display());
+        fs::copy(&path, backup_path).unwrap();
+    }
+
+    prune_backups(&db_name, config);
+}
+
+/// Deletes all but the newest `config.keep` backups of `db_name`.
+fn prune_backups(db_name: &str, config: &BackupConfig) {
+    let prefix = format!("{}.pre-", db_name);
+    let mut backups: Vec<_> = fs::read_dir(&config.dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect();
+
+    backups.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, old) in backups.into_iter().skip(config.keep) {
+        println!("Removing old backup {}", old.display());
+        if old.is_dir() {
+            fs::remove_dir_all(old).unwrap();
+        } else {
+            fs::remove_file(old).unwrap();
+        }
+    }
 }
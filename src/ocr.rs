@@ -0,0 +1,330 @@
+use std::{
+    env, fs,
+    io::{self, Cursor, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use image::{DynamicImage, ImageFormat};
+
+use crate::{dropout::drop_color, exports::page_fingerprint, scans::Scan, AssetsDir};
+
+/// Tesseract languages used unless `OCR_LANGUAGES` names others, e.g. `deu+eng`.
+const DEFAULT_LANGUAGES: &str = "eng";
+
+/// The tesseract languages scans are read in.
+pub fn languages() -> String {
+    env::var("OCR_LANGUAGES").unwrap_or(DEFAULT_LANGUAGES.to_string())
+}
+
+/// Language tag for text read in `languages`, from the first of them. Codes
+/// with a two-letter form use it, as BCP 47 asks; others pass through.
+pub fn language_tag(languages: &str) -> String {
+    let first = languages.split('+').next().unwrap_or_default();
+    let tag = match first {
+        "eng" => "en",
+        "deu" => "de",
+        "fra" => "fr",
+        "spa" => "es",
+        "ita" => "it",
+        "nld" => "nl",
+        "por" => "pt",
+        "swe" => "sv",
+        "dan" => "da",
+        "nor" => "no",
+        "fin" => "fi",
+        "pol" => "pl",
+        "ces" => "cs",
+        "rus" => "ru",
+        "jpn" => "ja",
+        "chi_sim" => "zh-Hans",
+        "chi_tra" => "zh-Hant",
+        "" => "und",
+        other => other,
+    };
+    tag.to_string()
+}
+
+/// Words that can follow a hyphen left hanging for a later compound, as in
+/// "Ein- und Ausgang" or "pre- and post-war", keyed by tesseract language.
+/// A line ending in such a hyphen is joined with the space kept.
+fn suspended_hyphen_words(language: &str) -> &'static [&'static str] {
+    match language {
+        "eng" => &["and", "or", "nor", "to"],
+        "deu" => &["und", "oder", "bis", "sowie", "als", "bzw"],
+        "nld" => &["en", "of", "tot"],
+        "fra" => &["et", "ou"],
+        "spa" | "por" | "ita" => &["y", "e", "o", "ou"],
+        "swe" => &["och", "eller", "till"],
+        "dan" | "nor" => &["og", "eller", "til"],
+        _ => &[],
+    }
+}
+
+/// Whether the line ends in a word broken across lines: a hyphen, or a
+/// soft hyphen, right after a letter or digit.
+fn hyphen_at_end(line: &str) -> Option<char> {
+    let mut chars = line.chars().rev();
+    let hyphen = chars
+        .next()
+        .filter(|c| matches!(c, '-' | '\u{2010}' | '\u{ad}'))?;
+    chars.next().filter(|c| c.is_alphanumeric())?;
+    Some(hyphen)
+}
+
+/// Tidies tesseract's output for storing and searching: words hyphenated
+/// across a line break are joined again, the lines of each paragraph are
+/// run together, and runs of whitespace become single spaces. Paragraphs
+/// stay separated by a blank line.
+///
+/// A hyphen at the end of a line is dropped when the next line carries on
+/// in lower case ("exam-" "ple"), but kept when it starts a capital or a
+/// number ("Nord-" "Amerika") or is one of the language's conjunctions
+/// after a suspended hyphen ("Ein-" "und Ausgang").
+pub fn normalize(text: &str, languages: &str) -> String {
+    let suspended: Vec<&str> = languages
+        .split('+')
+        .flat_map(|language| suspended_hyphen_words(language).iter().copied())
+        .collect();
+
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut paragraph = String::new();
+    // Tesseract ends each page with a form feed
+    for line in text.replace('\x0c', "\n").lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(std::mem::take(&mut paragraph));
+            }
+            continue;
+        }
+        if paragraph.is_empty() {
+            paragraph = line;
+            continue;
+        }
+
+        match hyphen_at_end(&paragraph) {
+            Some(hyphen) => {
+                let next_word = line
+                    .split(|c: char| !c.is_alphabetic())
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let starts_lower = line.chars().next().is_some_and(char::is_lowercase);
+                if hyphen != '\u{ad}' && suspended.contains(&next_word.as_str()) {
+                    paragraph.push(' ');
+                } else if hyphen == '\u{ad}' || starts_lower {
+                    paragraph.pop();
+                }
+            }
+            None => paragraph.push(' '),
+        }
+        paragraph.push_str(&line);
+    }
+    if !paragraph.is_empty() {
+        paragraphs.push(paragraph);
+    }
+
+    paragraphs.join("\n\n")
+}
+
+/// `tesseract --list-langs`: the heading, which names the tessdata
+/// directory in recent versions, and the installed languages.
+fn list_languages() -> io::Result<(String, Vec<String>)> {
+    let output = Command::new("tesseract")
+        .arg("--list-langs")
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other("tesseract is not installed"),
+            _ => e,
+        })?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let mut lines = listing.lines();
+    let heading = lines.next().unwrap_or_default().to_string();
+    Ok((heading, lines.map(|line| line.trim().to_string()).collect()))
+}
+
+/// Which of `languages` tesseract has no trained data for. Fails if
+/// tesseract isn't installed.
+pub fn missing_languages(languages: &str) -> io::Result<Vec<String>> {
+    let (_, installed) = list_languages()?;
+    Ok(languages
+        .split('+')
+        .filter(|language| !installed.iter().any(|installed| installed == language))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Where tesseract looks for trained data: `TESSDATA_PREFIX` if set, as
+/// tesseract itself does, otherwise the directory it lists languages from.
+fn tessdata_dir() -> io::Result<PathBuf> {
+    if let Ok(prefix) = env::var("TESSDATA_PREFIX") {
+        return Ok(PathBuf::from(prefix));
+    }
+    let (heading, _) = list_languages()?;
+    // e.g. `List of available languages in "/usr/share/tessdata/" (2):`
+    heading.split('"').nth(1).map(PathBuf::from).ok_or_else(|| {
+        io::Error::other("can't tell where tesseract keeps its data, set TESSDATA_PREFIX")
+    })
+}
+
+/// Downloads `{url}/{language}.traineddata` into tesseract's data directory,
+/// returning where it was saved.
+pub fn download_language(language: &str, url: &str) -> io::Result<PathBuf> {
+    if language.is_empty() || language.contains("..") {
+        return Err(io::Error::other(format!("bad language {:?}", language)));
+    }
+    let file = tessdata_dir()?.join(format!("{}.traineddata", language));
+    let agent = ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(
+            native_tls::TlsConnector::new().map_err(io::Error::other)?,
+        ))
+        .build();
+    let response = agent
+        .get(&format!(
+            "{}/{}.traineddata",
+            url.trim_end_matches('/'),
+            language
+        ))
+        .call()
+        .map_err(io::Error::other)?;
+
+    // Written next to it and renamed, so tesseract never sees half a file
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let partial = file.with_extension("partial");
+    io::copy(
+        &mut response.into_reader(),
+        &mut fs::File::create(&partial)?,
+    )?;
+    fs::rename(&partial, &file)?;
+    Ok(file)
+}
+
+/// Text tesseract reads on the image, in reading order.
+pub fn recognize(image: &DynamicImage, languages: &str) -> io::Result<String> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(io::Error::other)?;
+
+    let mut child = Command::new("tesseract")
+        .args(["stdin", "stdout", "-l", languages])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other("tesseract is not installed"),
+            _ => e,
+        })?;
+    // Tesseract reads all of its input before writing anything
+    child.stdin.take().unwrap().write_all(&png)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "tesseract exited with {}",
+            output.status
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Text read from a scan, kept so each image is only read once.
+#[derive(Debug, Clone)]
+pub struct ScanText {
+    pub scan_id: i32,
+    /// Tesseract's output tidied by `normalize`
+    pub text: String,
+    /// The tesseract languages it was read in
+    pub languages: String,
+    /// What the page looked like when it was read; see `page_fingerprint`
+    pub fingerprint: String,
+    pub recognized_at: DateTime<Utc>,
+}
+
+impl ScanText {
+    pub fn load(
+        scan_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> duckdb::Result<Option<Self>> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            "SELECT scan_id, text, languages, fingerprint, recognized_at
+             FROM scan_texts WHERE scan_id = ?",
+            params![scan_id],
+            |row| {
+                Ok(Self {
+                    scan_id: row.get(0)?,
+                    text: row.get(1)?,
+                    languages: row.get(2)?,
+                    fingerprint: row.get(3)?,
+                    recognized_at: row.get(4)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    pub fn save(&self, pool: &r2d2::Pool<DuckdbConnectionManager>) -> duckdb::Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO scan_texts (scan_id, text, languages, fingerprint, recognized_at)
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                self.scan_id,
+                self.text,
+                self.languages,
+                self.fingerprint,
+                self.recognized_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The scan's text, reading it with tesseract first if it hasn't been
+    /// read yet, was rotated or cropped since, or was read in other
+    /// languages than are configured now.
+    pub fn for_scan(
+        scan: &Scan,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> io::Result<Self> {
+        let scan_id = scan
+            .id
+            .ok_or_else(|| io::Error::other("scan not saved yet"))?;
+        let languages = languages();
+        let dropout = scan.color_dropout(pool).map_err(io::Error::other)?;
+        // Read again when the group's dropout colour changes
+        let fingerprint = match dropout {
+            Some(color) => format!("{}+{}", page_fingerprint(scan), color.as_str()),
+            None => page_fingerprint(scan),
+        };
+        if let Some(text) = ScanText::load(scan_id, pool)
+            .map_err(io::Error::other)?
+            .filter(|text| text.languages == languages && text.fingerprint == fingerprint)
+        {
+            return Ok(text);
+        }
+
+        let mut image = scan.open_image(assets_dir).map_err(io::Error::other)?;
+        if let Some(color) = dropout {
+            image = drop_color(image, color);
+        }
+        let text = ScanText {
+            scan_id,
+            text: normalize(&recognize(&image, &languages)?, &languages),
+            languages,
+            fingerprint,
+            recognized_at: Utc::now(),
+        };
+        text.save(pool).map_err(io::Error::other)?;
+        Ok(text)
+    }
+}
@@ -0,0 +1,135 @@
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager, Result};
+
+use crate::{exports::ExportFormat, xml::escape, PublicUrl};
+
+/// Entries on each page of the feed.
+pub const PAGE_SIZE: i64 = 50;
+/// What e-reader apps can download each book as.
+pub const DOWNLOAD_FORMATS: [ExportFormat; 3] =
+    [ExportFormat::Epub, ExportFormat::Pdf, ExportFormat::Cbz];
+pub const FEED_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+
+/// A finalized group as the catalog lists it.
+struct Book {
+    id: i32,
+    title: String,
+    comment: String,
+    tags: Vec<String>,
+    updated_at: DateTime<Utc>,
+}
+
+/// Finalized groups with at least one completed page, most recently
+/// updated first, and whether there are more after them.
+fn load_books(page: i64, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<(Vec<Book>, bool)> {
+    let conn = pool.get().unwrap();
+
+    let mut stmt = conn.prepare(
+        "SELECT id, title, comment, tags, updated_at FROM scan_groups g
+         WHERE status = 'finalized'
+           AND EXISTS (SELECT 1 FROM scans WHERE scan_group_id = g.id AND status = 'COMPLETE')
+         ORDER BY updated_at DESC, id DESC
+         LIMIT ? OFFSET ?",
+    )?;
+    let mut books = stmt
+        .query_map(
+            params![PAGE_SIZE + 1, page.saturating_mul(PAGE_SIZE)],
+            |row| {
+                let tags_json: String = row.get(3)?;
+                Ok(Book {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    comment: row.get(2)?,
+                    tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+                    updated_at: row.get(4)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>>>()?;
+
+    let more = books.len() as i64 > PAGE_SIZE;
+    books.truncate(PAGE_SIZE as usize);
+    Ok((books, more))
+}
+
+fn entry(book: &Book, public_url: &PublicUrl) -> String {
+    let mut xml = String::new();
+    xml.push_str("  <entry>\n");
+    xml.push_str(&format!("    <id>urn:scanserv:group:{}</id>\n", book.id));
+    xml.push_str(&format!("    <title>{}</title>\n", escape(&book.title)));
+    xml.push_str(&format!(
+        "    <updated>{}</updated>\n",
+        book.updated_at.to_rfc3339()
+    ));
+    if !book.comment.is_empty() {
+        xml.push_str(&format!(
+            "    <summary>{}</summary>\n",
+            escape(&book.comment)
+        ));
+    }
+    for tag in &book.tags {
+        xml.push_str(&format!(
+            "    <category term=\"{0}\" label=\"{0}\"/>\n",
+            escape(tag)
+        ));
+    }
+    for format in DOWNLOAD_FORMATS {
+        xml.push_str(&format!(
+            "    <link rel=\"http://opds-spec.org/acquisition\" href=\"{}\" type=\"{}\"/>\n",
+            escape(&public_url.download_url(book.id, format)),
+            format.content_type()
+        ));
+    }
+    xml.push_str(&format!(
+        "    <link rel=\"alternate\" href=\"{}\" type=\"text/html\"/>\n",
+        escape(&public_url.group_url(book.id))
+    ));
+    xml.push_str("  </entry>\n");
+    xml
+}
+
+/// OPDS 1.2 acquisition feed of finalized groups, `PAGE_SIZE` at a time,
+/// with a download link for each of `DOWNLOAD_FORMATS`.
+pub fn feed(
+    page: i64,
+    public_url: &PublicUrl,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Result<String> {
+    let (books, more) = load_books(page, pool)?;
+    let updated = books
+        .iter()
+        .map(|book| book.updated_at)
+        .max()
+        .unwrap_or_else(Utc::now);
+    let page_url = |page: i64| match page {
+        0 => public_url.opds_url(),
+        _ => format!("{}?page={}", public_url.opds_url(), page),
+    };
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:opds=\"http://opds-spec.org/2010/catalog\">\n");
+    xml.push_str(&format!("  <id>{}</id>\n", escape(&public_url.opds_url())));
+    xml.push_str("  <title>Scanned books</title>\n");
+    xml.push_str(&format!("  <updated>{}</updated>\n", updated.to_rfc3339()));
+    for (rel, target) in [
+        ("self", Some(page)),
+        ("start", Some(0)),
+        ("previous", page.checked_sub(1).filter(|page| *page >= 0)),
+        ("next", more.then_some(page + 1)),
+    ] {
+        if let Some(target) = target {
+            xml.push_str(&format!(
+                "  <link rel=\"{}\" href=\"{}\" type=\"{}\"/>\n",
+                rel,
+                escape(&page_url(target)),
+                FEED_TYPE
+            ));
+        }
+    }
+    for book in &books {
+        xml.push_str(&entry(book, public_url));
+    }
+    xml.push_str("</feed>\n");
+    Ok(xml)
+}
@@ -0,0 +1,200 @@
+use image::{imageops, imageops::FilterType, DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+
+use crate::{
+    dewarp::{ink_threshold, sample},
+    punch_holes::percentile,
+};
+
+/// Pages are analysed for skew at about this size.
+const ANALYSIS_SIZE: u32 = 1000;
+/// Skew is looked for up to this many degrees either way, in these steps.
+const MAX_SKEW: f32 = 5.0;
+const SKEW_STEP: f32 = 0.1;
+/// Pages with less ink than this fraction have no lines to square up to.
+const MIN_INK: f32 = 0.002;
+/// Edge rows and columns whose paper is darker than this fraction of the
+/// page's are the scanner's background, not the page.
+const BACKGROUND_DARKNESS: f32 = 0.7;
+/// Autocrop never takes more than this fraction off a side.
+const MAX_CROP: f32 = 0.15;
+/// Specks are dark spots no wider than this; a full stop at 8pt is bigger.
+const MAX_SPECK_MM: f32 = 0.2;
+
+/// Angle, in degrees, that the text lines slope down to the right, by the
+/// rotation that lines their ink up into the sharpest row profile.
+fn skew_angle(image: &DynamicImage) -> Option<f32> {
+    let gray = image.to_luma8();
+    let scale = ANALYSIS_SIZE as f32 / gray.width().max(gray.height()) as f32;
+    let small = if scale < 1.0 {
+        imageops::resize(
+            &gray,
+            (gray.width() as f32 * scale) as u32,
+            (gray.height() as f32 * scale) as u32,
+            FilterType::Triangle,
+        )
+    } else {
+        gray
+    };
+    let threshold = ink_threshold(&small);
+    let ink: Vec<(f32, f32)> = small
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[0] < threshold)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+    if (ink.len() as f32) < small.len() as f32 * MIN_INK {
+        return None;
+    }
+
+    let rows = (small.width() + small.height()) as usize * 2;
+    let offset = small.height() as f32 + small.width() as f32;
+    let steps = (MAX_SKEW / SKEW_STEP).round() as i32;
+    let (mut best, mut best_score) = (0.0, 0.0);
+    for step in -steps..=steps {
+        let angle = (step as f32 * SKEW_STEP).to_radians();
+        let (sin, cos) = angle.sin_cos();
+        let mut profile = vec![0f64; rows];
+        for (x, y) in &ink {
+            let row = (y * cos - x * sin + offset) as usize;
+            profile[row.min(rows - 1)] += 1.0;
+        }
+        let score: f64 = profile.iter().map(|count| count * count).sum();
+        if score > best_score {
+            best_score = score;
+            best = step as f32 * SKEW_STEP;
+        }
+    }
+    Some(best)
+}
+
+/// Squares up a page fed or laid on the glass at a slight angle. Corners
+/// turned in from outside the page are filled with its paper colour.
+pub fn deskew(image: DynamicImage) -> DynamicImage {
+    let Some(angle) = skew_angle(&image).filter(|angle| angle.abs() >= SKEW_STEP) else {
+        return image;
+    };
+    let source = image.to_rgb8();
+    let paper = percentile(image.to_luma8().pixels().map(|pixel| pixel[0])) as u8;
+    let (width, height) = source.dimensions();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (sin, cos) = angle.to_radians().sin_cos();
+
+    let output = RgbImage::from_fn(width, height, |u, v| {
+        let (dx, dy) = (u as f32 - cx, v as f32 - cy);
+        let (x, y) = (cx + dx * cos - dy * sin, cy + dx * sin + dy * cos);
+        if x < 0.0 || y < 0.0 || x > (width - 1) as f32 || y > (height - 1) as f32 {
+            Rgb([paper; 3])
+        } else {
+            sample(&source, x, y)
+        }
+    });
+    DynamicImage::ImageRgb8(output)
+}
+
+/// Trims the scanner's background from around the page: runs of rows and
+/// columns in from each edge that are much darker than the paper.
+pub fn autocrop(image: DynamicImage) -> DynamicImage {
+    let gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return image;
+    }
+    let paper = percentile(gray.pixels().map(|pixel| pixel[0]));
+    let background = |level: f32| level < paper * BACKGROUND_DARKNESS;
+    let column = |x: &u32| background(percentile((0..height).map(|y| gray.get_pixel(*x, y)[0])));
+    let row = |y: &u32| background(percentile((0..width).map(|x| gray.get_pixel(x, *y)[0])));
+
+    let max_x = (width as f32 * MAX_CROP) as u32;
+    let max_y = (height as f32 * MAX_CROP) as u32;
+    let left = (0..max_x).take_while(column).count() as u32;
+    let right = (width - max_x..width).rev().take_while(column).count() as u32;
+    let top = (0..max_y).take_while(row).count() as u32;
+    let bottom = (height - max_y..height).rev().take_while(row).count() as u32;
+    if left + right + top + bottom == 0 {
+        return image;
+    }
+    image.crop_imm(left, top, width - left - right, height - top - bottom)
+}
+
+/// Reduces the page to black ink on white paper, at the gray level that
+/// best separates the two (Otsu's method).
+pub fn binarize(image: DynamicImage) -> DynamicImage {
+    let mut gray = image.to_luma8();
+    let threshold = ink_threshold(&gray);
+    for pixel in gray.pixels_mut() {
+        pixel[0] = if pixel[0] < threshold { 0 } else { 255 };
+    }
+    DynamicImage::ImageLuma8(gray)
+}
+
+/// Clears isolated dark specks of dust and scanner noise, sized from `dpi`
+/// so punctuation is kept. Specks are painted the paper's colour.
+pub fn despeckle(image: DynamicImage, dpi: f32) -> DynamicImage {
+    let mut gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+    let max_side = (MAX_SPECK_MM * dpi / 25.4).round().max(1.0) as u32;
+    let max_area = (max_side * max_side) as usize;
+    let threshold = ink_threshold(&gray);
+    let paper = percentile(gray.pixels().map(|pixel| pixel[0])) as u8;
+
+    let mut visited = vec![false; (width * height) as usize];
+    let mut specks: Vec<Vec<(u32, u32)>> = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if visited[(y * width + x) as usize] || gray.get_pixel(x, y)[0] >= threshold {
+                continue;
+            }
+            let spot = dark_region(&gray, threshold, x, y, &mut visited);
+            if spot.len() <= max_area {
+                specks.push(spot);
+            }
+        }
+    }
+    if specks.is_empty() {
+        return image;
+    }
+
+    let specks = specks.into_iter().flatten();
+    match image {
+        DynamicImage::ImageLuma8(_) => {
+            for (x, y) in specks {
+                gray.put_pixel(x, y, Luma([paper]));
+            }
+            DynamicImage::ImageLuma8(gray)
+        }
+        // Colour pages keep their colour; only the specks are painted
+        _ => {
+            let mut rgb = image.to_rgb8();
+            for (x, y) in specks {
+                rgb.put_pixel(x, y, Rgb([paper; 3]));
+            }
+            DynamicImage::ImageRgb8(rgb)
+        }
+    }
+}
+
+/// The 8-connected pixels darker than `threshold` around `(x, y)`.
+fn dark_region(
+    gray: &GrayImage,
+    threshold: u8,
+    x: u32,
+    y: u32,
+    visited: &mut [bool],
+) -> Vec<(u32, u32)> {
+    let (width, height) = gray.dimensions();
+    let mut region = Vec::new();
+    let mut stack = vec![(x, y)];
+    visited[(y * width + x) as usize] = true;
+    while let Some((px, py)) = stack.pop() {
+        region.push((px, py));
+        for ny in py.saturating_sub(1)..=(py + 1).min(height - 1) {
+            for nx in px.saturating_sub(1)..=(px + 1).min(width - 1) {
+                let index = (ny * width + nx) as usize;
+                if !visited[index] && gray.get_pixel(nx, ny)[0] < threshold {
+                    visited[index] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+    region
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    io::{self, Cursor},
+    process::Stdio,
+};
+
+use async_graphql::SimpleObject;
+use duckdb::DuckdbConnectionManager;
+use image::{DynamicImage, ImageFormat};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{
+    scans::{Scan, ScanGroup},
+    AssetsDir,
+};
+
+/// Page numbers are looked for in this fraction of the page at the top and bottom.
+const MARGIN_BAND: f32 = 0.1;
+/// Larger numbers are more likely years or reference numbers than page numbers.
+const MAX_PAGE_NUMBER: i32 = 9999;
+
+/// The page number printed on one scan.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct DetectedPageNumber {
+    pub scan_id: i32,
+    /// Where the scan currently is in the group, from 1
+    pub position: i32,
+    /// None if no page number could be read
+    pub number: Option<i32>,
+}
+
+/// Printed page numbers compared with the order of a group's scans.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct PageNumberReport {
+    pub pages: Vec<DetectedPageNumber>,
+    /// Scans numbered lower than a page before them
+    pub out_of_order: Vec<i32>,
+    /// Numbers between the first and last page read that no scan carries
+    pub missing_numbers: Vec<i32>,
+    /// Numbers read on more than one scan
+    pub duplicate_numbers: Vec<i32>,
+    /// Scan ids sorted by page number. Pages without a readable number stay
+    /// after the page they currently follow.
+    pub suggested_order: Vec<i32>,
+}
+
+/// Every number tesseract finds in the image.
+async fn ocr_numbers(image: &DynamicImage) -> io::Result<Vec<i32>> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(io::Error::other)?;
+
+    let mut child = Command::new("tesseract")
+        .args(["stdin", "stdout", "--psm", "11"])
+        .args(["-c", "tessedit_char_whitelist=0123456789"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other("tesseract is not installed"),
+            _ => e,
+        })?;
+    child.stdin.take().unwrap().write_all(&png).await?;
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "tesseract exited with {}",
+            output.status
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|token| token.parse::<i32>().ok())
+        .filter(|number| (1..=MAX_PAGE_NUMBER).contains(number))
+        .collect())
+}
+
+/// Candidate page numbers from the header and footer of a scan.
+async fn candidates(scan: &Scan, assets_dir: &AssetsDir) -> io::Result<Vec<i32>> {
+    let image = scan.open_image(assets_dir).map_err(io::Error::other)?;
+    let band = ((image.height() as f32 * MARGIN_BAND) as u32).max(1);
+
+    let mut numbers = ocr_numbers(&image.crop_imm(0, 0, image.width(), band)).await?;
+    numbers
+        .extend(ocr_numbers(&image.crop_imm(0, image.height() - band, image.width(), band)).await?);
+    // Running headers often repeat the number in the footer
+    numbers.sort();
+    numbers.dedup();
+    Ok(numbers)
+}
+
+/// Picks one number per page. Where several numbers were read, e.g. a page
+/// and a chapter number, takes the one that follows on from the nearest page
+/// with a single unambiguous number.
+fn choose_numbers(candidates: &[Vec<i32>]) -> Vec<Option<i32>> {
+    let anchors: Vec<(i32, i32)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(position, numbers)| match numbers.as_slice() {
+            [number] => Some((position as i32, *number)),
+            _ => None,
+        })
+        .collect();
+
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(position, numbers)| {
+            let position = position as i32;
+            let expected = anchors
+                .iter()
+                .min_by_key(|(anchor, _)| (anchor - position).abs())
+                .map(|(anchor, number)| number + position - anchor);
+            match expected {
+                Some(expected) => numbers
+                    .iter()
+                    .min_by_key(|number| (*number - expected).abs())
+                    .copied(),
+                None => numbers.first().copied(),
+            }
+        })
+        .collect()
+}
+
+fn report(pages: Vec<DetectedPageNumber>) -> PageNumberReport {
+    let mut out_of_order = Vec::new();
+    let mut highest = None;
+    for page in &pages {
+        if let Some(number) = page.number {
+            if highest.is_some_and(|highest| number < highest) {
+                out_of_order.push(page.scan_id);
+            }
+            highest = highest.max(Some(number));
+        }
+    }
+
+    let mut counts: HashMap<i32, usize> = HashMap::new();
+    for number in pages.iter().filter_map(|page| page.number) {
+        *counts.entry(number).or_default() += 1;
+    }
+    let mut duplicate_numbers: Vec<i32> = counts
+        .iter()
+        .filter(|(_, count)| **count > 1)
+        .map(|(number, _)| *number)
+        .collect();
+    duplicate_numbers.sort();
+    let missing_numbers = match (counts.keys().min(), counts.keys().max()) {
+        (Some(low), Some(high)) => (*low..=*high).filter(|n| !counts.contains_key(n)).collect(),
+        _ => Vec::new(),
+    };
+
+    // Unnumbered pages borrow the number of the page before them so they
+    // move with it
+    let mut keyed = Vec::new();
+    let mut previous = i32::MIN;
+    for page in &pages {
+        let key = page.number.unwrap_or(previous);
+        previous = key;
+        keyed.push((key, page.number.is_none(), page.position, page.scan_id));
+    }
+    keyed.sort();
+    let suggested_order = keyed.into_iter().map(|(.., scan_id)| scan_id).collect();
+
+    PageNumberReport {
+        pages,
+        out_of_order,
+        missing_numbers,
+        duplicate_numbers,
+        suggested_order,
+    }
+}
+
+/// Reads the printed page number on each completed scan in the group and
+/// checks them against the group's page order. Needs `tesseract` on the PATH.
+pub async fn check_group(
+    group_id: i32,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<PageNumberReport, String> {
+    let group = ScanGroup::load(group_id, pool).map_err(|_| "Group not found".to_string())?;
+    let scans: Vec<&Scan> = group
+        .scans
+        .iter()
+        .filter(|scan| scan.status == "COMPLETE")
+        .collect();
+
+    let mut found = Vec::new();
+    for scan in &scans {
+        found.push(candidates(scan, assets_dir).await.map_err(|e| {
+            format!(
+                "Could not read page number of scan {}: {}",
+                scan.id.unwrap(),
+                e
+            )
+        })?);
+    }
+
+    let pages = scans
+        .iter()
+        .zip(choose_numbers(&found))
+        .enumerate()
+        .map(|(position, (scan, number))| DetectedPageNumber {
+            scan_id: scan.id.unwrap(),
+            position: position as i32 + 1,
+            number,
+        })
+        .collect();
+    Ok(report(pages))
+}
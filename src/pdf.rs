@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+
+/// One page of a PDF: a JPEG image drawn edge to edge.
+pub struct PdfPage {
+    pub jpeg: Vec<u8>,
+    pub width_px: u32,
+    pub height_px: u32,
+    pub width_pt: f32,
+    pub height_pt: f32,
+}
+
+/// Writes a minimal PDF with one full-page JPEG image per page.
+pub fn write_pdf(pages: &[PdfPage], out: &mut impl Write) -> io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::new();
+
+    buf.extend_from_slice(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n");
+
+    // Objects 1 and 2 are the catalog and page tree, then three objects per
+    // page: the page, its content stream and its image.
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| 3 + i * 3).collect();
+
+    offsets.push(buf.len());
+    buf.extend_from_slice(b"1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n");
+
+    offsets.push(buf.len());
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    buf.extend_from_slice(
+        format!(
+            "2 0 obj\n<< /Type /Pages /Kids [{}] /Count {} >>\nendobj\n",
+            kids.join(" "),
+            pages.len()
+        )
+        .as_bytes(),
+    );
+
+    for (page, page_id) in pages.iter().zip(page_ids) {
+        let content_id = page_id + 1;
+        let image_id = page_id + 2;
+
+        offsets.push(buf.len());
+        buf.extend_from_slice(
+            format!(
+                "{} 0 obj\n<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+                 /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>\nendobj\n",
+                page_id, page.width_pt, page.height_pt, image_id, content_id
+            )
+            .as_bytes(),
+        );
+
+        let content = format!(
+            "q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q",
+            page.width_pt, page.height_pt
+        );
+        offsets.push(buf.len());
+        buf.extend_from_slice(
+            format!(
+                "{} 0 obj\n<< /Length {} >>\nstream\n{}\nendstream\nendobj\n",
+                content_id,
+                content.len(),
+                content
+            )
+            .as_bytes(),
+        );
+
+        offsets.push(buf.len());
+        buf.extend_from_slice(
+            format!(
+                "{} 0 obj\n<< /Type /XObject /Subtype /Image /Width {} /Height {} \
+                 /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode /Length {} >>\nstream\n",
+                image_id,
+                page.width_px,
+                page.height_px,
+                page.jpeg.len()
+            )
+            .as_bytes(),
+        );
+        buf.extend_from_slice(&page.jpeg);
+        buf.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+
+    let xref_offset = buf.len();
+    buf.extend_from_slice(format!("xref\n0 {}\n", offsets.len() + 1).as_bytes());
+    buf.extend_from_slice(b"0000000000 65535 f \n");
+    for offset in &offsets {
+        buf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    buf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            offsets.len() + 1,
+            xref_offset
+        )
+        .as_bytes(),
+    );
+
+    out.write_all(&buf)
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use image::imageops::FilterType;
+
+use crate::{
+    dewarp::dewarp,
+    dropout::drop_color,
+    exports::DEFAULT_DPI,
+    gutter::remove_gutter_shadow,
+    punch_holes::remove_punch_holes,
+    scans::{crop, rotate, CropCoordinates, Scan, ScanGroup},
+    tiles::encode_jpeg,
+    AssetsDir,
+};
+
+/// Longest side of a preview, unless the request asks for another size.
+pub const DEFAULT_SIZE: u32 = 800;
+pub const MAX_SIZE: u32 = 2000;
+
+/// A rotation and crop being tried out in the editor, not yet saved.
+#[derive(Debug, Clone)]
+pub struct PreviewEdits {
+    /// Clockwise, in degrees; anything but 90, 180 or 270 is upright
+    pub rotation: i32,
+    /// As fractions of the rotated page, 0 to 1
+    pub crop: Option<CropCoordinates>,
+    /// Longest side of the preview in pixels
+    pub size: u32,
+}
+
+#[derive(Debug)]
+pub enum PreviewError {
+    /// The crop isn't inside the page or is empty
+    BadCrop,
+    Image(image::ImageError),
+}
+
+impl fmt::Display for PreviewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreviewError::BadCrop => write!(
+                f,
+                "crop must be fractions of the page, with a positive width and height"
+            ),
+            PreviewError::Image(e) => write!(f, "could not render preview: {}", e),
+        }
+    }
+}
+
+impl From<image::ImageError> for PreviewError {
+    fn from(e: image::ImageError) -> Self {
+        PreviewError::Image(e)
+    }
+}
+
+/// Renders `edits` on the scan as a JPEG, from its capture since saving
+/// either edit drops the edited copy. The page is shrunk first, then
+/// rotated and cropped as `Scan::open_image` does, and corrected for the
+/// group's book settings the way exports are, so a preview is quick to
+/// make and looks like the result.
+pub fn render(
+    scan: &Scan,
+    group: Option<&ScanGroup>,
+    edits: &PreviewEdits,
+    assets_dir: &AssetsDir,
+) -> Result<Vec<u8>, PreviewError> {
+    if edits.crop.as_ref().is_some_and(|crop| !crop.is_valid()) {
+        return Err(PreviewError::BadCrop);
+    }
+    let size = edits.size.clamp(1, MAX_SIZE);
+
+    // Shrunk so the cropped area comes out about `size` across
+    let kept = edits
+        .crop
+        .as_ref()
+        .map(|crop| crop.width.max(crop.height))
+        .unwrap_or(1.0);
+    let bound = (size as f32 / kept).ceil() as u32;
+    let mut image = assets_dir.read_image(&scan.path)?;
+    let captured_width = image.width();
+    if image.width().max(image.height()) > bound {
+        image = image.resize(bound, bound, FilterType::Triangle);
+    }
+    if scan.remove_punch_holes {
+        // Holes are found by their size, so at the shrunk page's resolution
+        let scale = image.width() as f32 / captured_width.max(1) as f32;
+        let dpi = scan.resolution().unwrap_or(DEFAULT_DPI) * scale;
+        image = remove_punch_holes(image, dpi);
+    }
+
+    image = rotate(image, edits.rotation);
+    if let Some(area) = &edits.crop {
+        image = crop(image, area);
+    }
+    if let Some(color) = group.and_then(|group| group.color_dropout) {
+        image = drop_color(image, color);
+    }
+    if group.is_some_and(|group| group.remove_gutter_shadow) {
+        image = remove_gutter_shadow(image);
+    }
+    if group.is_some_and(|group| group.dewarp) {
+        image = dewarp(image);
+    }
+    if image.width().max(image.height()) > size {
+        image = image.resize(size, size, FilterType::Triangle);
+    }
+
+    Ok(encode_jpeg(&image)?)
+}
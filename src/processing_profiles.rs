@@ -0,0 +1,334 @@
+use std::{fmt, fs, path::Path};
+
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use image::{imageops::FilterType, DynamicImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    asset_path::AssetPath,
+    exports::DEFAULT_DPI,
+    page_cleanup::{autocrop, binarize, deskew, despeckle},
+    scans::{rotate, ProcessingStatus, Scan},
+    tiles::encode_jpeg,
+    AssetsDir,
+};
+
+/// A cleanup operation on a captured page. Whatever order a profile lists
+/// them in, they run in the order declared here.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessingStep {
+    /// Square up a page fed at an angle
+    Deskew,
+    /// Trim the scanner's background from around the page
+    Autocrop,
+    /// Reduce the page to black and white
+    Binarize,
+    /// Clear dust and noise specks
+    Despeckle,
+}
+
+impl ProcessingStep {
+    fn apply(&self, image: DynamicImage, dpi: f32) -> DynamicImage {
+        match self {
+            ProcessingStep::Deskew => deskew(image),
+            ProcessingStep::Autocrop => autocrop(image),
+            ProcessingStep::Binarize => binarize(image),
+            ProcessingStep::Despeckle => despeckle(image, dpi),
+        }
+    }
+}
+
+/// Cleanup run on each page of a group as it's captured, so review starts
+/// from clean pages. The capture itself is kept.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ProcessingProfile {
+    pub id: i32,
+    pub name: String,
+    /// In the order they run
+    pub steps: Vec<ProcessingStep>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(InputObject, Debug, Clone)]
+pub struct ProcessingProfileInput {
+    pub name: String,
+    pub steps: Vec<ProcessingStep>,
+}
+
+const COLUMNS: &str = "id, name, steps, created_at";
+
+fn row_to_profile(row: &duckdb::Row) -> duckdb::Result<ProcessingProfile> {
+    let steps_json: String = row.get(2)?;
+
+    Ok(ProcessingProfile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        steps: serde_json::from_str(&steps_json).unwrap_or_default(),
+        created_at: row.get(3)?,
+    })
+}
+
+impl ProcessingProfile {
+    pub fn create(
+        input: ProcessingProfileInput,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+        let mut steps = input.steps;
+        steps.sort();
+        steps.dedup();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO processing_profiles (name, steps, created_at) VALUES (?, ?, ?) RETURNING id",
+            params![
+                input.name.trim(),
+                serde_json::to_string(&steps).unwrap(),
+                Utc::now()
+            ],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM processing_profiles WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_profile,
+        )
+    }
+
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<ProcessingProfile> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM processing_profiles ORDER BY name, id",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let profiles: Vec<ProcessingProfile> = stmt
+            .query_map([], row_to_profile)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        profiles
+    }
+
+    /// The profile of the scan's group, if it has one.
+    pub fn for_scan(
+        scan_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<Self>> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            "SELECT p.id, p.name, p.steps, p.created_at FROM scans s
+             JOIN scan_groups g ON g.id = s.scan_group_id
+             JOIN processing_profiles p ON p.id = g.processing_profile_id
+             WHERE s.id = ?",
+            params![scan_id],
+            row_to_profile,
+        )
+        .optional()
+    }
+
+    /// Deletes the profile and unassigns it from its groups. Pages already
+    /// processed keep their processed copy.
+    pub fn delete(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_groups SET processing_profile_id = NULL WHERE processing_profile_id = ?",
+            params![id],
+        )?;
+        let deleted = conn.execute("DELETE FROM processing_profiles WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProcessingError {
+    Image(image::ImageError),
+    Io(std::io::Error),
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for ProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessingError::Image(e) => write!(f, "could not process page: {}", e),
+            ProcessingError::Io(e) => write!(f, "io error: {}", e),
+            ProcessingError::Db(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<image::ImageError> for ProcessingError {
+    fn from(e: image::ImageError) -> Self {
+        ProcessingError::Image(e)
+    }
+}
+
+impl From<std::io::Error> for ProcessingError {
+    fn from(e: std::io::Error) -> Self {
+        ProcessingError::Io(e)
+    }
+}
+
+impl From<duckdb::Error> for ProcessingError {
+    fn from(e: duckdb::Error) -> Self {
+        ProcessingError::Db(e)
+    }
+}
+
+fn run_steps(image: DynamicImage, steps: &[ProcessingStep], dpi: f32) -> DynamicImage {
+    steps
+        .iter()
+        .fold(image, |image, step| step.apply(image, dpi))
+}
+
+fn processed_path(scan: &Scan) -> String {
+    let capture = scan.path.as_relative_path();
+    let capture = Path::new(&capture);
+    let stem = capture.file_stem().unwrap_or_default().to_string_lossy();
+    capture
+        .with_file_name(format!("{}_processed.png", stem))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn run_profile(
+    scan: &mut Scan,
+    profile: &ProcessingProfile,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<(), ProcessingError> {
+    let dpi = scan.resolution().unwrap_or(DEFAULT_DPI);
+    let image = run_steps(assets_dir.read_image(&scan.path)?, &profile.steps, dpi);
+
+    scan.invalidate_edit(assets_dir);
+    let edited: AssetPath = processed_path(scan).into();
+    assets_dir.write_image(&edited, &image)?;
+    scan.edited_path = Some(edited);
+    scan.save(pool)?;
+    Ok(())
+}
+
+/// Runs the profile of the scan's group on its capture, saving the result
+/// as the scan's edited copy. Does nothing for groups without a profile;
+/// returns whether the scan was processed. Blocks on the filesystem.
+pub fn process_scan(
+    scan_id: i32,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<bool, ProcessingError> {
+    let Some(profile) = ProcessingProfile::for_scan(scan_id, pool)? else {
+        return Ok(false);
+    };
+    let mut scan = Scan::load(scan_id, pool)?;
+    if scan.status != "COMPLETE" || profile.steps.is_empty() {
+        return Ok(false);
+    }
+
+    Scan::set_processing_status(scan_id, ProcessingStatus::Processing, pool)?;
+    let result = run_profile(&mut scan, &profile, pool, assets_dir);
+    let status = match result {
+        Ok(_) => ProcessingStatus::Done,
+        Err(_) => ProcessingStatus::Failed,
+    };
+    Scan::set_processing_status(scan_id, status, pool)?;
+    result.map(|_| true)
+}
+
+/// Most variants one comparison renders, the capture included.
+pub const MAX_VARIANTS: usize = 8;
+
+/// A profile to try in a comparison: a saved one, or steps not yet saved.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub profile_id: Option<i32>,
+    pub name: String,
+    pub steps: Vec<ProcessingStep>,
+}
+
+/// The sample page as one candidate profile leaves it.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ProfileVariant {
+    /// The saved profile rendered, if it was one
+    pub profile_id: Option<i32>,
+    pub name: String,
+    /// In the order they ran
+    pub steps: Vec<ProcessingStep>,
+    #[graphql(flatten)]
+    pub path: AssetPath,
+}
+
+fn comparison_dir(scan_id: i32) -> String {
+    format!("previews/{}", scan_id)
+}
+
+/// Deletes the images of the scan's last comparison, if any.
+pub fn remove_comparison(scan_id: i32, assets_dir: &AssetsDir) {
+    fs::remove_dir_all(Path::new(&assets_dir.0).join(comparison_dir(scan_id))).ok();
+}
+
+/// Renders the scan through each candidate, after the unprocessed capture,
+/// as JPEGs of at most `size` pixels a side, to compare side by side.
+/// Nothing about the scan changes. The previous comparison's images for
+/// the scan are replaced. Blocks on the filesystem.
+pub fn compare(
+    scan: &Scan,
+    candidates: Vec<Candidate>,
+    size: u32,
+    assets_dir: &AssetsDir,
+) -> Result<Vec<ProfileVariant>, ProcessingError> {
+    let scan_id = scan.id.unwrap_or_default();
+    let dir = comparison_dir(scan_id);
+    remove_comparison(scan_id, assets_dir);
+    fs::create_dir_all(Path::new(&assets_dir.0).join(&dir))?;
+
+    let capture = assets_dir.read_image(&scan.path)?;
+    let dpi = scan.resolution().unwrap_or(DEFAULT_DPI);
+    // New names each time, so browsers don't show a cached comparison
+    let stamp = Utc::now().timestamp_millis();
+
+    let unprocessed = Candidate {
+        profile_id: None,
+        name: "Capture".to_string(),
+        steps: Vec::new(),
+    };
+    std::iter::once(unprocessed)
+        .chain(candidates)
+        .take(MAX_VARIANTS)
+        .enumerate()
+        .map(|(i, mut candidate)| {
+            candidate.steps.sort();
+            candidate.steps.dedup();
+            let mut image = rotate(
+                run_steps(capture.clone(), &candidate.steps, dpi),
+                scan.rotation,
+            );
+            if image.width().max(image.height()) > size {
+                image = image.resize(size, size, FilterType::Triangle);
+            }
+
+            let path = AssetPath::from_relative_path(format!("{}/{}_{}.jpg", dir, stamp, i));
+            assets_dir.write(&path, &encode_jpeg(&image)?)?;
+            Ok(ProfileVariant {
+                profile_id: candidate.profile_id,
+                name: candidate.name,
+                steps: candidate.steps,
+                path,
+            })
+        })
+        .collect()
+}
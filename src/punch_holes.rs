@@ -0,0 +1,235 @@
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+
+/// Brightness percentile taken as the paper colour, high enough to skip
+/// over the text on it.
+const PAPER_PERCENTILE: f32 = 0.9;
+/// Holes sit within this distance of an edge: ISO 838 puts their centres
+/// 12mm in, US three-ring punches about 10mm.
+const HOLE_BAND_MM: f32 = 25.0;
+/// Hole diameters looked for. Common punches are 5 to 8mm; text and
+/// bullets are smaller.
+const MIN_HOLE_MM: f32 = 4.0;
+const MAX_HOLE_MM: f32 = 10.0;
+/// Pixels darker than this fraction of the paper can belong to a hole,
+/// whether the scanner's backing shows through black or grey.
+const HOLE_DARKNESS: f32 = 0.5;
+/// A hole's share of its bounding box; a disc fills about 0.79.
+const HOLE_FILL: std::ops::RangeInclusive<f32> = 0.6..=0.95;
+/// Pixels painted around a hole, for its anti-aliased rim.
+const HOLE_RIM: f32 = 2.0;
+/// Shadows are looked for this far in from each edge.
+const SHADOW_BAND_MM: f32 = 8.0;
+/// Lines whose paper is darker than this fraction of the page's are shadow.
+const SHADOW_DARKNESS: f32 = 0.85;
+
+fn mm_to_px(mm: f32, dpi: f32) -> u32 {
+    (mm * dpi / 25.4).round() as u32
+}
+
+/// The paper brightness among `values`, at `PAPER_PERCENTILE`.
+pub fn percentile(values: impl Iterator<Item = u8>) -> f32 {
+    let mut histogram = [0usize; 256];
+    let mut count = 0;
+    for value in values {
+        histogram[value as usize] += 1;
+        count += 1;
+    }
+    let rank = ((count.max(1) - 1) as f32 * PAPER_PERCENTILE) as usize;
+    let mut seen = 0;
+    for (level, n) in histogram.iter().enumerate() {
+        seen += n;
+        if seen > rank {
+            return level as f32;
+        }
+    }
+    255.0
+}
+
+/// Average colour of the pixels at least as light as `paper`.
+fn paper_color(rgb: &RgbImage, gray: &GrayImage, paper: f32) -> Rgb<u8> {
+    let mut sum = [0u64; 3];
+    let mut count = 0u64;
+    for (pixel, level) in rgb.pixels().zip(gray.pixels()) {
+        if level[0] as f32 >= paper {
+            for (total, channel) in sum.iter_mut().zip(pixel.0) {
+                *total += channel as u64;
+            }
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Rgb([255, 255, 255]);
+    }
+    Rgb(sum.map(|total| (total / count) as u8))
+}
+
+/// Paints the dark runs of rows and columns in from each edge, where an
+/// ADF's feed or a folded edge casts a shadow, stopping at the first line
+/// of clean paper.
+fn fill_edge_shadows(rgb: &mut RgbImage, gray: &GrayImage, paper: f32, fill: Rgb<u8>, dpi: f32) {
+    let (width, height) = gray.dimensions();
+    let band = mm_to_px(SHADOW_BAND_MM, dpi);
+    let shadowed = |level: f32| level < paper * SHADOW_DARKNESS;
+
+    let column_level = |x: &u32| percentile((0..height).map(|y| gray.get_pixel(*x, y)[0]));
+    let row_level = |y: &u32| percentile((0..width).map(|x| gray.get_pixel(x, *y)[0]));
+
+    let mut columns: Vec<u32> = (0..band.min(width))
+        .take_while(|x| shadowed(column_level(x)))
+        .collect();
+    columns.extend(
+        (width.saturating_sub(band)..width)
+            .rev()
+            .take_while(|x| shadowed(column_level(x))),
+    );
+    let mut rows: Vec<u32> = (0..band.min(height))
+        .take_while(|y| shadowed(row_level(y)))
+        .collect();
+    rows.extend(
+        (height.saturating_sub(band)..height)
+            .rev()
+            .take_while(|y| shadowed(row_level(y))),
+    );
+
+    for x in columns {
+        for y in 0..height {
+            rgb.put_pixel(x, y, fill);
+        }
+    }
+    for y in rows {
+        for x in 0..width {
+            rgb.put_pixel(x, y, fill);
+        }
+    }
+}
+
+/// A dark region found near an edge, as its bounding box and pixel count.
+struct Blob {
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+    area: usize,
+}
+
+impl Blob {
+    /// Whether the blob is shaped like a punched hole: a whole disc of a
+    /// hole's size, not cut off by the page edge.
+    fn is_hole(&self, width: u32, height: u32, dpi: f32) -> bool {
+        let (w, h) = (self.right - self.left + 1, self.bottom - self.top + 1);
+        let size = mm_to_px(MIN_HOLE_MM, dpi)..=mm_to_px(MAX_HOLE_MM, dpi);
+        let fill = self.area as f32 / (w * h) as f32;
+        size.contains(&w)
+            && size.contains(&h)
+            && w.max(h) as f32 / w.min(h) as f32 <= 1.3
+            && HOLE_FILL.contains(&fill)
+            && self.left > 0
+            && self.top > 0
+            && self.right < width - 1
+            && self.bottom < height - 1
+    }
+}
+
+/// Dark regions with a pixel in the band along the edges, found by flood
+/// fill. Regions grown past the largest hole are given up on early.
+fn edge_blobs(gray: &GrayImage, paper: f32, dpi: f32) -> Vec<Blob> {
+    let (width, height) = gray.dimensions();
+    let band = mm_to_px(HOLE_BAND_MM, dpi);
+    let max_side = mm_to_px(MAX_HOLE_MM, dpi);
+    let dark = |x: u32, y: u32| (gray.get_pixel(x, y)[0] as f32) < paper * HOLE_DARKNESS;
+    let in_band = |x: u32, y: u32| x < band || y < band || x + band >= width || y + band >= height;
+
+    let mut visited = vec![false; (width * height) as usize];
+    let mut blobs = Vec::new();
+    let mut stack = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let index = (y * width + x) as usize;
+            if visited[index] || !in_band(x, y) || !dark(x, y) {
+                continue;
+            }
+            visited[index] = true;
+            stack.push((x, y));
+            let mut blob = Blob {
+                left: x,
+                top: y,
+                right: x,
+                bottom: y,
+                area: 0,
+            };
+            while let Some((px, py)) = stack.pop() {
+                blob.area += 1;
+                blob.left = blob.left.min(px);
+                blob.top = blob.top.min(py);
+                blob.right = blob.right.max(px);
+                blob.bottom = blob.bottom.max(py);
+                let neighbours = [
+                    (px.wrapping_sub(1), py),
+                    (px + 1, py),
+                    (px, py.wrapping_sub(1)),
+                    (px, py + 1),
+                ];
+                for (nx, ny) in neighbours {
+                    if nx >= width || ny >= height {
+                        continue;
+                    }
+                    let index = (ny * width + nx) as usize;
+                    if !visited[index] && dark(nx, ny) {
+                        visited[index] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            // Still marked visited, so a large region is only walked once
+            if blob.right - blob.left <= max_side && blob.bottom - blob.top <= max_side {
+                blobs.push(blob);
+            }
+        }
+    }
+    blobs
+}
+
+fn fill_hole(rgb: &mut RgbImage, blob: &Blob, fill: Rgb<u8>) {
+    let cx = (blob.left + blob.right) as f32 / 2.0;
+    let cy = (blob.top + blob.bottom) as f32 / 2.0;
+    let radius = (blob.right - blob.left).max(blob.bottom - blob.top) as f32 / 2.0 + HOLE_RIM;
+    let reach = radius.ceil() as u32;
+
+    let x_range = (cx as u32).saturating_sub(reach)..(cx as u32 + reach + 1).min(rgb.width());
+    for y in (cy as u32).saturating_sub(reach)..(cy as u32 + reach + 1).min(rgb.height()) {
+        for x in x_range.clone() {
+            let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+            if dx * dx + dy * dy <= radius * radius {
+                rgb.put_pixel(x, y, fill);
+            }
+        }
+    }
+}
+
+/// Cleans up an ADF scan of a filed document: shadows along the edges and
+/// punched holes near them are painted over in the paper's colour.
+///
+/// Holes are told from text by size and shape, using `dpi` to know how
+/// big a punched hole is: a dark disc 4 to 10mm across, whole and near an
+/// edge. Anything else, such as a filled-in circle in the body of a form,
+/// is left alone.
+pub fn remove_punch_holes(image: DynamicImage, dpi: f32) -> DynamicImage {
+    if image.width() < 3 || image.height() < 3 || dpi <= 0.0 {
+        return image;
+    }
+    let gray = image.to_luma8();
+    let paper = percentile(gray.pixels().map(|pixel| pixel[0]));
+    if paper <= 0.0 {
+        return image;
+    }
+
+    let mut rgb = image.to_rgb8();
+    let fill = paper_color(&rgb, &gray, paper);
+    fill_edge_shadows(&mut rgb, &gray, paper, fill, dpi);
+    for blob in edge_blobs(&gray, paper, dpi) {
+        if blob.is_hole(gray.width(), gray.height(), dpi) {
+            fill_hole(&mut rgb, &blob, fill);
+        }
+    }
+    DynamicImage::ImageRgb8(rgb)
+}
@@ -0,0 +1,53 @@
+use image::{imageops, DynamicImage, GrayImage, Luma};
+use qrcode::{Color, QrCode};
+
+/// Blank modules around the code that scanners need to find it.
+const QUIET_ZONE: u32 = 4;
+
+/// Number of modules across the image `render` makes for `data`, quiet zone included.
+pub fn modules(data: &str) -> u32 {
+    QrCode::new(data.as_bytes()).unwrap().width() as u32 + QUIET_ZONE * 2
+}
+
+/// Renders `data` as a black on white QR code, each module `module_px`
+/// pixels square.
+pub fn render(data: &str, module_px: u32) -> GrayImage {
+    let code = QrCode::new(data.as_bytes()).unwrap();
+    let width = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (width + QUIET_ZONE * 2) * module_px;
+
+    GrayImage::from_fn(size, size, |x, y| {
+        let (mx, my) = (x / module_px, y / module_px);
+        let inside = (QUIET_ZONE..QUIET_ZONE + width).contains(&mx)
+            && (QUIET_ZONE..QUIET_ZONE + width).contains(&my);
+        if inside && colors[((my - QUIET_ZONE) * width + mx - QUIET_ZONE) as usize] == Color::Dark {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    })
+}
+
+/// Size of a stamped QR code, quiet zone included.
+const STAMP_INCHES: f32 = 0.8;
+/// Distance from the stamp to the bottom and right edges of the page.
+const STAMP_MARGIN_INCHES: f32 = 0.25;
+/// On small pages the stamp shrinks to at most this fraction of the shorter side.
+const STAMP_MAX_FRACTION: f32 = 0.2;
+
+/// Stamps a QR code for `data` in the bottom right corner of a page scanned
+/// at `dpi`. The quiet zone is drawn white, covering whatever was there.
+pub fn stamp(image: DynamicImage, data: &str, dpi: f32) -> DynamicImage {
+    let shorter = image.width().min(image.height()) as f32;
+    let size = (STAMP_INCHES * dpi).min(shorter * STAMP_MAX_FRACTION);
+    let module_px = (size as u32 / modules(data)).max(1);
+    let code = DynamicImage::ImageLuma8(render(data, module_px)).to_rgb8();
+    let margin = (STAMP_MARGIN_INCHES * dpi).min(shorter * STAMP_MAX_FRACTION / 4.0) as i64;
+
+    let mut page = image.to_rgb8();
+    let x = page.width() as i64 - margin - code.width() as i64;
+    let y = page.height() as i64 - margin - code.height() as i64;
+    imageops::overlay(&mut page, &code, x, y);
+    DynamicImage::ImageRgb8(page)
+}
@@ -0,0 +1,147 @@
+use std::{
+    env, fmt,
+    io::{self, Cursor, Read, Write},
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::{ImageReader, Limits};
+
+/// A file the worker hasn't decoded within this long is given up on.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest side the worker decodes. A 1200dpi A3 page is about 14000 x 20000.
+const MAX_DIMENSION: u32 = 30_000;
+/// Most memory the worker lets a decoder allocate.
+const MAX_ALLOC: u64 = 2 * 1024 * 1024 * 1024;
+/// How the worker exits when the file isn't an image it can decode.
+const EXIT_REJECTED: i32 = 65;
+
+#[derive(Debug)]
+pub enum SandboxError {
+    /// The worker couldn't be started or read from
+    Io(io::Error),
+    /// The file isn't an image the worker could decode within its limits
+    Rejected(String),
+    /// The worker died, e.g. on a crash in a decoder
+    Crashed(ExitStatus),
+    TimedOut,
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxError::Io(e) => write!(f, "could not run the decode worker: {}", e),
+            SandboxError::Rejected(reason) => write!(f, "not a readable image: {}", reason),
+            SandboxError::Crashed(status) => write!(f, "decode worker died ({})", status),
+            SandboxError::TimedOut => {
+                write!(f, "decode worker took longer than {}s", TIMEOUT.as_secs())
+            }
+        }
+    }
+}
+
+impl From<io::Error> for SandboxError {
+    fn from(e: io::Error) -> Self {
+        SandboxError::Io(e)
+    }
+}
+
+/// Decodes an untrusted image in a child process, returning its width and
+/// height. Images of more than `max_pixels` are turned away from their
+/// header, before decoding. A decoder that crashes, blows up in memory or
+/// hangs on a malformed file only takes the child down. Blocks until it's
+/// done.
+pub fn check_image(contents: &[u8], max_pixels: u64) -> Result<(u32, u32), SandboxError> {
+    let mut child = Command::new(env::current_exe()?)
+        .arg("decode-worker")
+        .arg("--max-pixels")
+        .arg(max_pixels.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // A worker that dies while reading closes the pipe; its status says why
+    child.stdin.take().unwrap().write_all(contents).ok();
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(SandboxError::TimedOut);
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    let (mut stdout, mut stderr) = (String::new(), String::new());
+    child.stdout.take().unwrap().read_to_string(&mut stdout)?;
+    child.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    match status.code() {
+        Some(0) => {
+            let mut dimensions = stdout.split_whitespace().map(str::parse);
+            match (dimensions.next(), dimensions.next()) {
+                (Some(Ok(width)), Some(Ok(height))) => Ok((width, height)),
+                _ => Err(SandboxError::Crashed(status)),
+            }
+        }
+        Some(EXIT_REJECTED) => Err(SandboxError::Rejected(stderr.trim().to_string())),
+        _ => Err(SandboxError::Crashed(status)),
+    }
+}
+
+/// The child side of `check_image`: decodes the image on stdin within the
+/// limits and prints its dimensions. Returns the exit code.
+pub fn run_worker(max_pixels: u64) -> i32 {
+    let mut contents = Vec::new();
+    if let Err(e) = io::stdin().read_to_end(&mut contents) {
+        eprintln!("{}", e);
+        return EXIT_REJECTED;
+    }
+
+    // A decompression bomb is a small file whose header claims a huge image
+    let dimensions = ImageReader::new(Cursor::new(&contents))
+        .with_guessed_format()
+        .map_err(image::ImageError::from)
+        .and_then(|reader| reader.into_dimensions());
+    match dimensions {
+        Ok((width, height)) if width as u64 * height as u64 > max_pixels => {
+            eprintln!(
+                "{}x{} is over the limit of {} pixels",
+                width, height, max_pixels
+            );
+            return EXIT_REJECTED;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_REJECTED;
+        }
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_ALLOC);
+
+    let decoded = ImageReader::new(Cursor::new(contents))
+        .with_guessed_format()
+        .map_err(image::ImageError::from)
+        .and_then(|mut reader| {
+            reader.limits(limits);
+            reader.decode()
+        });
+    match decoded {
+        Ok(image) => {
+            println!("{} {}", image.width(), image.height());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_REJECTED
+        }
+    }
+}
@@ -3,15 +3,66 @@ use chrono::{DateTime, Utc};
 use duckdb::Result;
 use duckdb::{params, DuckdbConnectionManager};
 
+/// Marks where one document ends and the next begins in the stream of
+/// scans, from before scans were put in groups.
 #[derive(Debug, Clone, SimpleObject)]
 pub struct ScanDivider {
     pub id: Option<i32>,
     pub ts: DateTime<Utc>,
+    /// The divider after this one, null for the last
+    pub next_ts: Option<DateTime<Utc>>,
+    /// Scans taken between this divider and the next
+    pub scan_count: i32,
+    /// When the first and last of those scans were taken
+    pub first_scanned_at: Option<DateTime<Utc>>,
+    pub last_scanned_at: Option<DateTime<Utc>>,
 }
 
 impl ScanDivider {
     pub fn new(ts: DateTime<Utc>) -> Self {
-        Self { id: None, ts }
+        Self {
+            id: None,
+            ts,
+            next_ts: None,
+            scan_count: 0,
+            first_scanned_at: None,
+            last_scanned_at: None,
+        }
+    }
+
+    /// Every divider in time order, with the scans up to the next one.
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<ScanDivider> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "WITH d AS (
+                    SELECT id, ts, lead(ts) OVER (ORDER BY ts, id) AS next_ts FROM scan_dividers
+                 )
+                 SELECT d.id, d.ts, d.next_ts, count(s.id), min(s.scanned_at), max(s.scanned_at)
+                 FROM d
+                 LEFT JOIN scans s ON s.scanned_at >= d.ts AND (d.next_ts IS NULL OR s.scanned_at < d.next_ts)
+                 GROUP BY d.id, d.ts, d.next_ts
+                 ORDER BY d.ts, d.id",
+            )
+            .unwrap();
+
+        let dividers: Vec<ScanDivider> = stmt
+            .query_map([], |row| {
+                Ok(ScanDivider {
+                    id: row.get(0)?,
+                    ts: row.get(1)?,
+                    next_ts: row.get(2)?,
+                    scan_count: row.get(3)?,
+                    first_scanned_at: row.get(4)?,
+                    last_scanned_at: row.get(5)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        dividers
     }
 
     pub fn save(&mut self, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<i32> {
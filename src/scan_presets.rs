@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+
+/// Named scan parameters for a device, e.g. `300dpi color flatbed` or
+/// `grayscale ADF`, to start scans from instead of writing the parameters
+/// out each time.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScanPreset {
+    pub id: i32,
+    pub name: String,
+    /// The device it's for, as listed by `scanners`
+    pub scanner: String,
+    /// As `scan` takes them, `{variables}` and all
+    pub scan_parameters: HashMap<String, String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(InputObject, Debug, Clone)]
+pub struct ScanPresetInput {
+    pub name: String,
+    pub scanner: String,
+    pub scan_parameters: HashMap<String, String>,
+}
+
+const COLUMNS: &str = "id, name, scanner, scan_parameters, created_at, updated_at";
+
+fn row_to_preset(row: &duckdb::Row) -> duckdb::Result<ScanPreset> {
+    let parameters_json: String = row.get(3)?;
+
+    Ok(ScanPreset {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        scanner: row.get(2)?,
+        scan_parameters: serde_json::from_str(&parameters_json).unwrap_or_default(),
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+    })
+}
+
+impl ScanPreset {
+    pub fn create(
+        input: ScanPresetInput,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+        let now = Utc::now();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO scan_presets (name, scanner, scan_parameters, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?) RETURNING id",
+            params![
+                input.name.trim(),
+                input.scanner,
+                serde_json::to_string(&input.scan_parameters).unwrap(),
+                now,
+                now
+            ],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM scan_presets WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_preset,
+        )
+    }
+
+    /// Every preset, or only those for `scanner`, by device then name.
+    pub fn load_all(
+        scanner: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<ScanPreset> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM scan_presets WHERE ? IS NULL OR scanner = ?
+                 ORDER BY scanner, name, id",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let presets: Vec<ScanPreset> = stmt
+            .query_map(params![scanner, scanner], row_to_preset)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        presets
+    }
+
+    /// Replaces the preset's name, device and parameters. Returns `None` if
+    /// there's no such preset.
+    pub fn update(
+        id: i32,
+        input: ScanPresetInput,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<Self>> {
+        let conn = pool.get().unwrap();
+        let updated = conn.execute(
+            "UPDATE scan_presets SET name = ?, scanner = ?, scan_parameters = ?, updated_at = ?
+             WHERE id = ?",
+            params![
+                input.name.trim(),
+                input.scanner,
+                serde_json::to_string(&input.scan_parameters).unwrap(),
+                Utc::now(),
+                id
+            ],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        Self::load(id, pool).optional()
+    }
+
+    /// Deletes the preset. Scans started from it keep their parameters.
+    pub fn delete(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        let deleted = conn.execute("DELETE FROM scan_presets WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
+}
+
+/// The device and parameters to start a scan with: those given, filling in
+/// from `preset` if there is one. Parameters given replace the preset's of
+/// the same name, so a preset can be used with a tweak or two. `None` if
+/// there's neither a device nor a preset to take one from.
+pub fn scan_settings(
+    preset: Option<ScanPreset>,
+    device: Option<String>,
+    parameters: HashMap<String, String>,
+) -> Option<(String, HashMap<String, String>)> {
+    match preset {
+        Some(preset) => {
+            let mut merged = preset.scan_parameters;
+            merged.extend(parameters);
+            Some((device.unwrap_or(preset.scanner), merged))
+        }
+        None => device.map(|device| (device, parameters)),
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::Enum;
+use tokio::sync::oneshot;
+
+/// Order in which queued scans get a device. Equal priorities go first come, first served.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Default)]
+pub enum ScanPriority {
+    /// Unattended batches that can wait
+    Low,
+    #[default]
+    Normal,
+    /// Someone is standing at the scanner
+    High,
+}
+
+impl ScanPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScanPriority::Low => "low",
+            ScanPriority::Normal => "normal",
+            ScanPriority::High => "high",
+        }
+    }
+
+    pub fn from_str(priority: &str) -> Self {
+        match priority {
+            "low" => ScanPriority::Low,
+            "high" => ScanPriority::High,
+            _ => ScanPriority::Normal,
+        }
+    }
+}
+
+struct Waiter {
+    priority: ScanPriority,
+    seq: u64,
+    /// The scan waiting, if it's one with a row
+    scan_id: Option<i32>,
+    wake: oneshot::Sender<()>,
+}
+
+impl Ord for Waiter {
+    // BinaryHeap pops the greatest: highest priority, then lowest sequence number
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+#[derive(Default)]
+struct DeviceState {
+    busy: bool,
+    waiting: BinaryHeap<Waiter>,
+}
+
+#[derive(Default)]
+struct Queues {
+    devices: HashMap<String, DeviceState>,
+    next_seq: u64,
+}
+
+/// Runs one scan at a time per device, handing the device to the highest
+/// priority waiting scan each time one finishes.
+#[derive(Clone, Default)]
+pub struct ScanQueue {
+    queues: Arc<Mutex<Queues>>,
+}
+
+/// A scan's place in the queue for a device. If the scan is cancelled just
+/// as the device is handed to it, the device is passed on rather than left
+/// busy.
+struct Place {
+    woken: oneshot::Receiver<()>,
+    queues: Arc<Mutex<Queues>>,
+    device: String,
+}
+
+impl Drop for Place {
+    fn drop(&mut self) {
+        if self.woken.try_recv().is_ok() {
+            drop(DeviceTurn {
+                queues: self.queues.clone(),
+                device: self.device.clone(),
+            });
+        }
+    }
+}
+
+/// Holds a device until dropped, then passes it to the next waiting scan.
+pub struct DeviceTurn {
+    queues: Arc<Mutex<Queues>>,
+    device: String,
+}
+
+impl ScanQueue {
+    /// Waits until `device` is free and it's this scan's turn. `queued` is
+    /// called first if the scan has to wait.
+    pub async fn acquire(
+        &self,
+        device: &str,
+        priority: ScanPriority,
+        scan_id: Option<i32>,
+        queued: impl FnOnce(),
+    ) -> DeviceTurn {
+        let waiting = {
+            let mut queues = self.queues.lock().unwrap();
+            let seq = queues.next_seq;
+            queues.next_seq += 1;
+
+            let state = queues.devices.entry(device.to_string()).or_default();
+            if state.busy {
+                let (wake, woken) = oneshot::channel();
+                state.waiting.push(Waiter {
+                    priority,
+                    seq,
+                    scan_id,
+                    wake,
+                });
+                Some(woken)
+            } else {
+                state.busy = true;
+                None
+            }
+        };
+
+        if let Some(woken) = waiting {
+            queued();
+            let mut place = Place {
+                woken,
+                queues: self.queues.clone(),
+                device: device.to_string(),
+            };
+            // The turn is handed over still marked busy, so nobody can slip in between
+            (&mut place.woken).await.unwrap();
+        }
+
+        DeviceTurn {
+            queues: self.queues.clone(),
+            device: device.to_string(),
+        }
+    }
+}
+
+impl ScanQueue {
+    /// Scans waiting for `device`, not counting the one using it.
+    pub fn waiting(&self, device: &str) -> usize {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .devices
+            .get(device)
+            .map_or(0, |state| state.waiting.len())
+    }
+
+    /// Where the scan is in its device's queue, 1 being next, or `None` if
+    /// it isn't waiting.
+    pub fn position(&self, scan_id: i32) -> Option<usize> {
+        let queues = self.queues.lock().unwrap();
+        queues.devices.values().find_map(|state| {
+            let waiter = state
+                .waiting
+                .iter()
+                .find(|waiter| waiter.scan_id == Some(scan_id))?;
+            Some(state.waiting.iter().filter(|other| *other > waiter).count() + 1)
+        })
+    }
+}
+
+impl Drop for DeviceTurn {
+    fn drop(&mut self) {
+        let mut queues = self.queues.lock().unwrap();
+        let state = queues.devices.get_mut(&self.device).unwrap();
+
+        // Skip waiters that gave up; their receiver is gone
+        while let Some(next) = state.waiting.pop() {
+            if next.wake.send(()).is_ok() {
+                return;
+            }
+        }
+        state.busy = false;
+    }
+}
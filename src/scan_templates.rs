@@ -0,0 +1,125 @@
+use std::{collections::HashMap, fmt};
+
+use async_graphql::{Enum, InputObject};
+
+/// Paper a scan is sized for, giving the `{page_width_mm}` and
+/// `{page_height_mm}` variables.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PaperSize {
+    A3,
+    A4,
+    A5,
+    A6,
+    Letter,
+    Legal,
+    Tabloid,
+    BusinessCard,
+}
+
+impl PaperSize {
+    /// Width and height in millimetres, portrait.
+    pub fn millimetres(&self) -> (f32, f32) {
+        match self {
+            PaperSize::A3 => (297.0, 420.0),
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::A5 => (148.0, 210.0),
+            PaperSize::A6 => (105.0, 148.0),
+            PaperSize::Letter => (215.9, 279.4),
+            PaperSize::Legal => (215.9, 355.6),
+            PaperSize::Tabloid => (279.4, 431.8),
+            PaperSize::BusinessCard => (85.6, 54.0),
+        }
+    }
+}
+
+/// A value for a `{name}` in the scan parameters, overriding the built-in
+/// ones.
+#[derive(InputObject, Debug, Clone)]
+pub struct TemplateVariable {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// A `{name}` with no value, e.g. `{page_height_mm}` without a paper size
+    Unknown(String),
+    /// A `{` with no `}` after it
+    Unclosed(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unknown(name) => write!(f, "no value for {{{}}}", name),
+            TemplateError::Unclosed(value) => write!(f, "unclosed {{ in {}", value),
+        }
+    }
+}
+
+/// Fills in the variables in the parameters' values, just before a scan is
+/// started: `{page_width_mm}` and `{page_height_mm}` from the paper size,
+/// `{dpi}` from the `--resolution` parameter, and any given `variables`.
+/// `{{` and `}}` stand for literal braces. Parameters without braces are
+/// passed through as they are.
+pub fn resolve(
+    parameters: &HashMap<String, String>,
+    paper_size: Option<PaperSize>,
+    variables: &[TemplateVariable],
+) -> Result<HashMap<String, String>, TemplateError> {
+    let mut values: HashMap<&str, String> = HashMap::new();
+    if let Some(paper_size) = paper_size {
+        let (width, height) = paper_size.millimetres();
+        values.insert("page_width_mm", width.to_string());
+        values.insert("page_height_mm", height.to_string());
+    }
+    let dpi = parameters
+        .iter()
+        .find(|(key, _)| key.trim_start_matches('-') == "resolution")
+        .map(|(_, value)| {
+            value
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>()
+        })
+        .filter(|dpi| !dpi.is_empty());
+    if let Some(dpi) = dpi {
+        values.insert("dpi", dpi);
+    }
+    for variable in variables {
+        values.insert(&variable.name, variable.value.clone());
+    }
+
+    parameters
+        .iter()
+        .map(|(key, value)| Ok((key.clone(), substitute(value, &values)?)))
+        .collect()
+}
+
+pub fn substitute(value: &str, values: &HashMap<&str, String>) -> Result<String, TemplateError> {
+    let mut resolved = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find(['{', '}']) {
+        resolved.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            resolved.push_str(&tail[..1]);
+            rest = &tail[2..];
+        } else if let Some(after) = tail.strip_prefix('}') {
+            resolved.push('}');
+            rest = after;
+        } else {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| TemplateError::Unclosed(value.to_string()))?;
+            let name = tail[1..end].trim();
+            let replacement = values
+                .get(name)
+                .ok_or_else(|| TemplateError::Unknown(name.to_string()))?;
+            resolved.push_str(replacement);
+            rest = &tail[end + 1..];
+        }
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
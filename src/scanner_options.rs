@@ -0,0 +1,179 @@
+use async_graphql::{Enum, SimpleObject};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What kind of value an option takes, as `scanimage -A` describes it.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ScannerOptionType {
+    /// `yes` or `no`
+    Boolean,
+    /// One of `values`
+    Choice,
+    /// A number from `min` to `max`
+    Range,
+    /// Free text, or a value scanimage doesn't describe further
+    Text,
+    /// An action with no value, e.g. `--clear-calibration`
+    Button,
+}
+
+/// One option a device accepts, to pass in a scan's `scan_parameters` by
+/// its `flag`.
+#[derive(Debug, Clone, PartialEq, SimpleObject, Serialize, Deserialize)]
+pub struct ScannerOption {
+    /// As given to scanimage, e.g. `--resolution`, or `-l` for geometry
+    pub flag: String,
+    /// The flag without its dashes, e.g. `resolution`
+    pub name: String,
+    /// The heading it's listed under, e.g. `Geometry`
+    pub group: Option<String>,
+    pub option_type: ScannerOptionType,
+    /// The choices, for CHOICE options, without their unit
+    pub values: Vec<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+    /// e.g. `dpi`, `mm` or `%`
+    pub unit: Option<String>,
+    pub default: Option<String>,
+    /// False when the option doesn't apply in the current settings, e.g. a
+    /// threshold outside lineart mode
+    pub active: bool,
+    /// Set by the device, e.g. a button or paper sensor, rather than by scans
+    pub read_only: bool,
+    pub description: String,
+}
+
+/// Units scanimage puts on the end of numbers.
+const UNITS: &[&str] = &["dpi", "mm", "%", "us", "bit", "pel"];
+
+/// Splits a unit off the end of a number, e.g. `300dpi`.
+fn split_unit(value: &str) -> (&str, Option<&str>) {
+    for unit in UNITS {
+        if let Some(number) = value.strip_suffix(unit) {
+            if number.parse::<f64>().is_ok() {
+                return (number, Some(unit));
+            }
+        }
+    }
+    (value, None)
+}
+
+/// Reads the option's value description, e.g. `75|150|300dpi` or
+/// `0..215.9mm (in steps of 0.1)`, into `option`.
+fn read_spec(spec: &str, option: &mut ScannerOption) {
+    let step = Regex::new(r"\s*\(in steps of ([-\d.]+)\)").unwrap();
+    let spec = match step.captures(spec) {
+        Some(captures) => {
+            option.step = captures[1].parse().ok();
+            step.replace(spec, "").trim().to_string()
+        }
+        None => spec.trim().to_string(),
+    };
+    let range = Regex::new(r"^(-?[\d.]+)\.\.(-?[\d.]+)([a-z%]*)$").unwrap();
+
+    if spec.is_empty() {
+        option.option_type = ScannerOptionType::Button;
+    } else if let Some(captures) = range.captures(&spec) {
+        option.option_type = ScannerOptionType::Range;
+        option.min = captures[1].parse().ok();
+        option.max = captures[2].parse().ok();
+        option.unit = Some(captures[3].to_string()).filter(|unit| !unit.is_empty());
+    } else if spec.starts_with('<') || spec.contains(",...") {
+        option.option_type = ScannerOptionType::Text;
+    } else {
+        option.option_type = ScannerOptionType::Choice;
+        let mut values: Vec<String> = spec.split('|').map(str::to_string).collect();
+        // Numeric choices carry the unit on the last one only
+        if let Some(last) = values.last_mut() {
+            if let (number, Some(unit)) = split_unit(last) {
+                option.unit = Some(unit.to_string());
+                *last = number.to_string();
+            }
+        }
+        option.values = values;
+    }
+}
+
+/// Parses `scanimage -A` output, e.g.
+///
+/// ```text
+///   Scan Mode:
+///     --mode Color|Gray|Lineart [Color]
+///         Selects the scan mode.
+///     --resolution 75|150|300|600dpi [300]
+///   Geometry:
+///     -l 0..215.9mm (in steps of 0.1) [0]
+///     --page-loaded[=(yes|no)] [no] [hardware]
+/// ```
+pub fn parse(listing: &str) -> Vec<ScannerOption> {
+    let group_line = Regex::new(r"^  (\S.*):$").unwrap();
+    let option_line =
+        Regex::new(r"^\s{2,6}(-{1,2}[A-Za-z0-9][\w-]*)(\[=\(yes\|no\)\])?(?:\s+(.*))?$").unwrap();
+    let tag = Regex::new(r"\[([^\]]*)\]").unwrap();
+
+    let mut options: Vec<ScannerOption> = Vec::new();
+    let mut group = None;
+    for line in listing.lines() {
+        if let Some(captures) = group_line.captures(line) {
+            group = Some(captures[1].to_string());
+            continue;
+        }
+        let Some(captures) = option_line.captures(line) else {
+            // Description lines are indented under their option
+            if let Some(option) = options.last_mut().filter(|_| line.starts_with("        ")) {
+                if !option.description.is_empty() {
+                    option.description.push(' ');
+                }
+                option.description.push_str(line.trim());
+            }
+            continue;
+        };
+
+        let flag = captures[1].to_string();
+        let rest = captures.get(3).map_or("", |rest| rest.as_str());
+        // The value description, then bracketed default and flags
+        let tags_at = rest
+            .match_indices('[')
+            .map(|(i, _)| i)
+            .find(|&i| i == 0 || rest[..i].ends_with(' '))
+            .unwrap_or(rest.len());
+        let (spec, tags) = rest.split_at(tags_at);
+
+        let mut option = ScannerOption {
+            name: flag.trim_start_matches('-').to_string(),
+            flag,
+            group: group.clone(),
+            option_type: ScannerOptionType::Text,
+            values: Vec::new(),
+            min: None,
+            max: None,
+            step: None,
+            unit: None,
+            default: None,
+            active: true,
+            read_only: false,
+            description: String::new(),
+        };
+        if captures.get(2).is_some() {
+            option.option_type = ScannerOptionType::Boolean;
+            option.values = vec!["yes".to_string(), "no".to_string()];
+        } else {
+            read_spec(spec, &mut option);
+        }
+        for (i, captures) in tag.captures_iter(tags).enumerate() {
+            match &captures[1] {
+                "inactive" => option.active = false,
+                "read-only" | "hardware" => option.read_only = true,
+                "advanced" | "software" | "emulated" | "automatic" => {}
+                default if i == 0 => {
+                    let (number, _) = split_unit(default);
+                    option.default = Some(number.to_string());
+                }
+                _ => {}
+            }
+        }
+        options.push(option);
+    }
+    options
+}
@@ -0,0 +1,140 @@
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use tokio::{process::Command, sync::Mutex};
+
+/// Commands that switch a scanner on before a scan and off once it's been
+/// idle a while, for scanners too noisy or power-hungry to leave on, e.g.
+/// `uhubctl -l 1-1 -p 2 -a on` to power the USB port it's plugged into.
+/// `{device}` in a command is replaced with the SANE device name.
+#[derive(Debug, Clone)]
+pub struct PowerConfig {
+    pub on_command: Option<String>,
+    pub off_command: Option<String>,
+    /// Waited after switching on, for the device to show up on the bus
+    pub settle: Duration,
+    /// Idle time before switching off, so a batch doesn't cycle the power
+    /// between pages
+    pub off_after: Duration,
+}
+
+impl PowerConfig {
+    /// From `SCANNER_POWER_ON`, `SCANNER_POWER_OFF`,
+    /// `SCANNER_POWER_SETTLE_SECONDS` (default 5) and
+    /// `SCANNER_POWER_OFF_AFTER_SECONDS` (default 120). None if neither
+    /// command is set.
+    pub fn from_env() -> Option<Self> {
+        let command = |name: &str| env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let seconds = |name: &str, default: u64| {
+            Duration::from_secs(
+                env::var(name)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+        let config = PowerConfig {
+            on_command: command("SCANNER_POWER_ON"),
+            off_command: command("SCANNER_POWER_OFF"),
+            settle: seconds("SCANNER_POWER_SETTLE_SECONDS", 5),
+            off_after: seconds("SCANNER_POWER_OFF_AFTER_SECONDS", 120),
+        };
+        (config.on_command.is_some() || config.off_command.is_some()).then_some(config)
+    }
+}
+
+#[derive(Debug, Default)]
+struct DevicePower {
+    on: bool,
+    /// Bumped by each scan, so a pending switch-off can tell it's stale
+    scans: u64,
+}
+
+/// Tracks which scanners have been switched on. Devices are assumed off
+/// at startup, so the first scan on each runs the on command.
+#[derive(Clone)]
+pub struct ScannerPower {
+    config: PowerConfig,
+    // Held while a command runs, so an on and an off never overlap
+    devices: Arc<Mutex<HashMap<String, DevicePower>>>,
+}
+
+impl ScannerPower {
+    pub fn new(config: PowerConfig) -> Self {
+        ScannerPower {
+            config,
+            devices: Default::default(),
+        }
+    }
+
+    pub async fn is_on(&self, device: &str) -> bool {
+        self.devices
+            .lock()
+            .await
+            .get(device)
+            .is_some_and(|power| power.on)
+    }
+
+    /// Switches the device on unless it already is, and cancels any
+    /// pending switch-off. Returns once a device just switched on has
+    /// settled.
+    pub async fn before_scan(&self, device: &str) -> Result<(), String> {
+        {
+            let mut devices = self.devices.lock().await;
+            let power = devices.entry(device.to_string()).or_default();
+            power.scans += 1;
+            if power.on {
+                return Ok(());
+            }
+            if let Some(command) = &self.config.on_command {
+                run(command, device).await?;
+            }
+            power.on = true;
+        }
+        tokio::time::sleep(self.config.settle).await;
+        Ok(())
+    }
+
+    /// Waits out the idle time, then switches the device off unless
+    /// another scan used it meanwhile. True if it was switched off.
+    pub async fn off_when_idle(&self, device: &str) -> Result<bool, String> {
+        let scans = match self.devices.lock().await.get(device) {
+            Some(power) => power.scans,
+            None => return Ok(false),
+        };
+        tokio::time::sleep(self.config.off_after).await;
+
+        let mut devices = self.devices.lock().await;
+        let Some(power) = devices.get_mut(device).filter(|power| power.scans == scans) else {
+            return Ok(false);
+        };
+        if !power.on {
+            return Ok(false);
+        }
+        if let Some(command) = &self.config.off_command {
+            run(command, device).await?;
+        }
+        power.on = false;
+        Ok(true)
+    }
+}
+
+/// Runs the command with `sh -c`, the device name quoted in for `{device}`.
+async fn run(command: &str, device: &str) -> Result<(), String> {
+    let quoted = format!("'{}'", device.replace('\'', r"'\''"));
+    let command = command.replace("{device}", &quoted);
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .output()
+        .await
+        .map_err(|e| format!("could not run {}: {}", command, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
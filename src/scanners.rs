@@ -1,27 +1,382 @@
-use async_graphql::SimpleObject;
+use async_graphql::{Enum, SimpleObject};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use duckdb::DuckdbConnectionManager;
+use image::DynamicImage;
 use rand::seq::SliceRandom;
 use regex::Regex;
 use std::{
     collections::HashMap,
-    env, fs,
-    path::Path,
-    sync::Arc,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::{process::Command, sync::Mutex};
+use tokio::{
+    io::AsyncReadExt,
+    process::{ChildStderr, Command},
+    sync::{watch, Mutex},
+};
 
-use crate::{scans::Scan, AssetsDir};
+use crate::{
+    activity::{Activity, ActivityKind},
+    asset_path::AssetPath,
+    dead_letters::DeadLetter,
+    escl, known_scanners, locale, processing_profiles,
+    scan_queue::{ScanPriority, ScanQueue},
+    scanner_options::{self, ScannerOption, ScannerOptionType},
+    scanner_power::{PowerConfig, ScannerPower},
+    scans::Scan,
+    simple_broker::SimpleBroker,
+    test_page, AssetsDir,
+};
 
 // Mock scanner constants
 const MOCK_SCANNER_NAME: &str = "mock:scanner";
 const MOCK_SCANNER_DESCRIPTION: &str = "Mock Scanner for Development";
+const MOCK_SCAN_DELAY: Duration = Duration::from_secs(3);
+// Sheets the mock scanner's feeder holds for a batch
+const MOCK_BATCH_PAGES: usize = 3;
+
+/// What the mock scanner says its options are, in `scanimage -A`'s format.
+const MOCK_SCANNER_OPTIONS: &str = "All options specific to device `mock:scanner':
+  Scan Mode:
+    --mode Color|Gray|Lineart [Color]
+        Selects the scan mode (e.g., lineart, monochrome, or color).
+    --resolution 75|150|300|600dpi [150]
+        Sets the resolution of the scanned image.
+    --source Flatbed|ADF|ADF Duplex [Flatbed]
+        Selects the scan source (such as a document-feeder).
+  Geometry:
+    -l 0..215.9mm (in steps of 0.1) [0]
+        Top-left x position of scan area.
+    -t 0..297mm (in steps of 0.1) [0]
+        Top-left y position of scan area.
+    -x 0..215.9mm (in steps of 0.1) [215.9]
+        Width of scan-area.
+    -y 0..297mm (in steps of 0.1) [297]
+        Height of scan-area.
+  Enhancement:
+    --brightness -100..100% (in steps of 1) [0]
+        Controls the brightness of the acquired image.
+    --threshold 0..100% (in steps of 1) [inactive]
+        Select minimum-brightness to get a white point
+  Sensors:
+    --page-loaded[=(yes|no)] [yes] [hardware]
+        Paper in the document feeder
+";
+
+/// Shown wherever scanning can't work because SANE isn't installed.
+pub const SCANIMAGE_MISSING: &str = "scanimage was not found. Install SANE (the sane-utils package on Debian and Ubuntu), set SCANNER_BACKEND=escl for network scanners, or set MOCK_SCANNER=true to try scanserv without a scanner.";
+
+// Simulated scans are letter size at this resolution unless --resolution is given
+const SIMULATED_DPI: f32 = 150.0;
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScannerState {
+    Idle,
+    Scanning,
+    /// The last scan failed, see `failure`
+    Error,
+    /// Being switched on by `SCANNER_POWER_ON` for a scan
+    PoweringOn,
+    /// Switched off by `SCANNER_POWER_OFF` after being idle
+    Off,
+}
+
+/// Published whenever a device starts or finishes a scan.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScannerActivity {
+    pub device: String,
+    pub state: ScannerState,
+    /// The scan being taken, or the one that just finished
+    pub scan_id: i32,
+    /// Why the scan failed, e.g. JAMMED, when the state is ERROR
+    pub failure: Option<String>,
+    /// Scans queued behind this one
+    pub waiting: i32,
+    /// Who asked for the scan, so others know whose job has the device
+    pub started_by: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// Published as a scan's image comes off the device. For batches it's the
+/// sheet being fed, under the first scan's id.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScanProgress {
+    pub scan_id: i32,
+    /// 0 to 100
+    pub percent: f64,
+}
 
 #[derive(Debug, Clone, SimpleObject)]
 pub struct ScannerInfo {
-    name: String,
-    description: String,
+    pub name: String,
+    pub description: String,
+    /// Whether `--source` offers a document feeder
+    pub has_feeder: bool,
+    /// Whether the feeder has paper in it, for backends with a sensor for
+    /// it. Read when the scanners were last listed.
+    pub paper_loaded: Option<bool>,
+    /// Seen before but not in the latest listing, e.g. switched off or
+    /// unplugged, or listed since startup only from what was stored
+    pub offline: bool,
+    pub last_seen_at: DateTime<Utc>,
+    /// Read when listed, and stored so an offline device's options can
+    /// still be shown
+    #[graphql(skip)]
+    pub options: Vec<ScannerOption>,
+}
+
+/// Published when a refresh finds devices plugged in or gone.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScannersChanged {
+    pub added: Vec<ScannerInfo>,
+    /// Names of the devices no longer listed
+    pub removed: Vec<String>,
+    /// Every device listed now
+    pub scanners: Vec<ScannerInfo>,
+    pub at: DateTime<Utc>,
+}
+
+/// Sensor options backends use to report paper in the feeder.
+const PAPER_SENSORS: &[&str] = &[
+    "page-loaded",
+    "paper-loaded",
+    "adf-loaded",
+    "document-loaded",
+    "paper-in",
+];
+
+/// Reads feeder presence and paper status from a device's `scanimage -A`
+/// listing, e.g. `--source Flatbed|ADF Duplex [Flatbed]` and
+/// `--page-loaded[=(yes|no)] [no] [hardware]`.
+fn feeder_status(options: &str) -> (bool, Option<bool>) {
+    let source = Regex::new(r"^\s*--source\s+(.*?)\s+\[").unwrap();
+    let sensor = Regex::new(r"^\s*--([a-z-]+)\[=\(yes\|no\)\]\s+\[(yes|no)\]").unwrap();
+
+    let mut has_feeder = false;
+    let mut paper_loaded = None;
+    for line in options.lines() {
+        if let Some(captures) = source.captures(line) {
+            has_feeder = captures[1].split('|').any(|choice| {
+                let choice = choice.to_lowercase();
+                choice.contains("adf") || choice.contains("feeder") || choice.contains("duplex")
+            });
+        } else if let Some(captures) = sensor.captures(line) {
+            if PAPER_SENSORS.contains(&&captures[1]) {
+                paper_loaded = Some(&captures[2] == "yes");
+            }
+        }
+    }
+    (has_feeder, paper_loaded)
+}
+
+/// Whether `scanimage` can be run at all.
+pub fn scanimage_installed() -> bool {
+    !matches!(
+        std::process::Command::new("scanimage").arg("--version").output(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound
+    )
+}
+
+/// Times scanimage is run for a scan before it's given up on as a dead letter.
+const SCAN_ATTEMPTS: i32 = 3;
+
+/// Resolution of preview scans, or the lowest the device offers above it.
+const PREVIEW_DPI: f64 = 75.0;
+/// Preview scans older than this are deleted when the next is taken.
+const PREVIEW_KEEP: Duration = Duration::from_secs(60 * 60);
+
+/// How often a batch's directory is checked for finished pages.
+const BATCH_POLL: Duration = Duration::from_millis(500);
+
+/// Reads scanimage's stderr, publishing `Progress: 42.5%` lines from
+/// `--progress` as whole percents change. Returns the rest of what it said.
+async fn read_progress(mut stderr: ChildStderr, scan_id: i32) -> String {
+    let progress = Regex::new(r"^Progress: ([\d.]+)%$").unwrap();
+    let mut said = Vec::new();
+    let mut line = Vec::new();
+    let mut last = None;
+    let mut buf = [0; 1024];
+    while let Ok(read @ 1..) = stderr.read(&mut buf).await {
+        // Progress lines end in \r so they overwrite each other on a terminal
+        for &byte in &buf[..read] {
+            if byte != b'\r' && byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+            line.clear();
+            match progress
+                .captures(&text)
+                .and_then(|c| c[1].parse::<f64>().ok())
+            {
+                Some(percent) if last != Some(percent.floor()) => {
+                    last = Some(percent.floor());
+                    SimpleBroker::publish(ScanProgress { scan_id, percent });
+                }
+                Some(_) => {}
+                None if !text.is_empty() => said.push(text),
+                None => {}
+            }
+        }
+    }
+    said.push(String::from_utf8_lossy(&line).trim().to_string());
+    said.join("\n").trim().to_string()
+}
+
+/// SANE statuses scanimage exits with when the paper, not the device, is the
+/// problem. Retrying these just repeats the error.
+fn paper_failure(exit_code: i32) -> Option<&'static str> {
+    match exit_code {
+        6 => Some("JAMMED"),
+        7 => Some("NO_DOCS"),
+        8 => Some("COVER_OPEN"),
+        _ => None,
+    }
+}
+
+/// Adds a finished scan to the activity feed.
+fn record_completed(scan: &Scan, pool: &r2d2::Pool<DuckdbConnectionManager>) {
+    if let Err(e) = Activity::record(
+        ActivityKind::ScanCompleted,
+        scan.group.as_ref().map(|group| group.id),
+        scan.id,
+        scan.started_by.as_deref(),
+        Some(&scan.scanner),
+        pool,
+    ) {
+        println!("Failed to record scan {:?} in activity: {}", scan.id, e);
+    }
+}
+
+/// Gives the scan a file under `scans/` that doesn't exist yet and saves it.
+/// Rescans keep their original's base name with a counter on the end.
+fn next_scan_path(
+    scan_id: i32,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Scan {
+    let mut scan = Scan::load(scan_id, pool).unwrap();
+
+    // Generate a unique filename that doesn't exist on disk
+    let mut counter = 0;
+    let mut file_path;
+
+    // For rescans, we want to keep the same base name but modify the suffix
+    // so we check if this is a rescan by looking at the original_path
+    let base_name = if let Some(original_path) = scan.original_path.as_ref() {
+        // This is a rescan, get the original file base name
+        let original_path_str = original_path.as_relative_path();
+        let filename = original_path_str.split('/').next_back().unwrap();
+        let parts: Vec<&str> = filename.split('.').collect();
+        parts.first().unwrap().to_string()
+    } else {
+        // This is a new scan, use the scan ID as the base name
+        scan_id.to_string()
+    };
+
+    loop {
+        let filename = if counter == 0 {
+            format!("{}.png", base_name)
+        } else {
+            format!("{}_{}.png", base_name, counter)
+        };
+
+        file_path = Path::new("scans")
+            .join(&filename)
+            .as_os_str()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // Check if the file exists on disk
+        let full_path = Path::new(&assets_dir.0).join(&file_path);
+        if !full_path.exists() {
+            break;
+        }
+
+        // If it exists, increment counter and try again
+        counter += 1;
+    }
+
+    // If this is the first scan, set the original_path
+    if scan.original_path.is_none() {
+        scan.original_path = Some(file_path.clone().into());
+    }
+
+    // Update the path for the current scan
+    scan.path = file_path.into();
+    scan.save(pool).unwrap();
+    scan
+}
+
+/// A new scan for the next page of a batch, in the same group as its
+/// first page, with its file allocated.
+fn next_batch_scan(
+    first: &Scan,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Scan {
+    let scan = Scan::create_pending(
+        &first.scanner,
+        &first.scan_parameters,
+        first.group.as_ref().map(|group| group.id),
+        first.started_by.clone(),
+        pool,
+        assets_dir,
+    )
+    .unwrap();
+    next_scan_path(scan.id.unwrap(), pool, assets_dir)
+}
+
+/// Marks a page of a batch complete, or failed with `failure`, and
+/// announces it. Returns its scan id.
+fn finish_batch_page(
+    mut scan: Scan,
+    failure: Option<&str>,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    landed: &(dyn Fn(i32) + Send + Sync),
+) -> i32 {
+    scan.status = match failure {
+        None => "COMPLETE",
+        Some(_) => "FAILED",
+    }
+    .to_string();
+    scan.save(pool).unwrap();
+    Scan::set_failure(scan.id.unwrap(), failure, pool).unwrap();
+    if failure.is_none() {
+        record_completed(&scan, pool);
+    }
+    landed(scan.id.unwrap());
+    scan.id.unwrap()
+}
+
+/// Where scanimage writes a batch's pages, removed with whatever is left in
+/// it once the batch is done or cancelled.
+struct BatchDir(PathBuf);
+
+impl Drop for BatchDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Whether the scan arguments pick a document feeder as the source, e.g.
+/// `--source ADF Duplex`.
+pub fn feeder_source(scan_arguments: &HashMap<String, String>) -> bool {
+    scan_arguments
+        .iter()
+        .filter(|(key, _)| key.trim_start_matches('-') == "source")
+        .any(|(_, source)| {
+            let source = source.to_lowercase();
+            source.contains("adf") || source.contains("feeder") || source.contains("duplex")
+        })
 }
 
 // Define the common trait for scanner managers
@@ -38,6 +393,36 @@ pub trait ScannerProvider {
         pool: &r2d2::Pool<DuckdbConnectionManager>,
         assets_dir: &AssetsDir,
     ) -> i32;
+
+    /// Scans every sheet in the feeder, the first into `scan_id` and the
+    /// rest into new scans in the same group, calling `landed` with each
+    /// as it's saved. Returns them all in page order. Backends that can't
+    /// batch take one page.
+    async fn complete_batch(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        let scan_id = self
+            .complete_scan(scan_id, name, scan_arguments, pool, assets_dir)
+            .await;
+        landed(scan_id);
+        vec![scan_id]
+    }
+
+    /// Scans a page to `path` without recording a scan, once, for a quick
+    /// look before the real one.
+    async fn preview_scan(
+        &self,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        path: &AssetPath,
+        assets_dir: &AssetsDir,
+    ) -> Result<(), String>;
 }
 
 // Define an enum that can hold either scanner implementation
@@ -45,6 +430,7 @@ pub trait ScannerProvider {
 pub enum ScannerManagerKind {
     Real(RealScannerManager),
     Mock(MockScannerManager),
+    Escl(EsclScannerManager),
 }
 
 // Real scanner implementation
@@ -52,6 +438,10 @@ pub enum ScannerManagerKind {
 pub struct RealScannerManager {
     cached: Arc<Mutex<Vec<ScannerInfo>>>,
     last_refreshed: Arc<Mutex<Instant>>,
+    // Probe the device but write a test page instead of scanning
+    simulate: bool,
+    // Set when scanimage couldn't be found, cleared when it runs again
+    scanimage_missing: Arc<AtomicBool>,
 }
 
 // Mock scanner implementation
@@ -59,6 +449,15 @@ pub struct RealScannerManager {
 pub struct MockScannerManager {
     cached: Arc<Mutex<Vec<ScannerInfo>>>,
     last_refreshed: Arc<Mutex<Instant>>,
+    // How long each scan pretends to take
+    delay: Duration,
+}
+
+// eSCL (AirScan) network scanner implementation
+#[derive(Clone)]
+pub struct EsclScannerManager {
+    cached: Arc<Mutex<Vec<ScannerInfo>>>,
+    last_refreshed: Arc<Mutex<Instant>>,
 }
 
 // Implementation for real scanners
@@ -70,21 +469,67 @@ impl ScannerProvider for RealScannerManager {
 
     async fn force_list_scanners(&self) -> Vec<ScannerInfo> {
         let re = Regex::new(r"device `([^']*)' is a (.*)$").unwrap();
-        let output = Command::new("scanimage")
+        let output = match Command::new("scanimage")
             .arg("--list-devices")
             .output()
             .await
-            .unwrap();
+        {
+            Ok(output) => output,
+            Err(e) => {
+                if e.kind() == io::ErrorKind::NotFound {
+                    self.scanimage_missing.store(true, Ordering::Relaxed);
+                    println!("{}", SCANIMAGE_MISSING);
+                } else {
+                    println!("Failed to list scanners: {}", e);
+                }
+                *self.cached.lock().await = vec![];
+                *self.last_refreshed.lock().await = Instant::now();
+                return vec![];
+            }
+        };
+        self.scanimage_missing.store(false, Ordering::Relaxed);
 
-        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
 
         let mut results = vec![];
 
+        let previous = self.cached.lock().await.clone();
         for line in stdout.lines() {
             if let Some(captures) = re.captures(line) {
+                let name = captures[1].to_string();
+                let options = Command::new("scanimage")
+                    .arg("-d")
+                    .arg(&name)
+                    .arg("-A")
+                    .output()
+                    .await;
+                let (has_feeder, paper_loaded, options) = match options {
+                    Ok(output) if output.status.success() => {
+                        let listing = String::from_utf8_lossy(&output.stdout);
+                        let (has_feeder, paper_loaded) = feeder_status(&listing);
+                        (has_feeder, paper_loaded, scanner_options::parse(&listing))
+                    }
+                    // Busy with a scan, most likely, so keep what we knew
+                    _ => previous
+                        .iter()
+                        .find(|scanner| scanner.name == name)
+                        .map(|scanner| {
+                            (
+                                scanner.has_feeder,
+                                scanner.paper_loaded,
+                                scanner.options.clone(),
+                            )
+                        })
+                        .unwrap_or((false, None, vec![])),
+                };
                 results.push(ScannerInfo {
-                    name: captures[1].to_string(),
+                    name,
                     description: captures[2].to_string(),
+                    has_feeder,
+                    paper_loaded,
+                    offline: false,
+                    last_seen_at: Utc::now(),
+                    options,
                 });
             }
         }
@@ -113,60 +558,73 @@ impl ScannerProvider for RealScannerManager {
         pool: &r2d2::Pool<DuckdbConnectionManager>,
         assets_dir: &AssetsDir,
     ) -> i32 {
-        let mut scan = Scan::load(scan_id, pool).unwrap();
+        let scan = next_scan_path(scan_id, pool, assets_dir);
 
-        // Generate a unique filename that doesn't exist on disk
-        let mut counter = 0;
-        let mut file_path;
-
-        // For rescans, we want to keep the same base name but modify the suffix
-        // so we check if this is a rescan by looking at the original_path
-        let base_name = if scan.original_path.is_some() {
-            // This is a rescan, get the original file base name
-            let original_path = scan.original_path.as_ref().unwrap();
-            let original_path_str = original_path.as_relative_path();
-            let filename = original_path_str.split('/').last().unwrap();
-            let parts: Vec<&str> = filename.split('.').collect();
-            parts.get(0).unwrap().to_string()
+        if self.simulate {
+            Self::do_simulated_scan(scan, name, scan_arguments, pool, assets_dir).await
         } else {
-            // This is a new scan, use the scan ID as the base name
-            scan_id.to_string()
-        };
-
-        loop {
-            let filename = if counter == 0 {
-                format!("{}.png", base_name)
-            } else {
-                format!("{}_{}.png", base_name, counter)
-            };
-
-            file_path = Path::new("scans")
-                .join(&filename)
-                .as_os_str()
-                .to_str()
-                .unwrap()
-                .to_string();
-
-            // Check if the file exists on disk
-            let full_path = Path::new(&assets_dir.0).join(&file_path);
-            if !full_path.exists() {
-                break;
-            }
-
-            // If it exists, increment counter and try again
-            counter += 1;
+            Self::do_scan(scan, name, scan_arguments, pool, assets_dir).await
         }
+    }
 
-        // If this is the first scan, set the original_path
-        if scan.original_path.is_none() {
-            scan.original_path = Some(file_path.clone().into());
+    async fn complete_batch(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        // A simulated scan has no paper to run out of
+        if self.simulate {
+            let scan_id = self
+                .complete_scan(scan_id, name, scan_arguments, pool, assets_dir)
+                .await;
+            landed(scan_id);
+            return vec![scan_id];
         }
+        let scan = next_scan_path(scan_id, pool, assets_dir);
 
-        // Update the path for the current scan
-        scan.path = file_path.into();
-        scan.save(pool).unwrap();
+        Self::do_batch_scan(scan, name, scan_arguments, pool, assets_dir, landed).await
+    }
 
-        Self::do_scan(scan, name, scan_arguments, pool, assets_dir).await
+    async fn preview_scan(
+        &self,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        path: &AssetPath,
+        assets_dir: &AssetsDir,
+    ) -> Result<(), String> {
+        if self.simulate {
+            let page = test_page::render(
+                (8.5 * PREVIEW_DPI) as u32,
+                (11.0 * PREVIEW_DPI) as u32,
+                &["preview".to_string(), name.to_string()],
+            );
+            return assets_dir
+                .write_image(path, &DynamicImage::ImageLuma8(page))
+                .map_err(|e| e.to_string());
+        }
+        let captured = assets_dir.capture_path(path);
+        let output = Command::new("scanimage")
+            .arg("--format")
+            .arg("png")
+            .arg("-d")
+            .arg(name)
+            .args(scan_arguments.iter().flat_map(|(k, v)| vec![k, v]))
+            .arg("-o")
+            .arg(&captured)
+            .output()
+            .await
+            .map_err(|e| format!("could not run scanimage: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "preview scan failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        assets_dir.store(path, &captured).map_err(|e| e.to_string())
     }
 }
 
@@ -181,6 +639,11 @@ impl ScannerProvider for MockScannerManager {
         let mock_scanner = ScannerInfo {
             name: MOCK_SCANNER_NAME.to_string(),
             description: MOCK_SCANNER_DESCRIPTION.to_string(),
+            has_feeder: true,
+            paper_loaded: Some(true),
+            offline: false,
+            last_seen_at: Utc::now(),
+            options: scanner_options::parse(MOCK_SCANNER_OPTIONS),
         };
         let results = vec![mock_scanner];
 
@@ -202,63 +665,219 @@ impl ScannerProvider for MockScannerManager {
     async fn complete_scan(
         &self,
         scan_id: i32,
-        name: &str,
-        scan_arguments: HashMap<String, String>,
+        _name: &str,
+        _scan_arguments: HashMap<String, String>,
         pool: &r2d2::Pool<DuckdbConnectionManager>,
         assets_dir: &AssetsDir,
     ) -> i32 {
-        let mut scan = Scan::load(scan_id, pool).unwrap();
+        let scan = next_scan_path(scan_id, pool, assets_dir);
 
-        // Generate a unique filename that doesn't exist on disk
-        let mut counter = 0;
-        let mut file_path;
-
-        // For rescans, we want to keep the same base name but modify the suffix
-        let base_name = if scan.original_path.is_some() {
-            // This is a rescan, get the original file base name
-            let original_path = scan.original_path.as_ref().unwrap();
-            let original_path_str = original_path.as_relative_path();
-            let filename = original_path_str.split('/').last().unwrap();
-            let parts: Vec<&str> = filename.split('.').collect();
-            parts.get(0).unwrap().to_string()
-        } else {
-            // This is a new scan, use the scan ID as the base name
-            scan_id.to_string()
-        };
+        Self::do_mock_scan(scan, self.delay, pool, assets_dir).await
+    }
 
-        loop {
-            let filename = if counter == 0 {
-                format!("{}.png", base_name)
+    async fn complete_batch(
+        &self,
+        scan_id: i32,
+        _name: &str,
+        _scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        let first = next_scan_path(scan_id, pool, assets_dir);
+        let mut scan_ids = vec![];
+        for page in 0..MOCK_BATCH_PAGES {
+            let scan_id = if page == 0 {
+                Self::do_mock_scan(first.clone(), self.delay, pool, assets_dir).await
             } else {
-                format!("{}_{}.png", base_name, counter)
+                // As with a real feeder, later pages get a scan once they land
+                tokio::time::sleep(self.delay).await;
+                let scan = next_batch_scan(&first, pool, assets_dir);
+                Self::do_mock_scan(scan, Duration::ZERO, pool, assets_dir).await
             };
+            landed(scan_id);
+            scan_ids.push(scan_id);
+        }
+        scan_ids
+    }
 
-            file_path = Path::new("scans")
-                .join(&filename)
-                .as_os_str()
-                .to_str()
-                .unwrap()
-                .to_string();
+    async fn preview_scan(
+        &self,
+        _name: &str,
+        _scan_arguments: HashMap<String, String>,
+        path: &AssetPath,
+        assets_dir: &AssetsDir,
+    ) -> Result<(), String> {
+        tokio::time::sleep(self.delay).await;
+        let page = test_page::render(
+            (8.5 * PREVIEW_DPI) as u32,
+            (11.0 * PREVIEW_DPI) as u32,
+            &["preview".to_string(), MOCK_SCANNER_NAME.to_string()],
+        );
+        assets_dir
+            .write_image(path, &DynamicImage::ImageLuma8(page))
+            .map_err(|e| e.to_string())
+    }
+}
 
-            // Check if the file exists on disk
-            let full_path = Path::new(&assets_dir.0).join(&file_path);
-            if !full_path.exists() {
-                break;
+// Implementation for eSCL scanners
+#[async_trait]
+impl ScannerProvider for EsclScannerManager {
+    async fn last_refreshed(&self) -> Instant {
+        *self.last_refreshed.lock().await
+    }
+
+    async fn force_list_scanners(&self) -> Vec<ScannerInfo> {
+        let previous = self.cached.lock().await.clone();
+        let results = tokio::task::spawn_blocking(move || {
+            let mut devices = escl::from_env();
+            match escl::discover() {
+                Ok(found) => devices.extend(
+                    found
+                        .into_iter()
+                        .filter(|device| !devices.iter().any(|known| known.url == device.url))
+                        .collect::<Vec<_>>(),
+                ),
+                Err(e) => println!("Failed to discover eSCL scanners: {}", e),
             }
 
-            counter += 1;
+            let mut results = vec![];
+            for device in devices {
+                let name = device.name();
+                let (has_feeder, options) = match escl::Capabilities::load(&device.url) {
+                    Ok(capabilities) => (capabilities.has_feeder(), capabilities.options()),
+                    Err(e) => {
+                        println!("Failed to read capabilities of {}: {}", device.url, e);
+                        continue;
+                    }
+                };
+                let paper_loaded = match escl::Status::load(&device.url) {
+                    Ok(status) => status.paper_loaded(),
+                    // Busy with a scan, most likely, so keep what we knew
+                    Err(_) => previous
+                        .iter()
+                        .find(|scanner| scanner.name == name)
+                        .and_then(|scanner| scanner.paper_loaded),
+                };
+                results.push(ScannerInfo {
+                    name,
+                    description: device.model,
+                    has_feeder,
+                    paper_loaded,
+                    offline: false,
+                    last_seen_at: Utc::now(),
+                    options,
+                });
+            }
+            results
+        })
+        .await
+        .unwrap();
+
+        *self.cached.lock().await = results.clone();
+        *self.last_refreshed.lock().await = Instant::now();
+
+        results
+    }
+
+    async fn list_scanners(&self) -> Vec<ScannerInfo> {
+        let last_refreshed = *self.last_refreshed.lock().await;
+        if last_refreshed.elapsed() > Duration::from_secs(60 * 10) {
+            self.force_list_scanners().await
+        } else {
+            self.cached.lock().await.clone()
         }
+    }
+
+    async fn complete_scan(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> i32 {
+        let scan = next_scan_path(scan_id, pool, assets_dir);
+
+        Self::do_scan(scan, name, scan_arguments, pool, assets_dir).await
+    }
+
+    async fn complete_batch(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        let first = next_scan_path(scan_id, pool, assets_dir);
+        let Some(url) = escl::device_url(name).map(str::to_string) else {
+            println!("Not an eSCL scanner: {}", name);
+            return vec![finish_batch_page(
+                first,
+                Some("UNKNOWN_DEVICE"),
+                pool,
+                landed,
+            )];
+        };
 
-        // If this is the first scan, set the original_path
-        if scan.original_path.is_none() {
-            scan.original_path = Some(file_path.clone().into());
+        // Pages are saved here as the device sends them
+        let (pages, mut documents) = tokio::sync::mpsc::unbounded_channel();
+        let cancel = CancelOnDrop::default();
+        let job = cancel.0.clone();
+        let scanning = tokio::task::spawn_blocking(move || {
+            escl::scan_pages(&url, &scan_arguments, None, &job, |document| {
+                pages.send(document).ok();
+            })
+        });
+
+        let mut scan_ids = vec![];
+        let mut next = Some(first.clone());
+        while let Some(document) = documents.recv().await {
+            let scan = next
+                .take()
+                .unwrap_or_else(|| next_batch_scan(&first, pool, assets_dir));
+            let stored = image::load_from_memory(&document)
+                .and_then(|page| assets_dir.write_image(&scan.path, &page));
+            let failure = stored.err().map(|e| {
+                println!("Failed to store scan: {}", e);
+                "IMAGE_FAILED"
+            });
+            scan_ids.push(finish_batch_page(scan, failure, pool, landed));
         }
 
-        // Update the path for the current scan
-        scan.path = file_path.into();
-        scan.save(pool).unwrap();
+        // As for scanimage batches, a problem gets a failed scan for the
+        // page that didn't make it
+        if let Err(e) = scanning.await.unwrap() {
+            println!("{}", e);
+            let scan = next
+                .take()
+                .unwrap_or_else(|| next_batch_scan(&first, pool, assets_dir));
+            scan_ids.push(finish_batch_page(scan, Some(&e.code()), pool, landed));
+        }
+        scan_ids
+    }
 
-        Self::do_mock_scan(scan, pool, assets_dir).await
+    async fn preview_scan(
+        &self,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        path: &AssetPath,
+        assets_dir: &AssetsDir,
+    ) -> Result<(), String> {
+        let url = escl::device_url(name)
+            .ok_or_else(|| format!("{} is not an eSCL scanner", name))?
+            .to_string();
+        let cancel = CancelOnDrop::default();
+        let job = cancel.0.clone();
+        let document = tokio::task::spawn_blocking(move || escl::scan(&url, &scan_arguments, &job))
+            .await
+            .unwrap()
+            .map_err(|e| format!("preview scan failed: {}", e))?;
+        image::load_from_memory(&document)
+            .and_then(|page| assets_dir.write_image(path, &page))
+            .map_err(|e| e.to_string())
     }
 }
 
@@ -269,6 +888,7 @@ impl ScannerProvider for ScannerManagerKind {
         match self {
             ScannerManagerKind::Real(real) => real.last_refreshed().await,
             ScannerManagerKind::Mock(mock) => mock.last_refreshed().await,
+            ScannerManagerKind::Escl(escl) => escl.last_refreshed().await,
         }
     }
 
@@ -276,6 +896,7 @@ impl ScannerProvider for ScannerManagerKind {
         match self {
             ScannerManagerKind::Real(real) => real.force_list_scanners().await,
             ScannerManagerKind::Mock(mock) => mock.force_list_scanners().await,
+            ScannerManagerKind::Escl(escl) => escl.force_list_scanners().await,
         }
     }
 
@@ -283,6 +904,7 @@ impl ScannerProvider for ScannerManagerKind {
         match self {
             ScannerManagerKind::Real(real) => real.list_scanners().await,
             ScannerManagerKind::Mock(mock) => mock.list_scanners().await,
+            ScannerManagerKind::Escl(escl) => escl.list_scanners().await,
         }
     }
 
@@ -303,21 +925,132 @@ impl ScannerProvider for ScannerManagerKind {
                 mock.complete_scan(scan_id, name, scan_arguments, pool, assets_dir)
                     .await
             }
+            ScannerManagerKind::Escl(escl) => {
+                escl.complete_scan(scan_id, name, scan_arguments, pool, assets_dir)
+                    .await
+            }
+        }
+    }
+
+    async fn complete_batch(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        match self {
+            ScannerManagerKind::Real(real) => {
+                real.complete_batch(scan_id, name, scan_arguments, pool, assets_dir, landed)
+                    .await
+            }
+            ScannerManagerKind::Mock(mock) => {
+                mock.complete_batch(scan_id, name, scan_arguments, pool, assets_dir, landed)
+                    .await
+            }
+            ScannerManagerKind::Escl(escl) => {
+                escl.complete_batch(scan_id, name, scan_arguments, pool, assets_dir, landed)
+                    .await
+            }
+        }
+    }
+
+    async fn preview_scan(
+        &self,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        path: &AssetPath,
+        assets_dir: &AssetsDir,
+    ) -> Result<(), String> {
+        match self {
+            ScannerManagerKind::Real(real) => {
+                real.preview_scan(name, scan_arguments, path, assets_dir)
+                    .await
+            }
+            ScannerManagerKind::Mock(mock) => {
+                mock.preview_scan(name, scan_arguments, path, assets_dir)
+                    .await
+            }
+            ScannerManagerKind::Escl(escl) => {
+                escl.preview_scan(name, scan_arguments, path, assets_dir)
+                    .await
+            }
         }
     }
 }
 
 // Implementation for RealScannerManager
 impl RealScannerManager {
-    pub fn new() -> Self {
+    pub fn new(simulate: bool) -> Self {
         Self {
             cached: Arc::new(Mutex::new(vec![])),
             last_refreshed: Arc::new(Mutex::new(
                 Instant::now() - SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
             )),
+            simulate,
+            scanimage_missing: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Checks the device answers and accepts the scan options, without
+    /// feeding paper, then saves a watermarked test page as the scan.
+    async fn do_simulated_scan(
+        mut scan: Scan,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> i32 {
+        // -A lists the device's options after applying ours, so a bad option
+        // or an unreachable device fails just like a real scan would
+        let probe = Command::new("scanimage")
+            .arg("-d")
+            .arg(name)
+            .args(scan_arguments.iter().flat_map(|(k, v)| vec![k, v]))
+            .arg("-A")
+            .kill_on_drop(true)
+            .output()
+            .await;
+
+        scan.status = match probe {
+            Ok(output) if output.status.success() => {
+                let dpi = scan.resolution().unwrap_or(SIMULATED_DPI);
+                let page = test_page::render(
+                    (8.5 * dpi) as u32,
+                    (11.0 * dpi) as u32,
+                    &[
+                        format!("scan #{}", scan.id.unwrap()),
+                        name.to_string(),
+                        locale::format_datetime(Utc::now()),
+                    ],
+                );
+                match assets_dir.write_image(&scan.path, &DynamicImage::ImageLuma8(page)) {
+                    Ok(_) => "COMPLETE".to_string(),
+                    Err(e) => {
+                        println!("Failed to write simulated scan: {}", e);
+                        "FAILED".to_string()
+                    }
+                }
+            }
+            Ok(output) => {
+                println!(
+                    "Simulated scan probe failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                "FAILED".to_string()
+            }
+            Err(e) => {
+                println!("Simulated scan probe failed: {}", e);
+                "FAILED".to_string()
+            }
+        };
+
+        scan.save(pool).unwrap();
+        scan.id.unwrap()
+    }
+
     async fn do_scan(
         mut scan: Scan,
         name: &str,
@@ -325,8 +1058,9 @@ impl RealScannerManager {
         pool: &r2d2::Pool<DuckdbConnectionManager>,
         assets_dir: &AssetsDir,
     ) -> i32 {
-        let scan_path = scan.path.as_disk_path(&assets_dir.0);
+        let scan_path = assets_dir.capture_path(&scan.path);
         let mut output_status;
+        let mut error;
         let mut attempts = 0;
 
         loop {
@@ -337,72 +1071,239 @@ impl RealScannerManager {
                 Command::new("scanimage")
                     .arg("--format")
                     .arg("png")
+                    .arg("--progress")
                     .arg("-d")
                     .arg(name)
                     .args(scan_arguments.iter().flat_map(|(k, v)| vec![k, v]))
                     .arg("-o")
                     .arg(scan_path.clone())
             );
-            let output = Command::new("scanimage")
+            let mut child = match Command::new("scanimage")
                 .arg("--format")
                 .arg("png")
+                .arg("--progress")
                 .arg("-d")
                 .arg(name)
                 .args(scan_arguments.iter().flat_map(|(k, v)| vec![k, v]))
                 .arg("-o")
                 .arg(scan_path.clone())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
                 .spawn()
-                .ok()
-                .unwrap()
-                .wait_with_output()
-                .await
-                .unwrap();
+            {
+                Ok(child) => child,
+                Err(e) => {
+                    println!("Failed to run scanimage: {}", e);
+                    let failure = if e.kind() == io::ErrorKind::NotFound {
+                        "SCANIMAGE_MISSING"
+                    } else {
+                        "SCANIMAGE_FAILED"
+                    };
+                    scan.status = "FAILED".to_string();
+                    scan.save(pool).unwrap();
+                    Scan::set_failure(scan.id.unwrap(), Some(failure), pool).unwrap();
+                    return scan.id.unwrap();
+                }
+            };
+            let stderr = child.stderr.take().unwrap();
+            let (status, said) =
+                tokio::join!(child.wait(), read_progress(stderr, scan.id.unwrap()));
+            let status = status.unwrap();
 
-            output_status = output.status.code().unwrap();
+            output_status = status.code().unwrap();
+            error = said;
 
-            println!(
-                "{}, {:?}, {:?}",
-                output.status, output.stdout, output.stderr
-            );
+            println!("{}, {}", status, error);
 
-            if (output_status == 0) || (attempts >= 3) {
+            if (output_status == 0)
+                || (attempts >= SCAN_ATTEMPTS)
+                || paper_failure(output_status).is_some()
+            {
                 break;
             }
 
             println!("Retrying scan");
         }
 
-        if output_status != 0 {
+        if output_status == 0 {
+            if let Err(e) = assets_dir.store(&scan.path, &scan_path) {
+                println!("Failed to store scan: {}", e);
+                output_status = -1;
+            }
+        }
+
+        let failure = if output_status != 0 {
             scan.status = "FAILED".to_string();
+            Some(
+                paper_failure(output_status)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("EXIT_{}", output_status)),
+            )
         } else {
             scan.status = "COMPLETE".to_string();
-        }
+            None
+        };
 
         scan.save(pool).unwrap();
+        Scan::set_failure(scan.id.unwrap(), failure.as_deref(), pool).unwrap();
+        if failure.is_none() {
+            record_completed(&scan, pool);
+        }
+
+        // Paper problems need the operator, not another run
+        if let Some(failure) = &failure {
+            if attempts >= SCAN_ATTEMPTS && paper_failure(output_status).is_none() {
+                if let Err(e) =
+                    DeadLetter::create(&scan, attempts, output_status, failure, &error, pool)
+                {
+                    println!("Failed to record dead letter for scan {:?}: {}", scan.id, e);
+                }
+            }
+        }
         scan.id.unwrap()
     }
 }
 
+impl RealScannerManager {
+    /// Runs `scanimage --batch` into a private directory and saves each
+    /// page as it's finished. scanimage writes a page to a `.part` file and
+    /// renames it when done, so a page appearing under its own name is
+    /// complete. A paper problem ends the batch with a failed scan for the
+    /// page that didn't make it, except for running out of paper after the
+    /// first page, which is how batches normally end. Batches aren't retried,
+    /// since the sheets already fed have gone through.
+    async fn do_batch_scan(
+        first: Scan,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        let dir = BatchDir(env::temp_dir().join(format!(
+            "scanserv-batch-{}-{}",
+            std::process::id(),
+            first.id.unwrap()
+        )));
+        let child = fs::create_dir_all(&dir.0).and_then(|_| {
+            Command::new("scanimage")
+                .arg("--format")
+                .arg("png")
+                .arg("--progress")
+                .arg("-d")
+                .arg(name)
+                .args(scan_arguments.iter().flat_map(|(k, v)| vec![k, v]))
+                .arg(format!("--batch={}", dir.0.join("page-%d.png").display()))
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+        });
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                println!("Failed to run scanimage: {}", e);
+                let failure = if e.kind() == io::ErrorKind::NotFound {
+                    "SCANIMAGE_MISSING"
+                } else {
+                    "SCANIMAGE_FAILED"
+                };
+                let mut scan = first;
+                scan.status = "FAILED".to_string();
+                scan.save(pool).unwrap();
+                Scan::set_failure(scan.id.unwrap(), Some(failure), pool).unwrap();
+                return vec![scan.id.unwrap()];
+            }
+        };
+
+        let said = tokio::spawn(read_progress(
+            child.stderr.take().unwrap(),
+            first.id.unwrap(),
+        ));
+
+        let mut scan_ids = vec![];
+        // The scan waiting for the next page; later pages get theirs as they land
+        let mut next = Some(first.clone());
+        let exited = loop {
+            // Checked before looking for pages, so none written just before
+            // scanimage exits are missed
+            let exited = child.try_wait();
+            loop {
+                let captured = dir.0.join(format!("page-{}.png", scan_ids.len() + 1));
+                if !captured.exists() {
+                    break;
+                }
+                let scan = next
+                    .take()
+                    .unwrap_or_else(|| next_batch_scan(&first, pool, assets_dir));
+                let stored = fs::read(&captured)
+                    .and_then(|contents| assets_dir.write(&scan.path, &contents));
+                fs::remove_file(&captured).ok();
+                let failure = stored.err().map(|e| {
+                    println!("Failed to store scan: {}", e);
+                    "EXIT_-1"
+                });
+                scan_ids.push(finish_batch_page(scan, failure, pool, landed));
+            }
+            match exited {
+                Ok(Some(_)) | Err(_) => break exited,
+                Ok(None) => tokio::time::sleep(BATCH_POLL).await,
+            }
+        };
+
+        let output_status = match exited {
+            Ok(Some(status)) => status.code().unwrap_or(-1),
+            _ => -1,
+        };
+        if let (Ok(status), Ok(said)) = (child.wait().await, said.await) {
+            println!("{}, {}", status, said);
+        }
+        let ended_normally = output_status == 0 || paper_failure(output_status) == Some("NO_DOCS");
+        if !ended_normally || scan_ids.is_empty() {
+            let failure = match output_status {
+                0 => "NO_DOCS".to_string(),
+                status => paper_failure(status)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("EXIT_{}", status)),
+            };
+            let scan = next
+                .take()
+                .unwrap_or_else(|| next_batch_scan(&first, pool, assets_dir));
+            scan_ids.push(finish_batch_page(scan, Some(&failure), pool, landed));
+        }
+        scan_ids
+    }
+}
+
 // Implementation for MockScannerManager
 impl MockScannerManager {
-    pub fn new() -> Self {
+    pub fn new(delay: Duration) -> Self {
         Self {
             cached: Arc::new(Mutex::new(vec![])),
             last_refreshed: Arc::new(Mutex::new(
                 Instant::now() - SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
             )),
+            delay,
         }
     }
 
     async fn do_mock_scan(
         mut scan: Scan,
+        delay: Duration,
         pool: &r2d2::Pool<DuckdbConnectionManager>,
         assets_dir: &AssetsDir,
     ) -> i32 {
-        let scan_path = scan.path.as_disk_path(&assets_dir.0);
-
-        // Simulate scanning delay
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        // Simulate scanning delay, in tenths so there's progress to show
+        for tenth in 0..10 {
+            SimpleBroker::publish(ScanProgress {
+                scan_id: scan.id.unwrap(),
+                percent: f64::from(tenth * 10),
+            });
+            tokio::time::sleep(delay / 10).await;
+        }
+        SimpleBroker::publish(ScanProgress {
+            scan_id: scan.id.unwrap(),
+            percent: 100.0,
+        });
 
         // Get a random sample image from the mock_scanner_samples directory
         let mock_samples_dir = Path::new(&assets_dir.0).join("mock_scanner_samples");
@@ -414,24 +1315,25 @@ impl MockScannerManager {
                 if entries.is_empty() {
                     println!("Warning: No sample images found in {:?}", mock_samples_dir);
                     // Create an empty file as fallback
-                    fs::File::create(&scan_path).ok();
+                    assets_dir.write(&scan.path, &[]).ok();
                     Ok(())
                 } else {
                     // Select a random sample image
                     if let Some(entry) = entries.choose(&mut rand::thread_rng()) {
                         let sample_path = entry.path();
                         println!("Using mock sample: {:?}", sample_path);
-                        fs::copy(sample_path, &scan_path).map(|_| ())
+                        fs::read(sample_path)
+                            .and_then(|contents| assets_dir.write(&scan.path, &contents))
                     } else {
                         println!("Failed to select a random sample image");
-                        fs::File::create(&scan_path).map(|_| ())
+                        assets_dir.write(&scan.path, &[])
                     }
                 }
             }
             Err(e) => {
                 println!("Warning: Could not read mock samples directory: {:?}", e);
                 // Create an empty file as fallback
-                fs::File::create(&scan_path).map(|_| ())
+                assets_dir.write(&scan.path, &[])
             }
         };
 
@@ -442,6 +1344,107 @@ impl MockScannerManager {
         }
 
         scan.save(pool).unwrap();
+        if result.is_ok() {
+            record_completed(&scan, pool);
+        }
+        scan.id.unwrap()
+    }
+}
+
+/// Cancels the eSCL job a blocking task is running when the future waiting
+/// on it is dropped, as `cancel_scan` does. Dropping the future alone would
+/// leave the device scanning.
+#[derive(Default)]
+struct CancelOnDrop(escl::Cancel);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        // Does nothing once the job has finished
+        self.0.cancel();
+    }
+}
+
+// Implementation for EsclScannerManager
+impl EsclScannerManager {
+    pub fn new() -> Self {
+        Self {
+            cached: Arc::new(Mutex::new(vec![])),
+            last_refreshed: Arc::new(Mutex::new(
+                Instant::now() - SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
+            )),
+        }
+    }
+
+    /// Scans a page over eSCL and saves it as PNG, retrying like scanimage
+    /// runs are, and failing with the same codes for paper problems.
+    async fn do_scan(
+        mut scan: Scan,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> i32 {
+        let Some(url) = escl::device_url(name).map(str::to_string) else {
+            println!("Not an eSCL scanner: {}", name);
+            scan.status = "FAILED".to_string();
+            scan.save(pool).unwrap();
+            Scan::set_failure(scan.id.unwrap(), Some("UNKNOWN_DEVICE"), pool).unwrap();
+            return scan.id.unwrap();
+        };
+        let cancel = CancelOnDrop::default();
+        let mut attempts = 0;
+
+        let result = loop {
+            attempts += 1;
+
+            println!("Scanning from {} with {:?}", url, scan_arguments);
+            let (url, arguments, job) = (url.clone(), scan_arguments.clone(), cancel.0.clone());
+            let result = tokio::task::spawn_blocking(move || escl::scan(&url, &arguments, &job))
+                .await
+                .unwrap()
+                .and_then(|document| {
+                    let page = image::load_from_memory(&document)?;
+                    Ok(assets_dir.write_image(&scan.path, &page)?)
+                });
+
+            match &result {
+                Ok(_) => break result,
+                Err(e) => println!("{}", e),
+            }
+            if attempts >= SCAN_ATTEMPTS || matches!(result, Err(escl::ScanFailure::Paper(_))) {
+                break result;
+            }
+
+            println!("Retrying scan");
+        };
+
+        let failure = match &result {
+            Ok(_) => {
+                scan.status = "COMPLETE".to_string();
+                None
+            }
+            Err(e) => {
+                scan.status = "FAILED".to_string();
+                Some(e.code())
+            }
+        };
+
+        scan.save(pool).unwrap();
+        Scan::set_failure(scan.id.unwrap(), failure.as_deref(), pool).unwrap();
+        if failure.is_none() {
+            record_completed(&scan, pool);
+        }
+
+        // Paper problems need the operator, not another run
+        if let (Err(e), Some(failure)) = (&result, &failure) {
+            if attempts >= SCAN_ATTEMPTS && !matches!(e, escl::ScanFailure::Paper(_)) {
+                if let Err(e) =
+                    DeadLetter::create(&scan, attempts, e.status(), failure, &e.to_string(), pool)
+                {
+                    println!("Failed to record dead letter for scan {:?}: {}", scan.id, e);
+                }
+            }
+        }
         scan.id.unwrap()
     }
 }
@@ -449,12 +1452,26 @@ impl MockScannerManager {
 // For backward compatibility, maintain the old struct name but delegate to the new implementation
 pub struct ScannerManager {
     inner: ScannerManagerKind,
+    queue: ScanQueue,
+    /// Latest activity of each device that has scanned since startup
+    activity: Arc<std::sync::Mutex<HashMap<String, ScannerActivity>>>,
+    power: Option<ScannerPower>,
+    /// Set to cancel a scan, by the id it was started with, while it waits
+    /// for the device or runs
+    cancels: Arc<std::sync::Mutex<HashMap<i32, watch::Sender<bool>>>>,
+    /// The devices as last listed, to tell when they change
+    known: Arc<std::sync::Mutex<Option<Vec<ScannerInfo>>>>,
 }
 
 impl Clone for ScannerManager {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            queue: self.queue.clone(),
+            activity: self.activity.clone(),
+            power: self.power.clone(),
+            cancels: self.cancels.clone(),
+            known: self.known.clone(),
         }
     }
 }
@@ -464,36 +1481,638 @@ impl ScannerManager {
         // Create the appropriate scanner manager based on the environment variable
         let inner = if env::var("MOCK_SCANNER").unwrap_or_default() == "true" {
             println!("Using mock scanner for development");
-            ScannerManagerKind::Mock(MockScannerManager::new())
+            ScannerManagerKind::Mock(MockScannerManager::new(MOCK_SCAN_DELAY))
+        } else if env::var("SCANNER_BACKEND").unwrap_or_default() == "escl" {
+            println!("Using eSCL network scanners");
+            ScannerManagerKind::Escl(EsclScannerManager::new())
         } else {
-            ScannerManagerKind::Real(RealScannerManager::new())
+            let simulate = env::var("SIMULATE_SCANS").unwrap_or_default() == "true";
+            if simulate {
+                println!("Simulating scans: devices are probed but no pages are scanned");
+            }
+            let real = RealScannerManager::new(simulate);
+            if !scanimage_installed() {
+                real.scanimage_missing.store(true, Ordering::Relaxed);
+                println!("Warning: {}", SCANIMAGE_MISSING);
+            }
+            ScannerManagerKind::Real(real)
         };
 
-        Self { inner }
+        let power = PowerConfig::from_env().map(|config| {
+            println!("Scanners are switched on for each job and off when idle");
+            ScannerPower::new(config)
+        });
+
+        Self {
+            inner,
+            queue: ScanQueue::default(),
+            activity: Default::default(),
+            power,
+            cancels: Default::default(),
+            known: Default::default(),
+        }
+    }
+
+    /// A mock scanner whose scans take `delay`, whatever the environment
+    /// says, for load tests.
+    pub fn mock(delay: Duration) -> Self {
+        Self {
+            inner: ScannerManagerKind::Mock(MockScannerManager::new(delay)),
+            queue: ScanQueue::default(),
+            activity: Default::default(),
+            power: None,
+            cancels: Default::default(),
+            known: Default::default(),
+        }
     }
 
     pub async fn last_refreshed(&self) -> Instant {
         self.inner.last_refreshed().await
     }
 
+    pub fn is_mock(&self) -> bool {
+        matches!(self.inner, ScannerManagerKind::Mock(_))
+    }
+
+    /// Why no scan can run, if scanimage was missing when last looked for.
+    pub fn unavailable(&self) -> Option<&'static str> {
+        match &self.inner {
+            ScannerManagerKind::Real(real) if real.scanimage_missing.load(Ordering::Relaxed) => {
+                Some(SCANIMAGE_MISSING)
+            }
+            _ => None,
+        }
+    }
+
     pub async fn force_list_scanners(&self) -> Vec<ScannerInfo> {
-        self.inner.force_list_scanners().await
+        let scanners = self.inner.force_list_scanners().await;
+        self.notice_changes(&scanners);
+        scanners
     }
 
     pub async fn list_scanners(&self) -> Vec<ScannerInfo> {
-        self.inner.list_scanners().await
+        let scanners = self.inner.list_scanners().await;
+        self.notice_changes(&scanners);
+        scanners
+    }
+
+    /// Lists the devices every `interval`, so ones plugged in or unplugged
+    /// are noticed without anyone asking, and stores what was found.
+    pub fn refresh_in_background(
+        &self,
+        interval: Duration,
+        pool: r2d2::Pool<DuckdbConnectionManager>,
+    ) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let scanners = manager.force_list_scanners().await;
+                if let Err(e) = known_scanners::save(&scanners, &pool) {
+                    println!("Failed to store scanners: {}", e);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    /// The devices listed now, then those seen before that aren't, marked
+    /// offline. Until the first listing since startup is done, only the
+    /// stored ones, so there's something to show straight away.
+    pub async fn list_known_scanners(
+        &self,
+        refresh: bool,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<ScannerInfo> {
+        if !refresh && self.known.lock().unwrap().is_none() {
+            let stored = known_scanners::load_all(pool);
+            if !stored.is_empty() {
+                return stored;
+            }
+        }
+        let listed = if refresh {
+            self.force_list_scanners().await
+        } else {
+            self.list_scanners().await
+        };
+        if let Err(e) = known_scanners::save(&listed, pool) {
+            println!("Failed to store scanners: {}", e);
+        }
+
+        // Devices kept while busy count as online
+        let mut scanners = self.known.lock().unwrap().clone().unwrap_or(listed);
+        let offline: Vec<ScannerInfo> = known_scanners::load_all(pool)
+            .into_iter()
+            .filter(|stored| !scanners.iter().any(|scanner| scanner.name == stored.name))
+            .collect();
+        scanners.extend(offline);
+        scanners
+    }
+
+    /// Publishes `ScannersChanged` if devices have come or gone since the
+    /// last listing. Some backends leave a device out while it's scanning,
+    /// so busy devices aren't counted as gone.
+    fn notice_changes(&self, scanners: &[ScannerInfo]) {
+        let busy: Vec<String> = self
+            .current_activity()
+            .into_iter()
+            .filter(|activity| {
+                matches!(
+                    activity.state,
+                    ScannerState::Scanning | ScannerState::PoweringOn
+                )
+            })
+            .map(|activity| activity.device)
+            .collect();
+        let mut known = self.known.lock().unwrap();
+        let Some(previous) = known.as_ref() else {
+            *known = Some(scanners.to_vec());
+            return;
+        };
+        let listed = |name: &str| scanners.iter().any(|scanner| scanner.name == name);
+
+        let added: Vec<ScannerInfo> = scanners
+            .iter()
+            .filter(|scanner| !previous.iter().any(|known| known.name == scanner.name))
+            .cloned()
+            .collect();
+        let (kept, gone): (Vec<ScannerInfo>, Vec<ScannerInfo>) = previous
+            .iter()
+            .filter(|known| !listed(&known.name))
+            .cloned()
+            .partition(|known| busy.contains(&known.name));
+        let mut now = scanners.to_vec();
+        now.extend(kept);
+        *known = Some(now.clone());
+
+        if !added.is_empty() || !gone.is_empty() {
+            for scanner in &added {
+                println!("Scanner plugged in: {}", scanner.name);
+            }
+            for scanner in &gone {
+                println!("Scanner went away: {}", scanner.name);
+            }
+            SimpleBroker::publish(ScannersChanged {
+                added,
+                removed: gone.into_iter().map(|scanner| scanner.name).collect(),
+                scanners: now,
+                at: Utc::now(),
+            });
+        }
+    }
+
+    /// The options the device accepts, from `scanimage -A`, or for eSCL
+    /// scanners from their capabilities. Fails while the device is busy with
+    /// a scan.
+    pub async fn scanner_options(&self, name: &str) -> Result<Vec<ScannerOption>, String> {
+        if self.is_mock() {
+            return Ok(scanner_options::parse(MOCK_SCANNER_OPTIONS));
+        }
+        if let ScannerManagerKind::Escl(_) = self.inner {
+            let url = escl::device_url(name)
+                .ok_or_else(|| format!("{} is not an eSCL scanner", name))?
+                .to_string();
+            return tokio::task::spawn_blocking(move || escl::Capabilities::load(&url))
+                .await
+                .unwrap()
+                .map(|capabilities| capabilities.options())
+                .map_err(|e| format!("could not read options of {}: {}", name, e));
+        }
+        if let Some(reason) = self.unavailable() {
+            return Err(reason.to_string());
+        }
+        let output = Command::new("scanimage")
+            .arg("-A")
+            .arg("-d")
+            .arg(name)
+            .output()
+            .await
+            .map_err(|e| format!("could not run scanimage: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "could not read options of {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(scanner_options::parse(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    /// The `--source` choice that feeds both sides of each sheet, e.g. `ADF
+    /// Duplex`, from the device's options. Fails like `scanner_options`, or
+    /// if the device has no such source.
+    pub async fn duplex_source(&self, name: &str) -> Result<String, String> {
+        self.scanner_options(name)
+            .await?
+            .into_iter()
+            .find(|option| option.name == "source")
+            .and_then(|source| {
+                source
+                    .values
+                    .into_iter()
+                    .find(|value| value.to_lowercase().contains("duplex"))
+            })
+            .ok_or_else(|| format!("{} has no duplex source", name))
+    }
+
+    /// Scans a quick low-resolution page from the device, ahead of any
+    /// queued scans, into a temporary file under `previews/`, and returns
+    /// its path. No scan is recorded. Previews over an hour old are deleted.
+    pub async fn preview_scan(
+        &self,
+        name: &str,
+        mut scan_arguments: HashMap<String, String>,
+        assets_dir: &AssetsDir,
+    ) -> Result<AssetPath, String> {
+        if let Some(reason) = self.unavailable() {
+            return Err(reason.to_string());
+        }
+        // Scanning at a resolution the device doesn't offer fails, so take
+        // the lowest it does from PREVIEW_DPI up, if it says
+        let resolution = self
+            .scanner_options(name)
+            .await
+            .ok()
+            .and_then(|options| {
+                options
+                    .into_iter()
+                    .find(|option| option.name == "resolution")
+            })
+            .and_then(|option| {
+                let mut offered: Vec<f64> = option
+                    .values
+                    .iter()
+                    .filter_map(|value| value.parse().ok())
+                    .chain(option.min)
+                    .collect();
+                offered.sort_by(f64::total_cmp);
+                offered
+                    .iter()
+                    .find(|dpi| **dpi >= PREVIEW_DPI)
+                    .or(offered.last())
+                    .copied()
+                    .map(|dpi| match option.option_type {
+                        ScannerOptionType::Range => dpi.max(PREVIEW_DPI),
+                        _ => dpi,
+                    })
+            })
+            .unwrap_or(PREVIEW_DPI);
+        scan_arguments.retain(|key, _| key.trim_start_matches('-') != "resolution");
+        scan_arguments.insert("--resolution".to_string(), resolution.to_string());
+
+        let previews = Path::new(&assets_dir.0).join("previews");
+        fs::create_dir_all(&previews).map_err(|e| e.to_string())?;
+        for entry in fs::read_dir(&previews).into_iter().flatten().flatten() {
+            let stale = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > PREVIEW_KEEP);
+            if stale {
+                fs::remove_file(entry.path()).ok();
+            }
+        }
+        let path = AssetPath::from_relative_path(format!(
+            "previews/{}.png",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        ));
+
+        let turn = self
+            .queue
+            .acquire(name, ScanPriority::High, None, || {})
+            .await;
+        if let Some(power) = &self.power {
+            power
+                .before_scan(name)
+                .await
+                .map_err(|e| format!("could not switch on {}: {}", name, e))?;
+        }
+        let result = self
+            .inner
+            .preview_scan(name, scan_arguments, &path, assets_dir)
+            .await;
+        drop(turn);
+        let last_scan_id = self
+            .activity
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|activity| activity.scan_id);
+        self.power_off_when_idle(name, last_scan_id);
+
+        result.map(|_| path)
     }
 
+    /// Waits for the device to be free, in priority order, switches it on if
+    /// it's power managed, then runs the scan and its group's processing
+    /// profile, if it has one.
     pub async fn complete_scan(
         &self,
         scan_id: i32,
         name: &str,
         scan_arguments: HashMap<String, String>,
+        priority: ScanPriority,
         pool: &r2d2::Pool<DuckdbConnectionManager>,
         assets_dir: &AssetsDir,
     ) -> i32 {
-        self.inner
-            .complete_scan(scan_id, name, scan_arguments, pool, assets_dir)
+        self.run(
+            scan_id,
+            name,
+            scan_arguments,
+            priority,
+            pool,
+            assets_dir,
+            None,
+        )
+        .await[0]
+    }
+
+    /// Like `complete_scan`, but scans every sheet in the feeder, each into
+    /// its own scan. Each page's activity is published as it lands, with
+    /// its scan id, and `landed` is called with it. Returns the scans in
+    /// page order, which if cancelled are those that landed.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn complete_batch(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        priority: ScanPriority,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        landed: &(dyn Fn(i32) + Send + Sync),
+    ) -> Vec<i32> {
+        self.run(
+            scan_id,
+            name,
+            scan_arguments,
+            priority,
+            pool,
+            assets_dir,
+            Some(landed),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn run(
+        &self,
+        scan_id: i32,
+        name: &str,
+        scan_arguments: HashMap<String, String>,
+        priority: ScanPriority,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+        batch: Option<&(dyn Fn(i32) + Send + Sync)>,
+    ) -> Vec<i32> {
+        let started_by = Scan::load(scan_id, pool)
+            .ok()
+            .and_then(|scan| scan.started_by);
+        // A batch's pages so far, kept if it's cancelled
+        let landed_ids = std::sync::Mutex::new(vec![]);
+        let (cancel, mut cancelled) = watch::channel(false);
+        self.cancels.lock().unwrap().insert(scan_id, cancel);
+        // Set once the backend has the scan, from when it has its own file
+        let started = AtomicBool::new(false);
+
+        // Waiting for the device, switching it on and scanning can all be
+        // cancelled. Dropping the scan kills its scanimage.
+        let scanning = async {
+            let queued = AtomicBool::new(false);
+            let turn = self
+                .queue
+                .acquire(name, priority, Some(scan_id), || {
+                    queued.store(true, Ordering::Relaxed);
+                    Scan::update_status_many(&[scan_id], "QUEUED", pool).unwrap();
+                })
+                .await;
+            if queued.load(Ordering::Relaxed) {
+                Scan::update_status_many(&[scan_id], "PENDING", pool).unwrap();
+            }
+            if let Some(power) = &self.power {
+                if !power.is_on(name).await {
+                    self.publish_activity(
+                        name,
+                        ScannerState::PoweringOn,
+                        scan_id,
+                        None,
+                        started_by.clone(),
+                    );
+                }
+                if let Err(e) = power.before_scan(name).await {
+                    println!("Failed to switch on {}: {}", name, e);
+                    let mut scan = Scan::load(scan_id, pool).unwrap();
+                    scan.status = "FAILED".to_string();
+                    scan.save(pool).unwrap();
+                    Scan::set_failure(scan_id, Some("POWER_ON_FAILED"), pool).unwrap();
+                    self.publish_activity(
+                        name,
+                        ScannerState::Error,
+                        scan_id,
+                        Some("POWER_ON_FAILED".to_string()),
+                        started_by.clone(),
+                    );
+                    return None;
+                }
+            }
+            self.publish_activity(
+                name,
+                ScannerState::Scanning,
+                scan_id,
+                None,
+                started_by.clone(),
+            );
+            started.store(true, Ordering::Relaxed);
+            let scan_ids = if let Some(batch_landed) = batch {
+                let landed = |landed_id| {
+                    landed_ids.lock().unwrap().push(landed_id);
+                    self.publish_activity(
+                        name,
+                        ScannerState::Scanning,
+                        landed_id,
+                        None,
+                        started_by.clone(),
+                    );
+                    batch_landed(landed_id);
+                };
+                self.inner
+                    .complete_batch(scan_id, name, scan_arguments, pool, assets_dir, &landed)
+                    .await
+            } else {
+                vec![
+                    self.inner
+                        .complete_scan(scan_id, name, scan_arguments, pool, assets_dir)
+                        .await,
+                ]
+            };
+            Some((turn, scan_ids))
+        };
+        let outcome = tokio::select! {
+            outcome = scanning => Some(outcome),
+            _ = cancelled.wait_for(|cancelled| *cancelled) => None,
+        };
+        self.cancels.lock().unwrap().remove(&scan_id);
+        let (turn, scan_ids) = match outcome {
+            Some(Some(scanned)) => scanned,
+            Some(None) => return vec![scan_id],
+            None => {
+                self.record_cancelled(
+                    scan_id,
+                    name,
+                    started.load(Ordering::Relaxed),
+                    started_by,
+                    pool,
+                    assets_dir,
+                );
+                let landed_ids = landed_ids.into_inner().unwrap();
+                if landed_ids.is_empty() {
+                    return vec![scan_id];
+                }
+                self.process(&landed_ids, pool, assets_dir).await;
+                return landed_ids;
+            }
+        };
+
+        // The last page decides how the device is left
+        let scan_id = *scan_ids.last().unwrap();
+        // The next scan can start while this page is cleaned up
+        drop(turn);
+        self.power_off_when_idle(name, Some(scan_id));
+
+        match Scan::load(scan_id, pool) {
+            Ok(scan) if scan.status == "FAILED" => {
+                let failure = Scan::load_failure(scan_id, pool)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_else(|| "FAILED".to_string());
+                self.publish_activity(
+                    name,
+                    ScannerState::Error,
+                    scan_id,
+                    Some(failure),
+                    started_by,
+                );
+            }
+            _ => self.publish_activity(name, ScannerState::Idle, scan_id, None, started_by),
+        }
+
+        self.process(&scan_ids, pool, assets_dir).await;
+        scan_ids
+    }
+
+    /// Runs the groups' processing profiles over scans just taken.
+    async fn process(
+        &self,
+        scan_ids: &[i32],
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) {
+        for &scan_id in scan_ids {
+            let (pool_clone, assets_clone) = (pool.clone(), assets_dir.clone());
+            let processed = tokio::task::spawn_blocking(move || {
+                processing_profiles::process_scan(scan_id, &pool_clone, &assets_clone)
+            })
             .await
+            .unwrap();
+            if let Err(e) = processed {
+                println!("Failed to process scan {}: {}", scan_id, e);
+            }
+        }
+    }
+
+    /// Where a QUEUED scan is in its device's queue, 1 being next.
+    pub fn queue_position(&self, scan_id: i32) -> Option<usize> {
+        self.queue.position(scan_id)
+    }
+
+    /// Cancels a scan waiting for its device or being taken, by the id it
+    /// was started with. Returns whether it was waiting or running.
+    pub fn cancel_scan(&self, scan_id: i32) -> bool {
+        match self.cancels.lock().unwrap().get(&scan_id) {
+            Some(cancel) => cancel.send(true).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Marks a cancelled scan CANCELLED and removes any partial file the
+    /// backend wrote. Pages a batch already saved are kept.
+    fn record_cancelled(
+        &self,
+        scan_id: i32,
+        name: &str,
+        started: bool,
+        started_by: Option<String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) {
+        let mut scan = Scan::load(scan_id, pool).unwrap();
+        if scan.status == "PENDING" || scan.status == "QUEUED" {
+            // Until the backend gives the scan its own file the path is a
+            // placeholder, or a rescan's previous image
+            if started {
+                fs::remove_file(scan.path.as_disk_path(&assets_dir.0)).ok();
+                fs::remove_file(assets_dir.capture_path(&scan.path)).ok();
+            }
+            scan.status = "CANCELLED".to_string();
+            scan.save(pool).unwrap();
+            Scan::set_failure(scan_id, None, pool).unwrap();
+        }
+        self.power_off_when_idle(name, Some(scan_id));
+        self.publish_activity(name, ScannerState::Idle, scan_id, None, started_by);
+    }
+
+    /// Switches the device off once it's been idle a while, if it's power
+    /// managed, and says so in its activity if it has any.
+    fn power_off_when_idle(&self, device: &str, scan_id: Option<i32>) {
+        let Some(power) = self.power.clone() else {
+            return;
+        };
+        let manager = self.clone();
+        let device = device.to_string();
+        tokio::spawn(async move {
+            match power.off_when_idle(&device).await {
+                Ok(true) => {
+                    if let Some(scan_id) = scan_id {
+                        manager.publish_activity(&device, ScannerState::Off, scan_id, None, None)
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => println!("Failed to switch off {}: {}", device, e),
+            }
+        });
+    }
+
+    fn publish_activity(
+        &self,
+        device: &str,
+        state: ScannerState,
+        scan_id: i32,
+        failure: Option<String>,
+        started_by: Option<String>,
+    ) {
+        let activity = ScannerActivity {
+            device: device.to_string(),
+            state,
+            scan_id,
+            failure,
+            waiting: self.queue.waiting(device) as i32,
+            started_by,
+            at: Utc::now(),
+        };
+        self.activity
+            .lock()
+            .unwrap()
+            .insert(device.to_string(), activity.clone());
+        SimpleBroker::publish(activity);
+    }
+
+    /// The latest activity of every device that has scanned since startup.
+    pub fn current_activity(&self) -> Vec<ScannerActivity> {
+        let mut activity: Vec<_> = self.activity.lock().unwrap().values().cloned().collect();
+        activity.sort_by(|a, b| a.device.cmp(&b.device));
+        activity
     }
 }
@@ -1,12 +1,236 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
-use async_graphql::SimpleObject;
-use chrono::{DateTime, Utc};
+use async_graphql::{ComplexObject, Context, Enum, InputObject, SimpleObject};
+use chrono::{DateTime, NaiveDate, Utc};
 use duckdb::Result;
-use duckdb::{params, DuckdbConnectionManager};
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use image::DynamicImage;
 use serde::{Deserialize, Serialize};
 
-use crate::asset_path::AssetPath;
+use crate::{
+    app_context::ContextExt,
+    asset_path::AssetPath,
+    db_config::{checkpoint, BATCH_CHECKPOINT_ROWS},
+    dropout::DropoutColor,
+    duplicates::GroupDuplicate,
+    export_history::GroupExport,
+    exports::DEFAULT_DPI,
+    locale, processing_profiles,
+    punch_holes::remove_punch_holes,
+    tag_suggestions::TagSuggestion,
+    tiles, title_suggestions, AssetsDir,
+};
+
+/// Pages are numbered by capture order within their group.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScanSort {
+    ScannedAtDesc,
+    Status,
+    /// Grouped together, newest first within each group
+    Group,
+    /// Reading order: grouped together, first page first
+    PageNumber,
+}
+
+impl ScanSort {
+    /// ORDER BY clause for the scans table, with `id` as a stable tiebreaker.
+    pub fn order_by(sort: Option<Self>) -> &'static str {
+        match sort {
+            None => "ORDER BY id",
+            Some(ScanSort::ScannedAtDesc) => "ORDER BY scanned_at DESC, id DESC",
+            Some(ScanSort::Status) => "ORDER BY status, scanned_at DESC, id DESC",
+            Some(ScanSort::Group) => "ORDER BY scan_group_id NULLS LAST, scanned_at DESC, id DESC",
+            Some(ScanSort::PageNumber) => {
+                "ORDER BY scan_group_id NULLS LAST, page_order, scanned_at, id"
+            }
+        }
+    }
+}
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GroupSort {
+    CreatedAtDesc,
+    UpdatedAtDesc,
+    Status,
+    Title,
+}
+
+impl GroupSort {
+    /// ORDER BY clause for the scan_groups table, with `id` as a stable tiebreaker.
+    pub fn order_by(sort: Option<Self>) -> &'static str {
+        match sort {
+            None => "ORDER BY id",
+            Some(GroupSort::CreatedAtDesc) => "ORDER BY created_at DESC, id DESC",
+            Some(GroupSort::UpdatedAtDesc) => "ORDER BY updated_at DESC, id DESC",
+            Some(GroupSort::Status) => "ORDER BY status, created_at DESC, id DESC",
+            Some(GroupSort::Title) => "ORDER BY title, id",
+        }
+    }
+}
+
+/// Work done on a scan after it is captured, separate from whether the
+/// capture itself succeeded.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ProcessingStatus {
+    /// Nothing to do for this scan
+    #[default]
+    None,
+    Queued,
+    Processing,
+    Done,
+    Failed,
+}
+
+impl ProcessingStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingStatus::None => "NONE",
+            ProcessingStatus::Queued => "QUEUED",
+            ProcessingStatus::Processing => "PROCESSING",
+            ProcessingStatus::Done => "DONE",
+            ProcessingStatus::Failed => "FAILED",
+        }
+    }
+
+    fn from_str(status: &str) -> Self {
+        match status {
+            "QUEUED" => ProcessingStatus::Queued,
+            "PROCESSING" => ProcessingStatus::Processing,
+            "DONE" => ProcessingStatus::Done,
+            "FAILED" => ProcessingStatus::Failed,
+            _ => ProcessingStatus::None,
+        }
+    }
+
+    /// Reads the nullable column as stored.
+    pub fn from_column(status: Option<String>) -> Self {
+        status.as_deref().map(Self::from_str).unwrap_or_default()
+    }
+}
+
+/// Where a page stands in QA, after whoever scanned it hands it over.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ReviewState {
+    #[default]
+    Unreviewed,
+    Approved,
+    /// Rejected by the reviewer; the page should be scanned again
+    NeedsRescan,
+}
+
+impl ReviewState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReviewState::Unreviewed => "UNREVIEWED",
+            ReviewState::Approved => "APPROVED",
+            ReviewState::NeedsRescan => "NEEDS_RESCAN",
+        }
+    }
+
+    fn from_str(state: &str) -> Self {
+        match state {
+            "APPROVED" => ReviewState::Approved,
+            "NEEDS_RESCAN" => ReviewState::NeedsRescan,
+            _ => ReviewState::Unreviewed,
+        }
+    }
+
+    /// Reads the nullable column as stored.
+    pub fn from_column(state: Option<String>) -> Self {
+        state.as_deref().map(Self::from_str).unwrap_or_default()
+    }
+}
+
+/// How far review of a group's pages has got.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ReviewProgress {
+    pub group_id: i32,
+    pub total: i32,
+    pub unreviewed: i32,
+    pub approved: i32,
+    pub needs_rescan: i32,
+    /// Every page approved
+    pub complete: bool,
+}
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum TagMatch {
+    #[default]
+    Any,
+    All,
+}
+
+#[derive(InputObject, Debug, Clone, Default)]
+pub struct GroupFilter {
+    pub tags: Option<Vec<String>>,
+    #[graphql(default)]
+    pub tag_match: TagMatch,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    /// Created on this day in the server's time zone, e.g. yesterday's
+    /// scanning session
+    pub created_on: Option<NaiveDate>,
+    /// Case-insensitive substring of the title or comment
+    pub search: Option<String>,
+}
+
+impl GroupFilter {
+    /// WHERE conditions for the scan_groups table and their bound parameters.
+    pub fn to_sql(&self) -> (Vec<String>, Vec<Box<dyn duckdb::ToSql>>) {
+        let mut conditions: Vec<String> = vec![];
+        let mut params: Vec<Box<dyn duckdb::ToSql>> = vec![];
+
+        if let Some(tags) = self.tags.as_ref().filter(|tags| !tags.is_empty()) {
+            // Tags are stored as compact JSON, where quotes inside a tag are
+            // escaped, so `,"tag",` only matches a whole element once the
+            // brackets are swapped for commas.
+            let tag_conditions: Vec<&str> = tags
+                .iter()
+                .map(|tag| {
+                    params.push(Box::new(format!(
+                        ",{},",
+                        serde_json::to_string(tag).unwrap()
+                    )));
+                    "contains(',' || trim(tags, '[]') || ',', ?)"
+                })
+                .collect();
+            let joiner = match self.tag_match {
+                TagMatch::Any => " OR ",
+                TagMatch::All => " AND ",
+            };
+            conditions.push(format!("({})", tag_conditions.join(joiner)));
+        }
+
+        let created_on = self.created_on.map(locale::day_bounds);
+        let date_bounds = [
+            ("created_at >", self.created_after),
+            ("created_at <", self.created_before),
+            ("created_at >=", created_on.map(|(start, _)| start)),
+            ("created_at <", created_on.map(|(_, end)| end)),
+            ("updated_at >", self.updated_after),
+            ("updated_at <", self.updated_before),
+        ];
+        for (comparison, bound) in date_bounds {
+            if let Some(bound) = bound {
+                conditions.push(format!("{} ?::TIMESTAMP", comparison));
+                params.push(Box::new(bound));
+            }
+        }
+
+        if let Some(search) = &self.search {
+            conditions.push(
+                "(contains(lower(title), lower(?)) OR contains(lower(comment), lower(?)))"
+                    .to_string(),
+            );
+            params.push(Box::new(search.clone()));
+            params.push(Box::new(search.clone()));
+        }
+
+        (conditions, params)
+    }
+}
 
 #[derive(Debug, Clone, SimpleObject)]
 pub struct ScanGroup {
@@ -17,7 +241,28 @@ pub struct ScanGroup {
     pub status: String,
     pub comment: String,
     pub tags: Vec<String>,
+    /// Set when a batch into this group scanned a different number of pages than expected
+    pub page_count_warning: Option<String>,
+    /// Flatten the curve of bound pages near the spine when exporting
+    pub dewarp: bool,
+    /// Lighten the shadow of the book's gutter when exporting
+    pub remove_gutter_shadow: bool,
+    /// Under a legal or retention hold: nothing in the group may be deleted until released
+    pub hold: bool,
+    /// Drop a form's printed colour when exporting and reading text
+    pub color_dropout: Option<DropoutColor>,
+    /// Cleanup run on each page as it's captured
+    pub processing_profile_id: Option<i32>,
     pub scans: Vec<Scan>,
+    /// Earlier exports, newest first
+    pub exports: Vec<GroupExport>,
+    /// Tags found in the pages' text, waiting to be accepted
+    pub suggested_tags: Vec<TagSuggestion>,
+    /// A title read from the first page once it's been through OCR, for
+    /// `acceptSuggestedTitle`
+    pub suggested_title: Option<String>,
+    /// Earlier groups this one looks like, until the user resolves them
+    pub possible_duplicates: Vec<GroupDuplicate>,
 }
 
 impl ScanGroup {
@@ -31,7 +276,43 @@ impl ScanGroup {
             status,
             comment: String::new(),
             tags: Vec::new(),
+            page_count_warning: None,
+            dewarp: false,
+            remove_gutter_shadow: false,
+            hold: false,
+            color_dropout: None,
+            processing_profile_id: None,
             scans: Vec::new(),
+            exports: Vec::new(),
+            suggested_tags: Vec::new(),
+            suggested_title: None,
+            possible_duplicates: Vec::new(),
+        }
+    }
+
+    /// The most recently created group with this title, or a new scanning group.
+    pub fn find_or_create_by_title(
+        title: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let existing: Option<i32> = pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT id FROM scan_groups WHERE title = ? ORDER BY created_at DESC, id DESC LIMIT 1",
+                params![title],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match existing {
+            Some(id) => Self::load(id, pool),
+            None => {
+                let mut group = Self::create("scanning".to_string());
+                group.title = title.to_string();
+                group.save(pool)?;
+                Ok(group)
+            }
         }
     }
 
@@ -39,23 +320,35 @@ impl ScanGroup {
         let conn = pool.get().unwrap();
 
         conn.query_row(
-            "SELECT id, title, created_at, updated_at, status, comment, tags FROM scan_groups WHERE id = ?",
+            "SELECT id, title, created_at, updated_at, status, comment, tags, page_count_warning, dewarp, remove_gutter_shadow, hold, color_dropout, processing_profile_id FROM scan_groups WHERE id = ?",
             params![id],
             |row| {
                 let tags_json: String = row.get(6)?;
                 let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
 
                 let scans = Scan::load_all_by_group(id, pool);
+                let title: String = row.get(1)?;
+                let suggested_title = title_suggestions::suggest(&scans, &title, pool);
 
                 Ok(Self {
                     id: row.get(0)?,
-                    title: row.get(1)?,
+                    title,
                     created_at: row.get(2)?,
                     updated_at: row.get(3)?,
                     status: row.get(4)?,
                     comment: row.get(5)?,
                     tags,
+                    page_count_warning: row.get(7)?,
+                    dewarp: row.get(8)?,
+                    remove_gutter_shadow: row.get(9)?,
+                    hold: row.get(10)?,
+                    color_dropout: DropoutColor::from_column(row.get(11)?),
+                    processing_profile_id: row.get(12)?,
                     scans,
+                    exports: GroupExport::load_all_by_group(id, pool),
+                    suggested_tags: TagSuggestion::load_all_by_group(id, pool),
+                    suggested_title,
+                    possible_duplicates: GroupDuplicate::load_all_by_group(id, pool),
                 })
             },
         )
@@ -70,15 +363,19 @@ impl ScanGroup {
         if self.id == 0 {
             // New record
             let id: i32 = conn.query_row(
-                "INSERT INTO scan_groups (title, created_at, updated_at, status, comment, tags)
-                 VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+                "INSERT INTO scan_groups (title, created_at, updated_at, status, comment, tags, dewarp, remove_gutter_shadow, color_dropout, processing_profile_id)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
                 params![
                     self.title,
                     self.created_at,
                     self.updated_at,
                     self.status,
                     self.comment,
-                    tags_json
+                    tags_json,
+                    self.dewarp,
+                    self.remove_gutter_shadow,
+                    self.color_dropout.map(|color| color.as_str()),
+                    self.processing_profile_id
                 ],
                 |row| row.get(0),
             )?;
@@ -87,14 +384,205 @@ impl ScanGroup {
         } else {
             // Update existing record
             conn.execute(
-                "UPDATE scan_groups SET title = ?, updated_at = ?, status = ?, comment = ?, tags = ? WHERE id = ?",
-                params![self.title, self.updated_at, self.status, self.comment, tags_json, self.id],
+                "UPDATE scan_groups SET title = ?, updated_at = ?, status = ?, comment = ?, tags = ?, dewarp = ?, remove_gutter_shadow = ?, color_dropout = ?, processing_profile_id = ? WHERE id = ?",
+                params![
+                    self.title,
+                    self.updated_at,
+                    self.status,
+                    self.comment,
+                    tags_json,
+                    self.dewarp,
+                    self.remove_gutter_shadow,
+                    self.color_dropout.map(|color| color.as_str()),
+                    self.processing_profile_id,
+                    self.id
+                ],
             )?;
             Ok(self.id)
         }
     }
+
+    /// Removes a group whose scans have all been deleted or moved, with its
+    /// tag suggestions, duplicate findings, comments, assignment and links.
+    /// Its export history is kept.
+    pub fn delete_empty(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        let scans: i64 = conn.query_row(
+            "SELECT count(*) FROM scans WHERE scan_group_id = ?",
+            params![id],
+            |row| row.get(0),
+        )?;
+        if scans > 0 {
+            return Ok(false);
+        }
+        conn.execute(
+            "DELETE FROM tag_suggestions WHERE scan_group_id = ?",
+            params![id],
+        )?;
+        conn.execute(
+            "DELETE FROM group_duplicates WHERE scan_group_id = ? OR duplicate_of_id = ?",
+            params![id, id],
+        )?;
+        conn.execute(
+            "DELETE FROM group_comments WHERE scan_group_id = ?",
+            params![id],
+        )?;
+        conn.execute(
+            "DELETE FROM group_assignments WHERE scan_group_id = ?",
+            params![id],
+        )?;
+        conn.execute(
+            "DELETE FROM group_links WHERE from_group_id = ? OR to_group_id = ?",
+            params![id, id],
+        )?;
+        let deleted = conn.execute("DELETE FROM scan_groups WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
+
+    pub fn set_page_count_warning(
+        id: i32,
+        warning: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_groups SET page_count_warning = ?, updated_at = ? WHERE id = ?",
+            params![warning, Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Places or releases a hold on the group.
+    pub fn set_hold(
+        id: i32,
+        hold: bool,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<usize> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scan_groups SET hold = ?, updated_at = ? WHERE id = ?",
+            params![hold, Utc::now(), id],
+        )
+    }
+
+    /// Finalized groups, oldest first, narrowed to those never exported and
+    /// to those changed since a time.
+    pub fn finalized_ids(
+        unexported: bool,
+        since: Option<DateTime<Utc>>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Vec<i32>> {
+        let conn = pool.get().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id FROM scan_groups g
+             WHERE status = 'finalized'
+               AND (NOT ? OR NOT EXISTS (SELECT 1 FROM exports e WHERE e.scan_group_id = g.id))
+               AND (?::TIMESTAMP IS NULL OR updated_at >= ?::TIMESTAMP)
+             ORDER BY created_at, id",
+        )?;
+        let ids = stmt
+            .query_map(params![unexported, since, since], |row| row.get(0))?
+            .collect();
+        ids
+    }
+
+    /// Sets the status of many groups at once, returning how many changed.
+    pub fn update_status_many(
+        ids: &[i32],
+        status: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<usize> {
+        let mut conn = pool.get().unwrap();
+        let tx = conn.transaction()?;
+        let now = Utc::now();
+
+        let mut updated = 0;
+        for id in ids {
+            updated += tx.execute(
+                "UPDATE scan_groups SET status = ?, updated_at = ? WHERE id = ?",
+                params![status, now, id],
+            )?;
+        }
+
+        tx.commit()?;
+        if updated >= BATCH_CHECKPOINT_ROWS {
+            checkpoint(&conn);
+        }
+        Ok(updated)
+    }
+
+    /// Counts the group's pages in each review state.
+    pub fn review_progress(
+        id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<ReviewProgress> {
+        let conn = pool.get().unwrap();
+        let (total, approved, needs_rescan): (i32, i32, i32) = conn.query_row(
+            "SELECT count(*),
+                    count(*) FILTER (WHERE review_state = 'APPROVED'),
+                    count(*) FILTER (WHERE review_state = 'NEEDS_RESCAN')
+             FROM scans WHERE scan_group_id = ?",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(ReviewProgress {
+            group_id: id,
+            total,
+            unreviewed: total - approved - needs_rescan,
+            approved,
+            needs_rescan,
+            complete: total > 0 && approved == total,
+        })
+    }
 }
 
+/// Cheap totals for notification badges.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScanCounts {
+    pub pending: i64,
+    pub failed: i64,
+    pub ungrouped: i64,
+    /// Finalized groups that have never been exported
+    pub unexported: i64,
+}
+
+impl ScanCounts {
+    pub fn load(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            "SELECT
+                COUNT(*) FILTER (WHERE status IN ('PENDING', 'QUEUED')),
+                COUNT(*) FILTER (WHERE status = 'FAILED'),
+                COUNT(*) FILTER (WHERE scan_group_id IS NULL),
+                (SELECT COUNT(*) FROM scan_groups g
+                 WHERE g.status = 'finalized'
+                   AND NOT EXISTS (SELECT 1 FROM exports e WHERE e.scan_group_id = g.id))
+             FROM scans",
+            [],
+            |row| {
+                Ok(Self {
+                    pending: row.get(0)?,
+                    failed: row.get(1)?,
+                    ungrouped: row.get(2)?,
+                    unexported: row.get(3)?,
+                })
+            },
+        )
+    }
+}
+
+/// Turns an image clockwise by a scan's `rotation`, in degrees.
+pub fn rotate(image: DynamicImage, rotation: i32) -> DynamicImage {
+    match rotation {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// A crop as fractions of the rotated page, 0 to 1.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CropCoordinates {
     pub x: f32,
@@ -103,9 +591,41 @@ pub struct CropCoordinates {
     pub height: f32,
 }
 
+impl CropCoordinates {
+    /// Whether the crop is inside the page and not empty.
+    pub fn is_valid(&self) -> bool {
+        let fraction = 0.0..=1.0;
+        fraction.contains(&self.x)
+            && fraction.contains(&self.y)
+            && self.width > 0.0
+            && self.height > 0.0
+            && self.x + self.width <= 1.0 + f32::EPSILON
+            && self.y + self.height <= 1.0 + f32::EPSILON
+    }
+
+    /// Left, top, width and height in pixels on a page of the given size,
+    /// at least one pixel across.
+    fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x * width as f32).round() as u32).min(width - 1);
+        let y = ((self.y * height as f32).round() as u32).min(height - 1);
+        let crop_width = ((self.width * width as f32).round() as u32).clamp(1, width - x);
+        let crop_height = ((self.height * height as f32).round() as u32).clamp(1, height - y);
+        (x, y, crop_width, crop_height)
+    }
+}
+
+/// Cuts a rotated page down to `crop`.
+pub fn crop(image: DynamicImage, crop: &CropCoordinates) -> DynamicImage {
+    let (x, y, width, height) = crop.bounds(image.width(), image.height());
+    image.crop_imm(x, y, width, height)
+}
+
 #[derive(Debug, Clone, SimpleObject)]
+#[graphql(complex)]
 pub struct Scan {
     pub id: Option<i32>,
+    /// QUEUED while waiting for the device, PENDING while being taken, then
+    /// COMPLETE, FAILED or CANCELLED
     pub status: String,
     pub scanned_at: DateTime<Utc>,
     pub scanner: String,
@@ -117,6 +637,27 @@ pub struct Scan {
     pub crop_coordinates: Option<String>,
     pub original_path: Option<AssetPath>,
     pub edited_path: Option<AssetPath>,
+    /// Progress of work on the captured image; `status` only covers the capture
+    pub processing_status: ProcessingStatus,
+    /// The user or API key that asked for the scan, if auth identified one
+    pub started_by: Option<String>,
+    /// Paint over punch holes and edge shadows wherever the image is used
+    pub remove_punch_holes: bool,
+    pub review_state: ReviewState,
+    /// The user or API key that last set `review_state`, if auth identified one
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+#[ComplexObject]
+impl Scan {
+    /// Where a QUEUED scan is in its device's queue, 1 being next
+    async fn queue_position(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<usize>> {
+        let app = ctx.app()?;
+        Ok(self
+            .id
+            .and_then(|id| app.scanner_manager.queue_position(id)))
+    }
 }
 
 impl Scan {
@@ -141,7 +682,111 @@ impl Scan {
             crop_coordinates: None,
             original_path: Some(asset_path),
             edited_path: None,
+            processing_status: ProcessingStatus::None,
+            started_by: None,
+            remove_punch_holes: false,
+            review_state: ReviewState::Unreviewed,
+            reviewed_by: None,
+            reviewed_at: None,
+        }
+    }
+
+    /// Saves a PENDING scan with a placeholder path, ready for `complete_scan`.
+    pub fn create_pending(
+        scanner: &str,
+        scan_parameters: &HashMap<String, String>,
+        group_id: Option<i32>,
+        started_by: Option<String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&assets_dir.0).unwrap();
+        std::fs::create_dir_all(Path::new(&assets_dir.0).join("scans")).unwrap();
+
+        let mut scan = Scan::new(
+            "PENDING".to_string(),
+            Path::new("scans")
+                .join("tmp.png")
+                .as_os_str()
+                .to_str()
+                .unwrap()
+                .to_string(),
+            scanner.to_string(),
+            scan_parameters.clone(),
+            Utc::now(),
+        );
+        scan.started_by = started_by;
+
+        // Save scan to get an ID
+        scan.save(pool)?;
+
+        // If a group_id is provided, immediately associate the scan with the group
+        if let Some(group_id) = group_id {
+            scan.set_group(group_id, pool)?;
+        }
+
+        Ok(scan)
+    }
+
+    /// Leading number of a scan parameter, e.g. 300 for `--resolution 300dpi`.
+    /// Dashes on the key are ignored.
+    pub fn numeric_parameter(&self, name: &str) -> Option<f32> {
+        self.scan_parameters
+            .iter()
+            .find(|(key, _)| key.trim_start_matches('-') == name)
+            .and_then(|(_, value)| {
+                let digits: String = value
+                    .chars()
+                    .take_while(|c| c.is_ascii_digit() || *c == '.')
+                    .collect();
+                digits.parse::<f32>().ok()
+            })
+    }
+
+    /// The saved crop, if there is one.
+    pub fn crop(&self) -> Option<CropCoordinates> {
+        let crop: CropCoordinates = serde_json::from_str(self.crop_coordinates.as_deref()?).ok()?;
+        crop.is_valid().then_some(crop)
+    }
+
+    /// The image as the user sees it: their edited copy if there is one, cleaned
+    /// of punch holes if asked, with rotation and crop applied.
+    pub fn open_image(&self, assets_dir: &AssetsDir) -> image::ImageResult<DynamicImage> {
+        let source = self.edited_path.as_ref().unwrap_or(&self.path);
+        let mut image = assets_dir.read_image(source)?;
+        if self.remove_punch_holes {
+            image = remove_punch_holes(image, self.resolution().unwrap_or(DEFAULT_DPI));
         }
+        image = rotate(image, self.rotation);
+
+        Ok(match self.crop() {
+            Some(area) => crop(image, &area),
+            None => image,
+        })
+    }
+
+    /// Width and height of `open_image`'s result, read without decoding it.
+    pub fn dimensions(&self, assets_dir: &AssetsDir) -> image::ImageResult<(u32, u32)> {
+        let source = self.edited_path.as_ref().unwrap_or(&self.path);
+        let (width, height) = assets_dir.image_dimensions(source)?;
+        let (width, height) = match self.rotation {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        };
+
+        Ok(match self.crop() {
+            Some(area) => {
+                let (_, _, width, height) = area.bounds(width, height);
+                (width, height)
+            }
+            None => (width, height),
+        })
+    }
+
+    /// Dots per inch from the `--resolution` scan parameter, if one was given.
+    pub fn resolution(&self) -> Option<f32> {
+        self.numeric_parameter("resolution")
+            .filter(|dpi| *dpi > 0.0)
     }
 
     pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
@@ -149,7 +794,7 @@ impl Scan {
 
         conn.query_row(
             "SELECT id, status, path, scanner, scan_parameters, scanned_at, scan_group_id,
-                    rotation, crop_coordinates, original_path, edited_path
+                    rotation, crop_coordinates, original_path, edited_path, processing_status, started_by, remove_punch_holes, review_state, reviewed_by, reviewed_at
              FROM scans WHERE id = ?",
             params![id],
             |row| {
@@ -173,6 +818,12 @@ impl Scan {
                     crop_coordinates: row.get(8)?,
                     original_path: original_path.map(|p| p.into()),
                     edited_path: edited_path.map(|p| p.into()),
+                    processing_status: ProcessingStatus::from_column(row.get(11)?),
+                    started_by: row.get(12)?,
+                    remove_punch_holes: row.get(13)?,
+                    review_state: ReviewState::from_column(row.get(14)?),
+                    reviewed_by: row.get(15)?,
+                    reviewed_at: row.get(16)?,
                 })
             },
         )
@@ -197,7 +848,9 @@ impl Scan {
                      rotation = ?,
                      crop_coordinates = ?,
                      original_path = ?,
-                     edited_path = ?
+                     edited_path = ?,
+                     started_by = ?,
+                     remove_punch_holes = ?
                      WHERE id = ?",
                     params![
                         self.status,
@@ -209,6 +862,8 @@ impl Scan {
                         self.crop_coordinates,
                         original_path,
                         edited_path,
+                        self.started_by,
+                        self.remove_punch_holes,
                         id
                     ],
                 )?;
@@ -225,9 +880,11 @@ impl Scan {
                         rotation,
                         crop_coordinates,
                         original_path,
-                        edited_path
+                        edited_path,
+                        started_by,
+                        remove_punch_holes
                     )
-                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
                     RETURNING id",
                     params![
                         self.status,
@@ -239,6 +896,8 @@ impl Scan {
                         self.crop_coordinates,
                         original_path,
                         edited_path,
+                        self.started_by,
+                        self.remove_punch_holes,
                     ],
                     |row| row.get(0),
                 )?;
@@ -264,15 +923,209 @@ impl Scan {
         } else {
             // Create a generic error result
             Err(duckdb::Error::ToSqlConversionFailure(Box::new(
-                std::io::Error::new(std::io::ErrorKind::Other, "Scan not saved yet"),
+                std::io::Error::other("Scan not saved yet"),
             )))
         }
     }
 
+    pub fn set_processing_status(
+        id: i32,
+        status: ProcessingStatus,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scans SET processing_status = ? WHERE id = ?",
+            params![status.as_str(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Sets the review state of several pages at once. Returns how many
+    /// were found.
+    pub fn set_review_state(
+        ids: &[i32],
+        state: ReviewState,
+        reviewed_by: Option<String>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<usize> {
+        let mut conn = pool.get().unwrap();
+        let tx = conn.transaction()?;
+        let now = Utc::now();
+        let mut updated = 0;
+        for id in ids {
+            updated += tx.execute(
+                "UPDATE scans SET review_state = ?, reviewed_by = ?, reviewed_at = ? WHERE id = ?",
+                params![state.as_str(), reviewed_by, now, id],
+            )?;
+        }
+        tx.commit()?;
+        if updated >= BATCH_CHECKPOINT_ROWS {
+            checkpoint(&conn);
+        }
+        Ok(updated)
+    }
+
+    /// Records why a scan failed, as the SANE status name (e.g. "JAMMED"), or clears it.
+    pub fn set_failure(
+        id: i32,
+        failure: Option<&str>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE scans SET failure = ? WHERE id = ?",
+            params![failure, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_failure(
+        id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<String>> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            "SELECT failure FROM scans WHERE id = ?",
+            params![id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Whether the scan's group is on hold.
+    pub fn is_held(&self, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            "SELECT COALESCE(bool_or(g.hold), false) FROM scans s
+             JOIN scan_groups g ON g.id = s.scan_group_id
+             WHERE s.id = ?",
+            params![self.id],
+            |row| row.get(0),
+        )
+    }
+
+    /// The colour dropped from the scan's group, if any.
+    pub fn color_dropout(
+        &self,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Option<DropoutColor>> {
+        let conn = pool.get().unwrap();
+        let color: Option<String> = conn
+            .query_row(
+                "SELECT g.color_dropout FROM scans s
+                 JOIN scan_groups g ON g.id = s.scan_group_id
+                 WHERE s.id = ?",
+                params![self.id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(DropoutColor::from_column(color))
+    }
+
+    /// Removes the scan and whatever it wrote to disk. Scans in a group on
+    /// hold are kept; returns whether the scan was deleted.
+    pub fn delete(
+        &self,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<bool> {
+        if self.is_held(pool)? {
+            return Ok(false);
+        }
+        let conn = pool.get().unwrap();
+        conn.execute("DELETE FROM scans WHERE id = ?", params![self.id])?;
+        conn.execute("DELETE FROM scan_texts WHERE scan_id = ?", params![self.id])?;
+        conn.execute(
+            "DELETE FROM dead_letters WHERE scan_id = ?",
+            params![self.id],
+        )?;
+        // Notes about the page stay on the group
+        conn.execute(
+            "UPDATE group_comments SET scan_id = NULL WHERE scan_id = ?",
+            params![self.id],
+        )?;
+        std::fs::remove_file(self.path.as_disk_path(&assets_dir.0)).ok();
+        if let Some(edited) = &self.edited_path {
+            std::fs::remove_file(edited.as_disk_path(&assets_dir.0)).ok();
+        }
+        if let Some(id) = self.id {
+            tiles::remove_tiles(id, assets_dir);
+            processing_profiles::remove_comparison(id, assets_dir);
+        }
+        Ok(true)
+    }
+
+    /// Drops the edited copy and zoom tiles, which were made for the old
+    /// rotation or crop, so the scan falls back to rendering from its
+    /// capture. Call before saving a change to either.
+    pub fn invalidate_edit(&mut self, assets_dir: &AssetsDir) {
+        if let Some(id) = self.id {
+            tiles::remove_tiles(id, assets_dir);
+        }
+        if let Some(edited) = self.edited_path.take() {
+            let edited_relative = edited.as_relative_path();
+            let shared = std::iter::once(&self.path)
+                .chain(&self.original_path)
+                .any(|path| path.as_relative_path() == edited_relative);
+            if !shared {
+                std::fs::remove_file(edited.as_disk_path(&assets_dir.0)).ok();
+            }
+        }
+    }
+
+    /// Sets the status of many scans at once, returning how many changed.
+    pub fn update_status_many(
+        ids: &[i32],
+        status: &str,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<usize> {
+        let mut conn = pool.get().unwrap();
+        let tx = conn.transaction()?;
+
+        let mut updated = 0;
+        for id in ids {
+            updated += tx.execute(
+                "UPDATE scans SET status = ? WHERE id = ?",
+                params![status, id],
+            )?;
+        }
+
+        tx.commit()?;
+        if updated >= BATCH_CHECKPOINT_ROWS {
+            checkpoint(&conn);
+        }
+        Ok(updated)
+    }
+
+    /// Puts the group's pages in the order of `ids`. Scans in the group that
+    /// aren't listed go after them, in the order they were scanned.
+    pub fn reorder(
+        group_id: i32,
+        ids: &[i32],
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let mut conn = pool.get().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "UPDATE scans SET page_order = NULL WHERE scan_group_id = ?",
+            params![group_id],
+        )?;
+        for (position, id) in ids.iter().enumerate() {
+            tx.execute(
+                "UPDATE scans SET page_order = ? WHERE id = ? AND scan_group_id = ?",
+                params![position as i32, id, group_id],
+            )?;
+        }
+
+        tx.commit()
+    }
+
     pub fn load_all_by_group(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<Scan> {
         let conn = pool.get().unwrap();
 
-        let sql = "SELECT id, status, path, scanner, scan_parameters, scanned_at, rotation, crop_coordinates, original_path, edited_path FROM scans WHERE scan_group_id = ?";
+        let sql = "SELECT id, status, path, scanner, scan_parameters, scanned_at, rotation, crop_coordinates, original_path, edited_path, processing_status, started_by, remove_punch_holes, review_state, reviewed_by, reviewed_at FROM scans WHERE scan_group_id = ? ORDER BY page_order, scanned_at, id";
 
         let mut stmt = conn.prepare(sql).unwrap();
 
@@ -292,6 +1145,12 @@ impl Scan {
                 crop_coordinates: row.get(7)?,
                 original_path: original_path.map(|p| p.into()),
                 edited_path: edited_path.map(|p| p.into()),
+                processing_status: ProcessingStatus::from_column(row.get(10)?),
+                started_by: row.get(11)?,
+                remove_punch_holes: row.get(12)?,
+                review_state: ReviewState::from_column(row.get(13)?),
+                reviewed_by: row.get(14)?,
+                reviewed_at: row.get(15)?,
                 group: None, // TODO: This is wrong?
             })
         };
@@ -304,4 +1163,80 @@ impl Scan {
 
         scans
     }
+
+    /// Loads scans matching a WHERE clause, with their groups attached.
+    pub fn load_where(
+        condition: &str,
+        params: &[Box<dyn duckdb::ToSql>],
+        sort: Option<ScanSort>,
+        limit: Option<i64>,
+        offset: i64,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<Scan> {
+        let conn = pool.get().unwrap();
+
+        let mut sql = format!(
+            "SELECT id, status, path, scanner, scan_parameters, scanned_at, scan_group_id,
+                    rotation, crop_coordinates, original_path, edited_path, processing_status, started_by, remove_punch_holes, review_state, reviewed_by, reviewed_at
+             FROM scans WHERE {} {}",
+            condition,
+            ScanSort::order_by(sort)
+        );
+        if let Some(limit) = limit {
+            sql = format!("{} LIMIT {}", sql, limit);
+        }
+        sql = format!("{} OFFSET {}", sql, offset);
+
+        let mut stmt = conn.prepare(&sql).unwrap();
+
+        let scans: Vec<Scan> = stmt
+            .query_map(duckdb::params_from_iter(params.iter()), |row| {
+                let path: String = row.get(2)?;
+                let original_path: Option<String> = row.get(9)?;
+                let edited_path: Option<String> = row.get(10)?;
+
+                Ok(Scan {
+                    id: Some(row.get(0)?),
+                    status: row.get(1)?,
+                    path: path.into(),
+                    scanner: row.get(3)?,
+                    scan_parameters: serde_json::from_str(&row.get::<usize, String>(4)?).unwrap(),
+                    scanned_at: row.get(5)?,
+                    group: match row.get::<usize, Option<i32>>(6)? {
+                        Some(group_id) => Some(ScanGroup::load(group_id, pool)?),
+                        None => None,
+                    },
+                    rotation: row.get(7)?,
+                    crop_coordinates: row.get(8)?,
+                    original_path: original_path.map(|p| p.into()),
+                    edited_path: edited_path.map(|p| p.into()),
+                    processing_status: ProcessingStatus::from_column(row.get(11)?),
+                    started_by: row.get(12)?,
+                    remove_punch_holes: row.get(13)?,
+                    review_state: ReviewState::from_column(row.get(14)?),
+                    reviewed_by: row.get(15)?,
+                    reviewed_at: row.get(16)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        scans
+    }
+
+    pub fn count_where(
+        condition: &str,
+        params: &[Box<dyn duckdb::ToSql>],
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> i64 {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT COUNT(*) FROM scans WHERE {}", condition),
+            duckdb::params_from_iter(params.iter()),
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
 }
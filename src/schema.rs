@@ -1,12 +1,62 @@
-use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use crate::{
-    scanners::{ScannerInfo, ScannerManager},
-    scans::{self, CropCoordinates, Scan, ScanGroup},
+    activity::{Activity, ActivityKind},
+    alerts::Alert,
+    analytics::AnalyticsExport,
+    api_keys::{ApiKey, CreatedApiKey},
+    app_context::{AppContext, ContextExt},
+    archive::{self, ArchiveReport},
+    artifacts::Artifact,
+    auth::{ClientIp, Principal, RequireScope, Scope, SessionToken, SESSION_COOKIE},
+    batches::{BatchPaused, ScanBatch},
+    calibration::CalibrationReport,
+    classification::{
+        classify_group, ClassificationRule, ClassificationRuleInput, ClassificationRun,
+        ClassificationTest, GroupText,
+    },
+    config_bundle::{ConfigBundle, ConfigImport},
+    db_config::{checkpoint, BATCH_CHECKPOINT_ROWS},
+    dead_letters::DeadLetter,
+    destination_mappings::{DestinationMapping, MetadataField},
+    digest::WeeklyDigest,
+    dropout::DropoutColor,
+    duplicates::{DuplicateResolution, GroupDuplicate},
+    export_history::GroupExport,
+    group_assignments::{GroupAssignment, GroupAssignmentChanged},
+    group_comments::{CommentChange, GroupComment, GroupCommentChanged},
+    group_links::{normalize_role, GroupLink, MAX_DEPTH},
+    ingest_rules::{IngestRule, IngestRuleInput},
+    known_scanners,
+    ldap_sync::{self, LdapSyncReport},
+    login_events::{LoginEvent, LoginOutcome},
+    page_numbers::{check_group, PageNumberReport},
+    preview,
+    processing_profiles::{
+        self, Candidate, ProcessingProfile, ProcessingProfileInput, ProfileVariant,
+    },
+    scan_presets::{self, ScanPreset, ScanPresetInput},
+    scan_queue::ScanPriority,
+    scan_templates::{self, PaperSize, TemplateVariable},
+    scanner_options::ScannerOption,
+    scanners::{self, ScanProgress, ScannerActivity, ScannerInfo, ScannersChanged},
+    scans::{
+        self, CropCoordinates, GroupFilter, GroupSort, ReviewProgress, ReviewState, Scan,
+        ScanCounts, ScanGroup, ScanSort,
+    },
+    schema_changes::{self, SchemaChange},
+    self_test::{self, SelfTestReport},
     simple_broker::SimpleBroker,
-    AssetsDir,
+    stitch::{stitch_scans, StitchDirection},
+    system_status::SystemStatus,
+    tag_suggestions::TagSuggestion,
+    tiles, title_suggestions,
+    uploads::Upload,
+    users::{Role, User, SESSION_LIFETIME_DAYS},
+    year_in_review::YearInReview,
 };
 use async_graphql::{Context, Enum, Object, Result, Schema, Subscription, ID};
+use chrono::{DateTime, NaiveDate, Utc};
 use duckdb::params;
 use futures_util::{lock::Mutex, Stream, StreamExt};
 use slab::Slab;
@@ -41,28 +91,81 @@ pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
-    async fn books(&self, ctx: &Context<'_>) -> Vec<Book> {
-        let books = ctx.data_unchecked::<Storage>().lock().await;
-        books.iter().map(|(_, book)| book).cloned().collect()
+    #[graphql(
+        guard = "RequireScope(Scope::Read)",
+        deprecation = "Demo data from the starter template, removed after 2027-01-13"
+    )]
+    async fn books(&self, ctx: &Context<'_>) -> Result<Vec<Book>> {
+        let books = ctx.app()?.books.lock().await;
+        Ok(books.iter().map(|(_, book)| book).cloned().collect())
     }
 
-    async fn scanners(&self, ctx: &Context<'_>) -> Vec<ScannerInfo> {
-        let scanner_manager = ctx.data_unchecked::<ScannerManager>();
-        scanner_manager.list_scanners().await
+    /// Pass `refresh` to probe the devices again rather than use the list
+    /// from the last ten minutes, e.g. to check for paper in the feeder
+    /// before starting a batch. Devices seen before but not listed now
+    /// follow, marked `offline`.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scanners(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] refresh: bool,
+    ) -> Result<Vec<ScannerInfo>> {
+        let scanner_manager = &ctx.app()?.scanner_manager;
+        let pool = &ctx.app()?.pool;
+        let scanners = scanner_manager.list_known_scanners(refresh, pool).await;
+        if let Some(reason) = scanner_manager.unavailable() {
+            return Err(reason.into());
+        }
+        Ok(scanners)
+    }
+
+    /// The options a device accepts, with their allowed values and
+    /// defaults, to pass in `scanParameters` by their flag. For a device
+    /// that can't be asked now, e.g. an offline or busy one, the options
+    /// stored when it was last listed.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scanner_options(&self, ctx: &Context<'_>, name: String) -> Result<Vec<ScannerOption>> {
+        let scanner_manager = &ctx.app()?.scanner_manager;
+        let pool = &ctx.app()?.pool;
+        match scanner_manager.scanner_options(&name).await {
+            Ok(options) => Ok(options),
+            Err(e) => Ok(known_scanners::options(&name, pool)?.ok_or(e)?),
+        }
+    }
+
+    /// Checks the deployment can store scans, run scanimage and tesseract,
+    /// and write to export destinations.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn self_test(&self, ctx: &Context<'_>) -> Result<SelfTestReport> {
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+        Ok(tokio::task::spawn_blocking(move || self_test::run(&pool, &assets_dir)).await?)
     }
 
-    async fn staleness(&self, ctx: &Context<'_>) -> u64 {
-        let scanner_manager = ctx.data_unchecked::<ScannerManager>();
+    /// Setup problems to warn about, e.g. SANE not being installed.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn system_status(&self, ctx: &Context<'_>) -> Result<SystemStatus> {
+        Ok(SystemStatus::check(&ctx.app()?.scanner_manager))
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn staleness(&self, ctx: &Context<'_>) -> Result<u64> {
+        let scanner_manager = &ctx.app()?.scanner_manager;
         let last_refreshed = scanner_manager.last_refreshed().await;
-        last_refreshed.elapsed().as_millis() as u64
+        Ok(last_refreshed.elapsed().as_millis() as u64)
     }
 
-    async fn scans(&self, ctx: &Context<'_>) -> Vec<crate::scans::Scan> {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scans(
+        &self,
+        ctx: &Context<'_>,
+        sort: Option<ScanSort>,
+    ) -> Result<Vec<crate::scans::Scan>> {
+        let pool = &ctx.app()?.pool;
         let conn = pool.get().unwrap();
 
         let mut stmt = conn
-            .prepare("SELECT id, status, path, scanner, scan_parameters, scanned_at, scan_group_id, rotation, crop_coordinates, original_path, edited_path FROM scans")
+            .prepare(&format!("SELECT id, status, path, scanner, scan_parameters, scanned_at, scan_group_id, rotation, crop_coordinates, original_path, edited_path, processing_status, started_by, remove_punch_holes, review_state, reviewed_by, reviewed_at FROM scans {}", ScanSort::order_by(sort)))
             .unwrap();
 
         let scans = stmt
@@ -78,7 +181,7 @@ impl QueryRoot {
                     scan_parameters,
                     scanned_at: row.get(5)?,
                     group: if row.get::<usize, Option<i32>>(6)?.is_some() {
-                        Some(crate::scans::ScanGroup::load(row.get(6)?, &pool).unwrap())
+                        Some(crate::scans::ScanGroup::load(row.get(6)?, pool).unwrap())
                     } else {
                         None
                     },
@@ -86,92 +189,123 @@ impl QueryRoot {
                     crop_coordinates: row.get(8)?,
                     original_path: row.get::<usize, Option<String>>(9)?.map(|p| p.into()),
                     edited_path: row.get::<usize, Option<String>>(10)?.map(|p| p.into()),
+                    processing_status: scans::ProcessingStatus::from_column(row.get(11)?),
+                    started_by: row.get(12)?,
+                    remove_punch_holes: row.get(13)?,
+                    review_state: scans::ReviewState::from_column(row.get(14)?),
+                    reviewed_by: row.get(15)?,
+                    reviewed_at: row.get(16)?,
                 })
             })
             .unwrap()
             .map(Result::unwrap)
             .collect();
 
-        scans
+        Ok(scans)
     }
 
-    async fn dividers(&self, ctx: &Context<'_>) -> Vec<crate::scan_dividers::ScanDivider> {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
-        let conn = pool.get().unwrap();
-
-        let mut stmt = conn.prepare("SELECT id, ts FROM scan_dividers").unwrap();
-
-        let dividers = stmt
-            .query_map([], |row| {
-                Ok(crate::scan_dividers::ScanDivider {
-                    id: row.get(0)?,
-                    ts: row.get(1)?,
-                })
-            })
-            .unwrap()
-            .map(Result::unwrap)
-            .collect();
-
-        dividers
+    /// Dividers in time order, each with the scans up to the next one.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn dividers(&self, ctx: &Context<'_>) -> Result<Vec<crate::scan_dividers::ScanDivider>> {
+        let pool = &ctx.app()?.pool;
+        Ok(crate::scan_dividers::ScanDivider::load_all(pool))
     }
 
+    #[graphql(guard = "RequireScope(Scope::Read)")]
     async fn groups(
         &self,
         ctx: &Context<'_>,
         status: Option<String>,
-    ) -> Vec<crate::scans::ScanGroup> {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+        filter: Option<GroupFilter>,
+        sort: Option<GroupSort>,
+        limit: Option<i64>,
+        #[graphql(default)] offset: i64,
+    ) -> Result<Vec<crate::scans::ScanGroup>> {
+        let pool = &ctx.app()?.pool;
         let conn = pool.get().unwrap();
 
-        let sql = match status {
-            Some(_) => "SELECT id, title, created_at, updated_at, status, comment, tags FROM scan_groups WHERE status = ?",
-            None => "SELECT id, title, created_at, updated_at, status, comment, tags FROM scan_groups",
-        };
+        let (mut conditions, mut params) = filter.unwrap_or_default().to_sql();
+        if let Some(status) = status {
+            conditions.push("status = ?".to_string());
+            params.push(Box::new(status));
+        }
 
-        let mut stmt = conn.prepare(sql).unwrap();
+        let mut sql =
+            "SELECT id, title, created_at, updated_at, status, comment, tags, page_count_warning, dewarp, remove_gutter_shadow, hold, color_dropout, processing_profile_id FROM scan_groups"
+                .to_string();
+        if !conditions.is_empty() {
+            sql = format!("{} WHERE {}", sql, conditions.join(" AND "));
+        }
+        sql = format!("{} {}", sql, GroupSort::order_by(sort));
+        if let Some(limit) = limit {
+            sql = format!("{} LIMIT ?", sql);
+            params.push(Box::new(limit));
+        }
+        sql = format!("{} OFFSET ?", sql);
+        params.push(Box::new(offset));
+
+        let mut stmt = conn.prepare(&sql).unwrap();
 
         let row_mapper = |row: &duckdb::Row| -> duckdb::Result<crate::scans::ScanGroup> {
             let tags_json: String = row.get(6)?;
             let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+            let scans = Scan::load_all_by_group(row.get(0)?, pool);
+            let title: String = row.get(1)?;
+            let suggested_title = title_suggestions::suggest(&scans, &title, pool);
 
             Ok(crate::scans::ScanGroup {
                 id: row.get(0)?,
-                title: row.get(1)?,
+                title,
                 created_at: row.get(2)?,
                 updated_at: row.get(3)?,
                 status: row.get(4)?,
                 comment: row.get(5)?,
                 tags,
-                scans: Scan::load_all_by_group(row.get(0)?, &pool),
+                page_count_warning: row.get(7)?,
+                dewarp: row.get(8)?,
+                remove_gutter_shadow: row.get(9)?,
+                hold: row.get(10)?,
+                color_dropout: DropoutColor::from_column(row.get(11)?),
+                processing_profile_id: row.get(12)?,
+                scans,
+                exports: GroupExport::load_all_by_group(row.get(0)?, pool),
+                suggested_tags: TagSuggestion::load_all_by_group(row.get(0)?, pool),
+                suggested_title,
+                possible_duplicates: GroupDuplicate::load_all_by_group(row.get(0)?, pool),
             })
         };
 
-        let groups = if let Some(status_val) = status {
-            stmt.query_map([status_val], row_mapper)
-        } else {
-            stmt.query_map([], row_mapper)
-        }
-        .unwrap()
-        .map(Result::unwrap)
-        .collect();
+        let groups = stmt
+            .query_map(duckdb::params_from_iter(params.iter()), row_mapper)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
 
-        groups
+        Ok(groups)
     }
 
-    async fn group_by_id(&self, ctx: &Context<'_>, id: i32) -> Option<crate::scans::ScanGroup> {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
-        match crate::scans::ScanGroup::load(id, &pool) {
-            Ok(group) => Some(group),
-            Err(_) => None,
-        }
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn group_by_id(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+    ) -> Result<Option<crate::scans::ScanGroup>> {
+        let pool = &ctx.app()?.pool;
+        Ok(crate::scans::ScanGroup::load(id, pool).ok())
     }
 
-    async fn scans_by_group(&self, ctx: &Context<'_>, group_id: i32) -> Vec<crate::scans::Scan> {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scans_by_group(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        sort: Option<ScanSort>,
+    ) -> Result<Vec<crate::scans::Scan>> {
+        let pool = &ctx.app()?.pool;
         let conn = pool.get().unwrap();
 
         let mut stmt = conn
-            .prepare("SELECT id, status, path, scanner, scan_parameters, scanned_at, scan_group_id, rotation, crop_coordinates, original_path, edited_path FROM scans WHERE scan_group_id = ?")
+            .prepare(&format!("SELECT id, status, path, scanner, scan_parameters, scanned_at, scan_group_id, rotation, crop_coordinates, original_path, edited_path, processing_status, started_by, remove_punch_holes, review_state, reviewed_by, reviewed_at FROM scans WHERE scan_group_id = ? {}", ScanSort::order_by(sort)))
             .unwrap();
 
         let scans = stmt
@@ -186,42 +320,123 @@ impl QueryRoot {
                     scanner: row.get(3)?,
                     scan_parameters,
                     scanned_at: row.get(5)?,
-                    group: Some(crate::scans::ScanGroup::load(row.get(6)?, &pool).unwrap()),
+                    group: Some(crate::scans::ScanGroup::load(row.get(6)?, pool).unwrap()),
                     rotation: row.get(7)?,
                     crop_coordinates: row.get(8)?,
                     original_path: row.get::<usize, Option<String>>(9)?.map(|p| p.into()),
                     edited_path: row.get::<usize, Option<String>>(10)?.map(|p| p.into()),
+                    processing_status: scans::ProcessingStatus::from_column(row.get(11)?),
+                    started_by: row.get(12)?,
+                    remove_punch_holes: row.get(13)?,
+                    review_state: scans::ReviewState::from_column(row.get(14)?),
+                    reviewed_by: row.get(15)?,
+                    reviewed_at: row.get(16)?,
                 })
             })
             .unwrap()
             .map(Result::unwrap)
             .collect();
 
-        scans
+        Ok(scans)
+    }
+
+    /// Scans not yet assigned to any group, the worklist for page assignment.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn ungrouped_scans(
+        &self,
+        ctx: &Context<'_>,
+        sort: Option<ScanSort>,
+        limit: Option<i64>,
+        #[graphql(default)] offset: i64,
+    ) -> Result<Vec<Scan>> {
+        let pool = &ctx.app()?.pool;
+        Ok(Scan::load_where(
+            "scan_group_id IS NULL",
+            &[],
+            sort,
+            limit,
+            offset,
+            pool,
+        ))
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn ungrouped_scan_count(&self, ctx: &Context<'_>) -> Result<i64> {
+        let pool = &ctx.app()?.pool;
+        Ok(Scan::count_where("scan_group_id IS NULL", &[], pool))
+    }
+
+    /// Failed scans, most recent first, optionally only those since a time.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn failed_scans(
+        &self,
+        ctx: &Context<'_>,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Scan>> {
+        let pool = &ctx.app()?.pool;
+
+        Ok(match since {
+            Some(since) => Scan::load_where(
+                "status = 'FAILED' AND scanned_at >= ?::TIMESTAMP",
+                &[Box::new(since)],
+                Some(ScanSort::ScannedAtDesc),
+                None,
+                0,
+                pool,
+            ),
+            None => Scan::load_where(
+                "status = 'FAILED'",
+                &[],
+                Some(ScanSort::ScannedAtDesc),
+                None,
+                0,
+                pool,
+            ),
+        })
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn counts(&self, ctx: &Context<'_>) -> Result<ScanCounts> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanCounts::load(pool).unwrap())
     }
 
-    async fn incomplete_groups(&self, ctx: &Context<'_>) -> Vec<ScanGroup> {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn incomplete_groups(&self, ctx: &Context<'_>) -> Result<Vec<ScanGroup>> {
+        let pool = &ctx.app()?.pool;
         let conn = pool.get().unwrap();
 
         // First, get all non-finalized groups (status = 'scanning')
-        let sql = "SELECT id, title, created_at, updated_at, status, comment, tags FROM scan_groups WHERE status != 'finalized' ORDER BY created_at ASC";
+        let sql = "SELECT id, title, created_at, updated_at, status, comment, tags, page_count_warning, dewarp, remove_gutter_shadow, hold, color_dropout, processing_profile_id FROM scan_groups WHERE status != 'finalized' ORDER BY created_at ASC";
 
         let mut stmt = conn.prepare(sql).unwrap();
 
         let row_mapper = |row: &duckdb::Row| -> duckdb::Result<crate::scans::ScanGroup> {
             let tags_json: String = row.get(6)?;
             let tags: Vec<String> = serde_json::from_str(&tags_json).unwrap_or_default();
+            let scans = Scan::load_all_by_group(row.get(0)?, pool);
+            let title: String = row.get(1)?;
+            let suggested_title = title_suggestions::suggest(&scans, &title, pool);
 
             Ok(crate::scans::ScanGroup {
                 id: row.get(0)?,
-                title: row.get(1)?,
+                title,
                 created_at: row.get(2)?,
                 updated_at: row.get(3)?,
                 status: row.get(4)?,
                 comment: row.get(5)?,
                 tags,
-                scans: Scan::load_all_by_group(row.get(0)?, &pool),
+                page_count_warning: row.get(7)?,
+                dewarp: row.get(8)?,
+                remove_gutter_shadow: row.get(9)?,
+                hold: row.get(10)?,
+                color_dropout: DropoutColor::from_column(row.get(11)?),
+                processing_profile_id: row.get(12)?,
+                scans,
+                exports: GroupExport::load_all_by_group(row.get(0)?, pool),
+                suggested_tags: TagSuggestion::load_all_by_group(row.get(0)?, pool),
+                suggested_title,
+                possible_duplicates: GroupDuplicate::load_all_by_group(row.get(0)?, pool),
             })
         };
 
@@ -231,16 +446,364 @@ impl QueryRoot {
             .map(Result::unwrap)
             .collect();
 
-        groups
+        Ok(groups)
+    }
+
+    /// Reads printed page numbers off the group's scans and checks them
+    /// against the page order. Needs tesseract installed on the server.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn page_number_check(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+    ) -> Result<PageNumberReport> {
+        let pool = &ctx.app()?.pool;
+        let assets_dir = &ctx.app()?.assets_dir;
+        Ok(check_group(group_id, pool, assets_dir).await?)
+    }
+
+    /// Feeder batches, newest first.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn batches(&self, ctx: &Context<'_>) -> Result<Vec<ScanBatch>> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanBatch::load_all(pool))
+    }
+
+    /// A stored export file; download it from `/api/artifacts/{id}`.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn artifact(&self, ctx: &Context<'_>, id: i32) -> Result<Option<Artifact>> {
+        let pool = &ctx.app()?.pool;
+        Ok(Artifact::load(id, pool)?)
+    }
+
+    /// Comments left on the group, oldest first.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn group_comments(&self, ctx: &Context<'_>, group_id: i32) -> Result<Vec<GroupComment>> {
+        let pool = &ctx.app()?.pool;
+        Ok(GroupComment::load_all_by_group(group_id, pool))
+    }
+
+    /// Earlier calibration scans measured, newest first, optionally only
+    /// for one scanner.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn calibrations(
+        &self,
+        ctx: &Context<'_>,
+        device: Option<String>,
+    ) -> Result<Vec<CalibrationReport>> {
+        let pool = &ctx.app()?.pool;
+        Ok(CalibrationReport::load_all(device.as_deref(), pool))
+    }
+
+    /// Groups related to this one, from links made at either end.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn group_links(&self, ctx: &Context<'_>, group_id: i32) -> Result<Vec<GroupLink>> {
+        let pool = &ctx.app()?.pool;
+        Ok(GroupLink::load_all_by_group(group_id, pool))
+    }
+
+    /// Follows links out from the group, up to `depth` links away, and
+    /// returns the link each related group was first reached by, nearest
+    /// first. With `linkedRole`, only follows links to groups in that role.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn linked_groups(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        linked_role: Option<String>,
+        #[graphql(default = 1)] depth: i32,
+    ) -> Result<Vec<GroupLink>> {
+        if !(1..=MAX_DEPTH as i32).contains(&depth) {
+            return Err(format!("depth must be between 1 and {}", MAX_DEPTH).into());
+        }
+        let pool = &ctx.app()?.pool;
+        Ok(GroupLink::traverse(
+            group_id,
+            linked_role.as_deref(),
+            depth as usize,
+            pool,
+        ))
+    }
+
+    /// What's been happening, newest first: scans completed, groups
+    /// finalized, exports delivered or failed, and comments, optionally only
+    /// some kinds.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn activity(
+        &self,
+        ctx: &Context<'_>,
+        kinds: Option<Vec<ActivityKind>>,
+        #[graphql(default = 50)] limit: i64,
+        #[graphql(default)] offset: i64,
+    ) -> Result<Vec<Activity>> {
+        let pool = &ctx.app()?.pool;
+        Ok(Activity::load_recent(kinds.as_deref(), limit, offset, pool))
+    }
+
+    /// How many of the group's pages are approved, waiting for review or
+    /// sent back for rescanning.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn review_progress(&self, ctx: &Context<'_>, group_id: i32) -> Result<ReviewProgress> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanGroup::review_progress(group_id, pool)?)
+    }
+
+    /// Who the group is assigned to for review, if anyone.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn group_assignment(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+    ) -> Result<Option<GroupAssignment>> {
+        let pool = &ctx.app()?.pool;
+        Ok(GroupAssignment::load(group_id, pool)?)
+    }
+
+    /// Groups assigned to the signed-in user, in the order they were assigned.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn assigned_to_me(&self, ctx: &Context<'_>) -> Result<Vec<ScanGroup>> {
+        let pool = &ctx.app()?.pool;
+        let Some(Principal::User(user)) = ctx.data_opt::<Principal>() else {
+            return Err("Only signed-in users have groups assigned to them".into());
+        };
+        Ok(GroupAssignment::load_all_by_user(user.id, pool)
+            .into_iter()
+            .filter_map(|assignment| ScanGroup::load(assignment.group_id, pool).ok())
+            .collect())
+    }
+
+    /// Deprecations and removals in the API, oldest first, optionally only
+    /// those announced on or after `since`.
+    async fn schema_changes(&self, since: Option<NaiveDate>) -> Vec<SchemaChange> {
+        schema_changes::all()
+            .into_iter()
+            .filter(|change| since.is_none_or(|since| change.announced_on >= since))
+            .collect()
+    }
+
+    /// The date of the latest entry in `schemaChanges`, null if there are none.
+    async fn api_version(&self) -> Option<NaiveDate> {
+        schema_changes::api_version()
+    }
+
+    /// Problems found by the alert rules, newest first, optionally only those
+    /// not yet resolved.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn alerts(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] active_only: bool,
+    ) -> Result<Vec<Alert>> {
+        let pool = &ctx.app()?.pool;
+        Ok(Alert::load_all(active_only, pool))
+    }
+
+    /// Weekly digests already sent, newest first.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn digests(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 10)] limit: i32,
+    ) -> Result<Vec<WeeklyDigest>> {
+        let pool = &ctx.app()?.pool;
+        Ok(WeeklyDigest::load_recent(limit, pool))
+    }
+
+    /// Scans that failed every attempt, newest first.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn dead_letters(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] include_requeued: bool,
+    ) -> Result<Vec<DeadLetter>> {
+        let pool = &ctx.app()?.pool;
+        Ok(DeadLetter::load_all(include_requeued, pool))
+    }
+
+    /// Recent uploads, newest first, with how far each has got.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn uploads(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 20)] limit: i32,
+    ) -> Result<Vec<Upload>> {
+        let pool = &ctx.app()?.pool;
+        Ok(Upload::load_recent(limit, pool))
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn upload(&self, ctx: &Context<'_>, id: i32) -> Result<Upload> {
+        let pool = &ctx.app()?.pool;
+        Ok(Upload::load(id, pool)?)
+    }
+
+    /// When the read-only analytics snapshot was taken, null if analytics read the live database.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn snapshot_refreshed_at(&self, ctx: &Context<'_>) -> Result<Option<DateTime<Utc>>> {
+        Ok(ctx.app()?.snapshot.refreshed_at())
+    }
+
+    /// Pages scanned, documents finalized, top tags, storage growth and the
+    /// busiest scanners in a calendar year, in the server's local time. Reads
+    /// the analytics snapshot if one has been taken.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn year_in_review(&self, ctx: &Context<'_>, year: i32) -> Result<YearInReview> {
+        if !(1970..=9999).contains(&year) {
+            return Err("year must be between 1970 and 9999".into());
+        }
+        let pool = ctx.app()?.snapshot.pool(&ctx.app()?.pool);
+        let assets_dir = ctx.app()?.assets_dir.clone();
+
+        tokio::task::spawn_blocking(move || {
+            YearInReview::load(year, &pool, &assets_dir).map_err(|e| e.to_string().into())
+        })
+        .await?
+    }
+
+    /// Saved scan parameters, optionally only those for `scanner`.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scan_presets(
+        &self,
+        ctx: &Context<'_>,
+        scanner: Option<String>,
+    ) -> Result<Vec<ScanPreset>> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanPreset::load_all(scanner.as_deref(), pool))
+    }
+
+    /// Named cleanup profiles that groups can run on their pages.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn processing_profiles(&self, ctx: &Context<'_>) -> Result<Vec<ProcessingProfile>> {
+        let pool = &ctx.app()?.pool;
+        Ok(ProcessingProfile::load_all(pool))
+    }
+
+    /// Rules filing documents that arrive by email or FTP.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn ingest_rules(&self, ctx: &Context<'_>) -> Result<Vec<IngestRule>> {
+        let pool = &ctx.app()?.pool;
+        Ok(IngestRule::load_all(pool))
+    }
+
+    /// Metadata written beside exports to each destination directory.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn destination_mappings(&self, ctx: &Context<'_>) -> Result<Vec<DestinationMapping>> {
+        let pool = &ctx.app()?.pool;
+        Ok(DestinationMapping::load_all(pool))
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn api_keys(&self, ctx: &Context<'_>) -> Result<Vec<ApiKey>> {
+        let pool = &ctx.app()?.pool;
+        Ok(ApiKey::load_all(pool))
     }
+
+    /// The logged-in user, if the request carries a valid session cookie.
+    async fn me(&self, ctx: &Context<'_>) -> Option<User> {
+        match ctx.data_opt::<Principal>() {
+            Some(Principal::User(user)) => Some(user.clone()),
+            _ => None,
+        }
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn users(&self, ctx: &Context<'_>) -> Result<Vec<User>> {
+        let pool = &ctx.app()?.pool;
+        Ok(User::load_all(pool))
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn login_events(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 100)] limit: i32,
+    ) -> Result<Vec<LoginEvent>> {
+        let pool = &ctx.app()?.pool;
+        Ok(LoginEvent::load_recent(limit, pool))
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn classification_rules(&self, ctx: &Context<'_>) -> Result<Vec<ClassificationRule>> {
+        let pool = &ctx.app()?.pool;
+        Ok(ClassificationRule::load_all(pool))
+    }
+
+    /// Ingest and classification rules as JSON, for `importConfigBundle` on
+    /// another server. Credentials aren't included.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn export_config_bundle(&self, ctx: &Context<'_>) -> Result<String> {
+        let pool = &ctx.app()?.pool;
+        Ok(serde_json::to_string_pretty(&ConfigBundle::export(pool))?)
+    }
+
+    /// Rules applied to groups and what they changed, newest first.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn classification_runs(
+        &self,
+        ctx: &Context<'_>,
+        group_id: Option<i32>,
+        rule_id: Option<i32>,
+        #[graphql(default = 100)] limit: i32,
+    ) -> Result<Vec<ClassificationRun>> {
+        let pool = &ctx.app()?.pool;
+        Ok(ClassificationRun::load_recent(
+            group_id, rule_id, limit, pool,
+        ))
+    }
+
+    /// What a rule would do, without saving it or changing anything: to
+    /// sample `text`, or to a group, whose pages are run through OCR first
+    /// if they haven't been.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn test_classification_rule(
+        &self,
+        ctx: &Context<'_>,
+        rule: ClassificationRuleInput,
+        text: Option<String>,
+        group_id: Option<i32>,
+    ) -> Result<ClassificationTest> {
+        let rule = ClassificationRule::unsaved(rule);
+        let (title, text) = match (text, group_id) {
+            (Some(text), None) => (String::new(), GroupText::new(text)),
+            (None, Some(group_id)) => {
+                let pool = ctx.app()?.pool.clone();
+                let assets_dir = ctx.app()?.assets_dir.clone();
+                tokio::task::spawn_blocking(move || {
+                    let group = ScanGroup::load(group_id, &pool)
+                        .map_err(|_| format!("group {} not found", group_id))?;
+                    let text = GroupText::read(&group, &pool, &assets_dir)
+                        .map_err(|e| format!("could not read text from scan: {}", e))?;
+                    Ok::<_, String>((group.title, text))
+                })
+                .await??
+            }
+            _ => return Err("give either text or groupId".into()),
+        };
+        Ok(rule.test(&title, &text))
+    }
+}
+
+/// Runs the group's processing profile on a scan whose edited copy was
+/// just dropped, without holding up the mutation.
+fn reprocess_in_background(scan_id: i32, app: &AppContext) {
+    let (pool, assets_dir) = (app.pool.clone(), app.assets_dir.clone());
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = processing_profiles::process_scan(scan_id, &pool, &assets_dir) {
+            println!("Failed to process scan {}: {}", scan_id, e);
+        }
+    });
 }
 
 pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
-    async fn create_book(&self, ctx: &Context<'_>, name: String, author: String) -> ID {
-        let mut books = ctx.data_unchecked::<Storage>().lock().await;
+    #[graphql(
+        guard = "RequireScope(Scope::Write)",
+        deprecation = "Demo data from the starter template, removed after 2027-01-13"
+    )]
+    async fn create_book(&self, ctx: &Context<'_>, name: String, author: String) -> Result<ID> {
+        let mut books = ctx.app()?.books.lock().await;
         let entry = books.vacant_entry();
         let id: ID = entry.key().into();
         let book = Book {
@@ -253,11 +816,15 @@ impl MutationRoot {
             mutation_type: MutationType::Created,
             id: id.clone(),
         });
-        id
+        Ok(id)
     }
 
+    #[graphql(
+        guard = "RequireScope(Scope::Write)",
+        deprecation = "Demo data from the starter template, removed after 2027-01-13"
+    )]
     async fn delete_book(&self, ctx: &Context<'_>, id: ID) -> Result<bool> {
-        let mut books = ctx.data_unchecked::<Storage>().lock().await;
+        let mut books = ctx.app()?.books.lock().await;
         let id = id.parse::<usize>()?;
         if books.contains(id) {
             books.remove(id);
@@ -271,85 +838,117 @@ impl MutationRoot {
         }
     }
 
+    /// Starts a scan and returns its id. With `batch` and a feeder source,
+    /// every sheet in the feeder is scanned, each into its own scan in the
+    /// same group; the id returned is the first page's, and the rest are
+    /// announced by `scannerActivity` as they land. It runs as a batch job,
+    /// listed by `batches`, so it pauses on jams and double feeds like one
+    /// from `startBatch`. `duplex` is a batch from the device's duplex
+    /// source, each sheet's front then back. Batches without a group get a
+    /// new one. With `presetId`, the device and
+    /// parameters come from the preset; any `parameters` given are applied
+    /// over the preset's, and `name` scans on another device.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    #[allow(clippy::too_many_arguments)]
     async fn scan(
         &self,
         ctx: &Context<'_>,
-        name: String,
-        parameters: String,
+        name: Option<String>,
+        parameters: Option<String>,
+        preset_id: Option<i32>,
         group_id: Option<i32>,
-    ) -> i32 {
-        // Clone all context data to ensure 'static lifetimes for the async task
-        let scanner_manager = ctx.data_unchecked::<ScannerManager>().clone();
-        let pool = ctx
-            .data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>()
-            .clone();
-        let assets_dir = ctx.data_unchecked::<AssetsDir>().clone();
-        let parameters: HashMap<String, String> = serde_json::from_str(&parameters).unwrap();
-
-        // First step: create the scan with a placeholder path
-        std::fs::create_dir_all(&assets_dir.0).unwrap();
-        std::fs::create_dir_all(&Path::new(&assets_dir.0).join("scans")).unwrap();
-
-        let mut scan = Scan::new(
-            "PENDING".to_string(),
-            Path::new("scans")
-                .join("tmp.png")
-                .as_os_str()
-                .to_str()
-                .unwrap()
-                .to_string(),
-            name.clone(),
-            parameters.clone(),
-            chrono::Utc::now(),
-        );
-
-        // Save scan to get an ID
-        scan.save(&pool).unwrap();
-        let scan_id = scan.id.unwrap();
-
-        // If a group_id is provided, immediately associate the scan with the group
-        if let Some(group_id) = group_id {
-            scan.set_group(group_id, &pool).unwrap();
-        }
-
-        // Create clones for the async task
-        let name_clone = name.clone();
-        let parameters_clone = parameters.clone();
-        let assets_dir_clone = assets_dir.clone();
-        let pool_clone = pool.clone();
-        let scanner_manager_clone = scanner_manager.clone();
+        #[graphql(default)] priority: ScanPriority,
+        paper_size: Option<PaperSize>,
+        variables: Option<Vec<TemplateVariable>>,
+        #[graphql(default)] batch: bool,
+        #[graphql(default)] duplex: bool,
+    ) -> Result<i32> {
+        let parameters: HashMap<String, String> = match parameters {
+            Some(parameters) => serde_json::from_str(&parameters)?,
+            None => HashMap::new(),
+        };
+        let started_by = ctx.data_opt::<Principal>().map(Principal::attribution);
+        start_scan(
+            ctx.app()?,
+            NewScan {
+                name,
+                parameters,
+                preset_id,
+                group_id,
+                priority,
+                paper_size,
+                variables: variables.unwrap_or_default(),
+                batch,
+                duplex,
+            },
+            started_by,
+        )
+        .await
+    }
 
-        // Start the actual scanning process in the background
-        tokio::spawn(async move {
-            scanner_manager_clone
-                .complete_scan(
-                    scan_id,
-                    &name_clone,
-                    parameters_clone,
-                    &pool_clone,
-                    &assets_dir_clone,
-                )
-                .await;
-        });
+    /// Stops a scan that's waiting for its device or being taken, killing
+    /// scanimage. The scan is marked CANCELLED and its partial file removed;
+    /// pages a batch already saved are kept. Returns false if the scan
+    /// wasn't waiting or running.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn cancel_scan(&self, ctx: &Context<'_>, scan_id: i32) -> Result<bool> {
+        Ok(ctx.app()?.scanner_manager.cancel_scan(scan_id))
+    }
 
-        // Return the scan ID immediately to the client
-        scan_id
+    /// Scans a quick low-resolution page with these parameters and returns
+    /// its temporary asset path, without recording a scan, to check the
+    /// page before scanning it properly. It goes ahead of queued scans.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn preview_scan(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        parameters: String,
+        paper_size: Option<PaperSize>,
+        variables: Option<Vec<TemplateVariable>>,
+    ) -> Result<String> {
+        let scanner_manager = ctx.app()?.scanner_manager.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+        let parameters: HashMap<String, String> = serde_json::from_str(&parameters)?;
+        let parameters = scan_templates::resolve(
+            &parameters,
+            paper_size,
+            variables.as_deref().unwrap_or_default(),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let path = scanner_manager
+            .preview_scan(&name, parameters, &assets_dir)
+            .await?;
+        Ok(path.as_web_path())
     }
 
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    #[allow(clippy::too_many_arguments)]
     async fn retry_scan(
         &self,
         ctx: &Context<'_>,
         name: String,
         parameters: String,
         scan_id: i32,
-    ) -> i32 {
+        #[graphql(default)] priority: ScanPriority,
+        paper_size: Option<PaperSize>,
+        variables: Option<Vec<TemplateVariable>>,
+    ) -> Result<i32> {
         // Clone all context data to ensure 'static lifetimes for the async task
-        let scanner_manager = ctx.data_unchecked::<ScannerManager>().clone();
-        let pool = ctx
-            .data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>()
-            .clone();
-        let assets_dir = ctx.data_unchecked::<AssetsDir>().clone();
+        let scanner_manager = ctx.app()?.scanner_manager.clone();
+        if let Some(reason) = scanner_manager.unavailable() {
+            return Err(reason.into());
+        }
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
         let parameters: HashMap<String, String> = serde_json::from_str(&parameters).unwrap();
+        let parameters = scan_templates::resolve(
+            &parameters,
+            paper_size,
+            variables.as_deref().unwrap_or_default(),
+        )
+        .map_err(|e| e.to_string())?;
 
         // Load the existing scan
         let mut scan = Scan::load(scan_id, &pool).unwrap();
@@ -358,6 +957,7 @@ impl MutationRoot {
         scan.status = "PENDING".to_string();
         scan.scanner = name.clone();
         scan.scan_parameters = parameters.clone();
+        scan.started_by = ctx.data_opt::<Principal>().map(Principal::attribution);
         scan.save(&pool).unwrap();
 
         // Create clones for the async task
@@ -374,6 +974,7 @@ impl MutationRoot {
                     scan_id,
                     &name_clone,
                     parameters_clone,
+                    priority,
                     &pool_clone,
                     &assets_dir_clone,
                 )
@@ -381,26 +982,158 @@ impl MutationRoot {
         });
 
         // Return the same scan ID
-        scan_id
+        Ok(scan_id)
+    }
+
+    /// Runs a dead-lettered scan again, with the scanner and parameters it
+    /// failed with. Returns the scan id.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn requeue_dead_letter(
+        &self,
+        ctx: &Context<'_>,
+        job_id: i32,
+        #[graphql(default)] priority: ScanPriority,
+    ) -> Result<i32> {
+        let scanner_manager = ctx.app()?.scanner_manager.clone();
+        if let Some(reason) = scanner_manager.unavailable() {
+            return Err(reason.into());
+        }
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+
+        let dead_letter = DeadLetter::load(job_id, &pool)
+            .map_err(|_| format!("No dead letter for job {}", job_id))?;
+        if dead_letter.requeued_at.is_some() {
+            return Err(format!("Job {} has already been requeued", job_id).into());
+        }
+
+        let mut scan = Scan::load(job_id, &pool)?;
+        scan.status = "PENDING".to_string();
+        scan.save(&pool)?;
+        Scan::set_failure(job_id, None, &pool)?;
+        DeadLetter::mark_requeued(job_id, &pool)?;
+
+        tokio::spawn(async move {
+            scanner_manager
+                .complete_scan(
+                    job_id,
+                    &dead_letter.scanner,
+                    dead_letter.scan_parameters,
+                    priority,
+                    &pool,
+                    &assets_dir,
+                )
+                .await;
+        });
+
+        Ok(job_id)
+    }
+
+    /// Scans every sheet in the document feeder into the group, until it runs
+    /// out. Jams and other paper problems pause the batch instead of failing it.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    #[allow(clippy::too_many_arguments)]
+    async fn start_batch(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        parameters: String,
+        group_id: Option<i32>,
+        #[graphql(default_with = "ScanPriority::Low")] priority: ScanPriority,
+        expected_pages: Option<i32>,
+        paper_size: Option<PaperSize>,
+        variables: Option<Vec<TemplateVariable>>,
+    ) -> Result<ScanBatch> {
+        if let Some(reason) = ctx.app()?.scanner_manager.unavailable() {
+            return Err(reason.into());
+        }
+        let parameters: HashMap<String, String> = serde_json::from_str(&parameters)?;
+        let parameters = scan_templates::resolve(
+            &parameters,
+            paper_size,
+            variables.as_deref().unwrap_or_default(),
+        )
+        .map_err(|e| e.to_string())?;
+        let started_by = ctx.data_opt::<Principal>().map(Principal::attribution);
+        let (batch, _) = ctx.app()?.batch_runner.start(
+            name,
+            parameters,
+            priority,
+            group_id,
+            expected_pages,
+            started_by,
+        )?;
+        Ok(batch)
+    }
+
+    /// Stops a running batch once the current page is done. False if it wasn't running.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn pause_batch(&self, ctx: &Context<'_>, job_id: i32) -> Result<bool> {
+        Ok(ctx.app()?.batch_runner.pause(job_id)?)
+    }
+
+    /// Continues a paused batch from the page after the last one scanned.
+    /// A page paused on as a possible double feed is kept, or with
+    /// `rescanSuspect` thrown away and scanned again from the re-fed sheet.
+    /// False if it wasn't paused.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn resume_batch(
+        &self,
+        ctx: &Context<'_>,
+        job_id: i32,
+        #[graphql(default)] rescan_suspect: bool,
+    ) -> Result<bool> {
+        if let Some(reason) = ctx.app()?.scanner_manager.unavailable() {
+            return Err(reason.into());
+        }
+        Ok(ctx.app()?.batch_runner.resume(job_id, rescan_suspect)?)
+    }
+
+    /// Dismisses a group's page count warning once the operator has checked it.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn clear_page_count_warning(&self, ctx: &Context<'_>, group_id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanGroup::set_page_count_warning(group_id, None, pool).is_ok())
+    }
+
+    /// Combines overlapping flatbed scans of an oversized original, in order,
+    /// into one new scan in the first scan's group.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn stitch_scans(
+        &self,
+        ctx: &Context<'_>,
+        scan_ids: Vec<i32>,
+        #[graphql(default)] direction: StitchDirection,
+    ) -> Result<Scan> {
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+
+        tokio::task::spawn_blocking(move || {
+            stitch_scans(&scan_ids, direction, &pool, &assets_dir).map_err(|e| e.to_string().into())
+        })
+        .await?
     }
 
-    async fn add_divider(&self, ctx: &Context<'_>) -> i32 {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn add_divider(&self, ctx: &Context<'_>) -> Result<i32> {
+        let pool = &ctx.app()?.pool;
 
         let ts = chrono::Utc::now();
 
-        crate::scan_dividers::ScanDivider::new(ts)
-            .save(&pool)
-            .unwrap()
+        Ok(crate::scan_dividers::ScanDivider::new(ts)
+            .save(pool)
+            .unwrap())
     }
 
-    async fn create_group(&self, ctx: &Context<'_>, status: String) -> i32 {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn create_group(&self, ctx: &Context<'_>, status: String) -> Result<i32> {
+        let pool = &ctx.app()?.pool;
 
         let mut group = ScanGroup::create(status);
-        group.save(&pool).unwrap()
+        Ok(group.save(pool).unwrap())
     }
 
+    #[graphql(guard = "RequireScope(Scope::Write)")]
     async fn update_group(
         &self,
         ctx: &Context<'_>,
@@ -409,16 +1142,25 @@ impl MutationRoot {
         status: Option<String>,
         comment: Option<String>,
         tags: Option<Vec<String>>,
-    ) -> bool {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    ) -> Result<bool> {
+        let app = ctx.app()?;
+        let pool = &app.pool;
 
-        match ScanGroup::load(id, &pool) {
+        Ok(match ScanGroup::load(id, pool) {
             Ok(mut group) => {
                 if let Some(title) = title {
                     group.title = title;
                 }
 
                 if let Some(status) = status {
+                    if status == "finalized" && group.status != status {
+                        GroupDuplicate::detect_later(
+                            vec![id],
+                            pool.clone(),
+                            app.assets_dir.clone(),
+                        );
+                        record_finalized(ctx, &[id], pool)?;
+                    }
                     group.status = status;
                 }
 
@@ -430,27 +1172,254 @@ impl MutationRoot {
                     group.tags = tags;
                 }
 
-                group.save(&pool).unwrap();
+                group.save(pool).unwrap();
                 true
             }
             Err(_) => false,
-        }
+        })
     }
 
-    async fn commit_group(&self, ctx: &Context<'_>, scan_ids: Vec<i32>, title: String) -> i32 {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
-        let conn = pool.get().unwrap();
+    /// Leaves a comment on a group, optionally about one of its pages or in
+    /// reply to another comment.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn add_group_comment(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        body: String,
+        scan_id: Option<i32>,
+        reply_to: Option<i32>,
+    ) -> Result<GroupComment> {
+        let pool = &ctx.app()?.pool;
+        let body = body.trim();
+        if body.is_empty() {
+            return Err("Comments can't be empty".into());
+        }
+        let group = ScanGroup::load(group_id, pool).map_err(|_| "No such group")?;
+        if let Some(scan_id) = scan_id {
+            if !group.scans.iter().any(|scan| scan.id == Some(scan_id)) {
+                return Err(format!("Scan {} is not in group {}", scan_id, group_id).into());
+            }
+        }
+        let thread_id = match reply_to {
+            Some(reply_to) => {
+                let parent = GroupComment::load(reply_to, pool).map_err(|_| "No such comment")?;
+                if parent.group_id != group_id {
+                    return Err(format!("Comment {} is not on group {}", reply_to, group_id).into());
+                }
+                Some(parent.thread_id.unwrap_or(parent.id))
+            }
+            None => None,
+        };
 
-        // Calculate the group_id by checking if all scans have the same group
-        let mut common_group_id: Option<i32> = None;
-        let mut all_same_group = true;
+        let author = ctx.data_opt::<Principal>().map(Principal::attribution);
+        let comment = GroupComment::create(group_id, thread_id, scan_id, author, body, pool)?;
+        Activity::record(
+            ActivityKind::Comment,
+            Some(group_id),
+            scan_id,
+            comment.author.as_deref(),
+            Some(body),
+            pool,
+        )?;
+        SimpleBroker::publish(GroupCommentChanged {
+            change: CommentChange::Created,
+            comment: comment.clone(),
+        });
+        Ok(comment)
+    }
 
-        for scan_id in &scan_ids {
-            let scan = Scan::load(*scan_id, &pool).unwrap();
-            if let Some(scan_group) = &scan.group {
-                if let Some(existing_id) = common_group_id {
-                    if existing_id != scan_group.id {
-                        all_same_group = false;
+    /// Changes a comment's text. Only its author or an admin can.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn edit_group_comment(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        body: String,
+    ) -> Result<GroupComment> {
+        let pool = &ctx.app()?.pool;
+        let body = body.trim();
+        if body.is_empty() {
+            return Err("Comments can't be empty".into());
+        }
+        let comment = GroupComment::load(id, pool).map_err(|_| "No such comment")?;
+        if !may_change_comment(ctx, &comment) {
+            return Err("Only the author or an admin can edit a comment".into());
+        }
+
+        let comment = GroupComment::edit(id, body, pool)?;
+        SimpleBroker::publish(GroupCommentChanged {
+            change: CommentChange::Edited,
+            comment: comment.clone(),
+        });
+        Ok(comment)
+    }
+
+    /// Deletes a comment, with its replies if it started a thread. Only its
+    /// author or an admin can.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn delete_group_comment(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        let Ok(comment) = GroupComment::load(id, pool) else {
+            return Ok(false);
+        };
+        if !may_change_comment(ctx, &comment) {
+            return Err("Only the author or an admin can delete a comment".into());
+        }
+
+        let deleted = GroupComment::delete(id, pool)?;
+        SimpleBroker::publish(GroupCommentChanged {
+            change: CommentChange::Deleted,
+            comment,
+        });
+        Ok(deleted)
+    }
+
+    /// Sends a digest of the last seven days now, whether or not one is
+    /// scheduled.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn send_digest(&self, ctx: &Context<'_>) -> Result<WeeklyDigest> {
+        let pool = ctx.app()?.pool.clone();
+        let digest = tokio::task::spawn_blocking(move || WeeklyDigest::create(&pool)).await??;
+        SimpleBroker::publish(digest.clone());
+        Ok(digest)
+    }
+
+    /// Records that two groups are related, each in its role, e.g. an
+    /// `invoice` and its `payment receipt`. Linking them again in the same
+    /// roles returns the existing link.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn link_groups(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        role: String,
+        linked_group_id: i32,
+        linked_role: String,
+    ) -> Result<GroupLink> {
+        if group_id == linked_group_id {
+            return Err("A group can't be linked to itself".into());
+        }
+        if normalize_role(&role).is_empty() || normalize_role(&linked_role).is_empty() {
+            return Err("Roles can't be empty".into());
+        }
+        let pool = &ctx.app()?.pool;
+        ScanGroup::load(group_id, pool).map_err(|_| "No such group")?;
+        ScanGroup::load(linked_group_id, pool).map_err(|_| "No such linked group")?;
+
+        let linked_by = ctx.data_opt::<Principal>().map(Principal::attribution);
+        Ok(GroupLink::link(
+            group_id,
+            &role,
+            linked_group_id,
+            &linked_role,
+            linked_by,
+            pool,
+        )?)
+    }
+
+    /// Removes the links between two groups, or only those where the linked
+    /// group is in `linkedRole`. Returns how many were removed.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn unlink_groups(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        linked_group_id: i32,
+        linked_role: Option<String>,
+    ) -> Result<i32> {
+        let pool = &ctx.app()?.pool;
+        Ok(GroupLink::unlink(group_id, linked_group_id, linked_role.as_deref(), pool)? as i32)
+    }
+
+    /// Hands a group to a user to review, or takes it back with a null
+    /// `user_id`. Replaces any earlier assignment.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn assign_group(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        user_id: Option<i32>,
+    ) -> Result<GroupAssignmentChanged> {
+        let pool = &ctx.app()?.pool;
+        ScanGroup::load(group_id, pool).map_err(|_| "No such group")?;
+        if let Some(user_id) = user_id {
+            User::load(user_id, pool).map_err(|_| "No such user")?;
+        }
+
+        let assigned_by = ctx.data_opt::<Principal>().map(Principal::attribution);
+        let changed = GroupAssignment::assign(group_id, user_id, assigned_by, pool)?;
+        if changed.user_id != changed.previous_user_id {
+            SimpleBroker::publish(changed.clone());
+        }
+        Ok(changed)
+    }
+
+    /// Reads the text of each group's pages and suggests tags for the dates,
+    /// organizations and amounts in it, replacing earlier suggestions.
+    /// Pages not read before are run through OCR, which can take a while.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn suggest_tags(
+        &self,
+        ctx: &Context<'_>,
+        group_ids: Vec<i32>,
+    ) -> Result<Vec<TagSuggestion>> {
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut suggestions = Vec::new();
+            for group_id in group_ids {
+                suggestions.extend(
+                    TagSuggestion::suggest_for_group(group_id, &pool, &assets_dir)
+                        .map_err(|e| format!("group {}: {}", group_id, e))?,
+                );
+            }
+            Ok(suggestions)
+        })
+        .await?
+    }
+
+    /// Adds the group's suggested tags to it: those named in `tags`, or all
+    /// of them.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn accept_suggested_tags(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        tags: Option<Vec<String>>,
+    ) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(TagSuggestion::accept(group_id, tags.as_deref(), pool).unwrap())
+    }
+
+    /// Renames the group to its `suggestedTitle`. False if it has none.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn accept_suggested_title(&self, ctx: &Context<'_>, group_id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        title_suggestions::accept(group_id, pool).map_err(|e| e.to_string().into())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn commit_group(
+        &self,
+        ctx: &Context<'_>,
+        scan_ids: Vec<i32>,
+        title: String,
+    ) -> Result<i32> {
+        let pool = &ctx.app()?.pool;
+        let conn = pool.get().unwrap();
+
+        // Calculate the group_id by checking if all scans have the same group
+        let mut common_group_id: Option<i32> = None;
+        let mut all_same_group = true;
+
+        for scan_id in &scan_ids {
+            let scan = Scan::load(*scan_id, pool).unwrap();
+            if let Some(scan_group) = &scan.group {
+                if let Some(existing_id) = common_group_id {
+                    if existing_id != scan_group.id {
+                        all_same_group = false;
                         break;
                     }
                 } else {
@@ -462,9 +1431,8 @@ impl MutationRoot {
             }
         }
 
-        let group_id = if all_same_group && common_group_id.is_some() {
+        let id = if let Some(group_id) = common_group_id.filter(|_| all_same_group) {
             // Update existing group to finalized status
-            let group_id = common_group_id.unwrap();
             conn.execute(
                 "UPDATE scan_groups SET title = ?, status = 'finalized', updated_at = CURRENT_TIMESTAMP WHERE id = ?",
                 params![title, group_id],
@@ -488,39 +1456,336 @@ impl MutationRoot {
                 )
                 .unwrap();
             }
+            if scan_ids.len() >= BATCH_CHECKPOINT_ROWS {
+                checkpoint(&conn);
+            }
             id
         };
+        GroupDuplicate::detect_later(vec![id], pool.clone(), ctx.app()?.assets_dir.clone());
+        record_finalized(ctx, &[id], pool)?;
+        Ok(id)
+    }
+
+    /// Measures a scan of the printed target from
+    /// `/api/calibration/target.pdf`: the scanner's real resolution and skew
+    /// and, from the resolution target, the finest detail it picks up.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn analyze_calibration_scan(
+        &self,
+        ctx: &Context<'_>,
+        scan_id: i32,
+    ) -> Result<CalibrationReport> {
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
 
-        group_id
+        tokio::task::spawn_blocking(move || {
+            CalibrationReport::analyze(scan_id, &pool, &assets_dir)
+                .map_err(|e| e.to_string().into())
+        })
+        .await?
     }
 
-    async fn add_scan_to_group(&self, ctx: &Context<'_>, scan_id: i32, group_id: i32) -> bool {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    /// Settles a duplicate warning: merges the group into the earlier one,
+    /// discards it, or keeps both. Returns the id of the group left.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn resolve_duplicate(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        duplicate_of: i32,
+        resolution: DuplicateResolution,
+    ) -> Result<i32> {
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+
+        tokio::task::spawn_blocking(move || {
+            GroupDuplicate::resolve(group_id, duplicate_of, resolution, &pool, &assets_dir)
+                .map_err(|e| e.to_string().into())
+        })
+        .await?
+    }
 
-        match Scan::load(scan_id, &pool) {
-            Ok(mut scan) => match scan.set_group(group_id, &pool) {
-                Ok(_) => true,
-                Err(_) => false,
-            },
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn add_scan_to_group(
+        &self,
+        ctx: &Context<'_>,
+        scan_id: i32,
+        group_id: i32,
+    ) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+
+        Ok(match Scan::load(scan_id, pool) {
+            Ok(mut scan) => scan.set_group(group_id, pool).is_ok(),
+            Err(_) => false,
+        })
+    }
+
+    /// Sets the status of many scans in one transaction, e.g. to reject junk captures.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn update_scans_status(
+        &self,
+        ctx: &Context<'_>,
+        scan_ids: Vec<i32>,
+        status: String,
+    ) -> Result<i32> {
+        let pool = &ctx.app()?.pool;
+        Ok(Scan::update_status_many(&scan_ids, &status, pool).unwrap() as i32)
+    }
+
+    /// Sorts the group's pages by their printed page numbers, as suggested by
+    /// `pageNumberCheck`. Returns the report the new order was taken from.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn reorder_group_by_page_numbers(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+    ) -> Result<PageNumberReport> {
+        let pool = &ctx.app()?.pool;
+        let assets_dir = &ctx.app()?.assets_dir;
+
+        let report = check_group(group_id, pool, assets_dir).await?;
+        Scan::reorder(group_id, &report.suggested_order, pool)?;
+        Ok(report)
+    }
+
+    /// Turns spine curvature correction on or off for the group's exports.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_group_dewarp(&self, ctx: &Context<'_>, id: i32, dewarp: bool) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+
+        Ok(match ScanGroup::load(id, pool) {
+            Ok(mut group) => {
+                group.dewarp = dewarp;
+                group.save(pool).unwrap();
+                true
+            }
+            Err(_) => false,
+        })
+    }
+
+    /// Turns gutter shadow removal on or off for the group's exports.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_group_remove_gutter_shadow(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        remove_gutter_shadow: bool,
+    ) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+
+        Ok(match ScanGroup::load(id, pool) {
+            Ok(mut group) => {
+                group.remove_gutter_shadow = remove_gutter_shadow;
+                group.save(pool).unwrap();
+                true
+            }
+            Err(_) => false,
+        })
+    }
+
+    /// Sets the ink colour dropped from the group's pages before exporting
+    /// and reading their text, for forms printed in red or blue; null
+    /// turns dropout off.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_group_color_dropout(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        color: Option<DropoutColor>,
+    ) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+
+        Ok(match ScanGroup::load(id, pool) {
+            Ok(mut group) => {
+                group.color_dropout = color;
+                group.save(pool).unwrap();
+                true
+            }
+            Err(_) => false,
+        })
+    }
+
+    /// Sets the processing profile run on each page the group captures from
+    /// now on; null stops processing. Pages already captured are left as
+    /// they are until reprocessed.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_group_processing_profile(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        profile_id: Option<i32>,
+    ) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        if let Some(profile_id) = profile_id {
+            if ProcessingProfile::load(profile_id, pool).is_err() {
+                return Err(format!("No processing profile {}", profile_id).into());
+            }
+        }
+
+        Ok(match ScanGroup::load(id, pool) {
+            Ok(mut group) => {
+                group.processing_profile_id = profile_id;
+                group.save(pool).unwrap();
+                true
+            }
             Err(_) => false,
+        })
+    }
+
+    /// Runs the group's processing profile on the scan again, e.g. after
+    /// the profile changed. Returns false if the group has no profile.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn reprocess_scan(&self, ctx: &Context<'_>, scan_id: i32) -> Result<bool> {
+        let app = ctx.app()?;
+        let (pool, assets_dir) = (app.pool.clone(), app.assets_dir.clone());
+
+        tokio::task::spawn_blocking(move || {
+            processing_profiles::process_scan(scan_id, &pool, &assets_dir)
+                .map_err(|e| e.to_string().into())
+        })
+        .await?
+    }
+
+    /// Renders one page through several candidate profiles, saved ones by id
+    /// and unsaved step lists, to compare before processing a whole group.
+    /// The first variant is the page as captured. Returns where each
+    /// rendering was written; the scan itself isn't changed.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn compare_processing_profiles(
+        &self,
+        ctx: &Context<'_>,
+        scan_id: i32,
+        #[graphql(default)] profile_ids: Vec<i32>,
+        #[graphql(default)] candidates: Vec<ProcessingProfileInput>,
+        #[graphql(default = 1200)] size: u32,
+    ) -> Result<Vec<ProfileVariant>> {
+        let app = ctx.app()?;
+        let pool = &app.pool;
+
+        let scan = Scan::load(scan_id, pool).map_err(|_| "Scan not found")?;
+        if scan.status != "COMPLETE" {
+            return Err("Only completed scans can be processed".into());
+        }
+        let mut all = Vec::new();
+        for id in profile_ids {
+            let profile = ProcessingProfile::load(id, pool)
+                .map_err(|_| format!("No processing profile {}", id))?;
+            all.push(Candidate {
+                profile_id: Some(profile.id),
+                name: profile.name,
+                steps: profile.steps,
+            });
+        }
+        all.extend(candidates.into_iter().map(|input| Candidate {
+            profile_id: None,
+            name: input.name,
+            steps: input.steps,
+        }));
+        if all.len() >= processing_profiles::MAX_VARIANTS {
+            return Err(format!(
+                "Compare at most {} profiles at once",
+                processing_profiles::MAX_VARIANTS - 1
+            )
+            .into());
+        }
+
+        let size = size.clamp(1, preview::MAX_SIZE);
+        let assets_dir = app.assets_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            processing_profiles::compare(&scan, all, size, &assets_dir)
+                .map_err(|e| e.to_string().into())
+        })
+        .await?
+    }
+
+    /// Places or releases a legal or retention hold on the group. While held,
+    /// the group's scans are exempt from every deletion, including the batch
+    /// runner discarding rejected pages.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn set_group_hold(&self, ctx: &Context<'_>, id: i32, hold: bool) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanGroup::set_hold(id, hold, pool).unwrap() > 0)
+    }
+
+    /// Marks pages as reviewed, or back to unreviewed. Returns how many were
+    /// found.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_review_state(
+        &self,
+        ctx: &Context<'_>,
+        scan_ids: Vec<i32>,
+        state: ReviewState,
+    ) -> Result<i32> {
+        let pool = &ctx.app()?.pool;
+        let reviewer = ctx.data_opt::<Principal>().map(Principal::attribution);
+        Ok(Scan::set_review_state(&scan_ids, state, reviewer, pool)? as i32)
+    }
+
+    /// Sets the review state of a whole group's pages, or only those no one
+    /// has reviewed yet, e.g. to approve the rest once the bad pages are
+    /// flagged.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_group_review_state(
+        &self,
+        ctx: &Context<'_>,
+        group_id: i32,
+        state: ReviewState,
+        #[graphql(default)] only_unreviewed: bool,
+    ) -> Result<ReviewProgress> {
+        let pool = &ctx.app()?.pool;
+        let group = ScanGroup::load(group_id, pool).map_err(|_| "No such group")?;
+        let scan_ids: Vec<i32> = group
+            .scans
+            .iter()
+            .filter(|scan| !only_unreviewed || scan.review_state == ReviewState::Unreviewed)
+            .filter_map(|scan| scan.id)
+            .collect();
+        let reviewer = ctx.data_opt::<Principal>().map(Principal::attribution);
+        Scan::set_review_state(&scan_ids, state, reviewer, pool)?;
+        Ok(ScanGroup::review_progress(group_id, pool)?)
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn update_groups_status(
+        &self,
+        ctx: &Context<'_>,
+        group_ids: Vec<i32>,
+        status: String,
+    ) -> Result<i32> {
+        let app = ctx.app()?;
+        let updated = ScanGroup::update_status_many(&group_ids, &status, &app.pool).unwrap();
+        if status == "finalized" {
+            record_finalized(ctx, &group_ids, &app.pool)?;
+            GroupDuplicate::detect_later(group_ids, app.pool.clone(), app.assets_dir.clone());
         }
+        Ok(updated as i32)
     }
 
-    async fn rotate_scan(&self, ctx: &Context<'_>, scan_id: i32, rotation: i32) -> bool {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn rotate_scan(&self, ctx: &Context<'_>, scan_id: i32, rotation: i32) -> Result<bool> {
+        let app = ctx.app()?;
+        let pool = &app.pool;
 
-        match Scan::load(scan_id, &pool) {
+        Ok(match Scan::load(scan_id, pool) {
             Ok(mut scan) => {
                 // Ensure rotation is in 90-degree increments (0, 90, 180, 270)
                 let normalized_rotation = (rotation % 360 + 360) % 360;
+                let changed = scan.rotation != normalized_rotation;
+                if changed {
+                    scan.invalidate_edit(&app.assets_dir);
+                }
                 scan.rotation = normalized_rotation;
-                scan.save(&pool).unwrap();
+                scan.save(pool).unwrap();
+                if changed {
+                    reprocess_in_background(scan_id, app);
+                }
                 true
             }
             Err(_) => false,
-        }
+        })
     }
 
+    #[graphql(guard = "RequireScope(Scope::Write)")]
     async fn crop_scan(
         &self,
         ctx: &Context<'_>,
@@ -529,10 +1794,11 @@ impl MutationRoot {
         y: f32,
         width: f32,
         height: f32,
-    ) -> bool {
-        let pool = ctx.data_unchecked::<r2d2::Pool<crate::DuckdbConnectionManager>>();
+    ) -> Result<bool> {
+        let app = ctx.app()?;
+        let pool = &app.pool;
 
-        match Scan::load(scan_id, &pool) {
+        Ok(match Scan::load(scan_id, pool) {
             Ok(mut scan) => {
                 let crop = CropCoordinates {
                     x,
@@ -541,15 +1807,460 @@ impl MutationRoot {
                     height,
                 };
                 let crop_json = serde_json::to_string(&crop).unwrap();
+                let changed = scan.crop_coordinates.as_deref() != Some(crop_json.as_str());
+                if changed {
+                    scan.invalidate_edit(&app.assets_dir);
+                }
                 scan.crop_coordinates = Some(crop_json);
-                scan.save(&pool).unwrap();
+                scan.save(pool).unwrap();
+                if changed {
+                    reprocess_in_background(scan_id, app);
+                }
+                true
+            }
+            Err(_) => false,
+        })
+    }
+
+    /// Turns punch hole and edge shadow cleanup on or off for a scan of a
+    /// filed document.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn set_scan_remove_punch_holes(
+        &self,
+        ctx: &Context<'_>,
+        scan_id: i32,
+        remove_punch_holes: bool,
+    ) -> Result<bool> {
+        let app = ctx.app()?;
+        let pool = &app.pool;
+
+        Ok(match Scan::load(scan_id, pool) {
+            Ok(mut scan) => {
+                if scan.remove_punch_holes != remove_punch_holes {
+                    // Cut from the image before the change
+                    tiles::remove_tiles(scan_id, &app.assets_dir);
+                }
+                scan.remove_punch_holes = remove_punch_holes;
+                scan.save(pool).unwrap();
                 true
             }
             Err(_) => false,
+        })
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn create_api_key(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+        scopes: Vec<Scope>,
+    ) -> Result<CreatedApiKey> {
+        let pool = &ctx.app()?.pool;
+        Ok(ApiKey::create(name, scopes, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn revoke_api_key(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(ApiKey::revoke(id, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn create_ingest_rule(
+        &self,
+        ctx: &Context<'_>,
+        input: IngestRuleInput,
+    ) -> Result<IngestRule> {
+        let pool = &ctx.app()?.pool;
+        Ok(IngestRule::create(input, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn delete_ingest_rule(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(IngestRule::delete(id, pool).unwrap())
+    }
+
+    /// Replaces the metadata written beside exports to the destination
+    /// directory. An empty list of fields removes its mapping.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn set_destination_mapping(
+        &self,
+        ctx: &Context<'_>,
+        destination: String,
+        fields: Vec<MetadataField>,
+    ) -> Result<Option<DestinationMapping>> {
+        if destination.trim().is_empty() {
+            return Err("Destination can't be empty".into());
+        }
+        if fields.iter().any(|field| field.key.trim().is_empty()) {
+            return Err("Metadata keys can't be empty".into());
+        }
+        DestinationMapping::validate(&fields).map_err(|e| e.to_string())?;
+        let pool = &ctx.app()?.pool;
+        Ok(DestinationMapping::set(&destination, fields, pool).unwrap())
+    }
+
+    /// Stops listing an offline device that's gone for good.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn forget_scanner(&self, ctx: &Context<'_>, name: String) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(known_scanners::forget(&name, pool)?)
+    }
+
+    /// Saves parameters to start scans from with `scan(presetId:)`.
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn create_scan_preset(
+        &self,
+        ctx: &Context<'_>,
+        input: ScanPresetInput,
+    ) -> Result<ScanPreset> {
+        if input.name.trim().is_empty() {
+            return Err("Preset name can't be empty".into());
+        }
+        let pool = &ctx.app()?.pool;
+        Ok(ScanPreset::create(input, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn update_scan_preset(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        input: ScanPresetInput,
+    ) -> Result<ScanPreset> {
+        if input.name.trim().is_empty() {
+            return Err("Preset name can't be empty".into());
+        }
+        let pool = &ctx.app()?.pool;
+        ScanPreset::update(id, input, pool)
+            .unwrap()
+            .ok_or_else(|| "No such scan preset".into())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Scan)")]
+    async fn delete_scan_preset(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(ScanPreset::delete(id, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn create_processing_profile(
+        &self,
+        ctx: &Context<'_>,
+        input: ProcessingProfileInput,
+    ) -> Result<ProcessingProfile> {
+        if input.name.trim().is_empty() {
+            return Err("Profile name can't be empty".into());
+        }
+        let pool = &ctx.app()?.pool;
+        Ok(ProcessingProfile::create(input, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn delete_processing_profile(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(ProcessingProfile::delete(id, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn create_classification_rule(
+        &self,
+        ctx: &Context<'_>,
+        input: ClassificationRuleInput,
+    ) -> Result<ClassificationRule> {
+        let pool = &ctx.app()?.pool;
+        Ok(ClassificationRule::create(input, pool).unwrap())
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn delete_classification_rule(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+        Ok(ClassificationRule::delete(id, pool).unwrap())
+    }
+
+    /// Creates the rules in a bundle from `exportConfigBundle`, skipping
+    /// ones the server already has. `replace` deletes the server's rules
+    /// first.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn import_config_bundle(
+        &self,
+        ctx: &Context<'_>,
+        bundle: String,
+        #[graphql(default)] replace: bool,
+    ) -> Result<ConfigImport> {
+        let pool = &ctx.app()?.pool;
+        ConfigBundle::import(&bundle, replace, pool).map_err(|e| e.to_string().into())
+    }
+
+    /// Runs the classification rules over groups already imported, e.g. a
+    /// backlog scanned before the rules were written. Pages not read before
+    /// are run through OCR, which can take a while.
+    #[graphql(guard = "RequireScope(Scope::Write)")]
+    async fn classify_groups(
+        &self,
+        ctx: &Context<'_>,
+        group_ids: Vec<i32>,
+    ) -> Result<Vec<ClassificationRun>> {
+        let pool = ctx.app()?.pool.clone();
+        let assets_dir = ctx.app()?.assets_dir.clone();
+        let public_url = ctx.app()?.public_url.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut runs = Vec::new();
+            for group_id in group_ids {
+                runs.extend(
+                    classify_group(group_id, &pool, &assets_dir, &public_url)
+                        .map_err(|e| format!("group {}: {}", group_id, e))?,
+                );
+            }
+            Ok(runs)
+        })
+        .await?
+    }
+
+    /// Exports scan and group metadata to Parquet files for offline analysis.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn export_analytics(&self, ctx: &Context<'_>) -> Result<AnalyticsExport> {
+        let pool = &ctx.app()?.pool;
+        let assets_dir = &ctx.app()?.assets_dir;
+        let snapshot = &ctx.app()?.snapshot;
+        let as_of = snapshot.refreshed_at().unwrap_or_else(Utc::now);
+        Ok(AnalyticsExport::create(
+            &snapshot.pool(pool),
+            as_of,
+            assets_dir,
+        )?)
+    }
+
+    /// Takes a fresh read-only snapshot for analytics queries. Returns when it was taken.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn refresh_snapshot(&self, ctx: &Context<'_>) -> Result<DateTime<Utc>> {
+        let pool = &ctx.app()?.pool;
+        let snapshot = &ctx.app()?.snapshot;
+        Ok(snapshot.refresh(pool)?)
+    }
+
+    /// Recompresses originals older than `ARCHIVE_AFTER_DAYS` to the archive
+    /// format now, instead of waiting for the background job.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn recompress_originals(&self, ctx: &Context<'_>) -> Result<ArchiveReport> {
+        let app = ctx.app()?;
+        let (config, pool, assets_dir) = (
+            app.archive_config.clone(),
+            app.pool.clone(),
+            app.assets_dir.clone(),
+        );
+        Ok(tokio::task::spawn_blocking(move || archive::run(&config, &pool, &assets_dir)).await?)
+    }
+
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn create_user(
+        &self,
+        ctx: &Context<'_>,
+        username: String,
+        password: String,
+        role: Role,
+    ) -> Result<User> {
+        let pool = &ctx.app()?.pool;
+        User::create(username, &password, role, pool)
+            .map_err(|_| "Username is already taken".into())
+    }
+
+    /// Syncs accounts from LDAP now instead of waiting for the next
+    /// scheduled sync.
+    #[graphql(guard = "RequireScope(Scope::Admin)")]
+    async fn sync_ldap(&self, ctx: &Context<'_>) -> Result<LdapSyncReport> {
+        let app = ctx.app()?;
+        let Some(config) = app.auth_config.ldap.clone() else {
+            return Err("LDAP isn't configured".into());
+        };
+        let pool = app.pool.clone();
+        Ok(tokio::task::spawn_blocking(move || ldap_sync::sync(&config, &pool)).await??)
+    }
+
+    async fn login(&self, ctx: &Context<'_>, username: String, password: String) -> Result<User> {
+        let pool = &ctx.app()?.pool;
+
+        let ip = ctx.data_opt::<ClientIp>().map(|ClientIp(ip)| ip.as_str());
+
+        if LoginEvent::is_locked_out(&username, ip, pool) {
+            LoginEvent::record(&username, ip, LoginOutcome::LockedOut, pool).unwrap();
+            return Err("Too many failed login attempts, try again later".into());
+        }
+
+        let ldap = ctx.app()?.auth_config.ldap.clone();
+        let (name, verify_pool) = (username.clone(), pool.clone());
+        let verified = tokio::task::spawn_blocking(move || {
+            User::verify_password(&name, &password, ldap.as_ref(), &verify_pool)
+        })
+        .await?;
+        let user = match verified {
+            Some(user) => user,
+            None => {
+                LoginEvent::record(&username, ip, LoginOutcome::Failure, pool).unwrap();
+                return Err("Invalid username or password".into());
+            }
+        };
+        LoginEvent::record(&username, ip, LoginOutcome::Success, pool).unwrap();
+        let token = user.create_session(pool).unwrap();
+
+        ctx.insert_http_header(
+            "Set-Cookie",
+            format!(
+                "{}={}; HttpOnly; Path=/; SameSite=Strict; Max-Age={}",
+                SESSION_COOKIE,
+                token,
+                SESSION_LIFETIME_DAYS * 24 * 60 * 60
+            ),
+        );
+
+        Ok(user)
+    }
+
+    async fn logout(&self, ctx: &Context<'_>) -> Result<bool> {
+        let pool = &ctx.app()?.pool;
+
+        if let Some(SessionToken(token)) = ctx.data_opt::<SessionToken>() {
+            User::end_session(token, pool).unwrap();
+        }
+
+        ctx.insert_http_header(
+            "Set-Cookie",
+            format!(
+                "{}=; HttpOnly; Path=/; SameSite=Strict; Max-Age=0",
+                SESSION_COOKIE
+            ),
+        );
+
+        Ok(true)
+    }
+}
+
+/// A scan to start, as `scan` takes it, so the gRPC service starts scans
+/// the same way.
+pub struct NewScan {
+    pub name: Option<String>,
+    pub parameters: HashMap<String, String>,
+    pub preset_id: Option<i32>,
+    pub group_id: Option<i32>,
+    pub priority: ScanPriority,
+    pub paper_size: Option<PaperSize>,
+    pub variables: Vec<TemplateVariable>,
+    pub batch: bool,
+    pub duplex: bool,
+}
+
+/// Starts the scan in the background and returns its id, or the first
+/// page's for a batch.
+pub async fn start_scan(
+    app: &AppContext,
+    scan: NewScan,
+    started_by: Option<String>,
+) -> Result<i32> {
+    // Clone all context data to ensure 'static lifetimes for the async task
+    let scanner_manager = app.scanner_manager.clone();
+    if let Some(reason) = scanner_manager.unavailable() {
+        return Err(reason.into());
+    }
+    let pool = app.pool.clone();
+    let assets_dir = app.assets_dir.clone();
+    let preset = match scan.preset_id {
+        Some(id) => Some(ScanPreset::load(id, &pool).map_err(|_| "No such scan preset")?),
+        None => None,
+    };
+    let Some((name, parameters)) = scan_presets::scan_settings(preset, scan.name, scan.parameters)
+    else {
+        return Err("Give a device name or a presetId".into());
+    };
+    let mut parameters = scan_templates::resolve(&parameters, scan.paper_size, &scan.variables)
+        .map_err(|e| e.to_string())?;
+    if scan.duplex {
+        let source = scanner_manager.duplex_source(&name).await?;
+        parameters.retain(|key, _| key.trim_start_matches('-') != "source");
+        parameters.insert("--source".to_string(), source);
+    }
+    let batch = scan.batch || scan.duplex;
+    if batch && !scanners::feeder_source(&parameters) {
+        return Err("batch scans need a document feeder --source".into());
+    }
+    let priority = scan.priority;
+    if batch {
+        let (_, first) =
+            app.batch_runner
+                .start(name, parameters, priority, scan.group_id, None, started_by)?;
+        return Ok(first);
+    }
+
+    // First step: create the scan with a placeholder path
+    let scan = Scan::create_pending(
+        &name,
+        &parameters,
+        scan.group_id,
+        started_by,
+        &pool,
+        &assets_dir,
+    )
+    .unwrap();
+    let scan_id = scan.id.unwrap();
+
+    // Create clones for the async task
+    let name_clone = name.clone();
+    let parameters_clone = parameters.clone();
+    let assets_dir_clone = assets_dir.clone();
+    let pool_clone = pool.clone();
+    let scanner_manager_clone = scanner_manager.clone();
+
+    // Start the actual scanning process in the background
+    tokio::spawn(async move {
+        scanner_manager_clone
+            .complete_scan(
+                scan_id,
+                &name_clone,
+                parameters_clone,
+                priority,
+                &pool_clone,
+                &assets_dir_clone,
+            )
+            .await;
+    });
+
+    // Return the scan ID immediately to the client
+    Ok(scan_id)
+}
+
+/// Whether the request may edit or delete the comment: its author can, and
+/// admins can. Without auth, anyone can.
+fn may_change_comment(ctx: &Context<'_>, comment: &GroupComment) -> bool {
+    match ctx.data_opt::<Principal>() {
+        Some(principal) => {
+            principal.has_scope(Scope::Admin)
+                || comment.author.as_deref() == Some(principal.attribution().as_str())
         }
+        None => true,
     }
 }
 
+/// Adds groups that were just finalized to the activity feed.
+fn record_finalized(
+    ctx: &Context<'_>,
+    group_ids: &[i32],
+    pool: &r2d2::Pool<duckdb::DuckdbConnectionManager>,
+) -> Result<()> {
+    let actor = ctx.data_opt::<Principal>().map(Principal::attribution);
+    for id in group_ids {
+        Activity::record(
+            ActivityKind::GroupFinalized,
+            Some(*id),
+            None,
+            actor.as_deref(),
+            None,
+            pool,
+        )?;
+    }
+    Ok(())
+}
+
 #[derive(Enum, Eq, PartialEq, Copy, Clone)]
 enum MutationType {
     Created,
@@ -573,7 +2284,7 @@ impl BookChanged {
     }
 
     async fn book(&self, ctx: &Context<'_>) -> Result<Option<Book>> {
-        let books = ctx.data_unchecked::<Storage>().lock().await;
+        let books = ctx.app()?.books.lock().await;
         let id = self.id.parse::<usize>()?;
         Ok(books.get(id).cloned())
     }
@@ -583,6 +2294,7 @@ pub struct SubscriptionRoot;
 
 #[Subscription]
 impl SubscriptionRoot {
+    #[graphql(guard = "RequireScope(Scope::Read)")]
     async fn interval(&self, #[graphql(default = 1)] n: i32) -> impl Stream<Item = i32> {
         let mut value = 0;
         async_stream::stream! {
@@ -594,6 +2306,95 @@ impl SubscriptionRoot {
         }
     }
 
+    /// Batches that stopped for the operator, optionally only `batch_id`.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn batch_paused(&self, batch_id: Option<i32>) -> impl Stream<Item = BatchPaused> {
+        SimpleBroker::<BatchPaused>::subscribe().filter(move |event| {
+            let res = batch_id.is_none_or(|batch_id| event.batch_id == batch_id);
+            async move { res }
+        })
+    }
+
+    /// Comments written, edited or deleted on the group.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn group_comments(&self, group_id: i32) -> impl Stream<Item = GroupCommentChanged> {
+        SimpleBroker::<GroupCommentChanged>::subscribe().filter(move |event| {
+            let res = event.comment.group_id == group_id;
+            async move { res }
+        })
+    }
+
+    /// Groups being assigned or unassigned, optionally only those given to
+    /// or taken from `user_id`.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn group_assignments(
+        &self,
+        user_id: Option<i32>,
+    ) -> impl Stream<Item = GroupAssignmentChanged> {
+        SimpleBroker::<GroupAssignmentChanged>::subscribe().filter(move |event| {
+            let res = user_id.is_none_or(|user_id| {
+                event.user_id == Some(user_id) || event.previous_user_id == Some(user_id)
+            });
+            async move { res }
+        })
+    }
+
+    /// Alerts as they're raised and resolved.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn alerts(&self) -> impl Stream<Item = Alert> {
+        SimpleBroker::<Alert>::subscribe()
+    }
+
+    /// Weekly digests as they're sent.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn digest(&self) -> impl Stream<Item = WeeklyDigest> {
+        SimpleBroker::<WeeklyDigest>::subscribe()
+    }
+
+    /// Finalized groups found to duplicate earlier ones.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn duplicate_found(&self) -> impl Stream<Item = GroupDuplicate> {
+        SimpleBroker::<GroupDuplicate>::subscribe()
+    }
+
+    /// Devices plugged in or unplugged, as background refreshes find them.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scanners_changed(&self) -> impl Stream<Item = ScannersChanged> {
+        SimpleBroker::<ScannersChanged>::subscribe()
+    }
+
+    /// How far along the scan is, as it comes off the device.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scan_progress(&self, scan_id: i32) -> impl Stream<Item = ScanProgress> {
+        SimpleBroker::<ScanProgress>::subscribe().filter(move |event| {
+            let res = event.scan_id == scan_id;
+            async move { res }
+        })
+    }
+
+    /// Devices starting and finishing scans, optionally only `device`. Starts
+    /// with the latest state of each device that has scanned since startup.
+    #[graphql(guard = "RequireScope(Scope::Read)")]
+    async fn scanner_activity(
+        &self,
+        ctx: &Context<'_>,
+        device: Option<String>,
+    ) -> Result<impl Stream<Item = ScannerActivity>> {
+        // Subscribe before taking the snapshot so nothing in between is missed
+        let updates = SimpleBroker::<ScannerActivity>::subscribe();
+        let current = ctx.app()?.scanner_manager.current_activity();
+        Ok(futures_util::stream::iter(current)
+            .chain(updates)
+            .filter(move |event| {
+                let res = device.as_ref().is_none_or(|device| event.device == *device);
+                async move { res }
+            }))
+    }
+
+    #[graphql(
+        guard = "RequireScope(Scope::Read)",
+        deprecation = "Demo data from the starter template, removed after 2027-01-13"
+    )]
     async fn books(&self, mutation_type: Option<MutationType>) -> impl Stream<Item = BookChanged> {
         SimpleBroker::<BookChanged>::subscribe().filter(move |event| {
             let res = if let Some(mutation_type) = mutation_type {
@@ -0,0 +1,86 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{Duration, NaiveDate};
+
+/// Deprecated parts of the API are kept at least this long before they're
+/// removed, so clients have a release or two to move over.
+pub const DEPRECATION_WINDOW_DAYS: i64 = 90;
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SchemaChangeKind {
+    Added,
+    /// Still works, but will be removed after `removeAfter`
+    Deprecated,
+    Removed,
+}
+
+/// A change to the API that clients may need to act on.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SchemaChange {
+    /// What changed, as `Type.field` or `Type.field(argument)`
+    pub coordinate: &'static str,
+    pub kind: SchemaChangeKind,
+    pub description: &'static str,
+    /// What to use instead, if anything
+    pub replacement: Option<&'static str>,
+    pub announced_on: NaiveDate,
+    /// The first day it may be gone, for deprecations
+    pub remove_after: Option<NaiveDate>,
+}
+
+/// Every change clients have been told about, oldest first. To deprecate a
+/// field, mark it `#[graphql(deprecation = "...")]` so introspection shows
+/// it, and add a `Deprecated` entry here with `remove_after` at least
+/// `DEPRECATION_WINDOW_DAYS` later; when it's removed, add a `Removed` entry
+/// for the same coordinate.
+pub fn all() -> Vec<SchemaChange> {
+    let announced_on = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+    let remove_after = NaiveDate::from_ymd_opt(2027, 1, 13);
+    let demo_books = |coordinate| SchemaChange {
+        coordinate,
+        kind: SchemaChangeKind::Deprecated,
+        description: "Demo data from the starter template, kept in memory and unrelated to scans",
+        replacement: None,
+        announced_on,
+        remove_after,
+    };
+    vec![
+        demo_books("QueryRoot.books"),
+        demo_books("MutationRoot.createBook"),
+        demo_books("MutationRoot.deleteBook"),
+        demo_books("SubscriptionRoot.books"),
+    ]
+}
+
+/// Warnings about the registry, printed at startup: deprecations given
+/// less than the window, and ones past their date that can now be removed.
+pub fn check(today: NaiveDate) -> Vec<String> {
+    let changes = all();
+    let mut warnings = Vec::new();
+    for change in changes.iter() {
+        let Some(remove_after) = change.remove_after else {
+            continue;
+        };
+        if remove_after < change.announced_on + Duration::days(DEPRECATION_WINDOW_DAYS) {
+            warnings.push(format!(
+                "{} is deprecated for less than {} days",
+                change.coordinate, DEPRECATION_WINDOW_DAYS
+            ));
+        }
+        let removed = changes.iter().any(|other| {
+            other.coordinate == change.coordinate && other.kind == SchemaChangeKind::Removed
+        });
+        if remove_after < today && !removed {
+            warnings.push(format!(
+                "{} was due for removal after {}",
+                change.coordinate, remove_after
+            ));
+        }
+    }
+    warnings
+}
+
+/// The date of the latest change, which clients can compare against the
+/// version they were written for.
+pub fn api_version() -> Option<NaiveDate> {
+    all().iter().map(|change| change.announced_on).max()
+}
@@ -0,0 +1,169 @@
+use std::{env, fs, io, path::Path};
+
+use async_graphql::{Enum, SimpleObject};
+use duckdb::DuckdbConnectionManager;
+
+use crate::{
+    classification::ClassificationRule, db_config, ingest_rules::IngestRule, ocr, scanners,
+    AssetsDir,
+};
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CheckOutcome {
+    Pass,
+    Fail,
+    /// Not needed with this configuration
+    Skip,
+}
+
+impl CheckOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckOutcome::Pass => "PASS",
+            CheckOutcome::Fail => "FAIL",
+            CheckOutcome::Skip => "SKIP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub outcome: CheckOutcome,
+    /// What was found, or what to fix
+    pub detail: String,
+}
+
+/// Whether the server can do its job where it's deployed, check by check.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SelfTestReport {
+    /// True if no check failed
+    pub passed: bool,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+fn check(name: impl Into<String>, result: Result<String, String>) -> SelfTestCheck {
+    let (outcome, detail) = match result {
+        Ok(detail) => (CheckOutcome::Pass, detail),
+        Err(detail) => (CheckOutcome::Fail, detail),
+    };
+    SelfTestCheck {
+        name: name.into(),
+        outcome,
+        detail,
+    }
+}
+
+/// Creates `dir` if needed, then writes and removes a file in it.
+fn writable(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".scanserv-self-test");
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)
+}
+
+fn database(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<String, String> {
+    let mut conn = pool.get().map_err(|e| e.to_string())?;
+    // A write rolled back: read-only files and held locks fail here
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    tx.execute_batch("CREATE TABLE self_test (id INTEGER); INSERT INTO self_test VALUES (1);")
+        .map_err(|e| e.to_string())?;
+    tx.rollback().map_err(|e| e.to_string())?;
+    let path = db_config::database_path(&conn).map_err(|e| e.to_string())?;
+    Ok(format!("{} accepts writes", path))
+}
+
+fn assets(assets_dir: &AssetsDir) -> Result<String, String> {
+    let scans = Path::new(&assets_dir.0).join("scans");
+    writable(&scans).map_err(|e| format!("{}: {}", scans.display(), e))?;
+    Ok(format!("{} is writable", scans.display()))
+}
+
+fn scanimage() -> SelfTestCheck {
+    if env::var("MOCK_SCANNER").unwrap_or_default() == "true" {
+        return SelfTestCheck {
+            name: "scanimage".to_string(),
+            outcome: CheckOutcome::Skip,
+            detail: "MOCK_SCANNER is set".to_string(),
+        };
+    }
+    if env::var("SCANNER_BACKEND").unwrap_or_default() == "escl" {
+        return SelfTestCheck {
+            name: "scanimage".to_string(),
+            outcome: CheckOutcome::Skip,
+            detail: "scanning over eSCL".to_string(),
+        };
+    }
+    check(
+        "scanimage",
+        if scanners::scanimage_installed() {
+            Ok("found".to_string())
+        } else {
+            Err(scanners::SCANIMAGE_MISSING.to_string())
+        },
+    )
+}
+
+/// OCR only runs for EPUB exports, tag suggestions and classification
+/// rules, so a missing tesseract only fails the check if it's configured
+/// or rules depend on it.
+fn tesseract(pool: &r2d2::Pool<DuckdbConnectionManager>) -> SelfTestCheck {
+    let languages = ocr::languages();
+    let result = match ocr::missing_languages(&languages) {
+        Ok(missing) if missing.is_empty() => Ok(format!("found, with {}", languages)),
+        Ok(missing) => Err(format!("no trained data for {}", missing.join(", "))),
+        Err(e) => Err(e.to_string()),
+    };
+    let needed =
+        env::var("OCR_LANGUAGES").is_ok() || !ClassificationRule::load_all(pool).is_empty();
+    match result {
+        Err(detail) if !needed => SelfTestCheck {
+            name: "tesseract".to_string(),
+            outcome: CheckOutcome::Skip,
+            detail: format!("{}; only needed for EPUB exports and OCR", detail),
+        },
+        result => check("tesseract", result),
+    }
+}
+
+/// Export directories named by ingest and classification rules.
+fn destinations(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<SelfTestCheck> {
+    let mut dirs: Vec<String> = IngestRule::load_all(pool)
+        .into_iter()
+        .filter_map(|rule| rule.export_dir)
+        .chain(
+            ClassificationRule::load_all(pool)
+                .into_iter()
+                .filter_map(|rule| rule.destination),
+        )
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+
+    dirs.into_iter()
+        .map(|dir| {
+            let result = writable(Path::new(&dir))
+                .map(|_| "writable".to_string())
+                .map_err(|e| e.to_string());
+            check(format!("destination {}", dir), result)
+        })
+        .collect()
+}
+
+/// Runs every check. Blocks on the filesystem and external commands.
+pub fn run(pool: &r2d2::Pool<DuckdbConnectionManager>, assets_dir: &AssetsDir) -> SelfTestReport {
+    let mut checks = vec![
+        check("database", database(pool)),
+        check("assets", assets(assets_dir)),
+        scanimage(),
+        tesseract(pool),
+    ];
+    checks.extend(destinations(pool));
+
+    SelfTestReport {
+        passed: checks
+            .iter()
+            .all(|check| check.outcome != CheckOutcome::Fail),
+        checks,
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use duckdb::{params, AccessMode, Connection, DuckdbConnectionManager, Result};
+
+use crate::db_config::DbConfig;
+
+const SNAPSHOT_ALIAS: &str = "snapshot_target";
+
+struct SnapshotPool {
+    pool: r2d2::Pool<DuckdbConnectionManager>,
+    path: PathBuf,
+    refreshed_at: DateTime<Utc>,
+}
+
+/// A read-only copy of the database for heavy analytical queries, so they
+/// don't hold connections or locks that interactive scanning needs.
+///
+/// Until the first refresh, queries fall back to the main pool.
+#[derive(Clone)]
+pub struct Snapshot {
+    dir: PathBuf,
+    db_config: DbConfig,
+    current: Arc<RwLock<Option<SnapshotPool>>>,
+    refreshing: Arc<Mutex<()>>,
+}
+
+impl Snapshot {
+    pub fn new(dir: PathBuf, db_config: DbConfig) -> Self {
+        // Snapshots from a previous run are stale, and nothing has them open
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.filter_map(Result::ok) {
+                if entry.file_name().to_string_lossy().starts_with("snapshot-") {
+                    fs::remove_file(entry.path()).ok();
+                }
+            }
+        }
+
+        Self {
+            dir,
+            db_config,
+            current: Arc::new(RwLock::new(None)),
+            refreshing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// The pool to run heavy reads against.
+    pub fn pool(
+        &self,
+        main: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> r2d2::Pool<DuckdbConnectionManager> {
+        match self.current.read().unwrap().as_ref() {
+            Some(snapshot) => snapshot.pool.clone(),
+            None => main.clone(),
+        }
+    }
+
+    /// When the snapshot was taken, or `None` if reads still go to the main pool.
+    pub fn refreshed_at(&self) -> Option<DateTime<Utc>> {
+        self.current
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|snapshot| snapshot.refreshed_at)
+    }
+
+    /// Copies the database into a new snapshot file and switches reads to it.
+    pub fn refresh(&self, main: &r2d2::Pool<DuckdbConnectionManager>) -> Result<DateTime<Utc>> {
+        let _refreshing = self.refreshing.lock().unwrap();
+
+        fs::create_dir_all(&self.dir).unwrap();
+        let refreshed_at = Utc::now();
+        let path = self.dir.join(format!(
+            "snapshot-{}.duckdb",
+            refreshed_at.timestamp_millis()
+        ));
+
+        // Copying inside one transaction, rather than copying the file, means
+        // the snapshot can't catch a checkpoint half written
+        let mut conn = main.get().unwrap();
+        let source: String =
+            conn.query_row("SELECT current_database()", params![], |row| row.get(0))?;
+        conn.execute(
+            &format!(
+                "ATTACH '{}' AS {}",
+                path.to_string_lossy().replace('\'', "''"),
+                SNAPSHOT_ALIAS
+            ),
+            params![],
+        )?;
+        let copied = copy_tables(&mut conn, &source);
+        conn.execute(&format!("DETACH {}", SNAPSHOT_ALIAS), params![])?;
+        if let Err(e) = copied {
+            fs::remove_file(&path).ok();
+            return Err(e);
+        }
+
+        // Same memory limit as the main database, on top of it
+        let config = self
+            .db_config
+            .to_duckdb_config()?
+            .access_mode(AccessMode::ReadOnly)?;
+        let manager = DuckdbConnectionManager::file_with_flags(&path, config)?;
+        let pool = r2d2::Pool::builder().max_size(4).build(manager).unwrap();
+
+        let previous = self.current.write().unwrap().replace(SnapshotPool {
+            pool,
+            path,
+            refreshed_at,
+        });
+
+        // Readers still holding the old pool keep the file open; on unix
+        // removing it only frees the space once they're done
+        if let Some(previous) = previous {
+            fs::remove_file(&previous.path).ok();
+        }
+
+        Ok(refreshed_at)
+    }
+}
+
+/// Copies every table's rows into the attached snapshot. Constraints and
+/// sequences are left behind since the snapshot is only ever read.
+fn copy_tables(conn: &mut Connection, source: &str) -> Result<()> {
+    let tx = conn.transaction()?;
+
+    let tables = tx
+        .prepare(
+            "SELECT table_name FROM duckdb_tables() WHERE database_name = ? AND schema_name = 'main'",
+        )?
+        .query_map(params![source], |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<_>>>()?;
+
+    for table in tables {
+        tx.execute(
+            &format!(
+                "CREATE TABLE {}.\"{}\" AS SELECT * FROM \"{}\".main.\"{}\"",
+                SNAPSHOT_ALIAS, table, source, table
+            ),
+            params![],
+        )?;
+    }
+
+    tx.commit()
+}
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use async_graphql::Enum;
+use chrono::Utc;
+use duckdb::DuckdbConnectionManager;
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, GrayImage, RgbImage,
+};
+
+use crate::{locale, scans::Scan, AssetsDir};
+
+/// Overlap is searched between these fractions of a tile's width.
+const MIN_OVERLAP: f32 = 0.05;
+const MAX_OVERLAP: f32 = 0.6;
+/// How far a tile may have drifted across the stitching direction, as a
+/// fraction of its height, when it was placed on the glass.
+const MAX_DRIFT: f32 = 0.1;
+/// Tiles are downscaled to about this width for the coarse search.
+const COARSE_WIDTH: u32 = 200;
+/// Pixel stride when scoring the full resolution refinement.
+const FINE_STRIDE: u32 = 4;
+
+/// How the tiles of an oversized original were laid out on the flatbed.
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum StitchDirection {
+    /// Left to right, each tile overlapping the one before it
+    #[default]
+    Horizontal,
+    /// Top to bottom
+    Vertical,
+}
+
+#[derive(Debug)]
+pub enum StitchError {
+    /// At least two scans are needed
+    TooFewScans,
+    ScanNotFound(i32),
+    /// Still pending, or failed
+    NotComplete(i32),
+    Image(String),
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for StitchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StitchError::TooFewScans => write!(f, "need at least two scans to stitch"),
+            StitchError::ScanNotFound(id) => write!(f, "scan {} not found", id),
+            StitchError::NotComplete(id) => write!(f, "scan {} is not complete", id),
+            StitchError::Image(e) => write!(f, "could not stitch images: {}", e),
+            StitchError::Db(e) => write!(f, "could not save stitched scan: {}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for StitchError {
+    fn from(e: duckdb::Error) -> Self {
+        StitchError::Db(e)
+    }
+}
+
+/// Mean absolute difference where `b` overlaps the right `overlap` columns of
+/// `a`, shifted down by `drift`. None if they barely overlap.
+fn overlap_score(
+    a: &GrayImage,
+    b: &GrayImage,
+    overlap: i64,
+    drift: i64,
+    stride: u32,
+) -> Option<f64> {
+    let x0 = a.width() as i64 - overlap;
+    let y0 = drift.max(0);
+    let y1 = (drift + b.height() as i64).min(a.height() as i64);
+    if overlap <= 0 || overlap > b.width() as i64 || y1 - y0 < a.height() as i64 / 2 {
+        return None;
+    }
+
+    let mut total = 0u64;
+    let mut count = 0u64;
+    for y in (y0..y1).step_by(stride as usize) {
+        for x in (0..overlap).step_by(stride as usize) {
+            let pa = a.get_pixel((x0 + x) as u32, y as u32)[0];
+            let pb = b.get_pixel(x as u32, (y - drift) as u32)[0];
+            total += pa.abs_diff(pb) as u64;
+            count += 1;
+        }
+    }
+    Some(total as f64 / count as f64)
+}
+
+/// The (overlap, drift) pair in the given ranges that lines the tiles up best.
+fn best_alignment(
+    a: &GrayImage,
+    b: &GrayImage,
+    overlaps: std::ops::RangeInclusive<i64>,
+    drifts: std::ops::RangeInclusive<i64>,
+    stride: u32,
+) -> Option<(i64, i64)> {
+    let mut best: Option<(f64, i64, i64)> = None;
+    for overlap in overlaps {
+        for drift in drifts.clone() {
+            if let Some(score) = overlap_score(a, b, overlap, drift, stride) {
+                if best.is_none_or(|(best_score, _, _)| score < best_score) {
+                    best = Some((score, overlap, drift));
+                }
+            }
+        }
+    }
+    best.map(|(_, overlap, drift)| (overlap, drift))
+}
+
+/// Where `b` goes relative to `a` when `b` continues `a` to the right:
+/// coarse search on downscaled copies, then refined at full resolution.
+fn align(a: &RgbImage, b: &RgbImage) -> Result<(i64, i64), StitchError> {
+    let a_gray = DynamicImage::ImageRgb8(a.clone()).to_luma8();
+    let b_gray = DynamicImage::ImageRgb8(b.clone()).to_luma8();
+
+    let scale = a.width().div_ceil(COARSE_WIDTH).max(1);
+    let shrink = |image: &GrayImage| {
+        imageops::resize(
+            image,
+            (image.width() / scale).max(1),
+            (image.height() / scale).max(1),
+            FilterType::Triangle,
+        )
+    };
+    let (a_small, b_small) = (shrink(&a_gray), shrink(&b_gray));
+
+    let width = a_small.width().min(b_small.width()) as f32;
+    let max_drift = (a_small.height() as f32 * MAX_DRIFT) as i64;
+    let (overlap, drift) = best_alignment(
+        &a_small,
+        &b_small,
+        ((width * MIN_OVERLAP) as i64).max(1)..=(width * MAX_OVERLAP) as i64,
+        -max_drift..=max_drift,
+        1,
+    )
+    .ok_or_else(|| StitchError::Image("tiles are too different in size".to_string()))?;
+
+    let scale = scale as i64;
+    best_alignment(
+        &a_gray,
+        &b_gray,
+        (overlap - 1) * scale..=(overlap + 1) * scale,
+        (drift - 1) * scale..=(drift + 1) * scale,
+        FINE_STRIDE,
+    )
+    .ok_or_else(|| StitchError::Image("could not align tiles".to_string()))
+}
+
+/// Lays overlapping tiles out left to right into one image. Later tiles are
+/// drawn over earlier ones where they overlap.
+fn stitch_horizontal(tiles: &[RgbImage]) -> Result<RgbImage, StitchError> {
+    let mut positions = vec![(0i64, 0i64)];
+    for pair in tiles.windows(2) {
+        let (overlap, drift) = align(&pair[0], &pair[1])?;
+        let (x, y) = *positions.last().unwrap();
+        positions.push((x + pair[0].width() as i64 - overlap, y + drift));
+    }
+
+    let top = positions.iter().map(|(_, y)| *y).min().unwrap();
+    let width = positions
+        .iter()
+        .zip(tiles)
+        .map(|((x, _), tile)| x + tile.width() as i64)
+        .max()
+        .unwrap();
+    let bottom = positions
+        .iter()
+        .zip(tiles)
+        .map(|((_, y), tile)| y + tile.height() as i64)
+        .max()
+        .unwrap();
+
+    let mut canvas = RgbImage::from_pixel(
+        width as u32,
+        (bottom - top) as u32,
+        image::Rgb([255, 255, 255]),
+    );
+    for ((x, y), tile) in positions.iter().zip(tiles) {
+        imageops::overlay(&mut canvas, tile, *x, y - top);
+    }
+    Ok(canvas)
+}
+
+/// Combines flatbed scans of parts of an oversized original into a new scan
+/// in the first scan's group. The source scans are left as they are.
+pub fn stitch_scans(
+    scan_ids: &[i32],
+    direction: StitchDirection,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+    assets_dir: &AssetsDir,
+) -> Result<Scan, StitchError> {
+    if scan_ids.len() < 2 {
+        return Err(StitchError::TooFewScans);
+    }
+
+    let scans = scan_ids
+        .iter()
+        .map(|id| Scan::load(*id, pool).map_err(|_| StitchError::ScanNotFound(*id)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut tiles = Vec::new();
+    for scan in &scans {
+        if scan.status != "COMPLETE" {
+            return Err(StitchError::NotComplete(scan.id.unwrap()));
+        }
+        let image = scan
+            .open_image(assets_dir)
+            .map_err(|e| StitchError::Image(e.to_string()))?;
+        // Vertical layouts are turned on their side so they stitch left to right
+        tiles.push(match direction {
+            StitchDirection::Horizontal => image.to_rgb8(),
+            StitchDirection::Vertical => image.rotate270().to_rgb8(),
+        });
+    }
+
+    let stitched = stitch_horizontal(&tiles)?;
+    let stitched = match direction {
+        StitchDirection::Horizontal => stitched,
+        StitchDirection::Vertical => imageops::rotate90(&stitched),
+    };
+
+    let first = &scans[0];
+    let path = Path::new("scans")
+        .join(format!(
+            "stitched_{}_{}.png",
+            first.id.unwrap(),
+            locale::filename_stamp(Utc::now())
+        ))
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let mut scan_parameters: HashMap<String, String> = first
+        .scan_parameters
+        .iter()
+        .filter(|(key, _)| key.trim_start_matches('-') == "resolution")
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    scan_parameters.insert(
+        "stitchedFrom".to_string(),
+        scan_ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+
+    let mut scan = Scan::new(
+        "COMPLETE".to_string(),
+        path,
+        first.scanner.clone(),
+        scan_parameters,
+        Utc::now(),
+    );
+    assets_dir
+        .write_image(&scan.path, &DynamicImage::ImageRgb8(stitched))
+        .map_err(|e| StitchError::Image(e.to_string()))?;
+    scan.save(pool)?;
+    if let Some(group) = &first.group {
+        scan.set_group(group.id, pool)?;
+    }
+
+    Ok(scan)
+}
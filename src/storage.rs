@@ -0,0 +1,127 @@
+use std::{
+    fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use image::{DynamicImage, ImageFormat, ImageReader};
+
+use crate::{asset_path::AssetPath, AssetsDir};
+
+/// Marks an encrypted file, followed by the nonce and the AES-256-GCM ciphertext.
+/// Files without it are read as they are, so existing scans stay readable
+/// after encryption is turned on.
+const MAGIC: &[u8] = b"SCANSERV-AES256GCM\0";
+const NONCE_LEN: usize = 12;
+
+/// Key scan files are encrypted with, from the file named by `STORAGE_KEY_FILE`.
+#[derive(Clone)]
+pub struct StorageKey(Key<Aes256Gcm>);
+
+impl StorageKey {
+    /// Reads a key file holding 32 raw bytes or 64 hex digits.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let text = String::from_utf8_lossy(&contents);
+        let text = text.trim();
+
+        let bytes = if contents.len() == 32 {
+            contents
+        } else if text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit()) {
+            (0..32)
+                .map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).unwrap())
+                .collect()
+        } else {
+            return Err(io::Error::other(
+                "storage key must be 32 bytes or 64 hex digits",
+            ));
+        };
+        Ok(Self(*Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(&self.0).encrypt(&nonce, plain).unwrap();
+        [MAGIC, nonce.as_slice(), &ciphertext].concat()
+    }
+
+    fn decrypt(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(io::Error::other("encrypted file is truncated"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        Aes256Gcm::new(&self.0)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| io::Error::other("could not decrypt file, wrong storage key?"))
+    }
+}
+
+/// Scan files go through these so they are encrypted when a storage key is
+/// configured and stored as they are otherwise.
+impl AssetsDir {
+    pub fn read(&self, path: &AssetPath) -> io::Result<Vec<u8>> {
+        let contents = fs::read(path.as_disk_path(&self.0))?;
+        match (contents.strip_prefix(MAGIC), &self.1) {
+            (None, _) => Ok(contents),
+            (Some(sealed), Some(key)) => key.decrypt(sealed),
+            (Some(_), None) => Err(io::Error::other(
+                "file is encrypted but no STORAGE_KEY_FILE is configured",
+            )),
+        }
+    }
+
+    pub fn write(&self, path: &AssetPath, contents: &[u8]) -> io::Result<()> {
+        match &self.1 {
+            Some(key) => fs::write(path.as_disk_path(&self.0), key.encrypt(contents)),
+            None => fs::write(path.as_disk_path(&self.0), contents),
+        }
+    }
+
+    pub fn read_image(&self, path: &AssetPath) -> image::ImageResult<DynamicImage> {
+        ImageReader::new(Cursor::new(self.read(path)?))
+            .with_guessed_format()?
+            .decode()
+    }
+
+    pub fn image_dimensions(&self, path: &AssetPath) -> image::ImageResult<(u32, u32)> {
+        ImageReader::new(Cursor::new(self.read(path)?))
+            .with_guessed_format()?
+            .into_dimensions()
+    }
+
+    /// Saves the image as a PNG.
+    pub fn write_image(&self, path: &AssetPath, image: &DynamicImage) -> image::ImageResult<()> {
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        Ok(self.write(path, &png)?)
+    }
+
+    /// Where an outside program such as scanimage should write a file meant
+    /// for `path`. With encryption on this is a private temporary file, so
+    /// the plain image never lands on the assets volume; pass it to `store`
+    /// once written.
+    pub fn capture_path(&self, path: &AssetPath) -> PathBuf {
+        match &self.1 {
+            Some(_) => std::env::temp_dir().join(format!(
+                "scanserv-{}-{}",
+                std::process::id(),
+                path.as_relative_path().replace('/', "_")
+            )),
+            None => PathBuf::from(path.as_disk_path(&self.0)),
+        }
+    }
+
+    /// Moves a file written to `capture_path` into place, encrypting it.
+    pub fn store(&self, path: &AssetPath, captured: &Path) -> io::Result<()> {
+        if self.1.is_none() {
+            return Ok(());
+        }
+        let result = fs::read(captured).and_then(|contents| self.write(path, &contents));
+        fs::remove_file(captured).ok();
+        result
+    }
+}
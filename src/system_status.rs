@@ -0,0 +1,26 @@
+use async_graphql::SimpleObject;
+
+use crate::scanners::ScannerManager;
+
+/// Problems with how the server is set up, for the UI to point out on a
+/// first run rather than leaving scans to fail.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SystemStatus {
+    /// Whether scans come from the mock scanner (MOCK_SCANNER=true)
+    pub mock_scanner: bool,
+    /// Whether SANE's scanimage could be found when last looked for
+    pub scanimage_installed: bool,
+    /// What needs fixing, in plain words
+    pub warnings: Vec<String>,
+}
+
+impl SystemStatus {
+    pub fn check(scanner_manager: &ScannerManager) -> Self {
+        let unavailable = scanner_manager.unavailable();
+        SystemStatus {
+            mock_scanner: scanner_manager.is_mock(),
+            scanimage_installed: unavailable.is_none(),
+            warnings: unavailable.into_iter().map(str::to_string).collect(),
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::{fmt, io};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Utc};
+use duckdb::{params, DuckdbConnectionManager, Result};
+
+use crate::{
+    entities::{extract, Entity, EntityKind},
+    ocr::ScanText,
+    scans::ScanGroup,
+    AssetsDir,
+};
+
+/// A tag the group's text suggests, waiting to be accepted.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct TagSuggestion {
+    pub id: i32,
+    pub group_id: i32,
+    pub tag: String,
+    pub kind: EntityKind,
+    /// Where it came from, as it appears on the page
+    pub evidence: String,
+    /// The page it was found on
+    pub scan_id: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum SuggestError {
+    GroupNotFound,
+    /// A page's text could not be read, e.g. tesseract isn't installed
+    Ocr(io::Error),
+    Db(duckdb::Error),
+}
+
+impl fmt::Display for SuggestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuggestError::GroupNotFound => write!(f, "group not found"),
+            SuggestError::Ocr(e) => write!(f, "could not read text from scan: {}", e),
+            SuggestError::Db(e) => write!(f, "could not save suggestions: {}", e),
+        }
+    }
+}
+
+impl From<duckdb::Error> for SuggestError {
+    fn from(e: duckdb::Error) -> Self {
+        SuggestError::Db(e)
+    }
+}
+
+/// The entities worth tagging a group with: every year and organization
+/// mentioned, and the largest amount in each currency, which on an invoice
+/// or statement is usually the total. Earlier pages win ties.
+fn worth_tagging(found: Vec<(i32, Entity)>) -> Vec<(i32, Entity)> {
+    let mut chosen: Vec<(i32, Entity)> = Vec::new();
+    for (scan_id, entity) in found {
+        let currency = |entity: &Entity| entity.tag.split(' ').next().map(str::to_string);
+        let existing = chosen.iter().position(|(_, other)| {
+            other.kind == entity.kind
+                && match entity.kind {
+                    EntityKind::Amount => currency(other) == currency(&entity),
+                    _ => other.tag.to_lowercase() == entity.tag.to_lowercase(),
+                }
+        });
+        match existing {
+            None => chosen.push((scan_id, entity)),
+            Some(i) if entity.cents > chosen[i].1.cents => chosen[i] = (scan_id, entity),
+            Some(_) => {}
+        }
+    }
+    chosen
+}
+
+impl TagSuggestion {
+    /// The group's suggestions, in the order they were found.
+    pub fn load_all_by_group(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Vec<TagSuggestion> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, scan_group_id, tag, kind, evidence, scan_id, created_at
+                 FROM tag_suggestions WHERE scan_group_id = ? ORDER BY id",
+            )
+            .unwrap();
+
+        let suggestions: Vec<TagSuggestion> = stmt
+            .query_map([group_id], |row| {
+                let kind: String = row.get(3)?;
+                Ok(TagSuggestion {
+                    id: row.get(0)?,
+                    group_id: row.get(1)?,
+                    tag: row.get(2)?,
+                    kind: EntityKind::from_str(&kind),
+                    evidence: row.get(4)?,
+                    scan_id: row.get(5)?,
+                    created_at: row.get(6)?,
+                })
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        suggestions
+    }
+
+    /// Reads the text of the group's completed pages, running OCR where it
+    /// hasn't been yet, and replaces the group's suggestions with the tags
+    /// its dates, organizations and amounts suggest. Tags the group already
+    /// has aren't suggested.
+    pub fn suggest_for_group(
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<Vec<TagSuggestion>, SuggestError> {
+        let group = ScanGroup::load(group_id, pool).map_err(|_| SuggestError::GroupNotFound)?;
+
+        let mut found: Vec<(i32, Entity)> = Vec::new();
+        for scan in group.scans.iter().filter(|scan| scan.status == "COMPLETE") {
+            let text = ScanText::for_scan(scan, pool, assets_dir).map_err(SuggestError::Ocr)?;
+            found.extend(
+                extract(&text.text)
+                    .into_iter()
+                    .map(|entity| (text.scan_id, entity)),
+            );
+        }
+        let found: Vec<(i32, Entity)> = worth_tagging(found)
+            .into_iter()
+            .filter(|(_, entity)| {
+                !group
+                    .tags
+                    .iter()
+                    .any(|tag| tag.to_lowercase() == entity.tag.to_lowercase())
+            })
+            .collect();
+
+        let mut conn = pool.get().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM tag_suggestions WHERE scan_group_id = ?",
+            params![group_id],
+        )?;
+        let now = Utc::now();
+        for (scan_id, entity) in &found {
+            tx.execute(
+                "INSERT INTO tag_suggestions (scan_group_id, tag, kind, evidence, scan_id, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    group_id,
+                    entity.tag,
+                    entity.kind.as_str(),
+                    entity.text,
+                    scan_id,
+                    now
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(Self::load_all_by_group(group_id, pool))
+    }
+
+    /// Adds the suggested `tags`, or every suggestion if none are named, to
+    /// the group's tags and drops them from its suggestions. Returns false
+    /// if there is no such group.
+    pub fn accept(
+        group_id: i32,
+        tags: Option<&[String]>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<bool> {
+        let Ok(mut group) = ScanGroup::load(group_id, pool) else {
+            return Ok(false);
+        };
+
+        let accepted: Vec<&TagSuggestion> = group
+            .suggested_tags
+            .iter()
+            .filter(|suggestion| tags.is_none_or(|tags| tags.contains(&suggestion.tag)))
+            .collect();
+        let ids: Vec<i32> = accepted.iter().map(|suggestion| suggestion.id).collect();
+        let new_tags: Vec<String> = accepted
+            .iter()
+            .map(|suggestion| suggestion.tag.clone())
+            .filter(|tag| !group.tags.contains(tag))
+            .collect();
+        if ids.is_empty() {
+            return Ok(true);
+        }
+
+        group.tags.extend(new_tags);
+        group.save(pool)?;
+
+        let conn = pool.get().unwrap();
+        for id in ids {
+            conn.execute("DELETE FROM tag_suggestions WHERE id = ?", params![id])?;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,46 @@
+use image::{GrayImage, Luma};
+
+use crate::bitmap_font::{fill, text_width, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const PAPER: Luma<u8> = Luma([255]);
+const INK: Luma<u8> = Luma([0]);
+const STRIPE: Luma<u8> = Luma([225]);
+
+/// Draws `text` horizontally centred with its top edge at `y`.
+fn draw_text(image: &mut GrayImage, text: &str, y: u32, scale: u32) {
+    let x = image.width().saturating_sub(text_width(text, scale)) / 2;
+    crate::bitmap_font::draw_text(image, text, x, y, scale, INK);
+}
+
+/// A stand-in page for simulated scans: diagonally striped so it can't be
+/// mistaken for a real document, stamped SIMULATED, with `details` below.
+pub fn render(width: u32, height: u32, details: &[String]) -> GrayImage {
+    let mut image = GrayImage::from_pixel(width, height, PAPER);
+
+    let stripe_period = (width / 12).max(8);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if (x + y) % stripe_period < stripe_period / 4 {
+            *pixel = STRIPE;
+        }
+    }
+
+    let border = (width / 100).max(2);
+    fill(&mut image, 0, 0, width, border, INK);
+    fill(&mut image, 0, height - border, width, border, INK);
+    fill(&mut image, 0, 0, border, height, INK);
+    fill(&mut image, width - border, 0, border, height, INK);
+
+    let title = "SIMULATED";
+    let title_scale = (width * 3 / 4 / ((GLYPH_WIDTH + 1) * title.len() as u32)).max(1);
+    let mut y = height / 3;
+    draw_text(&mut image, title, y, title_scale);
+    y += (GLYPH_HEIGHT + 4) * title_scale;
+
+    let detail_scale = (title_scale / 3).max(1);
+    for line in details {
+        draw_text(&mut image, line, y, detail_scale);
+        y += (GLYPH_HEIGHT + 3) * detail_scale;
+    }
+
+    image
+}
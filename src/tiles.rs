@@ -0,0 +1,194 @@
+use std::{fs, io::Cursor, path::Path};
+
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageResult};
+use serde::{Deserialize, Serialize};
+
+use crate::{asset_path::AssetPath, scans::Scan, AssetsDir, PublicUrl};
+
+/// Width and height of every tile, except at the right and bottom edges.
+pub const TILE_SIZE: u32 = 512;
+const JPEG_QUALITY: u8 = 85;
+
+/// The levels a scan's tiles were cut at. Level `s` is the image shrunk by
+/// `s`; the last level fits in a single tile.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pyramid {
+    pub width: u32,
+    pub height: u32,
+    pub scale_factors: Vec<u32>,
+}
+
+fn tiles_dir(scan_id: i32) -> String {
+    format!("tiles/{}", scan_id)
+}
+
+fn pyramid_path(scan_id: i32) -> AssetPath {
+    AssetPath::from_relative_path(format!("{}/pyramid.json", tiles_dir(scan_id)))
+}
+
+fn tile_path(scan_id: i32, scale: u32, col: u32, row: u32) -> AssetPath {
+    AssetPath::from_relative_path(format!(
+        "{}/{}/{}_{}.jpg",
+        tiles_dir(scan_id),
+        scale,
+        col,
+        row
+    ))
+}
+
+/// Deletes the scan's tiles, so they are cut again from its current image.
+pub fn remove_tiles(scan_id: i32, assets_dir: &AssetsDir) {
+    fs::remove_dir_all(Path::new(&assets_dir.0).join(tiles_dir(scan_id))).ok();
+}
+
+pub fn encode_jpeg(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(JpegEncoder::new_with_quality(
+        &mut Cursor::new(&mut jpeg),
+        JPEG_QUALITY,
+    ))?;
+    Ok(jpeg)
+}
+
+/// Cuts every level of the scan's image into tiles. The pyramid is written
+/// last, so a half-cut set of tiles is never mistaken for a finished one.
+fn cut(scan_id: i32, scan: &Scan, assets_dir: &AssetsDir) -> ImageResult<Pyramid> {
+    let mut level = scan.open_image(assets_dir)?;
+    let mut pyramid = Pyramid {
+        width: level.width(),
+        height: level.height(),
+        scale_factors: Vec::new(),
+    };
+
+    let mut scale = 1;
+    loop {
+        let dir = Path::new(&assets_dir.0)
+            .join(tiles_dir(scan_id))
+            .join(scale.to_string());
+        fs::create_dir_all(dir)?;
+        for row in 0..level.height().div_ceil(TILE_SIZE) {
+            for col in 0..level.width().div_ceil(TILE_SIZE) {
+                let tile = level.crop_imm(
+                    col * TILE_SIZE,
+                    row * TILE_SIZE,
+                    TILE_SIZE.min(level.width() - col * TILE_SIZE),
+                    TILE_SIZE.min(level.height() - row * TILE_SIZE),
+                );
+                assets_dir.write(&tile_path(scan_id, scale, col, row), &encode_jpeg(&tile)?)?;
+            }
+        }
+        pyramid.scale_factors.push(scale);
+
+        if level.width() <= TILE_SIZE && level.height() <= TILE_SIZE {
+            break;
+        }
+        scale *= 2;
+        level = level.resize_exact(
+            pyramid.width.div_ceil(scale),
+            pyramid.height.div_ceil(scale),
+            FilterType::Triangle,
+        );
+    }
+
+    assets_dir.write(
+        &pyramid_path(scan_id),
+        &serde_json::to_vec(&pyramid).unwrap(),
+    )?;
+    Ok(pyramid)
+}
+
+/// The whole image at full size. IIIF manifests name this as each page's
+/// image, for viewers that don't tile.
+pub fn full_image(scan: &Scan, assets_dir: &AssetsDir) -> ImageResult<Vec<u8>> {
+    encode_jpeg(&scan.open_image(assets_dir)?)
+}
+
+/// The scan's tile pyramid, cutting its tiles first if they don't exist yet.
+pub fn pyramid(scan: &Scan, assets_dir: &AssetsDir) -> ImageResult<Pyramid> {
+    let scan_id = scan.id.unwrap();
+    if let Some(pyramid) = assets_dir
+        .read(&pyramid_path(scan_id))
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+    {
+        return Ok(pyramid);
+    }
+    cut(scan_id, scan, assets_dir)
+}
+
+/// The tile an IIIF Image API request names, if it is one of the tiles in
+/// the pyramid. Only the tiles `info.json` advertises are served (level 0):
+/// a region on the tile grid of one level, scaled to that level, unrotated.
+pub fn tile(
+    scan_id: i32,
+    pyramid: &Pyramid,
+    region: &str,
+    size: &str,
+    rotation: &str,
+    file: &str,
+    assets_dir: &AssetsDir,
+) -> Option<Vec<u8>> {
+    if rotation != "0" || !matches!(file, "default.jpg" | "color.jpg") {
+        return None;
+    }
+
+    let (x, y, w, h) = match region {
+        "full" => (0, 0, pyramid.width, pyramid.height),
+        _ => {
+            let parts: Vec<u32> = region
+                .split(',')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            match parts[..] {
+                [x, y, w, h] => (x, y, w, h),
+                _ => return None,
+            }
+        }
+    };
+    if w == 0 || h == 0 || x + w > pyramid.width || y + h > pyramid.height {
+        return None;
+    }
+
+    let scale = pyramid.scale_factors.iter().copied().find(|scale| {
+        let span = TILE_SIZE * scale;
+        let expected = (w.div_ceil(*scale), h.div_ceil(*scale));
+        let size_matches = match size {
+            "max" => *scale == 1,
+            _ => match size.split_once(',') {
+                Some((width, "")) => width.parse() == Ok(expected.0),
+                Some((width, height)) => {
+                    (width.parse(), height.parse()) == (Ok(expected.0), Ok(expected.1))
+                }
+                None => false,
+            },
+        };
+        x % span == 0
+            && y % span == 0
+            && w == span.min(pyramid.width - x)
+            && h == span.min(pyramid.height - y)
+            && size_matches
+    })?;
+
+    let span = TILE_SIZE * scale;
+    assets_dir
+        .read(&tile_path(scan_id, scale, x / span, y / span))
+        .ok()
+}
+
+/// IIIF Image API 3.0 description of the scan's tiles, for viewers such as
+/// OpenSeadragon and Mirador.
+pub fn info_json(scan_id: i32, pyramid: &Pyramid, public_url: &PublicUrl) -> serde_json::Value {
+    serde_json::json!({
+        "@context": "http://iiif.io/api/image/3/context.json",
+        "id": public_url.image_service_url(scan_id),
+        "type": "ImageService3",
+        "protocol": "http://iiif.io/api/image",
+        "profile": "level0",
+        "width": pyramid.width,
+        "height": pyramid.height,
+        "tiles": [{
+            "width": TILE_SIZE,
+            "scaleFactors": pyramid.scale_factors,
+        }],
+    })
+}
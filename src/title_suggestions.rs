@@ -0,0 +1,69 @@
+use duckdb::{DuckdbConnectionManager, Result};
+
+use crate::{
+    entities::{extract, EntityKind},
+    ocr::ScanText,
+    scans::{Scan, ScanGroup},
+};
+
+/// Longer first lines are cut at a word to about this many characters.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// A title from the text on a page: the organization it's from and the
+/// first date on it, e.g. "Acme Widgets Ltd 31.12.2024", or failing that
+/// its first line that reads like a heading rather than a number.
+fn title_from_text(text: &str) -> Option<String> {
+    let entities = extract(text);
+    let first = |kind: EntityKind| entities.iter().find(|entity| entity.kind == kind);
+    if let Some(organization) = first(EntityKind::Organization) {
+        return Some(match first(EntityKind::Date) {
+            Some(date) => format!("{} {}", organization.tag, date.text),
+            None => organization.tag.clone(),
+        });
+    }
+
+    let line = text.split("\n\n").map(str::trim).find(|line| {
+        let letters = line.chars().filter(|c| c.is_alphabetic()).count();
+        let digits = line.chars().filter(char::is_ascii_digit).count();
+        letters >= 3 && letters > digits
+    })?;
+    if line.chars().count() <= MAX_TITLE_CHARS {
+        return Some(line.to_string());
+    }
+    let mut title = String::new();
+    for word in line.split(' ') {
+        if !title.is_empty() && title.chars().count() + 1 + word.chars().count() > MAX_TITLE_CHARS {
+            break;
+        }
+        if !title.is_empty() {
+            title.push(' ');
+        }
+        title.push_str(word);
+    }
+    Some(title)
+}
+
+/// A title for a group from its first page's text, once that page has been
+/// through OCR. None until then, or if it's already the group's title.
+/// Reading the text is left to whatever OCRs the page first; this doesn't
+/// run tesseract.
+pub fn suggest(
+    scans: &[Scan],
+    title: &str,
+    pool: &r2d2::Pool<DuckdbConnectionManager>,
+) -> Option<String> {
+    let first = scans.iter().find(|scan| scan.status == "COMPLETE")?;
+    let text = ScanText::load(first.id?, pool).ok()??;
+    title_from_text(&text.text).filter(|suggested| suggested != title)
+}
+
+/// Renames the group to its suggested title. False if there's none.
+pub fn accept(group_id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<bool> {
+    let mut group = ScanGroup::load(group_id, pool)?;
+    let Some(title) = group.suggested_title.take() else {
+        return Ok(false);
+    };
+    group.title = title;
+    group.save(pool)?;
+    Ok(true)
+}
@@ -0,0 +1,186 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager};
+
+/// Bytes received are written back at most once per this many.
+pub const PROGRESS_STEP: i64 = 1 << 20;
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UploadStatus {
+    /// The files are still arriving
+    Receiving,
+    /// The pages are being filed
+    Importing,
+    Complete,
+    Failed,
+}
+
+impl UploadStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UploadStatus::Receiving => "receiving",
+            UploadStatus::Importing => "importing",
+            UploadStatus::Complete => "complete",
+            UploadStatus::Failed => "failed",
+        }
+    }
+
+    fn from_str(status: &str) -> Self {
+        match status {
+            "receiving" => UploadStatus::Receiving,
+            "importing" => UploadStatus::Importing,
+            "complete" => UploadStatus::Complete,
+            _ => UploadStatus::Failed,
+        }
+    }
+}
+
+/// Documents POSTed to `/api/upload`, with their progress kept on the row
+/// so a reloaded page can pick it up again.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Upload {
+    pub id: i32,
+    /// The user or API key that sent it, if auth identified one
+    pub uploader: Option<String>,
+    pub status: UploadStatus,
+    pub bytes_received: i64,
+    /// From the request's Content-Length, if it sent one
+    pub bytes_total: Option<i64>,
+    pub pages_imported: i32,
+    /// Known once every file has arrived
+    pub pages_total: Option<i32>,
+    /// The group the pages were filed in
+    pub group_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const COLUMNS: &str = "id, uploader, status, bytes_received, bytes_total, pages_imported, pages_total, scan_group_id, created_at, updated_at";
+
+fn row_to_upload(row: &duckdb::Row) -> duckdb::Result<Upload> {
+    Ok(Upload {
+        id: row.get(0)?,
+        uploader: row.get(1)?,
+        status: UploadStatus::from_str(&row.get::<usize, String>(2)?),
+        bytes_received: row.get(3)?,
+        bytes_total: row.get(4)?,
+        pages_imported: row.get(5)?,
+        pages_total: row.get(6)?,
+        group_id: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+impl Upload {
+    pub fn create(
+        uploader: Option<&str>,
+        bytes_total: Option<i64>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<i32> {
+        let conn = pool.get().unwrap();
+        let now = Utc::now();
+        conn.query_row(
+            "INSERT INTO uploads (uploader, status, bytes_total, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?) RETURNING id",
+            params![
+                uploader,
+                UploadStatus::Receiving.as_str(),
+                bytes_total,
+                now,
+                now
+            ],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Upload> {
+        let conn = pool.get().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM uploads WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_upload,
+        )
+    }
+
+    /// The latest `limit` uploads, newest first.
+    pub fn load_recent(limit: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<Upload> {
+        let conn = pool.get().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM uploads ORDER BY created_at DESC, id DESC LIMIT ?",
+                COLUMNS
+            ))
+            .unwrap();
+
+        let uploads: Vec<Upload> = stmt
+            .query_map(params![limit], row_to_upload)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        uploads
+    }
+
+    pub fn set_bytes_received(
+        id: i32,
+        bytes: i64,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE uploads SET bytes_received = ?, updated_at = ? WHERE id = ?",
+            params![bytes, Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Records that every file arrived, holding `pages_total` pages.
+    pub fn start_import(
+        id: i32,
+        bytes: i64,
+        pages_total: usize,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE uploads SET status = ?, bytes_received = ?, pages_total = ?, updated_at = ? WHERE id = ?",
+            params![
+                UploadStatus::Importing.as_str(),
+                bytes,
+                pages_total as i32,
+                Utc::now(),
+                id
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_pages_imported(
+        id: i32,
+        pages: usize,
+        group_id: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE uploads SET pages_imported = ?, scan_group_id = ?, updated_at = ? WHERE id = ?",
+            params![pages as i32, group_id, Utc::now(), id],
+        )?;
+        Ok(())
+    }
+
+    pub fn finish(
+        id: i32,
+        status: UploadStatus,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<()> {
+        let conn = pool.get().unwrap();
+        conn.execute(
+            "UPDATE uploads SET status = ?, updated_at = ? WHERE id = ?",
+            params![status.as_str(), Utc::now(), id],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Duration, Utc};
+use duckdb::Result;
+use duckdb::{params, DuckdbConnectionManager, OptionalExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{generate_token, hash_token, Scope},
+    ldap_sync::{self, LdapConfig},
+};
+
+const SESSION_PREFIX: &str = "sss_";
+pub const SESSION_LIFETIME_DAYS: i64 = 30;
+
+#[derive(Enum, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub enum Role {
+    /// Read-only access
+    Viewer,
+    /// Can scan and edit, but not manage keys or users
+    Operator,
+    Admin,
+}
+
+impl Role {
+    pub fn scopes(&self) -> Vec<Scope> {
+        match self {
+            Role::Viewer => vec![Scope::Read],
+            Role::Operator => vec![Scope::Read, Scope::Scan, Scope::Write],
+            Role::Admin => vec![Scope::Admin],
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn from_str(role: &str) -> Self {
+        match role {
+            "admin" => Role::Admin,
+            "operator" => Role::Operator,
+            _ => Role::Viewer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    /// Set for accounts synced from LDAP, which sign in with their directory
+    /// password and get their role from its groups
+    pub ldap_dn: Option<String>,
+    /// When LDAP sync found them gone from the directory or its role groups.
+    /// They can't sign in until they're back.
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+const COLUMNS: &str = "id, username, role, created_at, ldap_dn, disabled_at";
+
+fn row_to_user(row: &duckdb::Row) -> duckdb::Result<User> {
+    let role: String = row.get(2)?;
+
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        role: Role::from_str(&role),
+        created_at: row.get(3)?,
+        ldap_dn: row.get(4)?,
+        disabled_at: row.get(5)?,
+    })
+}
+
+impl User {
+    pub fn create(
+        username: String,
+        password: &str,
+        role: Role,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+
+        let id: i32 = conn.query_row(
+            "INSERT INTO users (username, password_hash, role, created_at)
+             VALUES (?, ?, ?, ?) RETURNING id",
+            params![username, password_hash, role.as_str(), Utc::now()],
+            |row| row.get(0),
+        )?;
+
+        Self::load(id, pool)
+    }
+
+    pub fn load(id: i32, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<Self> {
+        let conn = pool.get().unwrap();
+
+        conn.query_row(
+            &format!("SELECT {} FROM users WHERE id = ?", COLUMNS),
+            params![id],
+            row_to_user,
+        )
+    }
+
+    pub fn load_all(pool: &r2d2::Pool<DuckdbConnectionManager>) -> Vec<User> {
+        let conn = pool.get().unwrap();
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM users ORDER BY id", COLUMNS))
+            .unwrap();
+
+        let users: Vec<User> = stmt
+            .query_map([], row_to_user)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        users
+    }
+
+    /// Returns the user if the password matches their stored argon2 hash or,
+    /// for LDAP accounts, the directory accepts it. Disabled accounts never
+    /// match. Blocks on the directory, so call it off the async runtime.
+    pub fn verify_password(
+        username: &str,
+        password: &str,
+        ldap: Option<&LdapConfig>,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+    ) -> Option<Self> {
+        let conn = pool.get().unwrap();
+
+        let (id, password_hash, ldap_dn): (i32, String, Option<String>) = conn
+            .query_row(
+                "SELECT id, password_hash, ldap_dn FROM users
+                 WHERE username = ? AND disabled_at IS NULL",
+                params![username],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .unwrap()?;
+
+        match ldap_dn {
+            Some(dn) => {
+                if !ldap_sync::authenticate(ldap?, &dn, password) {
+                    return None;
+                }
+            }
+            None => {
+                let parsed_hash = PasswordHash::new(&password_hash).ok()?;
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed_hash)
+                    .ok()?;
+            }
+        }
+
+        Self::load(id, pool).ok()
+    }
+
+    /// Starts a session and returns the plaintext token for the cookie.
+    pub fn create_session(&self, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<String> {
+        let conn = pool.get().unwrap();
+        let token = generate_token(SESSION_PREFIX);
+        let now = Utc::now();
+
+        conn.execute(
+            "INSERT INTO sessions (token_hash, user_id, created_at, expires_at) VALUES (?, ?, ?, ?)",
+            params![
+                hash_token(&token),
+                self.id,
+                now,
+                now + Duration::days(SESSION_LIFETIME_DAYS)
+            ],
+        )?;
+
+        Ok(token)
+    }
+
+    pub fn from_session(token: &str, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Option<Self> {
+        let conn = pool.get().unwrap();
+
+        let user_id: i32 = conn
+            .query_row(
+                "SELECT user_id FROM sessions WHERE token_hash = ? AND expires_at > ?::TIMESTAMP",
+                params![hash_token(token), Utc::now()],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()?;
+
+        Self::load(user_id, pool)
+            .ok()
+            .filter(|user| user.disabled_at.is_none())
+    }
+
+    pub fn end_session(token: &str, pool: &r2d2::Pool<DuckdbConnectionManager>) -> Result<()> {
+        let conn = pool.get().unwrap();
+
+        conn.execute(
+            "DELETE FROM sessions WHERE token_hash = ?",
+            params![hash_token(token)],
+        )?;
+
+        Ok(())
+    }
+}
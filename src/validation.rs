@@ -0,0 +1,162 @@
+use std::{
+    env, fmt,
+    io::{self, Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+
+use crate::sandbox::{self, SandboxError};
+
+/// Read from the environment the first time an incoming file is checked.
+pub static CONFIG: Lazy<ValidationConfig> = Lazy::new(ValidationConfig::from_env);
+
+/// clamd is sent files in chunks of this size.
+const CLAMD_CHUNK: usize = 64 * 1024;
+const CLAMD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Limits on files pushed to us by upload, the drop folder or email.
+#[derive(Debug, Clone)]
+pub struct ValidationConfig {
+    /// Largest file taken, in bytes
+    pub max_file_bytes: u64,
+    /// Largest page taken once decoded, so a small file can't unpack into a huge one
+    pub max_pixels: u64,
+    /// Where clamd listens, as `host:port` or the path to its socket
+    pub clamd: Option<String>,
+}
+
+impl ValidationConfig {
+    /// From `UPLOAD_MAX_FILE_MB`, `UPLOAD_MAX_MEGAPIXELS` and `CLAMD_ADDRESS`.
+    pub fn from_env() -> Self {
+        ValidationConfig {
+            max_file_bytes: env::var("UPLOAD_MAX_FILE_MB")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(100)
+                * 1024
+                * 1024,
+            max_pixels: env::var("UPLOAD_MAX_MEGAPIXELS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(250)
+                * 1_000_000,
+            clamd: env::var("CLAMD_ADDRESS").ok().filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+/// Why an incoming file or page was turned away.
+#[derive(Debug)]
+pub enum Rejection {
+    TooLarge {
+        bytes: u64,
+    },
+    /// Starts like a program, whatever it's called
+    Executable(&'static str),
+    Undecodable(SandboxError),
+    Infected(String),
+    /// clamd is configured but couldn't be asked; files aren't let through unscanned
+    ScannerUnavailable(io::Error),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooLarge { bytes } => write!(
+                f,
+                "{} bytes is over the limit of {}",
+                bytes, CONFIG.max_file_bytes
+            ),
+            Rejection::Executable(kind) => write!(f, "looks like a {} executable", kind),
+            Rejection::Undecodable(e) => write!(f, "{}", e),
+            Rejection::Infected(signature) => write!(f, "clamd found {}", signature),
+            Rejection::ScannerUnavailable(e) => write!(f, "could not reach clamd: {}", e),
+        }
+    }
+}
+
+/// The kind of program `contents` starts like, if any.
+fn executable_kind(contents: &[u8]) -> Option<&'static str> {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"MZ", "Windows"),
+        (b"\x7fELF", "ELF"),
+        (b"\xfe\xed\xfa\xce", "Mach-O"),
+        (b"\xfe\xed\xfa\xcf", "Mach-O"),
+        (b"\xce\xfa\xed\xfe", "Mach-O"),
+        (b"\xcf\xfa\xed\xfe", "Mach-O"),
+        (b"\xca\xfe\xba\xbe", "Mach-O or Java"),
+        (b"#!", "script"),
+    ];
+    MAGIC
+        .iter()
+        .find(|(magic, _)| contents.starts_with(magic))
+        .map(|(_, kind)| *kind)
+}
+
+/// Checks a whole incoming file before its pages are looked for: its size,
+/// and that it isn't a program. Files of other unknown kinds give no pages
+/// anyway.
+pub fn check_file(contents: &[u8]) -> Result<(), Rejection> {
+    if contents.len() as u64 > CONFIG.max_file_bytes {
+        return Err(Rejection::TooLarge {
+            bytes: contents.len() as u64,
+        });
+    }
+    if let Some(kind) = executable_kind(contents) {
+        return Err(Rejection::Executable(kind));
+    }
+    Ok(())
+}
+
+/// Checks a page before it's written to the assets directory: it must
+/// decode, in a worker, to no more than the pixel limit, and clamd, if
+/// configured, must pass it. Blocks until done.
+pub fn check_page(contents: &[u8]) -> Result<(), Rejection> {
+    sandbox::check_image(contents, CONFIG.max_pixels).map_err(Rejection::Undecodable)?;
+    if let Some(address) = &CONFIG.clamd {
+        clamd_scan(address, contents)?;
+    }
+    Ok(())
+}
+
+/// Sends `contents` to clamd with its INSTREAM command.
+fn clamd_scan(address: &str, contents: &[u8]) -> Result<(), Rejection> {
+    let reply = if address.contains('/') {
+        let stream = UnixStream::connect(address).map_err(Rejection::ScannerUnavailable)?;
+        stream.set_read_timeout(Some(CLAMD_TIMEOUT)).ok();
+        instream(stream, contents)
+    } else {
+        let stream = TcpStream::connect(address).map_err(Rejection::ScannerUnavailable)?;
+        stream.set_read_timeout(Some(CLAMD_TIMEOUT)).ok();
+        instream(stream, contents)
+    }
+    .map_err(Rejection::ScannerUnavailable)?;
+
+    // "stream: OK", or "stream: <signature> FOUND"
+    let verdict = reply.trim_end_matches('\0').trim();
+    match verdict.strip_suffix(" FOUND") {
+        Some(found) => Err(Rejection::Infected(
+            found.trim_start_matches("stream:").trim().to_string(),
+        )),
+        None if verdict.ends_with("OK") => Ok(()),
+        None => Err(Rejection::ScannerUnavailable(io::Error::other(
+            verdict.to_string(),
+        ))),
+    }
+}
+
+fn instream(mut stream: impl Read + Write, contents: &[u8]) -> io::Result<String> {
+    stream.write_all(b"zINSTREAM\0")?;
+    for chunk in contents.chunks(CLAMD_CHUNK) {
+        stream.write_all(&(chunk.len() as u32).to_be_bytes())?;
+        stream.write_all(chunk)?;
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
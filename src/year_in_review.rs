@@ -0,0 +1,156 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Datelike, Local, Utc};
+use duckdb::{params, DuckdbConnectionManager, Result};
+
+use crate::{locale, AssetsDir};
+
+/// Tags listed in `topTags`.
+const TOP_TAGS: usize = 10;
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct TagCount {
+    pub tag: String,
+    pub groups: i64,
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+pub struct ScannerCount {
+    pub scanner: String,
+    pub pages: i64,
+}
+
+/// A year of the archive at a glance, for a personal year-end review or
+/// for justifying the office scanner budget.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct YearInReview {
+    pub year: i32,
+    /// Completed scans captured during the year
+    pub pages_scanned: i64,
+    /// Of those, pages scanned in each month, January first
+    pub pages_by_month: Vec<i64>,
+    /// Groups started during the year that have since been finalized
+    pub documents_finalized: i64,
+    /// Most used tags on groups started during the year, most used first
+    pub top_tags: Vec<TagCount>,
+    /// Bytes on disk of the scans captured during the year, with their
+    /// originals and edited copies
+    pub storage_added_bytes: i64,
+    /// Bytes on disk of every scan captured up to the end of the year
+    pub storage_total_bytes: i64,
+    /// Scanners by pages scanned during the year, busiest first
+    pub scanners: Vec<ScannerCount>,
+    pub busiest_scanner: Option<ScannerCount>,
+}
+
+fn file_size(path: &str, assets_dir: &AssetsDir) -> i64 {
+    fs::metadata(Path::new(&assets_dir.0).join(path))
+        .map(|metadata| metadata.len() as i64)
+        .unwrap_or(0)
+}
+
+impl YearInReview {
+    pub fn load(
+        year: i32,
+        pool: &r2d2::Pool<DuckdbConnectionManager>,
+        assets_dir: &AssetsDir,
+    ) -> Result<Self> {
+        let conn = pool.get().unwrap();
+        // The year as it was lived, not as it was in Greenwich
+        let (start, end) = locale::year_bounds(year);
+
+        let mut pages_by_month = vec![0i64; 12];
+        // Bucketed here rather than by month() so months are local too
+        let mut stmt = conn.prepare(
+            "SELECT scanned_at FROM scans
+             WHERE status = 'COMPLETE' AND scanned_at >= ? AND scanned_at < ?",
+        )?;
+        for scanned_at in
+            stmt.query_map(params![start, end], |row| row.get::<_, DateTime<Utc>>(0))?
+        {
+            let month = scanned_at?.with_timezone(&Local).month0();
+            pages_by_month[month as usize] += 1;
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT scanner, count(*) AS pages FROM scans
+             WHERE status = 'COMPLETE' AND scanned_at >= ? AND scanned_at < ?
+             GROUP BY scanner
+             ORDER BY pages DESC, scanner",
+        )?;
+        let scanners = stmt
+            .query_map(params![start, end], |row| {
+                Ok(ScannerCount {
+                    scanner: row.get(0)?,
+                    pages: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        let documents_finalized: i64 = conn.query_row(
+            "SELECT count(*) FROM scan_groups
+             WHERE status = 'finalized' AND created_at >= ? AND created_at < ?",
+            params![start, end],
+            |row| row.get(0),
+        )?;
+
+        let mut tag_counts: HashMap<String, i64> = HashMap::new();
+        let mut stmt =
+            conn.prepare("SELECT tags FROM scan_groups WHERE created_at >= ? AND created_at < ?")?;
+        for tags_json in stmt.query_map(params![start, end], |row| row.get::<_, String>(0))? {
+            let tags: Vec<String> = serde_json::from_str(&tags_json?).unwrap_or_default();
+            for tag in tags {
+                *tag_counts.entry(tag).or_default() += 1;
+            }
+        }
+        let mut top_tags: Vec<TagCount> = tag_counts
+            .into_iter()
+            .map(|(tag, groups)| TagCount { tag, groups })
+            .collect();
+        top_tags.sort_by(|a, b| b.groups.cmp(&a.groups).then_with(|| a.tag.cmp(&b.tag)));
+        top_tags.truncate(TOP_TAGS);
+
+        // Sizes aren't recorded, so they are read from disk
+        let mut storage_added_bytes = 0;
+        let mut storage_total_bytes = 0;
+        let mut stmt = conn.prepare(
+            "SELECT scanned_at >= ?, path, original_path, edited_path FROM scans
+             WHERE scanned_at < ?",
+        )?;
+        let files = stmt.query_map(params![start, end], |row| {
+            Ok((
+                row.get::<_, bool>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?;
+        for file in files {
+            let (this_year, path, original_path, edited_path) = file?;
+            let mut paths = vec![path];
+            for other in [original_path, edited_path].into_iter().flatten() {
+                if !paths.contains(&other) {
+                    paths.push(other);
+                }
+            }
+            let bytes: i64 = paths.iter().map(|path| file_size(path, assets_dir)).sum();
+            storage_total_bytes += bytes;
+            if this_year {
+                storage_added_bytes += bytes;
+            }
+        }
+
+        Ok(YearInReview {
+            year,
+            pages_scanned: pages_by_month.iter().sum(),
+            pages_by_month,
+            documents_finalized,
+            top_tags,
+            storage_added_bytes,
+            storage_total_bytes,
+            busiest_scanner: scanners.first().cloned(),
+            scanners,
+        })
+    }
+}
@@ -0,0 +1,73 @@
+use std::io::{self, Write};
+
+/// Flags, method (stored), time and date (1980-01-01, the earliest zip allows)
+const DATE_FIELDS: [u8; 8] = [0, 0, 0, 0, 0, 0, 0x21, 0];
+
+/// CRC-32 as zip uses it (IEEE polynomial, reflected).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Writes a minimal zip archive holding each `(name, contents)` entry in
+/// order, uncompressed. Scans are already JPEG or PNG, so deflating them
+/// would gain little; formats like EPUB also need some entries stored.
+pub fn write_zip(entries: &[(&str, &[u8])], out: &mut impl Write) -> io::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut central: Vec<u8> = Vec::new();
+
+    for (name, contents) in entries {
+        let offset = buf.len() as u32;
+        let crc = crc32(contents);
+        let size = contents.len() as u32;
+        let name = name.as_bytes();
+
+        // Local file header: version 1.0, no flags, stored, dated 1980-01-01
+        buf.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        buf.extend_from_slice(&10u16.to_le_bytes());
+        buf.extend_from_slice(&DATE_FIELDS);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(name);
+        buf.extend_from_slice(contents);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&10u16.to_le_bytes());
+        central.extend_from_slice(&10u16.to_le_bytes());
+        central.extend_from_slice(&DATE_FIELDS);
+        central.extend_from_slice(&crc.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // Extra field, comment, disk number, internal and external attributes
+        central.extend_from_slice(&[0; 12]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name);
+    }
+
+    let central_offset = buf.len() as u32;
+    buf.extend_from_slice(&central);
+
+    buf.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&central_offset.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+
+    out.write_all(&buf)
+}